@@ -0,0 +1,244 @@
+extern crate piston_meta;
+extern crate range;
+extern crate dyon;
+
+use std::sync::Arc;
+use self::piston_meta::MetaData;
+use self::range::Range;
+use self::dyon::{error, load_meta, Module, Runtime};
+
+fn main() {
+    let mut module = Module::new();
+    let source: Arc<String> = Arc::new(String::from("// Use `any` implicitly.
+fn foo(x) -> {return x + 1}
+// Add simple refinement types to catch more errors.
+(f64) -> f64
+(any) -> vec4
+
+bar(x: f64) = x + 1
+
+fn main() {
+    x := bar(foo((1, 2)))
+    println(x)
+}
+"));
+    let s: Vec<Arc<String>> = vec![
+        Arc::new(String::from("uses")),
+        Arc::new(String::from("name")),
+        Arc::new(String::from("arg")),
+        Arc::new(String::from("expr")),
+        Arc::new(String::from("item")),
+        Arc::new(String::from("mul")),
+        Arc::new(String::from("add")),
+        Arc::new(String::from("return")),
+        Arc::new(String::from("block")),
+        Arc::new(String::from("ty_arg")),
+        Arc::new(String::from("f64")),
+        Arc::new(String::from("ty_ret")),
+        Arc::new(String::from("ty")),
+        Arc::new(String::from("fn")),
+        Arc::new(String::from("type")),
+        Arc::new(String::from("+")),
+        Arc::new(String::from("num")),
+        Arc::new(String::from("returns")),
+        Arc::new(String::from("left")),
+        Arc::new(String::from("call")),
+        Arc::new(String::from("call_arg")),
+        Arc::new(String::from("vec4")),
+        Arc::new(String::from("x")),
+        Arc::new(String::from("y")),
+        Arc::new(String::from("right")),
+        Arc::new(String::from("assign")),
+        Arc::new(String::from("bar")),
+        Arc::new(String::from("foo")),
+    ];
+    let meta_data: Vec<Range<MetaData>> = vec![
+    Range {offset: 25, length: 0, data: MetaData::StartNode(s[0].clone())},
+    Range {offset: 25, length: 0, data: MetaData::EndNode(s[0].clone())},
+    Range {offset: 25, length: 0, data: MetaData::StartNode(s[13].clone())},
+    Range {offset: 28, length: 3, data: MetaData::String(s[1].clone(), s[27].clone())},
+    Range {offset: 32, length: 0, data: MetaData::StartNode(s[2].clone())},
+    Range {offset: 32, length: 1, data: MetaData::String(s[1].clone(), s[22].clone())},
+    Range {offset: 32, length: 1, data: MetaData::EndNode(s[2].clone())},
+    Range {offset: 35, length: 2, data: MetaData::Bool(s[17].clone(), true)},
+    Range {offset: 38, length: 0, data: MetaData::StartNode(s[8].clone())},
+    Range {offset: 39, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 46, length: 0, data: MetaData::StartNode(s[7].clone())},
+    Range {offset: 46, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 46, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 46, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 46, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 46, length: 0, data: MetaData::StartNode(s[4].clone())},
+    Range {offset: 46, length: 1, data: MetaData::String(s[1].clone(), s[22].clone())},
+    Range {offset: 46, length: 1, data: MetaData::EndNode(s[4].clone())},
+    Range {offset: 46, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 46, length: 1, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 46, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 48, length: 1, data: MetaData::Bool(s[15].clone(), true)},
+    Range {offset: 50, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 50, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 50, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 50, length: 1, data: MetaData::F64(s[16].clone(), 1.0)},
+    Range {offset: 50, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 50, length: 1, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 50, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 46, length: 5, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 46, length: 5, data: MetaData::EndNode(s[7].clone())},
+    Range {offset: 39, length: 12, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 38, length: 14, data: MetaData::EndNode(s[8].clone())},
+    Range {offset: 106, length: 0, data: MetaData::StartNode(s[12].clone())},
+    Range {offset: 107, length: 0, data: MetaData::StartNode(s[9].clone())},
+    Range {offset: 107, length: 3, data: MetaData::Bool(s[10].clone(), true)},
+    Range {offset: 107, length: 3, data: MetaData::EndNode(s[9].clone())},
+    Range {offset: 115, length: 0, data: MetaData::StartNode(s[11].clone())},
+    Range {offset: 115, length: 3, data: MetaData::Bool(s[10].clone(), true)},
+    Range {offset: 115, length: 3, data: MetaData::EndNode(s[11].clone())},
+    Range {offset: 106, length: 12, data: MetaData::EndNode(s[12].clone())},
+    Range {offset: 119, length: 0, data: MetaData::StartNode(s[12].clone())},
+    Range {offset: 120, length: 0, data: MetaData::StartNode(s[9].clone())},
+    Range {offset: 120, length: 3, data: MetaData::Bool(Arc::new(String::from("any")), true)},
+    Range {offset: 120, length: 3, data: MetaData::EndNode(s[9].clone())},
+    Range {offset: 128, length: 0, data: MetaData::StartNode(s[11].clone())},
+    Range {offset: 128, length: 4, data: MetaData::Bool(s[21].clone(), true)},
+    Range {offset: 128, length: 4, data: MetaData::EndNode(s[11].clone())},
+    Range {offset: 119, length: 13, data: MetaData::EndNode(s[12].clone())},
+    Range {offset: 25, length: 109, data: MetaData::EndNode(s[13].clone())},
+    Range {offset: 134, length: 0, data: MetaData::StartNode(s[13].clone())},
+    Range {offset: 134, length: 3, data: MetaData::String(s[1].clone(), s[26].clone())},
+    Range {offset: 138, length: 0, data: MetaData::StartNode(s[2].clone())},
+    Range {offset: 138, length: 1, data: MetaData::String(s[1].clone(), s[22].clone())},
+    Range {offset: 141, length: 0, data: MetaData::StartNode(s[14].clone())},
+    Range {offset: 141, length: 3, data: MetaData::Bool(s[10].clone(), true)},
+    Range {offset: 141, length: 3, data: MetaData::EndNode(s[14].clone())},
+    Range {offset: 138, length: 6, data: MetaData::EndNode(s[2].clone())},
+    Range {offset: 148, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 148, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 148, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 148, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 148, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 148, length: 0, data: MetaData::StartNode(s[4].clone())},
+    Range {offset: 148, length: 1, data: MetaData::String(s[1].clone(), s[22].clone())},
+    Range {offset: 148, length: 1, data: MetaData::EndNode(s[4].clone())},
+    Range {offset: 148, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 148, length: 1, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 148, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 150, length: 1, data: MetaData::Bool(s[15].clone(), true)},
+    Range {offset: 152, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 152, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 152, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 152, length: 1, data: MetaData::F64(s[16].clone(), 1.0)},
+    Range {offset: 152, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 152, length: 1, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 152, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 148, length: 5, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 148, length: 5, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 134, length: 19, data: MetaData::EndNode(s[13].clone())},
+    Range {offset: 155, length: 0, data: MetaData::StartNode(s[13].clone())},
+    Range {offset: 158, length: 4, data: MetaData::String(s[1].clone(), Arc::new(String::from("main")))},
+    Range {offset: 165, length: 0, data: MetaData::Bool(s[17].clone(), false)},
+    Range {offset: 165, length: 0, data: MetaData::StartNode(s[8].clone())},
+    Range {offset: 171, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 171, length: 0, data: MetaData::StartNode(s[25].clone())},
+    Range {offset: 171, length: 0, data: MetaData::StartNode(s[18].clone())},
+    Range {offset: 171, length: 0, data: MetaData::StartNode(s[4].clone())},
+    Range {offset: 171, length: 1, data: MetaData::String(s[1].clone(), s[22].clone())},
+    Range {offset: 171, length: 1, data: MetaData::EndNode(s[4].clone())},
+    Range {offset: 171, length: 1, data: MetaData::EndNode(s[18].clone())},
+    Range {offset: 173, length: 2, data: MetaData::Bool(Arc::new(String::from(":=")), true)},
+    Range {offset: 176, length: 0, data: MetaData::StartNode(s[24].clone())},
+    Range {offset: 176, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 176, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 176, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 176, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 176, length: 0, data: MetaData::StartNode(s[19].clone())},
+    Range {offset: 176, length: 3, data: MetaData::String(s[1].clone(), s[26].clone())},
+    Range {offset: 180, length: 0, data: MetaData::StartNode(s[20].clone())},
+    Range {offset: 180, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 180, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 180, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 180, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 180, length: 0, data: MetaData::StartNode(s[19].clone())},
+    Range {offset: 180, length: 3, data: MetaData::String(s[1].clone(), s[27].clone())},
+    Range {offset: 184, length: 0, data: MetaData::StartNode(s[20].clone())},
+    Range {offset: 184, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 184, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 184, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 184, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 184, length: 0, data: MetaData::StartNode(s[21].clone())},
+    Range {offset: 185, length: 0, data: MetaData::StartNode(s[22].clone())},
+    Range {offset: 185, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 185, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 185, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 185, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 185, length: 1, data: MetaData::F64(s[16].clone(), 1.0)},
+    Range {offset: 185, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 185, length: 1, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 185, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 185, length: 1, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 185, length: 1, data: MetaData::EndNode(s[22].clone())},
+    Range {offset: 188, length: 0, data: MetaData::StartNode(s[23].clone())},
+    Range {offset: 188, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 188, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 188, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 188, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 188, length: 1, data: MetaData::F64(s[16].clone(), 2.0)},
+    Range {offset: 188, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 188, length: 1, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 188, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 188, length: 1, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 188, length: 1, data: MetaData::EndNode(s[23].clone())},
+    Range {offset: 184, length: 6, data: MetaData::EndNode(s[21].clone())},
+    Range {offset: 184, length: 6, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 184, length: 6, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 184, length: 6, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 184, length: 6, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 184, length: 6, data: MetaData::EndNode(s[20].clone())},
+    Range {offset: 180, length: 11, data: MetaData::EndNode(s[19].clone())},
+    Range {offset: 180, length: 11, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 180, length: 11, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 180, length: 11, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 180, length: 11, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 180, length: 11, data: MetaData::EndNode(s[20].clone())},
+    Range {offset: 176, length: 16, data: MetaData::EndNode(s[19].clone())},
+    Range {offset: 176, length: 16, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 176, length: 16, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 176, length: 16, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 176, length: 16, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 176, length: 16, data: MetaData::EndNode(s[24].clone())},
+    Range {offset: 171, length: 21, data: MetaData::EndNode(s[25].clone())},
+    Range {offset: 171, length: 21, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 197, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 197, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 197, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 197, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 197, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 197, length: 0, data: MetaData::StartNode(s[19].clone())},
+    Range {offset: 197, length: 7, data: MetaData::String(s[1].clone(), Arc::new(String::from("println")))},
+    Range {offset: 205, length: 0, data: MetaData::StartNode(s[20].clone())},
+    Range {offset: 205, length: 0, data: MetaData::StartNode(s[6].clone())},
+    Range {offset: 205, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 205, length: 0, data: MetaData::StartNode(s[5].clone())},
+    Range {offset: 205, length: 0, data: MetaData::StartNode(s[3].clone())},
+    Range {offset: 205, length: 0, data: MetaData::StartNode(s[4].clone())},
+    Range {offset: 205, length: 1, data: MetaData::String(s[1].clone(), s[22].clone())},
+    Range {offset: 205, length: 1, data: MetaData::EndNode(s[4].clone())},
+    Range {offset: 205, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 205, length: 1, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 205, length: 1, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 205, length: 1, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 205, length: 1, data: MetaData::EndNode(s[20].clone())},
+    Range {offset: 197, length: 10, data: MetaData::EndNode(s[19].clone())},
+    Range {offset: 197, length: 10, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 197, length: 10, data: MetaData::EndNode(s[5].clone())},
+    Range {offset: 197, length: 10, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 197, length: 10, data: MetaData::EndNode(s[6].clone())},
+    Range {offset: 197, length: 10, data: MetaData::EndNode(s[3].clone())},
+    Range {offset: 165, length: 44, data: MetaData::EndNode(s[8].clone())},
+    Range {offset: 155, length: 55, data: MetaData::EndNode(s[13].clone())},
+    ];
+    if error(load_meta("source/test.dyon", source, &meta_data, &mut module)) {return;}
+    let mut runtime = Runtime::new();
+    if error(runtime.run(&Arc::new(module))) {
+        return;
+    }
+}
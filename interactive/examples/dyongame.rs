@@ -11,7 +11,7 @@ extern crate image;
 use std::sync::Arc;
 use current::CurrentGuard;
 use dyon::{error, load, Module, Dfn, Runtime, Type};
-use dyon_interactive::{FontNames, ImageNames};
+use dyon_interactive::{FontNames, ImageNames, Watcher};
 use image::RgbaImage;
 use piston::input::Event;
 use piston::window::WindowSettings;
@@ -82,6 +82,7 @@ fn main() {
     let mut textures = vec![];
     let mut gl = GlGraphics::new(opengl);
     let mut events = Events::new(EventSettings::new());
+    let mut watcher = Watcher::new(&[file.as_str()]);
 
     let mut e: Option<Event> = None;
     let sdl = window.sdl_context.clone();
@@ -95,6 +96,7 @@ fn main() {
     let textures_guard: CurrentGuard<Vec<Texture>> = CurrentGuard::new(&mut textures);
     let gl_guard: CurrentGuard<GlGraphics> = CurrentGuard::new(&mut gl);
     let events_guard: CurrentGuard<Events> = CurrentGuard::new(&mut events);
+    let watcher_guard: CurrentGuard<Watcher> = CurrentGuard::new(&mut watcher);
 
     music::start_context::<Music, Sound, _>(&sdl, 16, || {
         if error(dyon_runtime.run(&dyon_module)) {
@@ -102,6 +104,7 @@ fn main() {
         }
     });
 
+    drop(watcher_guard);
     drop(events_guard);
     drop(gl_guard);
     drop(textures_guard);
@@ -165,7 +168,7 @@ mod dyon_functions {
     use piston::event_loop::Events;
     use opengl_graphics::{GlGraphics, GlyphCache, Texture, TextureSettings};
     use dyon::{Runtime, Variable};
-    use dyon_interactive::{draw_2d, NO_EVENT};
+    use dyon_interactive::{draw_2d, Watcher, NO_EVENT};
     use current::Current;
     use std::sync::Arc;
     use music;
@@ -228,18 +231,31 @@ mod dyon_functions {
         Ok(())
     }
 
-    dyon_fn!{fn next_event() -> bool {
+    /// Polls the next event, reloading changed source files first.
+    pub fn next_event(rt: &mut Runtime) -> Result<Variable, String> {
+        use dyon::embed::PushVariable;
+
         let window = unsafe { &mut *Current::<Sdl2Window>::new() };
         let events = unsafe { &mut *Current::<Events>::new() };
         let e = unsafe { &mut *Current::<Option<Event>>::new() };
-        if let Some(new_e) = events.next(window) {
+        let watcher = unsafe { &mut *Current::<Watcher>::new() };
+        let mut module = rt.module.clone();
+        for (file, res) in watcher.reload(rt, &mut module) {
+            match res {
+                Ok(ref reload) if reload.needs_restart() =>
+                    println!("Functions in `{}` changed signature, restart to reload", file),
+                Ok(_) => println!("Reloaded `{}`", file),
+                Err(err) => println!("{}", err),
+            }
+        }
+        Ok(if let Some(new_e) = events.next(window) {
             *e = Some(new_e);
             true
         } else {
             *e = None;
             false
-        }
-    }}
+        }.push_var())
+    }
 
     dyon_fn!{fn bind_sound__name_file(name: Arc<String>, file: Arc<String>) {
         music::bind_sound_file(Sound::Name(name), &**file);
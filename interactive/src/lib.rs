@@ -6,7 +6,9 @@ extern crate texture;
 extern crate image;
 
 use std::any::Any;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::SystemTime;
 use self::dyon::*;
 use self::current::Current;
 use self::piston::input::*;
@@ -438,6 +440,61 @@ dyon_fn!{fn font_names() -> Vec<Arc<String>> {
     font_names.0.clone()
 }}
 
+/// Watches source files for changes, used to hot reload a running module.
+pub struct Watcher {
+    /// File names as loaded, with the last modified time.
+    files: Vec<(String, Option<SystemTime>)>,
+}
+
+impl Watcher {
+    /// Creates a new watcher of the loaded files.
+    pub fn new(files: &[&str]) -> Watcher {
+        Watcher {
+            files: files.iter().map(|&f| (f.into(), modified(f))).collect()
+        }
+    }
+
+    /// Returns the files that changed since the last call.
+    pub fn changed(&mut self) -> Vec<String> {
+        let mut changed = vec![];
+        for &mut (ref file, ref mut time) in &mut self.files {
+            let new_time = modified(file);
+            if new_time != *time {
+                *time = new_time;
+                changed.push(file.clone());
+            }
+        }
+        changed
+    }
+
+    /// Reloads changed files into a module.
+    ///
+    /// Returns the result of reloading each changed file.
+    /// See `Runtime::reload_str` for how a running module is changed.
+    pub fn reload(
+        &mut self,
+        rt: &mut Runtime,
+        module: &mut Arc<Module>
+    ) -> Vec<(String, Result<reload::Reload, DyonError>)> {
+        use std::fs::read_to_string;
+
+        let mut res = vec![];
+        for file in self.changed() {
+            let r = match read_to_string(&file) {
+                Ok(source) => rt.reload_str(module, &file, Arc::new(source)),
+                Err(err) => Err(DyonError::Io(ErrorInfo::from_text(
+                    format!("Could not read `{}`, {}", file, err)))),
+            };
+            res.push((file, r));
+        }
+        res
+    }
+}
+
+fn modified(file: &str) -> Option<SystemTime> {
+    PathBuf::from(file).metadata().and_then(|m| m.modified()).ok()
+}
+
 /// Helper method for loading fonts.
 pub fn load_font<F, T>(rt: &mut Runtime) -> Result<Variable, String>
     where F: 'static + Clone, T: 'static +
@@ -0,0 +1,205 @@
+//! Source analysis for editor tooling.
+//!
+//! Used by the `dyon-lsp` language server to report diagnostics,
+//! find definitions, show function signatures and complete function names.
+
+use std::sync::Arc;
+use range::Range;
+use piston_meta::parse;
+use piston_meta::bootstrap::Convert;
+
+use ast::{FnAlias, UseLookup, Uses};
+use lifetime::{self, Kind, Node};
+use {load_str, DyonError, FnIndex, Module, Prelude};
+
+/// Stores what is known about a source file.
+pub struct Analysis {
+    file: Arc<String>,
+    source: Arc<String>,
+    nodes: Vec<Node>,
+    uses: Option<Uses>,
+    module: Module,
+    error: Option<DyonError>,
+    warnings: Vec<Range<String>>,
+}
+
+/// The location of a definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    /// The file where the function is declared.
+    pub file: Arc<String>,
+    /// The source of the file.
+    pub source: Arc<String>,
+    /// The range of the function in source.
+    pub range: Range,
+}
+
+impl Analysis {
+    /// Analyzes source using the functions in a module.
+    ///
+    /// The module is used as prelude, e.g. the standard library
+    /// and modules that the source imports.
+    pub fn new(file: &str, source: Arc<String>, module: &Module) -> Analysis {
+        let mut loaded = module.clone();
+        let error = load_str(file, source.clone(), &mut loaded).err();
+        let module = if error.is_none() {loaded} else {module.clone()};
+
+        let mut nodes = vec![];
+        let mut uses = None;
+        let mut warnings = vec![];
+        if let Ok(syntax_rules) = ::SYNTAX_RULES.as_ref() {
+            let mut data = vec![];
+            if parse(syntax_rules, &source, &mut data).is_ok() {
+                let prelude = Prelude::from_module(&module);
+                let _ = lifetime::check_core(&mut nodes, &data, &prelude, &mut warnings);
+                uses = nodes.iter().find(|n| n.kind == Kind::Uses).and_then(|n| {
+                    let convert = Convert::new(&data[n.start..n.end]);
+                    Uses::from_meta_data(convert, &mut vec![]).ok().map(|(_, val)| val)
+                });
+            }
+        }
+
+        Analysis {
+            file: Arc::new(file.into()),
+            source,
+            nodes,
+            uses,
+            module,
+            error,
+            warnings,
+        }
+    }
+
+    /// Returns the error reported when loading the source, if any.
+    pub fn error(&self) -> Option<&DyonError> {self.error.as_ref()}
+
+    /// Returns the warnings reported by the type checker.
+    pub fn warnings(&self) -> &[Range<String>] {&self.warnings}
+
+    /// Finds the declaration of the function called at offset in source.
+    pub fn definition(&self, offset: usize) -> Option<Definition> {
+        let call = self.call_at(offset)?;
+        if let Some(decl) = self.nodes[call].declaration {
+            return Some(Definition {
+                file: self.file.clone(),
+                source: self.source.clone(),
+                range: self.nodes[decl].source,
+            });
+        }
+        let i = self.loaded_function(call)?;
+        let f = &self.module.functions[i];
+        Some(Definition {
+            file: f.file.clone(),
+            source: f.source.clone(),
+            range: f.source_range,
+        })
+    }
+
+    /// Returns the signature of the function called or declared at offset in source.
+    ///
+    /// Uses the return type refined by the type checker when the source loads.
+    pub fn hover(&self, offset: usize) -> Option<String> {
+        if let Some(call) = self.call_at(offset) {
+            if let Some(i) = self.loaded_function(call) {
+                return Some(signature_loaded(&self.module.functions[i]));
+            }
+            let name = self.nodes[call].name()?;
+            let prelude = Prelude::from_module(&self.module);
+            let &i = prelude.functions.get(name)?;
+            let dfn = &prelude.list[i];
+            let args: Vec<String> = dfn.tys.iter().map(|ty| ty.description()).collect();
+            return Some(signature(name, &args, &dfn.ret.description()));
+        }
+        let decl = self.nodes.iter().position(|n| {
+            n.kind == Kind::Fn && n.name().map(|name| {
+                let name = base_name(name);
+                n.source.offset <= offset &&
+                offset < n.source.offset + n.source.length &&
+                self.name_range(n, name).map(|r| r.0 <= offset && offset <= r.1)
+                    .unwrap_or(false)
+            }).unwrap_or(false)
+        })?;
+        let name = self.nodes[decl].name()?;
+        match self.module.find_function(name, 0) {
+            FnIndex::Loaded(i) => Some(signature_loaded(&self.module.functions[i as usize])),
+            _ => None
+        }
+    }
+
+    /// Returns sorted names of prelude and loaded functions starting with a prefix.
+    pub fn completions(&self, prefix: &str) -> Vec<Arc<String>> {
+        let prelude = Prelude::from_module(&self.module);
+        let mut names: Vec<Arc<String>> = prelude.functions.keys()
+            .map(|name| base_name(name))
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Arc::new(name.into()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Finds the innermost call where the offset is on the function name.
+    fn call_at(&self, offset: usize) -> Option<usize> {
+        let mut found: Option<usize> = None;
+        for (i, n) in self.nodes.iter().enumerate() {
+            if n.kind != Kind::Call {continue};
+            if offset < n.source.offset {continue};
+            let name = match n.name() {Some(x) => base_name(x), None => continue};
+            let mut len = name.len();
+            if let Some(ref alias) = n.alias {len += alias.len() + 2};
+            if offset > n.source.offset + len {continue};
+            match found {
+                Some(j) if self.nodes[j].source.length <= n.source.length => {}
+                _ => found = Some(i),
+            }
+        }
+        found
+    }
+
+    /// Resolves a call to a loaded function in the module.
+    fn loaded_function(&self, call: usize) -> Option<usize> {
+        let n = &self.nodes[call];
+        let name = n.name()?;
+        if let Some(ref alias) = n.alias {
+            let uses = self.uses.as_ref()?;
+            let lookup = UseLookup::from_uses_module(uses, &self.module);
+            return match lookup.aliases.get(alias).and_then(|map| map.get(name)) {
+                Some(&FnAlias::Loaded(i)) => Some(i),
+                _ => None
+            };
+        }
+        match self.module.find_function(name, 0) {
+            FnIndex::Loaded(i) => Some(i as usize),
+            _ => None
+        }
+    }
+
+    /// Finds the start and end offset of a function name in its declaration.
+    fn name_range(&self, n: &Node, name: &str) -> Option<(usize, usize)> {
+        let end = ::std::cmp::min(n.source.offset + n.source.length, self.source.len());
+        let text = self.source.get(n.source.offset..end)?;
+        let start = n.source.offset + text.find(name)?;
+        Some((start, start + name.len()))
+    }
+}
+
+/// Strips mutability information from a function name, e.g. `foo(mut,_)`.
+fn base_name(name: &str) -> &str {
+    match name.find('(') {
+        Some(i) => &name[..i],
+        None => name
+    }
+}
+
+fn signature(name: &str, args: &[String], ret: &str) -> String {
+    format!("fn {}({}) -> {}", base_name(name), args.join(", "), ret)
+}
+
+fn signature_loaded(f: &::ast::Function) -> String {
+    let args: Vec<String> = f.args.iter()
+        .map(|arg| format!("{}{}: {}", if arg.mutable {"mut "} else {""},
+                           arg.name, arg.ty.description()))
+        .collect();
+    signature(&f.name, &args, &f.ret.description())
+}
@@ -9,6 +9,7 @@ use super::{
     CallClosure,
     Expression,
     ForN,
+    GoCall,
     Id,
     Item,
 };
@@ -48,6 +49,14 @@ fn infer_expr(
                 if res.is_some() { return res; }
             }
         }
+        Interp(ref interp) => {
+            for part in &interp.parts {
+                if let super::InterpPart::Item(ref item) = *part {
+                    let res = infer_expr(&item.expr, name, decls);
+                    if res.is_some() { return res; }
+                }
+            }
+        }
         Item(ref item) => {
             let res = infer_item(item, name, decls);
             if res.is_some() { return res; }
@@ -70,13 +79,19 @@ fn infer_expr(
                 if res.is_some() { return res; }
             }
         }
+        Variant(ref variant) => {
+            for expr in &variant.args {
+                let res = infer_expr(expr, name, decls);
+                if res.is_some() { return res; }
+            }
+        }
         ArrayFill(ref arr_fill) => {
             let fill = infer_expr(&arr_fill.fill, name, decls);
             if fill.is_some() { return fill; }
             let n = infer_expr(&arr_fill.n, name, decls);
             if n.is_some() { return n; }
         }
-        Return(ref ret_expr) => {
+        Return(ref ret_expr) | Yield(ref ret_expr) => {
             let res = infer_expr(ret_expr, name, decls);
             if res.is_some() { return res; }
         }
@@ -88,7 +103,10 @@ fn infer_expr(
             if res.is_some() { return res; }
         }
         Go(ref go) => {
-            let res = infer_call(&go.call, name, decls);
+            let res = match go.call {
+                GoCall::Call(ref call) => infer_call(call, name, decls),
+                GoCall::Closure(ref call) => infer_call_closure(call, name, decls),
+            };
             if res.is_some() { return res; }
         }
         Call(ref call) => {
@@ -131,6 +149,10 @@ fn infer_expr(
             let res = infer_expr(&for_in_expr.iter, name, decls);
             if res.is_some() { return res; }
         }
+        Lock(ref lock) => {
+            let res = infer_expr(&lock.mutex, name, decls);
+            if res.is_some() { return res; }
+        }
         Sum(ref for_n_expr) => {
             return infer_for_n(for_n_expr, name, decls)
         }
@@ -210,6 +232,17 @@ fn infer_expr(
                 if res.is_some() { return res; }
             }
         }
+        Match(ref match_expr) => {
+            let res = infer_expr(&match_expr.expr, name, decls);
+            if res.is_some() { return res; }
+            for arm in &match_expr.arms {
+                let mut binds = vec![];
+                arm.pattern.binds(&mut binds);
+                if binds.iter().any(|bind| **bind == name) { continue; }
+                let res = infer_expr(&arm.expr, name, decls);
+                if res.is_some() { return res; }
+            }
+        }
         Variable(_) => {}
         Try(ref expr) => {
             let res = infer_expr(expr, name, decls);
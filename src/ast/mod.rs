@@ -9,6 +9,7 @@ use piston_meta::MetaData;
 
 use FnIndex;
 use Module;
+use runtime::bytecode::Chunk;
 use Prelude;
 use Type;
 use Variable;
@@ -45,6 +46,12 @@ pub fn convert(
         Function::from_meta_data(&namespace, &file, &source, "fn", convert, ignored) {
             convert.update(range);
             module.register(function);
+        } else if let Ok((range, record)) = Record::from_meta_data(convert, ignored) {
+            convert.update(range);
+            module.register_record(record);
+        } else if let Ok((range, enum_decl)) = Enum::from_meta_data(convert, ignored) {
+            convert.update(range);
+            module.register_enum(enum_decl);
         } else if convert.remaining_data_len() > 0 {
             return Err(());
         } else {
@@ -355,6 +362,8 @@ pub struct Function {
     pub block: Block,
     /// The return type of function.
     pub ret: Type,
+    /// The record named by the return type, looked up when loading.
+    pub(crate) ret_record: Option<Arc<String>>,
     /// Whether local variable references has been resolved.
     pub resolved: Arc<sync::atomic::AtomicBool>,
     /// The range in source.
@@ -364,6 +373,8 @@ pub struct Function {
         sync::atomic::AtomicBool,
         sync::Mutex<Vec<sync::mpsc::Sender<Variable>>>
     )>,
+    /// Bytecode compiled from the function block on first use.
+    pub(crate) bytecode: Arc<sync::OnceLock<Chunk>>,
 }
 
 impl Function {
@@ -474,14 +485,21 @@ impl Function {
             currents,
             block,
             ret,
+            ret_record: None,
             source_range: convert.source(start).unwrap(),
             senders: Arc::new((AtomicBool::new(false), Mutex::new(vec![]))),
+            bytecode: Arc::new(sync::OnceLock::new()),
         }))
     }
 
     /// Returns `true` if the function returns something.
     pub fn returns(&self) -> bool { self.ret != Type::Void }
 
+    /// Returns the bytecode of the function block, compiling it if needed.
+    pub(crate) fn bytecode(&self) -> &Chunk {
+        self.bytecode.get_or_init(|| Chunk::compile(&self.block))
+    }
+
     fn resolve_locals(&mut self, relative: usize, module: &Module, use_lookup: &UseLookup) {
         use std::sync::atomic::Ordering;
 
@@ -504,6 +522,178 @@ impl Function {
     }
 }
 
+/// Record, a named object type with declared fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The name of the record.
+    pub name: Arc<String>,
+    /// The fields with types, in declared order.
+    pub fields: Vec<(Arc<String>, Type)>,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+impl Record {
+    /// Creates record from meta data.
+    pub fn from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, Record), ()> {
+        let start = convert;
+        let node = "record";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut fields: Vec<(Arc<String>, Type)> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Record::field_from_meta_data(convert, ignored) {
+                convert.update(range);
+                fields.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = name.ok_or(())?;
+        Ok((convert.subtract(start), Record {
+            name,
+            fields,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    fn field_from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, (Arc<String>, Type)), ()> {
+        let start = convert;
+        let node = "record_field";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut ty: Option<Type> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "type", convert, ignored) {
+                convert.update(range);
+                ty = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        Ok((convert.subtract(start), (name.ok_or(())?, ty.ok_or(())?)))
+    }
+}
+
+/// Enum, a named type with variants that can carry values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+    /// The name of the enum.
+    pub name: Arc<String>,
+    /// The variants with payload types, in declared order.
+    pub variants: Vec<(Arc<String>, Vec<Type>)>,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+impl Enum {
+    /// Creates enum from meta data.
+    pub fn from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, Enum), ()> {
+        let start = convert;
+        let node = "enum";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut variants: Vec<(Arc<String>, Vec<Type>)> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Enum::variant_from_meta_data(convert, ignored) {
+                convert.update(range);
+                variants.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = name.ok_or(())?;
+        Ok((convert.subtract(start), Enum {
+            name,
+            variants,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    fn variant_from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>
+    ) -> Result<(Range, (Arc<String>, Vec<Type>)), ()> {
+        let start = convert;
+        let node = "enum_variant";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut tys: Vec<Type> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok(range) = convert.start_node("variant_arg") {
+                convert.update(range);
+                let (range, val) = Type::from_meta_data("type", convert, ignored)?;
+                convert.update(range);
+                let range = convert.end_node("variant_arg")?;
+                convert.update(range);
+                tys.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        Ok((convert.subtract(start), (name.ok_or(())?, tys)))
+    }
+
+    /// Gets the payload types of a variant.
+    pub fn variant(&self, name: &str) -> Option<&[Type]> {
+        self.variants.iter().find(|v| **v.0 == *name).map(|v| &v.1[..])
+    }
+}
+
 /// Closure.
 #[derive(Debug, Clone)]
 pub struct Closure {
@@ -980,6 +1170,8 @@ impl Block {
 pub enum Expression {
     /// Link expression.
     Link(Box<Link>),
+    /// Interpolated string expression.
+    Interp(Box<Interp>),
     /// Object expression.
     Object(Box<Object>),
     /// Array expression.
@@ -990,6 +1182,8 @@ pub enum Expression {
     Return(Box<Expression>),
     /// Returns with value expression.
     ReturnVoid(Box<Range>),
+    /// Yield expression, which suspends a coroutine.
+    Yield(Box<Expression>),
     /// Break expression.
     Break(Box<Break>),
     /// Continue expression.
@@ -1026,6 +1220,8 @@ pub enum Expression {
     ForN(Box<ForN>),
     /// For-in expression.
     ForIn(Box<ForIn>),
+    /// Lock expression, e.g. `lock x := m { ... }`.
+    Lock(Box<Lock>),
     /// Sum for-n expression.
     Sum(Box<ForN>),
     /// Sum-in for-n expression.
@@ -1064,6 +1260,10 @@ pub enum Expression {
     LinkIn(Box<ForIn>),
     /// If-expression.
     If(Box<If>),
+    /// Match expression.
+    Match(Box<Match>),
+    /// Enum variant expression.
+    Variant(Box<Variant>),
     /// Variable.
     ///
     /// This means it contains no members that depends on other expressions.
@@ -1112,6 +1312,10 @@ impl Expression {
                     file, source, convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::Link(Box::new(val)));
+            } else if let Ok((range, val)) = Interp::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Interp(Box::new(val)));
             } else if let Ok((range, val)) = Object::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
@@ -1128,6 +1332,10 @@ impl Expression {
                     file, source, "return", convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::Return(Box::new(val)));
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                    file, source, "yield", convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Yield(Box::new(val)));
             } else if let Ok((range, _)) = convert.meta_bool("return_void") {
                 convert.update(range);
                 result = Some(Expression::ReturnVoid(Box::new(
@@ -1180,6 +1388,12 @@ impl Expression {
                     convert.source(start).unwrap(),
                     Variable::f64(val)
                 ))));
+            } else if let Ok((range, val)) = convert.meta_string("i64") {
+                convert.update(range);
+                result = Some(Expression::Variable(Box::new((
+                    convert.source(start).unwrap(),
+                    Variable::I64(i64_literal(&val)?)
+                ))));
             } else if let Ok((range, val)) = Vec4::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
@@ -1234,6 +1448,10 @@ impl Expression {
                     file, source, "for_in", convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::ForIn(Box::new(val)));
+            } else if let Ok((range, val)) = Lock::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Lock(Box::new(val)));
             } else if let Ok((range, val)) = ForN::from_meta_data(
                     file, source, "for_n", convert, ignored) {
                 convert.update(range);
@@ -1318,6 +1536,10 @@ impl Expression {
                     file, source, convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::If(Box::new(val)));
+            } else if let Ok((range, val)) = Match::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Match(Box::new(val)));
             } else if let Ok((range, _)) = convert.meta_bool("try") {
                 convert.update(range);
                 result = Some(Expression::Try(Box::new(result.unwrap())));
@@ -1373,6 +1595,7 @@ impl Expression {
             Object(ref obj) => obj.precompute(),
             Vec4(ref vec4) => vec4.precompute(),
             Link(ref link) => link.precompute(),
+            Interp(ref interp) => interp.precompute(),
             Variable(ref range_var) => Some(range_var.1.clone()),
             _ => None
         }
@@ -1384,10 +1607,12 @@ impl Expression {
 
         match *self {
             Link(ref link) => link.source_range,
+            Interp(ref interp) => interp.source_range,
             Object(ref obj) => obj.source_range,
             Array(ref arr) => arr.source_range,
             ArrayFill(ref arr_fill) => arr_fill.source_range,
             Return(ref expr) => expr.source_range(),
+            Yield(ref expr) => expr.source_range(),
             ReturnVoid(ref range) => **range,
             Break(ref br) => br.source_range,
             Continue(ref c) => c.source_range,
@@ -1407,6 +1632,7 @@ impl Expression {
             For(ref for_expr) => for_expr.source_range,
             ForN(ref for_n_expr) => for_n_expr.source_range,
             ForIn(ref for_in_expr) => for_in_expr.source_range,
+            Lock(ref lock) => lock.source_range,
             Sum(ref for_n_expr) => for_n_expr.source_range,
             SumIn(ref for_in_expr) => for_in_expr.source_range,
             SumVec4(ref for_n_expr) => for_n_expr.source_range,
@@ -1426,6 +1652,8 @@ impl Expression {
             LinkFor(ref for_n_expr) => for_n_expr.source_range,
             LinkIn(ref for_in_expr) => for_in_expr.source_range,
             If(ref if_expr) => if_expr.source_range,
+            Match(ref match_expr) => match_expr.source_range,
+            Variant(ref variant) => variant.source_range,
             Variable(ref range_var) => range_var.0,
             Try(ref expr) => expr.source_range(),
             Swizzle(ref swizzle) => swizzle.source_range,
@@ -1450,13 +1678,15 @@ impl Expression {
         match *self {
             Link(ref mut link) =>
                 link.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Interp(ref mut interp) =>
+                interp.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Object(ref mut obj) =>
                 obj.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Array(ref mut arr) =>
                 arr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             ArrayFill(ref mut arr_fill) =>
                 arr_fill.resolve_locals(relative, stack, closure_stack, module, use_lookup),
-            Return(ref mut expr) => {
+            Return(ref mut expr) | Yield(ref mut expr) => {
                 let st = stack.len();
                 expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
                 stack.truncate(st);
@@ -1468,6 +1698,16 @@ impl Expression {
                 bl.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Go(ref mut go) =>
                 go.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Call(ref mut call) if call.is_variant(module, use_lookup) => {
+                let mut variant = self::Variant {
+                    enum_name: call.info.alias.clone().unwrap(),
+                    name: call.info.name.clone(),
+                    args: call.args.clone(),
+                    source_range: call.info.source_range,
+                };
+                variant.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+                *self = Expression::Variant(Box::new(variant));
+            }
             Call(ref mut call) => {
                 call.resolve_locals(relative, stack, closure_stack, module, use_lookup);
                 match call.f_index {
@@ -1539,6 +1779,8 @@ impl Expression {
                 for_n_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             ForIn(ref mut for_n_expr) =>
                 for_n_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Lock(ref mut lock) =>
+                lock.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Sum(ref mut for_n_expr) =>
                 for_n_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             SumIn(ref mut for_in_expr) =>
@@ -1577,6 +1819,10 @@ impl Expression {
                 for_in_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             If(ref mut if_expr) =>
                 if_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Match(ref mut match_expr) =>
+                match_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Variant(ref mut variant) =>
+                variant.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Variable(_) => {}
             Try(ref mut expr) =>
                 expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
@@ -1668,6 +1914,178 @@ impl Link {
     }
 }
 
+/// Interpolated string expression, e.g. `$"pos: {x}, {y:.2}"`.
+#[derive(Debug, Clone)]
+pub struct Interp {
+    /// Text and embedded expressions, in order.
+    pub parts: Vec<InterpPart>,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+/// Part of an interpolated string.
+#[derive(Debug, Clone)]
+pub enum InterpPart {
+    /// Literal text.
+    Text(Arc<String>),
+    /// Embedded expression.
+    Item(Box<InterpItem>),
+}
+
+impl Interp {
+    /// Creates interpolated string expression from meta data.
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Interp), ()> {
+        let start = convert;
+        let node = "interp";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut parts: Vec<InterpPart> = vec![];
+        loop {
+            let text = if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("interp_text") {
+                convert.update(range);
+                val
+            } else if let Ok((range, _)) = convert.meta_bool("interp_lbrace") {
+                convert.update(range);
+                Arc::new("{".into())
+            } else if let Ok((range, _)) = convert.meta_bool("interp_rbrace") {
+                convert.update(range);
+                Arc::new("}".into())
+            } else if let Ok((range, _)) = convert.meta_bool("interp_quote") {
+                convert.update(range);
+                Arc::new("\"".into())
+            } else if let Ok((range, _)) = convert.meta_bool("interp_backslash") {
+                convert.update(range);
+                Arc::new("\\".into())
+            } else if let Ok((range, _)) = convert.meta_bool("interp_newline") {
+                convert.update(range);
+                Arc::new("\n".into())
+            } else if let Ok((range, _)) = convert.meta_bool("interp_tab") {
+                convert.update(range);
+                Arc::new("\t".into())
+            } else if let Ok((range, val)) = InterpItem::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                parts.push(InterpPart::Item(Box::new(val)));
+                continue;
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+                continue;
+            };
+            // Join escaped characters with surrounding text.
+            if let Some(&mut InterpPart::Text(ref mut last)) = parts.last_mut() {
+                Arc::make_mut(last).push_str(&text);
+                continue;
+            }
+            parts.push(InterpPart::Text(text));
+        }
+
+        Ok((convert.subtract(start), Interp {
+            parts,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    fn precompute(&self) -> Option<Variable> {
+        match self.parts.len() {
+            0 => Some(Variable::Str(Arc::new(String::new()))),
+            1 => if let InterpPart::Text(ref text) = self.parts[0] {
+                Some(Variable::Str(text.clone()))
+            } else {
+                None
+            },
+            _ => None
+        }
+    }
+
+    fn resolve_locals(
+        &mut self,
+        relative: usize,
+        stack: &mut Vec<Option<Arc<String>>>,
+        closure_stack: &mut Vec<usize>,
+        module: &Module,
+        use_lookup: &UseLookup,
+    ) {
+        let st = stack.len();
+        for part in &mut self.parts {
+            if let InterpPart::Item(ref mut item) = *part {
+                item.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+            }
+        }
+        stack.truncate(st);
+    }
+}
+
+/// Embedded expression in interpolated string, e.g. `{y:8.2}`.
+#[derive(Debug, Clone)]
+pub struct InterpItem {
+    /// The expression to write.
+    pub expr: Expression,
+    /// The minimum number of characters.
+    pub width: Option<usize>,
+    /// The number of decimals.
+    pub precision: Option<usize>,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+impl InterpItem {
+    /// Creates embedded expression from meta data.
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, InterpItem), ()> {
+        let start = convert;
+        let node = "interp_item";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut expr: Option<Expression> = None;
+        let mut width: Option<usize> = None;
+        let mut precision: Option<usize> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                    file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("width") {
+                convert.update(range);
+                width = Some(val.parse::<u16>().map_err(|_| ())? as usize);
+            } else if let Ok((range, val)) = convert.meta_string("precision") {
+                convert.update(range);
+                precision = Some(val.parse::<u16>().map_err(|_| ())? as usize);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let expr = expr.ok_or(())?;
+        Ok((convert.subtract(start), InterpItem {
+            expr,
+            width,
+            precision,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+}
+
 /// Object expression.
 #[derive(Debug, Clone)]
 pub struct Object {
@@ -2001,6 +2419,21 @@ impl BinOpSeq {
             } else if let Ok((range, _)) = convert.meta_bool("&&") {
                 convert.update(range);
                 ops.push(BinOp::AndAlso);
+            } else if let Ok((range, _)) = convert.meta_bool("&") {
+                convert.update(range);
+                ops.push(BinOp::BitAnd);
+            } else if let Ok((range, _)) = convert.meta_bool("|") {
+                convert.update(range);
+                ops.push(BinOp::BitOr);
+            } else if let Ok((range, _)) = convert.meta_bool("xor") {
+                convert.update(range);
+                ops.push(BinOp::Xor);
+            } else if let Ok((range, _)) = convert.meta_bool("<<") {
+                convert.update(range);
+                ops.push(BinOp::Shl);
+            } else if let Ok((range, _)) = convert.meta_bool(">>") {
+                convert.update(range);
+                ops.push(BinOp::Shr);
             } else if let Ok((range, _)) = convert.meta_bool("<") {
                 convert.update(range);
                 ops.push(BinOp::Less);
@@ -2076,6 +2509,16 @@ pub enum BinOp {
     OrElse,
     /// Lazy AND operator (`&&`).
     AndAlso,
+    /// Bitwise AND operator (`&`).
+    BitAnd,
+    /// Bitwise OR operator (`|`).
+    BitOr,
+    /// Exclusive OR operator (`xor`).
+    Xor,
+    /// Left shift operator (`<<`).
+    Shl,
+    /// Right shift operator (`>>`).
+    Shr,
     /// Less.
     Less,
     /// Less or equal.
@@ -2109,6 +2552,11 @@ impl BinOp {
             BinOp::Pow => "^",
             BinOp::OrElse => "||",
             BinOp::AndAlso => "&&",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::Xor => "xor",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
             BinOp::Less => "<",
             BinOp::LessOrEqual => "<=",
             BinOp::Greater => ">",
@@ -2135,10 +2583,11 @@ impl BinOp {
             BinOp::Greater | BinOp::GreaterOrEqual |
             BinOp::Equal | BinOp::NotEqual => BINOP_PREC_OR,
             BinOp::OrElse | BinOp::AndAlso => BINOP_PREC_OR,
-            BinOp::Add | BinOp::Sub => BINOP_PREC_ADD,
+            BinOp::Add | BinOp::Sub | BinOp::BitOr => BINOP_PREC_ADD,
             BinOp::Mul | BinOp::Dot | BinOp::Cross
-            | BinOp::Div | BinOp::Rem => BINOP_PREC_MUL,
-            BinOp::Pow => BINOP_PREC_POW,
+            | BinOp::Div | BinOp::Rem
+            | BinOp::BitAnd | BinOp::Shl | BinOp::Shr => BINOP_PREC_MUL,
+            BinOp::Pow | BinOp::Xor => BINOP_PREC_POW,
         }
     }
 }
@@ -2351,13 +2800,22 @@ impl Item {
 /// Go call.
 #[derive(Debug, Clone)]
 pub struct Go {
-    /// Function call.
-    pub call: Call,
+    /// Function or closure call.
+    pub call: GoCall,
     /// The range in source.
     pub source_range: Range,
 }
 
-impl Go {
+/// The call that runs on a new thread.
+#[derive(Debug, Clone)]
+pub enum GoCall {
+    /// Function call.
+    Call(Call),
+    /// Closure call.
+    Closure(CallClosure),
+}
+
+impl Go {
     /// Creates go call from meta data.
     pub fn from_meta_data(
         file: &Arc<String>,
@@ -2370,7 +2828,7 @@ impl Go {
         let start_range = convert.start_node(node)?;
         convert.update(start_range);
 
-        let mut call: Option<Call> = None;
+        let mut call: Option<GoCall> = None;
         loop {
             if let Ok(range) = convert.end_node(node) {
                 convert.update(range);
@@ -2378,11 +2836,19 @@ impl Go {
             } else if let Ok((range, val)) = Call::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
-                call = Some(val);
+                call = Some(GoCall::Call(val));
             } else if let Ok((range, val)) = Call::named_from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
-                call = Some(val);
+                call = Some(GoCall::Call(val));
+            } else if let Ok((range, val)) = CallClosure::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                call = Some(GoCall::Closure(val));
+            } else if let Ok((range, val)) = CallClosure::named_from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                call = Some(GoCall::Closure(val));
             } else {
                 let range = convert.ignore();
                 convert.update(range);
@@ -2406,7 +2872,16 @@ impl Go {
         use_lookup: &UseLookup,
     ) {
         let st = stack.len();
-        for arg in &mut self.call.args {
+        let args = match self.call {
+            GoCall::Call(ref mut call) => &mut call.args,
+            GoCall::Closure(ref mut call) => {
+                call.item.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+                stack.truncate(st);
+                &mut call.args
+            }
+        };
+        // The arguments are evaluated before pushing anything on the stack.
+        for arg in args {
             let st = stack.len();
             arg.resolve_locals(relative, stack, closure_stack, module, use_lookup);
             stack.truncate(st);
@@ -2498,6 +2973,36 @@ pub struct CallVoid {
     pub info: Box<CallInfo>,
 }
 
+/// Enum variant expression, e.g. `Shape::Circle(2)`.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// The name of the enum.
+    pub enum_name: Arc<String>,
+    /// The name of the variant.
+    pub name: Arc<String>,
+    /// Payload expressions.
+    pub args: Vec<Expression>,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+impl Variant {
+    fn resolve_locals(
+        &mut self,
+        relative: usize,
+        stack: &mut Vec<Option<Arc<String>>>,
+        closure_stack: &mut Vec<usize>,
+        module: &Module,
+        use_lookup: &UseLookup,
+    ) {
+        let st = stack.len();
+        for arg in &mut self.args {
+            arg.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+            stack.truncate(st);
+        }
+    }
+}
+
 /// Function call.
 #[derive(Debug, Clone)]
 pub struct Call {
@@ -2660,6 +3165,20 @@ impl Call {
         }))
     }
 
+    /// Returns `true` if the call constructs an enum variant, e.g. `Shape::Circle(2)`.
+    ///
+    /// An alias from `use` takes precedence over an enum with the same name.
+    fn is_variant(&self, module: &Module, use_lookup: &UseLookup) -> bool {
+        match self.info.alias {
+            Some(ref alias) if !use_lookup.aliases.contains_key(alias) => {
+                module.find_enum(alias)
+                    .map(|e| e.variant(&self.info.name).is_some())
+                    .unwrap_or(false)
+            }
+            _ => false
+        }
+    }
+
     fn resolve_locals(
         &mut self,
         relative: usize,
@@ -3026,6 +3545,11 @@ impl BinOpExpression {
                     Cross => crate::CROSS.clone(),
                     AndAlso => crate::AND_ALSO.clone(),
                     OrElse => crate::OR_ELSE.clone(),
+                    BitAnd => crate::BIT_AND.clone(),
+                    BitOr => crate::BIT_OR.clone(),
+                    Xor => crate::XOR.clone(),
+                    Shl => crate::SHL.clone(),
+                    Shr => crate::SHR.clone(),
                     Less => crate::LESS.clone(),
                     LessOrEqual => crate::LESS_OR_EQUAL.clone(),
                     Greater => crate::GREATER.clone(),
@@ -3843,6 +4367,84 @@ impl ForIn {
     }
 }
 
+/// Lock expression.
+///
+/// Binds a name to the value guarded by a mutex while running the block.
+#[derive(Debug, Clone)]
+pub struct Lock {
+    /// Name of the guarded value.
+    pub name: Arc<String>,
+    /// The mutex expression.
+    pub mutex: Expression,
+    /// Block expression.
+    pub block: Block,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+impl Lock {
+    /// Creates lock expression from meta data.
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Lock), ()> {
+        let start = convert;
+        let node = "lock";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut mutex: Option<Expression> = None;
+        let mut block: Option<Block> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                mutex = Some(val);
+            } else if let Ok((range, val)) = Block::from_meta_data(
+                    file, source, "block", convert, ignored) {
+                convert.update(range);
+                block = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = name.ok_or(())?;
+        let mutex = mutex.ok_or(())?;
+        let block = block.ok_or(())?;
+        Ok((convert.subtract(start), Lock {
+            name, mutex, block,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    fn resolve_locals(
+        &mut self, relative: usize,
+        stack: &mut Vec<Option<Arc<String>>>,
+        closure_stack: &mut Vec<usize>,
+        module: &Module,
+        use_lookup: &UseLookup,
+    ) {
+        let st = stack.len();
+        self.mutex.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+        stack.truncate(st);
+        stack.push(Some(self.name.clone()));
+        self.block.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+        stack.truncate(st);
+    }
+}
+
 /// For-N expression.
 #[derive(Debug, Clone)]
 pub struct ForN {
@@ -3860,6 +4462,8 @@ pub struct ForN {
     pub block: Block,
     /// Loop label.
     pub label: Option<Arc<String>>,
+    /// Whether the index range is split across threads.
+    pub par: bool,
     /// The range in source.
     pub source_range: Range,
 }
@@ -3880,6 +4484,7 @@ impl ForN {
         let mut indices: Vec<(Arc<String>, Option<Expression>, Option<Expression>)> = vec![];
         let mut block: Option<Block> = None;
         let mut label: Option<Arc<String>> = None;
+        let mut par = false;
         loop {
             if let Ok(range) = convert.end_node(node) {
                 convert.update(range);
@@ -3891,6 +4496,9 @@ impl ForN {
             } else if let Ok((range, val)) = convert.meta_string("label") {
                 convert.update(range);
                 label = Some(val);
+            } else if let Ok((range, val)) = convert.meta_bool("par") {
+                convert.update(range);
+                par = val;
             } else if let Ok((range, val)) = convert.meta_string("name") {
                 convert.update(range);
                 let mut start_expr: Option<Expression> = None;
@@ -3918,6 +4526,7 @@ impl ForN {
             convert.subtract(start),
             convert.source(start).unwrap(),
             label,
+            par,
             &indices,
             block
         )
@@ -3928,6 +4537,7 @@ impl ForN {
         range: Range,
         source_range: Range,
         label: Option<Arc<String>>,
+        par: bool,
         indices: &[(Arc<String>, Option<Expression>, Option<Expression>)],
         mut block: Option<Block>
     ) -> Result<(Range, ForN), ()> {
@@ -3943,6 +4553,7 @@ impl ForN {
                 range,
                 source_range,
                 None,
+                false,
                 &indices[1..],
                 block
             )?;
@@ -3979,6 +4590,7 @@ impl ForN {
             end: end_expr,
             block,
             label,
+            par,
             source_range,
         }))
     }
@@ -4267,6 +4879,314 @@ impl If {
     }
 }
 
+/// Match expression, e.g. `match x { some(y) => y, none() => 0 }`.
+#[derive(Debug, Clone)]
+pub struct Match {
+    /// The expression to match.
+    pub expr: Expression,
+    /// Match arms, tried in order.
+    pub arms: Vec<MatchArm>,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+impl Match {
+    /// Creates match expression from meta data.
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Match), ()> {
+        let start = convert;
+        let node = "match";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut expr: Option<Expression> = None;
+        let mut arms: Vec<MatchArm> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else if let Ok((range, val)) = MatchArm::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                arms.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let expr = expr.ok_or(())?;
+        Ok((convert.subtract(start), Match {
+            expr,
+            arms,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    fn resolve_locals(
+        &mut self,
+        relative: usize,
+        stack: &mut Vec<Option<Arc<String>>>,
+        closure_stack: &mut Vec<usize>,
+        module: &Module,
+        use_lookup: &UseLookup,
+    ) {
+        let st = stack.len();
+        self.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+        stack.truncate(st);
+        for arm in &mut self.arms {
+            let mut binds = vec![];
+            arm.pattern.binds(&mut binds);
+            for name in binds {
+                stack.push(Some(name));
+            }
+            arm.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+            stack.truncate(st);
+        }
+    }
+}
+
+/// Match arm, e.g. `some(y) => y`.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    /// The pattern to match.
+    pub pattern: Pattern,
+    /// The expression to evaluate when the pattern matches.
+    pub expr: Expression,
+    /// The range in source.
+    pub source_range: Range,
+}
+
+impl MatchArm {
+    /// Creates match arm from meta data.
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, MatchArm), ()> {
+        let start = convert;
+        let node = "match_arm";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut pattern: Option<Pattern> = None;
+        let mut expr: Option<Expression> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                    "pattern", convert, ignored) {
+                convert.update(range);
+                pattern = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let pattern = pattern.ok_or(())?;
+        let expr = expr.ok_or(())?;
+        Ok((convert.subtract(start), MatchArm {
+            pattern,
+            expr,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+}
+
+/// Parses the digits of an `i64` literal, e.g. `1_000` in `1_000i64`.
+fn i64_literal(text: &str) -> Result<i64, ()> {
+    text.replace('_', "").parse().map_err(|_| ())
+}
+
+/// Pattern in a match arm.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// Matches anything without binding, `_`.
+    Any,
+    /// Matches anything and binds it to a variable.
+    Bind(Arc<String>),
+    /// Matches a number.
+    F64(f64),
+    /// Matches an `i64` number.
+    I64(i64),
+    /// Matches a string.
+    Str(Arc<String>),
+    /// Matches a bool.
+    Bool(bool),
+    /// Matches `some(_)`.
+    Some(Box<Pattern>),
+    /// Matches `none()`.
+    None,
+    /// Matches `ok(_)`.
+    Ok(Box<Pattern>),
+    /// Matches `err(_)`, using the error message.
+    Err(Box<Pattern>),
+    /// Matches an object with at least the listed keys.
+    Object(Vec<(Arc<String>, Pattern)>),
+    /// Matches an enum variant, e.g. `Shape::Circle(r)`.
+    Variant(Arc<String>, Arc<String>, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Creates pattern from meta data.
+    pub fn from_meta_data(
+        node: &str,
+        convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Pattern), ()> {
+        let (range, _, pattern) = Pattern::keyed_from_meta_data(node, convert, ignored)?;
+        Ok((range, pattern))
+    }
+
+    /// Creates pattern from meta data, with the key when it is an object field.
+    fn keyed_from_meta_data(
+        node: &str,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Option<Arc<String>>, Pattern), ()> {
+        let start = convert;
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut key: Option<Arc<String>> = None;
+        let mut pattern: Option<Pattern> = None;
+        let mut fields: Option<Vec<(Arc<String>, Pattern)>> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("key") {
+                convert.update(range);
+                key = Some(val);
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                    "pattern_some", convert, ignored) {
+                convert.update(range);
+                pattern = Some(Pattern::Some(Box::new(val)));
+            } else if let Ok(range) = convert.start_node("pattern_none") {
+                convert.update(range);
+                let range = convert.end_node("pattern_none")?;
+                convert.update(range);
+                pattern = Some(Pattern::None);
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                    "pattern_ok", convert, ignored) {
+                convert.update(range);
+                pattern = Some(Pattern::Ok(Box::new(val)));
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                    "pattern_err", convert, ignored) {
+                convert.update(range);
+                pattern = Some(Pattern::Err(Box::new(val)));
+            } else if let Ok((range, _)) = convert.meta_bool("object") {
+                convert.update(range);
+                fields = Some(vec![]);
+            } else if let Ok((range, field_key, val)) = Pattern::keyed_from_meta_data(
+                    "pattern_field", convert, ignored) {
+                convert.update(range);
+                match (fields.as_mut(), field_key) {
+                    (Some(fields), Some(field_key)) => fields.push((field_key, val)),
+                    _ => return Err(()),
+                }
+            } else if let Ok((range, val)) = Pattern::variant_from_meta_data(convert, ignored) {
+                convert.update(range);
+                pattern = Some(val);
+            } else if let Ok((range, val)) = convert.meta_bool("bool") {
+                convert.update(range);
+                pattern = Some(Pattern::Bool(val));
+            } else if let Ok((range, val)) = convert.meta_f64("num") {
+                convert.update(range);
+                pattern = Some(Pattern::F64(val));
+            } else if let Ok((range, val)) = convert.meta_string("i64") {
+                convert.update(range);
+                pattern = Some(Pattern::I64(i64_literal(&val)?));
+            } else if let Ok((range, val)) = convert.meta_string("text") {
+                convert.update(range);
+                pattern = Some(Pattern::Str(val));
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                pattern = Some(if &**val == "_" {Pattern::Any} else {Pattern::Bind(val)});
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let pattern = match fields {
+            Some(fields) => Pattern::Object(fields),
+            None => pattern.ok_or(())?,
+        };
+        Ok((convert.subtract(start), key, pattern))
+    }
+
+    fn variant_from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Pattern), ()> {
+        let start = convert;
+        let node = "pattern_variant";
+        let start_range = convert.start_node(node)?;
+        convert.update(start_range);
+
+        let mut enum_name: Option<Arc<String>> = None;
+        let mut name: Option<Arc<String>> = None;
+        let mut args: Vec<Pattern> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("alias") {
+                convert.update(range);
+                enum_name = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                    "pattern_arg", convert, ignored) {
+                convert.update(range);
+                args.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        Ok((convert.subtract(start), Pattern::Variant(enum_name.ok_or(())?, name.ok_or(())?, args)))
+    }
+
+    /// Pushes the names of bound variables, in the order they are bound.
+    pub fn binds(&self, names: &mut Vec<Arc<String>>) {
+        match *self {
+            Pattern::Bind(ref name) => names.push(name.clone()),
+            Pattern::Some(ref p) | Pattern::Ok(ref p) | Pattern::Err(ref p) => p.binds(names),
+            Pattern::Object(ref fields) => {
+                for (_, p) in fields {p.binds(names)}
+            }
+            Pattern::Variant(_, _, ref args) => {
+                for p in args {p.binds(names)}
+            }
+            Pattern::Any | Pattern::F64(_) | Pattern::I64(_) | Pattern::Str(_) |
+            Pattern::Bool(_) | Pattern::None => {}
+        }
+    }
+}
+
 /// Stores `in <function>` expression.
 #[derive(Debug, Clone)]
 pub struct In {
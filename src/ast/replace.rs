@@ -13,15 +13,23 @@ use super::{
     ForN,
     ForIn,
     Go,
+    GoCall,
     Id,
     If,
+    Interp,
+    InterpItem,
+    InterpPart,
     Item,
     Link,
+    Lock,
+    Match,
+    MatchArm,
     Object,
     Swizzle,
     Vec4,
     Mat4,
     TryExpr,
+    Variant,
 };
 
 /// Replaces an item with a number.
@@ -42,6 +50,20 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
                 source_range: link_expr.source_range,
             }))
         }
+        E::Interp(ref interp) => {
+            E::Interp(Box::new(Interp {
+                parts: interp.parts.iter().map(|part| match *part {
+                    InterpPart::Text(_) => part.clone(),
+                    InterpPart::Item(ref item) => InterpPart::Item(Box::new(InterpItem {
+                        expr: number(&item.expr, name, val),
+                        width: item.width,
+                        precision: item.precision,
+                        source_range: item.source_range,
+                    })),
+                }).collect(),
+                source_range: interp.source_range,
+            }))
+        }
         E::Item(ref item) => {
             if &item.name == name {
                 E::Variable(Box::new((item.source_range, Variable::f64(val))))
@@ -107,6 +129,18 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
                 source_range: array_expr.source_range,
             }))
         }
+        E::Variant(ref variant_expr) => {
+            let mut new_args: Vec<Expression> = vec![];
+            for arg in &variant_expr.args {
+                new_args.push(number(arg, name, val));
+            }
+            E::Variant(Box::new(Variant {
+                enum_name: variant_expr.enum_name.clone(),
+                name: variant_expr.name.clone(),
+                args: new_args,
+                source_range: variant_expr.source_range,
+            }))
+        }
         E::ArrayFill(ref array_fill_expr) => {
             E::ArrayFill(Box::new(ArrayFill {
                 fill: number(&array_fill_expr.fill, name, val),
@@ -117,12 +151,19 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
         E::Return(ref ret_expr) => {
             E::Return(Box::new(number(ret_expr, name, val)))
         }
+        E::Yield(ref yield_expr) => {
+            E::Yield(Box::new(number(yield_expr, name, val)))
+        }
         E::ReturnVoid(_) => expr.clone(),
         E::Break(_) => expr.clone(),
         E::Continue(_) => expr.clone(),
         E::Go(ref go) => {
             E::Go(Box::new(Go {
-                call: number_call(&go.call, name, val),
+                call: match go.call {
+                    GoCall::Call(ref call) => GoCall::Call(number_call(call, name, val)),
+                    GoCall::Closure(ref call) =>
+                        GoCall::Closure(number_call_closure(call, name, val)),
+                },
                 source_range: go.source_range,
             }))
         }
@@ -190,6 +231,14 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
                 source_range: for_in_expr.source_range,
             }))
         }
+        E::Lock(ref lock) => {
+            E::Lock(Box::new(Lock {
+                name: lock.name.clone(),
+                mutex: number(&lock.mutex, name, val),
+                block: number_block(&lock.block, name, val),
+                source_range: lock.source_range,
+            }))
+        }
         E::SumIn(ref for_in_expr) => {
             E::SumIn(Box::new(ForIn {
                 label: for_in_expr.label.clone(),
@@ -314,6 +363,27 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
                 source_range: if_expr.source_range,
             }))
         }
+        E::Match(ref match_expr) => {
+            let mut new_arms: Vec<MatchArm> = vec![];
+            for arm in &match_expr.arms {
+                let mut binds = vec![];
+                arm.pattern.binds(&mut binds);
+                new_arms.push(MatchArm {
+                    pattern: arm.pattern.clone(),
+                    expr: if binds.contains(name) {
+                        arm.expr.clone()
+                    } else {
+                        number(&arm.expr, name, val)
+                    },
+                    source_range: arm.source_range,
+                });
+            }
+            E::Match(Box::new(Match {
+                expr: number(&match_expr.expr, name, val),
+                arms: new_arms,
+                source_range: match_expr.source_range,
+            }))
+        }
         E::Variable(_) => expr.clone(),
         E::Try(ref expr) => E::Try(Box::new(number(expr, name, val))),
         E::Swizzle(ref swizzle_expr) => {
@@ -401,6 +471,7 @@ fn number_for_n(for_n_expr: &ForN, name: &Arc<String>, val: f64) -> ForN {
     } else {
         ForN {
             label: for_n_expr.label.clone(),
+            par: for_n_expr.par,
             name: for_n_expr.name.clone(),
             start: for_n_expr.start.as_ref()
                 .map(|start| number(start, name, val)),
@@ -0,0 +1,300 @@
+//! Language server for Dyon, speaking the Language Server Protocol over stdio.
+//!
+//! Usage: `dyon-lsp [<module.dyon>...]`
+//!
+//! The modules given as arguments are loaded before each document,
+//! such that calls to imported functions can be resolved.
+
+extern crate dyon;
+#[macro_use]
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::Arc;
+
+use dyon::analysis::Analysis;
+use dyon::{load, Module};
+use serde_json::Value;
+
+fn main() {
+    let mut module = Module::new();
+    for file in std::env::args().skip(1) {
+        if let Err(err) = load(&file, &mut module) {
+            eprintln!("{}", err);
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut server = Server {
+        module,
+        documents: HashMap::new(),
+    };
+    while let Some(msg) = read_message(&mut input) {
+        let method = msg["method"].as_str().unwrap_or("");
+        if method == "exit" {break};
+        for reply in server.handle(method, &msg) {
+            write_message(&mut output, &reply).unwrap();
+        }
+    }
+}
+
+/// Stores open documents.
+struct Server {
+    module: Module,
+    documents: HashMap<String, Arc<String>>,
+}
+
+impl Server {
+    /// Handles a message, returning responses and notifications to send.
+    fn handle(&mut self, method: &str, msg: &Value) -> Vec<Value> {
+        let id = msg.get("id").cloned();
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {}
+                },
+                "serverInfo": {"name": "dyon-lsp"}
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), Arc::new(text.into()));
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri.clone(), Arc::new(text.into()));
+                }
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![notification("textDocument/publishDiagnostics", json!({
+                    "uri": uri,
+                    "diagnostics": []
+                }))];
+            }
+            "textDocument/definition" => {
+                match self.analyze(&uri, &params["position"]) {
+                    Some((analysis, offset)) => match analysis.definition(offset) {
+                        Some(def) => json!({
+                            "uri": if *def.file == path_from_uri(&uri) {uri.clone()}
+                                   else {uri_from_path(&def.file)},
+                            "range": lsp_range(&def.source, def.range.offset, def.range.length)
+                        }),
+                        None => Value::Null
+                    },
+                    None => Value::Null
+                }
+            }
+            "textDocument/hover" => {
+                match self.analyze(&uri, &params["position"])
+                    .and_then(|(analysis, offset)| analysis.hover(offset)) {
+                    Some(text) => json!({
+                        "contents": {"kind": "plaintext", "value": text}
+                    }),
+                    None => Value::Null
+                }
+            }
+            "textDocument/completion" => {
+                match self.analyze(&uri, &params["position"]) {
+                    Some((analysis, offset)) => {
+                        let source = &self.documents[&uri];
+                        let prefix = word_before(source, offset);
+                        Value::Array(analysis.completions(prefix).iter()
+                            .map(|name| json!({"label": &**name, "kind": 3}))
+                            .collect())
+                    }
+                    None => Value::Null
+                }
+            }
+            _ => {
+                return match id {
+                    Some(id) => vec![json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {
+                            "code": -32601,
+                            "message": format!("Unknown method `{}`", method)
+                        }
+                    })],
+                    None => vec![]
+                };
+            }
+        };
+        match id {
+            Some(id) => vec![json!({"jsonrpc": "2.0", "id": id, "result": result})],
+            None => vec![]
+        }
+    }
+
+    /// Analyzes a document and converts the position to an offset in source.
+    fn analyze(&self, uri: &str, position: &Value) -> Option<(Analysis, usize)> {
+        let source = self.documents.get(uri)?;
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let offset = offset_from_position(source, line, character);
+        Some((Analysis::new(&path_from_uri(uri), source.clone(), &self.module), offset))
+    }
+
+    /// Returns a notification that publishes diagnostics for a document.
+    fn diagnostics(&self, uri: &str) -> Value {
+        let mut diagnostics = vec![];
+        if let Some(source) = self.documents.get(uri) {
+            let analysis = Analysis::new(&path_from_uri(uri), source.clone(), &self.module);
+            if let Some(err) = analysis.error() {
+                let range = match err.info().location {
+                    Some(loc) => lsp_range(source, loc.range.offset, loc.range.length),
+                    None => lsp_range(source, 0, 0),
+                };
+                diagnostics.push(json!({
+                    "range": range,
+                    "severity": 1,
+                    "source": "dyon",
+                    "message": err.message()
+                }));
+            }
+            for warning in analysis.warnings() {
+                diagnostics.push(json!({
+                    "range": lsp_range(source, warning.offset, warning.length),
+                    "severity": 2,
+                    "source": "dyon",
+                    "message": warning.data
+                }));
+            }
+        }
+        notification("textDocument/publishDiagnostics", json!({
+            "uri": uri,
+            "diagnostics": diagnostics
+        }))
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+/// Reads a message with a `Content-Length` header.
+fn read_message<R: BufRead>(input: &mut R) -> Option<Value> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {return None};
+        let line = line.trim_end();
+        if line.is_empty() {break};
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            len = n.trim().parse::<usize>().ok();
+        }
+    }
+    let mut buf = vec![0; len?];
+    input.read_exact(&mut buf).ok()?;
+    Some(serde_json::from_slice(&buf).unwrap_or(Value::Null))
+}
+
+fn write_message<W: Write>(output: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Converts a byte range to an LSP range.
+fn lsp_range(source: &str, offset: usize, length: usize) -> Value {
+    let (line, character) = position_from_offset(source, offset);
+    let (end_line, end_character) = position_from_offset(source, offset + length);
+    json!({
+        "start": {"line": line, "character": character},
+        "end": {"line": end_line, "character": end_character}
+    })
+}
+
+/// Converts a byte offset to line and UTF-16 character.
+fn position_from_offset(source: &str, offset: usize) -> (usize, usize) {
+    let mut line = 0;
+    let mut character = 0;
+    for (i, c) in source.char_indices() {
+        if i >= offset {break};
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    (line, character)
+}
+
+/// Converts line and UTF-16 character to a byte offset.
+fn offset_from_position(source: &str, line: usize, character: usize) -> usize {
+    let mut current_line = 0;
+    let mut current_character = 0;
+    for (i, c) in source.char_indices() {
+        if current_line == line && current_character >= character {return i};
+        if c == '\n' {
+            if current_line == line {return i};
+            current_line += 1;
+            current_character = 0;
+        } else {
+            current_character += c.len_utf16();
+        }
+    }
+    source.len()
+}
+
+/// Returns the identifier that ends at offset.
+fn word_before(source: &str, offset: usize) -> &str {
+    let before = &source[..offset];
+    let start = before.char_indices().rev()
+        .find(|&(_, c)| !(c.is_alphanumeric() || c == '_'))
+        .map(|(i, c)| i + c.len_utf8()).unwrap_or(0);
+    &before[start..]
+}
+
+fn path_from_uri(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut res = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(c) = u8::from_str_radix(hex, 16) {
+                res.push(c);
+                i += 3;
+                continue;
+            }
+        }
+        res.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+fn uri_from_path(path: &str) -> String {
+    let path = std::fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.into());
+    format!("file://{}", path.replace('%', "%25").replace(' ', "%20"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_before_non_ascii() {
+        assert_eq!(word_before("x := a·fo", "x := a·fo".len()), "fo");
+        assert_eq!(word_before("·", "·".len()), "");
+        assert_eq!(word_before("(x²", "(x²".len()), "x²");
+        assert_eq!(word_before("föö", 3), "fö");
+    }
+}
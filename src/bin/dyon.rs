@@ -0,0 +1,280 @@
+//! Interactive REPL, source formatter and debugger for Dyon.
+//!
+//! Usage: `dyon [<file.dyon>...]`
+//!
+//! Functions in the files given as arguments are loaded before the session starts.
+//! Input continues on the next line while there are unclosed brackets.
+//!
+//! Usage: `dyon fmt [--ascii | --unicode] [--check] [<file.dyon>...]`
+//!
+//! Formats files in place, or source from stdin to stdout when no files are given.
+//! With `--check`, lists files that are not formatted instead of changing them.
+//!
+//! Usage: `dyon debug <file.dyon>`
+//!
+//! Runs a program in a terminal debugger, pausing at the first statement.
+//! Type `help` when paused for a list of commands.
+
+extern crate dyon;
+
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+use std::sync::Arc;
+
+use dyon::debug::{format_variable, Breakpoints, Debugger, Pause, Step};
+use dyon::format::{format_str, Operators};
+use dyon::repl::Repl;
+use dyon::{Module, Runtime};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|arg| &**arg) == Some("fmt") {
+        process::exit(fmt(&args[1..]));
+    }
+    if args.first().map(|arg| &**arg) == Some("debug") {
+        process::exit(debug(&args[1..]));
+    }
+
+    let mut repl = Repl::new();
+    for file in args {
+        print_result(repl.eval(&format!(":load {}", file)));
+    }
+
+    let stdin = io::stdin();
+    let mut input = String::new();
+    prompt("> ");
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(x) => x,
+            Err(_) => break
+        };
+        input.push_str(&line);
+        input.push('\n');
+        if open_brackets(&input) > 0 {
+            prompt("... ");
+            continue;
+        }
+        match input.trim() {
+            ":quit" | ":q" => break,
+            _ => print_result(repl.eval(&input)),
+        }
+        input.clear();
+        prompt("> ");
+    }
+}
+
+/// Runs the `fmt` command, returning the exit code.
+fn fmt(args: &[String]) -> i32 {
+    let mut operators = Operators::Preserve;
+    let mut check = false;
+    let mut files = vec![];
+    for arg in args {
+        match &**arg {
+            "--ascii" => operators = Operators::Ascii,
+            "--unicode" => operators = Operators::Unicode,
+            "--check" => check = true,
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(err) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Could not read stdin, {}", err);
+            return 1;
+        }
+        return match format_str("<stdin>", &source, operators) {
+            Ok(text) => {
+                print!("{}", text);
+                0
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                1
+            }
+        };
+    }
+
+    let mut code = 0;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!("Could not read `{}`, {}", file, err);
+                code = 1;
+                continue;
+            }
+        };
+        match format_str(file, &source, operators) {
+            Ok(ref text) if *text == source => {}
+            Ok(_) if check => {
+                println!("{}", file);
+                code = 1;
+            }
+            Ok(text) => {
+                if let Err(err) = fs::write(file, text) {
+                    eprintln!("Could not write `{}`, {}", file, err);
+                    code = 1;
+                }
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// Runs the `debug` command, returning the exit code.
+fn debug(args: &[String]) -> i32 {
+    let file = match args {
+        [file] => file,
+        _ => {
+            eprintln!("Usage: dyon debug <file.dyon>");
+            return 1;
+        }
+    };
+    let mut module = Module::new();
+    if let Err(err) = dyon::load(file, &mut module) {
+        eprintln!("{}", err);
+        return 1;
+    }
+    let mut rt = Runtime::new();
+    rt.debug_hook = Some(Box::new(Debugger::new(on_pause)));
+    match rt.run(&Arc::new(module)) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Text printed by the `help` command of the debugger.
+const DEBUG_HELP: &str = "\
+c, continue           Run until the next breakpoint
+s, step               Step to the next statement, into calls
+n, next               Step to the next statement, over calls
+o, out                Step out of the current function
+b, break [file:]line  Set a breakpoint
+d, delete [file:]line Delete a breakpoint
+breakpoints           List breakpoints
+l, locals             List locals
+p, print <name>       Print a local
+bt, backtrace         Show the call stack
+q, quit               Stop the program
+help                  Show this help";
+
+/// Reads debugger commands until the program should continue.
+fn on_pause(breakpoints: &mut Breakpoints, rt: &Runtime, pause: &Pause) -> Step {
+    let file = pause.file.as_ref().map(|f| f.to_string()).unwrap_or_default();
+    let text = rt.call_stack.last()
+        .and_then(|call| rt.call_source(call))
+        .and_then(|source| source.lines().nth(pause.line.wrapping_sub(1)))
+        .unwrap_or("");
+    if pause.breakpoint {println!("Breakpoint")};
+    println!("{}:{}: {}", file, pause.line, text.trim());
+
+    let stdin = io::stdin();
+    loop {
+        prompt("(debug) ");
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return Step::Stop,
+            Ok(_) => {}
+        }
+        let line = line.trim();
+        let (cmd, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match cmd {
+            "c" | "continue" => return Step::Continue,
+            "s" | "step" => return Step::Into,
+            "n" | "next" => return Step::Over,
+            "o" | "out" => return Step::Out,
+            "q" | "quit" => return Step::Stop,
+            "b" | "break" | "d" | "delete" => {
+                let (bfile, bline) = match arg.rfind(':') {
+                    Some(i) => (&arg[..i], &arg[i + 1..]),
+                    None => (&*file, arg),
+                };
+                match bline.parse::<usize>() {
+                    Ok(bline) if cmd.starts_with('b') => {
+                        breakpoints.add(bfile, bline);
+                        println!("Breakpoint at {}:{}", bfile, bline);
+                    }
+                    Ok(bline) => if !breakpoints.remove(bfile, bline) {
+                        println!("No breakpoint at {}:{}", bfile, bline);
+                    },
+                    Err(_) => println!("Expected `[file:]line`"),
+                }
+            }
+            "breakpoints" => {
+                for &(ref bfile, bline) in breakpoints.list() {
+                    println!("{}:{}", bfile, bline);
+                }
+            }
+            "l" | "locals" => {
+                for (name, v) in rt.locals() {
+                    println!("{} := {}", name, format_variable(rt, v));
+                }
+            }
+            "p" | "print" => match rt.locals().into_iter().find(|l| **l.0 == *arg) {
+                Some((_, v)) => println!("{}", format_variable(rt, v)),
+                None => println!("Could not find local `{}`", arg),
+            },
+            "bt" | "backtrace" => {
+                for call in rt.call_stack.iter().rev() {
+                    println!("{}", call);
+                }
+            }
+            "help" => println!("{}", DEBUG_HELP),
+            "" => {}
+            _ => println!("Unknown command `{}`, type `help` for a list of commands", cmd),
+        }
+    }
+}
+
+fn prompt(text: &str) {
+    print!("{}", text);
+    io::stdout().flush().unwrap();
+}
+
+fn print_result(res: Result<String, dyon::DyonError>) {
+    match res {
+        Ok(ref text) if text.is_empty() => {}
+        Ok(text) => println!("{}", text),
+        Err(err) => println!("{}", err),
+    }
+}
+
+/// Counts brackets that are not closed, ignoring strings and comments.
+fn open_brackets(input: &str) -> i32 {
+    let mut n = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => n += 1,
+            ')' | ']' | '}' => n -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {chars.next();}
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(c) = chars.next() {
+                    if c == '\n' {break};
+                }
+            }
+            _ => {}
+        }
+    }
+    n
+}
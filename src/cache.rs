@@ -0,0 +1,1739 @@
+//! Binary cache of compiled sources.
+//!
+//! A compiled source stores the functions, records and enums of a source
+//! after lifetime checking, type checking and resolving calls.
+//! Loading a compiled source skips parsing, checking and conversion to AST.
+//!
+//! External functions are stored as indices into the external functions of the module,
+//! and loaded functions by index relative to the calling function,
+//! so a compiled source can only be loaded into a module with the same functions.
+//!
+//! The format starts with a magic number, a format version and the Dyon version,
+//! followed by a key that is a hash of the source and the signatures in the module.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::cell::Cell;
+use std::collections::HashMap;
+use range::Range;
+
+use ast::{self, Expression, Id, InterpPart, GoCall, Pattern, AssignOp};
+use {check_error, lifetime, load_meta, name_record_returns, parse_str, refine_returns};
+use {DyonError, Lazy, Link, Lt, Module, Prelude, Type, Dfn, Variable, LAZY_NO};
+use {FnBinOpRef, FnExt, FnExternal, FnIndex, FnReturnRef, FnUnOpRef, FnVoidRef};
+
+/// Identifies files of compiled sources.
+const MAGIC: &[u8] = b"DYONC";
+/// The version of the binary format.
+const FORMAT_VERSION: u64 = 2;
+
+/// Stores a source that passed the lifetime and type checker.
+#[derive(Clone, Debug)]
+pub struct Compiled {
+    /// A hash of the source and the signatures in the module it was checked with.
+    pub key: u64,
+    /// The checked functions, with resolved calls.
+    pub functions: Vec<ast::Function>,
+    /// Records declared in the source.
+    pub records: Vec<ast::Record>,
+    /// Enums declared in the source.
+    pub enums: Vec<ast::Enum>,
+}
+
+impl Compiled {
+    /// Parses, checks and resolves a source with the functions in a module.
+    pub fn compile(source: &str, d: &Arc<String>, module: &Module) -> Result<Compiled, DyonError> {
+        let data = parse_str(source, d)?;
+        let prelude = Prelude::from_module(module);
+        let refined_rets = lifetime::check(&data, &prelude)
+            .map_err(|err| check_error(source, d, err))?;
+        let mut new_module = module.clone();
+        load_meta(source, d.clone(), &data, &mut new_module)?;
+        refine_returns(&mut new_module, refined_rets.iter());
+        let functions = new_module.functions.split_off(module.functions.len());
+        let records = new_module.records.into_iter()
+            .filter(|r| !module.records.contains(r)).collect();
+        let enums = new_module.enums.into_iter()
+            .filter(|e| !module.enums.contains(e)).collect();
+        Ok(Compiled {key: key(d, module), functions, records, enums})
+    }
+
+    /// Loads the compiled source into a module.
+    ///
+    /// The module should have the same functions as when the source was compiled.
+    pub fn load(self, module: &mut Module) {
+        for f in self.functions {module.register(f)};
+        for r in self.records {module.register_record(r)};
+        for e in self.enums {module.register_enum(e)};
+        name_record_returns(module);
+    }
+
+    /// Writes the compiled source in binary format.
+    ///
+    /// The module should be the one the source was compiled with.
+    /// Returns an error if a function is not found in the module.
+    pub fn write<W: Write>(&self, w: &mut W, module: &Module) -> io::Result<()> {
+        let mut buf: Vec<u8> = vec![];
+        {
+            let mut writer = Writer {w: &mut buf, module};
+            write_u64(writer.w, self.functions.len() as u64)?;
+            for f in &self.functions {writer.function(f)?};
+            write_u64(writer.w, self.records.len() as u64)?;
+            for r in &self.records {
+                write_str(writer.w, &r.name)?;
+                write_u64(writer.w, r.fields.len() as u64)?;
+                for (name, ty) in &r.fields {
+                    write_str(writer.w, name)?;
+                    write_type(writer.w, ty)?;
+                }
+                write_range(writer.w, r.source_range)?;
+            }
+            write_u64(writer.w, self.enums.len() as u64)?;
+            for e in &self.enums {
+                write_str(writer.w, &e.name)?;
+                write_u64(writer.w, e.variants.len() as u64)?;
+                for (name, tys) in &e.variants {
+                    write_str(writer.w, name)?;
+                    write_types(writer.w, tys)?;
+                }
+                write_range(writer.w, e.source_range)?;
+            }
+        }
+
+        w.write_all(MAGIC)?;
+        write_u64(w, FORMAT_VERSION)?;
+        write_str(w, env!("CARGO_PKG_VERSION"))?;
+        write_u64(w, self.key)?;
+        w.write_all(&buf)
+    }
+
+    /// Reads a compiled source in binary format.
+    ///
+    /// The source name and text are stored in the functions,
+    /// and the module is used to look up external functions.
+    /// Returns an error if the format or Dyon version is different.
+    pub fn read<R: Read>(
+        r: &mut R,
+        source: &str,
+        d: &Arc<String>,
+        module: &Module
+    ) -> io::Result<Compiled> {
+        let mut magic = [0; 5];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {return Err(invalid("Expected compiled Dyon source"))};
+        if read_u64(r)? != FORMAT_VERSION || *read_str(r)? != env!("CARGO_PKG_VERSION") {
+            return Err(invalid("Compiled with another version of Dyon"));
+        }
+        let key = read_u64(r)?;
+        let n = read_u64(r)?;
+        let mut reader = Reader {
+            r,
+            module,
+            file: Arc::new(source.into()),
+            source: d.clone(),
+            relative: 0,
+            len: module.functions.len().saturating_add(n as usize),
+        };
+        let mut functions = vec![];
+        for i in 0..n {
+            reader.relative = module.functions.len() + i as usize;
+            functions.push(reader.function()?);
+        }
+        let r = reader.r;
+        let n = read_u64(r)?;
+        let mut records = vec![];
+        for _ in 0..n {
+            let name = read_str(r)?;
+            let m = read_u64(r)?;
+            let mut fields = vec![];
+            for _ in 0..m {
+                let name = read_str(r)?;
+                fields.push((name, read_type(r)?));
+            }
+            records.push(ast::Record {name, fields, source_range: read_range(r)?});
+        }
+        let n = read_u64(r)?;
+        let mut enums = vec![];
+        for _ in 0..n {
+            let name = read_str(r)?;
+            let m = read_u64(r)?;
+            let mut variants = vec![];
+            for _ in 0..m {
+                let name = read_str(r)?;
+                variants.push((name, read_types(r)?));
+            }
+            enums.push(ast::Enum {name, variants, source_range: read_range(r)?});
+        }
+        Ok(Compiled {key, functions, records, enums})
+    }
+}
+
+/// Loads a source, using a compiled source from the cache directory if unchanged.
+///
+/// Sources that are compiled are written to the cache directory,
+/// ignoring errors when writing.
+pub(crate) fn load_str_cached(
+    dir: &Path,
+    source: &str,
+    d: Arc<String>,
+    module: &mut Module
+) -> Result<(), DyonError> {
+    let key = key(&d, module);
+    let path = dir.join(format!("{:016x}.dyonc", key));
+    let cached = fs::File::open(&path).ok()
+        .and_then(|file| {
+            Compiled::read(&mut io::BufReader::new(file), source, &d, module).ok()
+        })
+        .filter(|compiled| compiled.key == key);
+    let compiled = match cached {
+        Some(compiled) => compiled,
+        None => {
+            let compiled = Compiled::compile(source, &d, module)?;
+            let _ = write_cache_file(dir, &path, &compiled, module);
+            compiled
+        }
+    };
+    compiled.load(module);
+    Ok(())
+}
+
+/// Writes a compiled source to a temporary file that is renamed when complete,
+/// such that a file in the cache directory is never partially written.
+fn write_cache_file(
+    dir: &Path,
+    path: &Path,
+    compiled: &Compiled,
+    module: &Module
+) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut buf = vec![];
+    compiled.write(&mut buf, module)?;
+    fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!("{:016x}.{}-{}.tmp", compiled.key, process::id(),
+                               COUNTER.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&tmp, buf).and_then(|_| fs::rename(&tmp, path)).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+/// Computes the key of a source checked with the functions in a module.
+fn key(d: &str, module: &Module) -> u64 {
+    let mut h = Fnv::new();
+    h.write(d.as_bytes());
+    // Writing to memory only fails for lazy invariants that can not be written,
+    // which are hashed by type instead.
+    let mut buf: Vec<u8> = vec![];
+    for f in &module.ext_prelude {
+        buf.clear();
+        buf.push(ext_kind(f.f));
+        write_signature(&mut buf, &f.namespace, &f.name, &f.p, f.p.lazy.iter().map(|lazy| &**lazy));
+        h.write(&buf);
+    }
+    for f in &module.functions {
+        buf.clear();
+        write_signature(&mut buf, &f.namespace, &f.name, &Dfn::new(f),
+                        f.lazy_inv.iter().map(|lazy| &lazy[..]));
+        h.write(&buf);
+    }
+    for name in &module.denied {h.write(name.as_bytes())};
+    for r in &module.records {
+        buf.clear();
+        let _ = write_str(&mut buf, &r.name);
+        for (name, ty) in &r.fields {
+            let _ = write_str(&mut buf, name).and_then(|_| write_type(&mut buf, ty));
+        }
+        h.write(&buf);
+    }
+    for e in &module.enums {
+        buf.clear();
+        let _ = write_str(&mut buf, &e.name);
+        for (name, tys) in &e.variants {
+            let _ = write_str(&mut buf, name).and_then(|_| write_types(&mut buf, tys));
+        }
+        h.write(&buf);
+    }
+    h.0
+}
+
+/// Writes the signature of a function for computing the key.
+fn write_signature<'a, I>(
+    buf: &mut Vec<u8>,
+    namespace: &[Arc<String>],
+    name: &str,
+    dfn: &Dfn,
+    lazy_inv: I
+) where I: Iterator<Item = &'a [Lazy]> {
+    let _ = write_strs(buf, namespace);
+    let _ = write_str(buf, name);
+    let _ = write_dfn(buf, dfn);
+    for lazy in lazy_inv {
+        let _ = write_u64(buf, lazy.len() as u64);
+        for lazy in lazy {
+            let _ = write_lazy(buf, lazy).or_else(|_| match *lazy {
+                Lazy::Variable(ref v) => write_str(buf, &v.typeof_var()),
+                _ => Ok(()),
+            });
+        }
+    }
+}
+
+/// The FNV-1a hash function, which is stable across platforms and versions.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {Fnv(0xcbf2_9ce4_8422_2325)}
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        // Separate writes, such that concatenated data hashes differently.
+        self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+    }
+}
+
+/// Writes functions, looking up external functions in a module.
+struct Writer<'a, W: 'a> {
+    w: &'a mut W,
+    module: &'a Module,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    fn tag(&mut self, tag: u8) -> io::Result<()> {self.w.write_all(&[tag])}
+
+    fn function(&mut self, f: &ast::Function) -> io::Result<()> {
+        write_strs(self.w, &f.namespace)?;
+        write_str(self.w, &f.name)?;
+        self.args(&f.args)?;
+        write_u64(self.w, f.lazy_inv.len() as u64)?;
+        for lazy_inv in &f.lazy_inv {
+            write_u64(self.w, lazy_inv.len() as u64)?;
+            for lazy in lazy_inv {write_lazy(self.w, lazy)?};
+        }
+        self.currents(&f.currents)?;
+        self.block(&f.block)?;
+        write_type(self.w, &f.ret)?;
+        write_range(self.w, f.source_range)
+    }
+
+    fn args(&mut self, args: &[ast::Arg]) -> io::Result<()> {
+        write_u64(self.w, args.len() as u64)?;
+        for arg in args {
+            write_str(self.w, &arg.name)?;
+            write_opt_str(self.w, &arg.lifetime)?;
+            write_type(self.w, &arg.ty)?;
+            write_range(self.w, arg.source_range)?;
+            write_bool(self.w, arg.mutable)?;
+        }
+        Ok(())
+    }
+
+    fn currents(&mut self, currents: &[ast::Current]) -> io::Result<()> {
+        write_u64(self.w, currents.len() as u64)?;
+        for current in currents {
+            write_str(self.w, &current.name)?;
+            write_range(self.w, current.source_range)?;
+            write_bool(self.w, current.mutable)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &ast::Block) -> io::Result<()> {
+        self.exprs(&block.expressions)?;
+        write_range(self.w, block.source_range)
+    }
+
+    fn opt_block(&mut self, block: &Option<ast::Block>) -> io::Result<()> {
+        match *block {
+            None => write_bool(self.w, false),
+            Some(ref block) => {
+                write_bool(self.w, true)?;
+                self.block(block)
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expression]) -> io::Result<()> {
+        write_u64(self.w, exprs.len() as u64)?;
+        for expr in exprs {self.expr(expr)?};
+        Ok(())
+    }
+
+    fn opt_expr(&mut self, expr: &Option<Expression>) -> io::Result<()> {
+        match *expr {
+            None => write_bool(self.w, false),
+            Some(ref expr) => {
+                write_bool(self.w, true)?;
+                self.expr(expr)
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) -> io::Result<()> {
+        use ast::Expression as E;
+
+        match *expr {
+            E::Link(ref link) => {
+                self.tag(0)?;
+                self.exprs(&link.items)?;
+                write_range(self.w, link.source_range)
+            }
+            E::Interp(ref interp) => {
+                self.tag(1)?;
+                write_u64(self.w, interp.parts.len() as u64)?;
+                for part in &interp.parts {
+                    match *part {
+                        InterpPart::Text(ref text) => {
+                            self.tag(0)?;
+                            write_str(self.w, text)?;
+                        }
+                        InterpPart::Item(ref item) => {
+                            self.tag(1)?;
+                            self.expr(&item.expr)?;
+                            write_opt_usize(self.w, item.width)?;
+                            write_opt_usize(self.w, item.precision)?;
+                            write_range(self.w, item.source_range)?;
+                        }
+                    }
+                }
+                write_range(self.w, interp.source_range)
+            }
+            E::Object(ref obj) => {
+                self.tag(2)?;
+                write_u64(self.w, obj.key_values.len() as u64)?;
+                for (key, value) in &obj.key_values {
+                    write_str(self.w, key)?;
+                    self.expr(value)?;
+                }
+                write_range(self.w, obj.source_range)
+            }
+            E::Array(ref arr) => {
+                self.tag(3)?;
+                self.exprs(&arr.items)?;
+                write_range(self.w, arr.source_range)
+            }
+            E::ArrayFill(ref arr) => {
+                self.tag(4)?;
+                self.expr(&arr.fill)?;
+                self.expr(&arr.n)?;
+                write_range(self.w, arr.source_range)
+            }
+            E::Return(ref expr) => {
+                self.tag(5)?;
+                self.expr(expr)
+            }
+            E::ReturnVoid(ref range) => {
+                self.tag(6)?;
+                write_range(self.w, **range)
+            }
+            E::Yield(ref expr) => {
+                self.tag(7)?;
+                self.expr(expr)
+            }
+            E::Break(ref br) => {
+                self.tag(8)?;
+                write_opt_str(self.w, &br.label)?;
+                write_range(self.w, br.source_range)
+            }
+            E::Continue(ref c) => {
+                self.tag(9)?;
+                write_opt_str(self.w, &c.label)?;
+                write_range(self.w, c.source_range)
+            }
+            E::Block(ref block) => {
+                self.tag(10)?;
+                self.block(block)
+            }
+            E::Go(ref go) => {
+                self.tag(11)?;
+                match go.call {
+                    GoCall::Call(ref call) => {
+                        self.tag(0)?;
+                        self.call(call)?;
+                    }
+                    GoCall::Closure(ref call) => {
+                        self.tag(1)?;
+                        self.call_closure(call)?;
+                    }
+                }
+                write_range(self.w, go.source_range)
+            }
+            E::Call(ref call) => {
+                self.tag(12)?;
+                self.call(call)
+            }
+            E::CallVoid(ref call) => {
+                self.tag(13)?;
+                self.exprs(&call.args)?;
+                self.ext(FnExt::Void(call.fun.0))?;
+                self.call_info(&call.info)
+            }
+            E::CallReturn(ref call) => {
+                self.tag(14)?;
+                self.exprs(&call.args)?;
+                self.ext(FnExt::Return(call.fun.0))?;
+                self.call_info(&call.info)
+            }
+            E::CallLazy(ref call) => {
+                self.tag(15)?;
+                self.exprs(&call.args)?;
+                self.ext(FnExt::Return(call.fun.0))?;
+                self.call_info(&call.info)
+            }
+            E::CallLoaded(ref call) => {
+                self.tag(16)?;
+                self.exprs(&call.args)?;
+                write_u64(self.w, call.fun as i64 as u64)?;
+                self.call_info(&call.info)?;
+                write_opt_str(self.w, &call.custom_source)
+            }
+            E::CallBinOp(ref call) => {
+                self.tag(17)?;
+                self.expr(&call.left)?;
+                self.expr(&call.right)?;
+                self.ext(FnExt::BinOp(call.fun.0))?;
+                self.call_info(&call.info)
+            }
+            E::CallUnOp(ref call) => {
+                self.tag(18)?;
+                self.expr(&call.arg)?;
+                self.ext(FnExt::UnOp(call.fun.0))?;
+                self.call_info(&call.info)
+            }
+            E::Item(ref item) => {
+                self.tag(19)?;
+                self.item(item)
+            }
+            E::Assign(ref assign) => {
+                self.tag(20)?;
+                self.tag(match assign.op {
+                    AssignOp::Assign => 0,
+                    AssignOp::Set => 1,
+                    AssignOp::Add => 2,
+                    AssignOp::Sub => 3,
+                    AssignOp::Mul => 4,
+                    AssignOp::Div => 5,
+                    AssignOp::Rem => 6,
+                    AssignOp::Pow => 7,
+                })?;
+                self.expr(&assign.left)?;
+                self.expr(&assign.right)?;
+                write_range(self.w, assign.source_range)
+            }
+            E::Vec4(ref vec4) => {
+                self.tag(21)?;
+                self.exprs(&vec4.args)?;
+                write_range(self.w, vec4.source_range)
+            }
+            E::Mat4(ref mat4) => {
+                self.tag(22)?;
+                self.exprs(&mat4.args)?;
+                write_range(self.w, mat4.source_range)
+            }
+            E::For(ref for_expr) => {
+                self.tag(23)?;
+                self.expr(&for_expr.init)?;
+                self.expr(&for_expr.cond)?;
+                self.expr(&for_expr.step)?;
+                self.block(&for_expr.block)?;
+                write_opt_str(self.w, &for_expr.label)?;
+                write_range(self.w, for_expr.source_range)
+            }
+            E::ForN(ref for_n) => {self.tag(24)?; self.for_n(for_n)}
+            E::ForIn(ref for_in) => {self.tag(25)?; self.for_in(for_in)}
+            E::Lock(ref lock) => {
+                self.tag(26)?;
+                write_str(self.w, &lock.name)?;
+                self.expr(&lock.mutex)?;
+                self.block(&lock.block)?;
+                write_range(self.w, lock.source_range)
+            }
+            E::Sum(ref for_n) => {self.tag(27)?; self.for_n(for_n)}
+            E::SumIn(ref for_in) => {self.tag(28)?; self.for_in(for_in)}
+            E::SumVec4(ref for_n) => {self.tag(29)?; self.for_n(for_n)}
+            E::Prod(ref for_n) => {self.tag(30)?; self.for_n(for_n)}
+            E::ProdIn(ref for_in) => {self.tag(31)?; self.for_in(for_in)}
+            E::ProdVec4(ref for_n) => {self.tag(32)?; self.for_n(for_n)}
+            E::Min(ref for_n) => {self.tag(33)?; self.for_n(for_n)}
+            E::MinIn(ref for_in) => {self.tag(34)?; self.for_in(for_in)}
+            E::Max(ref for_n) => {self.tag(35)?; self.for_n(for_n)}
+            E::MaxIn(ref for_in) => {self.tag(36)?; self.for_in(for_in)}
+            E::Sift(ref for_n) => {self.tag(37)?; self.for_n(for_n)}
+            E::SiftIn(ref for_in) => {self.tag(38)?; self.for_in(for_in)}
+            E::Any(ref for_n) => {self.tag(39)?; self.for_n(for_n)}
+            E::AnyIn(ref for_in) => {self.tag(40)?; self.for_in(for_in)}
+            E::All(ref for_n) => {self.tag(41)?; self.for_n(for_n)}
+            E::AllIn(ref for_in) => {self.tag(42)?; self.for_in(for_in)}
+            E::LinkFor(ref for_n) => {self.tag(43)?; self.for_n(for_n)}
+            E::LinkIn(ref for_in) => {self.tag(44)?; self.for_in(for_in)}
+            E::If(ref if_expr) => {
+                self.tag(45)?;
+                self.expr(&if_expr.cond)?;
+                self.block(&if_expr.true_block)?;
+                self.exprs(&if_expr.else_if_conds)?;
+                write_u64(self.w, if_expr.else_if_blocks.len() as u64)?;
+                for block in &if_expr.else_if_blocks {self.block(block)?};
+                self.opt_block(&if_expr.else_block)?;
+                write_range(self.w, if_expr.source_range)
+            }
+            E::Match(ref match_expr) => {
+                self.tag(46)?;
+                self.expr(&match_expr.expr)?;
+                write_u64(self.w, match_expr.arms.len() as u64)?;
+                for arm in &match_expr.arms {
+                    write_pattern(self.w, &arm.pattern)?;
+                    self.expr(&arm.expr)?;
+                    write_range(self.w, arm.source_range)?;
+                }
+                write_range(self.w, match_expr.source_range)
+            }
+            E::Variant(ref variant) => {
+                self.tag(47)?;
+                write_str(self.w, &variant.enum_name)?;
+                write_str(self.w, &variant.name)?;
+                self.exprs(&variant.args)?;
+                write_range(self.w, variant.source_range)
+            }
+            E::Variable(ref range_var) => {
+                self.tag(48)?;
+                write_range(self.w, range_var.0)?;
+                write_variable(self.w, &range_var.1)
+            }
+            E::Try(ref expr) => {
+                self.tag(49)?;
+                self.expr(expr)
+            }
+            E::Swizzle(ref swizzle) => {
+                self.tag(50)?;
+                write_u64(self.w, swizzle.sw0 as u64)?;
+                write_u64(self.w, swizzle.sw1 as u64)?;
+                write_opt_usize(self.w, swizzle.sw2)?;
+                write_opt_usize(self.w, swizzle.sw3)?;
+                self.expr(&swizzle.expr)?;
+                write_range(self.w, swizzle.source_range)
+            }
+            E::Closure(ref closure) => {
+                self.tag(51)?;
+                self.args(&closure.args)?;
+                self.currents(&closure.currents)?;
+                self.expr(&closure.expr)?;
+                write_type(self.w, &closure.ret)?;
+                write_range(self.w, closure.source_range)
+            }
+            E::CallClosure(ref call) => {
+                self.tag(52)?;
+                self.call_closure(call)
+            }
+            E::Grab(ref grab) => {
+                self.tag(53)?;
+                write_u64(self.w, u64::from(grab.level))?;
+                self.expr(&grab.expr)?;
+                write_range(self.w, grab.source_range)
+            }
+            E::TryExpr(ref try_expr) => {
+                self.tag(54)?;
+                self.expr(&try_expr.expr)?;
+                write_range(self.w, try_expr.source_range)
+            }
+            E::In(ref in_expr) => {
+                self.tag(55)?;
+                write_opt_str(self.w, &in_expr.alias)?;
+                write_str(self.w, &in_expr.name)?;
+                self.fn_index(in_expr.f_index.get())?;
+                write_range(self.w, in_expr.source_range)
+            }
+        }
+    }
+
+    fn for_n(&mut self, for_n: &ast::ForN) -> io::Result<()> {
+        write_str(self.w, &for_n.name)?;
+        self.opt_expr(&for_n.start)?;
+        self.expr(&for_n.end)?;
+        self.block(&for_n.block)?;
+        write_opt_str(self.w, &for_n.label)?;
+        write_bool(self.w, for_n.par)?;
+        write_range(self.w, for_n.source_range)
+    }
+
+    fn for_in(&mut self, for_in: &ast::ForIn) -> io::Result<()> {
+        write_str(self.w, &for_in.name)?;
+        self.expr(&for_in.iter)?;
+        self.block(&for_in.block)?;
+        write_opt_str(self.w, &for_in.label)?;
+        write_range(self.w, for_in.source_range)
+    }
+
+    fn call(&mut self, call: &ast::Call) -> io::Result<()> {
+        self.exprs(&call.args)?;
+        self.fn_index(call.f_index)?;
+        self.call_info(&call.info)?;
+        write_opt_str(self.w, &call.custom_source)
+    }
+
+    fn call_closure(&mut self, call: &ast::CallClosure) -> io::Result<()> {
+        self.item(&call.item)?;
+        self.exprs(&call.args)?;
+        write_range(self.w, call.source_range)
+    }
+
+    fn call_info(&mut self, info: &ast::CallInfo) -> io::Result<()> {
+        write_str(self.w, &info.name)?;
+        write_opt_str(self.w, &info.alias)?;
+        write_range(self.w, info.source_range)
+    }
+
+    fn item(&mut self, item: &ast::Item) -> io::Result<()> {
+        write_str(self.w, &item.name)?;
+        write_opt_usize(self.w, item.stack_id.get())?;
+        write_opt_usize(self.w, item.static_stack_id.get())?;
+        write_bool(self.w, item.current)?;
+        write_bool(self.w, item.try)?;
+        write_u64(self.w, item.ids.len() as u64)?;
+        for id in &item.ids {
+            match *id {
+                Id::String(range, ref name) => {
+                    self.tag(0)?;
+                    write_range(self.w, range)?;
+                    write_str(self.w, name)?;
+                }
+                Id::F64(range, val) => {
+                    self.tag(1)?;
+                    write_range(self.w, range)?;
+                    write_u64(self.w, val.to_bits())?;
+                }
+                Id::Expression(ref expr) => {
+                    self.tag(2)?;
+                    self.expr(expr)?;
+                }
+            }
+        }
+        write_u64(self.w, item.try_ids.len() as u64)?;
+        for &ind in &item.try_ids {write_u64(self.w, ind as u64)?};
+        write_range(self.w, item.source_range)
+    }
+
+    fn fn_index(&mut self, f_index: FnIndex) -> io::Result<()> {
+        match f_index {
+            FnIndex::None => self.tag(0),
+            FnIndex::Loaded(ind) => {
+                self.tag(1)?;
+                write_u64(self.w, ind as i64 as u64)
+            }
+            FnIndex::Void(f) => {self.tag(2)?; self.ext(FnExt::Void(f.0))}
+            FnIndex::Return(f) => {self.tag(3)?; self.ext(FnExt::Return(f.0))}
+            FnIndex::Lazy(f, _) => {self.tag(4)?; self.ext(FnExt::Return(f.0))}
+            FnIndex::BinOp(f) => {self.tag(5)?; self.ext(FnExt::BinOp(f.0))}
+            FnIndex::UnOp(f) => {self.tag(6)?; self.ext(FnExt::UnOp(f.0))}
+        }
+    }
+
+    /// Writes the index of an external function in the module.
+    fn ext(&mut self, f: FnExt) -> io::Result<()> {
+        let addr = ext_addr(f);
+        match self.module.ext_prelude.iter()
+            .position(|ext| ext_kind(ext.f) == ext_kind(f) && ext_addr(ext.f) == addr)
+        {
+            Some(ind) => write_u64(self.w, ind as u64),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                       "External function is not in module")),
+        }
+    }
+}
+
+/// Reads functions, looking up external functions in a module.
+struct Reader<'a, R: 'a> {
+    r: &'a mut R,
+    module: &'a Module,
+    /// The source name.
+    file: Arc<String>,
+    /// The source text.
+    source: Arc<String>,
+    /// The index of the function being read.
+    relative: usize,
+    /// The number of functions after loading.
+    len: usize,
+}
+
+impl<'a, R: Read> Reader<'a, R> {
+    fn function(&mut self) -> io::Result<ast::Function> {
+        let namespace = Arc::new(read_strs(self.r)?);
+        let name = read_str(self.r)?;
+        let args = self.args()?;
+        let n = read_u64(self.r)?;
+        let mut lazy_inv = vec![];
+        for _ in 0..n {
+            let m = read_u64(self.r)?;
+            let mut lazy = vec![];
+            for _ in 0..m {lazy.push(read_lazy(self.r)?)};
+            lazy_inv.push(lazy);
+        }
+        let currents = self.currents()?;
+        let block = self.block()?;
+        let ret = read_type(self.r)?;
+        Ok(ast::Function {
+            namespace,
+            name,
+            file: self.file.clone(),
+            source: self.source.clone(),
+            args,
+            lazy_inv,
+            currents,
+            block,
+            ret,
+            ret_record: None,
+            resolved: Arc::new(AtomicBool::new(true)),
+            source_range: read_range(self.r)?,
+            senders: Arc::new((AtomicBool::new(false), Mutex::new(vec![]))),
+            bytecode: Arc::new(OnceLock::new()),
+        })
+    }
+
+    fn args(&mut self) -> io::Result<Vec<ast::Arg>> {
+        let n = read_u64(self.r)?;
+        let mut args = vec![];
+        for _ in 0..n {
+            args.push(ast::Arg {
+                name: read_str(self.r)?,
+                lifetime: read_opt_str(self.r)?,
+                ty: read_type(self.r)?,
+                source_range: read_range(self.r)?,
+                mutable: read_bool(self.r)?,
+            });
+        }
+        Ok(args)
+    }
+
+    fn currents(&mut self) -> io::Result<Vec<ast::Current>> {
+        let n = read_u64(self.r)?;
+        let mut currents = vec![];
+        for _ in 0..n {
+            currents.push(ast::Current {
+                name: read_str(self.r)?,
+                source_range: read_range(self.r)?,
+                mutable: read_bool(self.r)?,
+            });
+        }
+        Ok(currents)
+    }
+
+    fn block(&mut self) -> io::Result<ast::Block> {
+        let expressions = self.exprs()?;
+        Ok(ast::Block {expressions, source_range: read_range(self.r)?})
+    }
+
+    fn opt_block(&mut self) -> io::Result<Option<ast::Block>> {
+        Ok(if read_bool(self.r)? {Some(self.block()?)} else {None})
+    }
+
+    fn exprs(&mut self) -> io::Result<Vec<Expression>> {
+        let n = read_u64(self.r)?;
+        let mut exprs = vec![];
+        for _ in 0..n {exprs.push(self.expr()?)};
+        Ok(exprs)
+    }
+
+    fn opt_expr(&mut self) -> io::Result<Option<Expression>> {
+        Ok(if read_bool(self.r)? {Some(self.expr()?)} else {None})
+    }
+
+    fn expr(&mut self) -> io::Result<Expression> {
+        use ast::Expression as E;
+
+        Ok(match read_u8(self.r)? {
+            0 => {
+                let items = self.exprs()?;
+                E::Link(Box::new(ast::Link {items, source_range: read_range(self.r)?}))
+            }
+            1 => {
+                let n = read_u64(self.r)?;
+                let mut parts = vec![];
+                for _ in 0..n {
+                    parts.push(match read_u8(self.r)? {
+                        0 => InterpPart::Text(read_str(self.r)?),
+                        1 => InterpPart::Item(Box::new(ast::InterpItem {
+                            expr: self.expr()?,
+                            width: read_opt_usize(self.r)?,
+                            precision: read_opt_usize(self.r)?,
+                            source_range: read_range(self.r)?,
+                        })),
+                        _ => return Err(invalid("Invalid interpolation")),
+                    });
+                }
+                E::Interp(Box::new(ast::Interp {parts, source_range: read_range(self.r)?}))
+            }
+            2 => {
+                let n = read_u64(self.r)?;
+                let mut key_values = vec![];
+                for _ in 0..n {
+                    let key = read_str(self.r)?;
+                    key_values.push((key, self.expr()?));
+                }
+                E::Object(Box::new(ast::Object {key_values, source_range: read_range(self.r)?}))
+            }
+            3 => {
+                let items = self.exprs()?;
+                E::Array(Box::new(ast::Array {items, source_range: read_range(self.r)?}))
+            }
+            4 => E::ArrayFill(Box::new(ast::ArrayFill {
+                fill: self.expr()?,
+                n: self.expr()?,
+                source_range: read_range(self.r)?,
+            })),
+            5 => E::Return(Box::new(self.expr()?)),
+            6 => E::ReturnVoid(Box::new(read_range(self.r)?)),
+            7 => E::Yield(Box::new(self.expr()?)),
+            8 => E::Break(Box::new(ast::Break {
+                label: read_opt_str(self.r)?,
+                source_range: read_range(self.r)?,
+            })),
+            9 => E::Continue(Box::new(ast::Continue {
+                label: read_opt_str(self.r)?,
+                source_range: read_range(self.r)?,
+            })),
+            10 => E::Block(Box::new(self.block()?)),
+            11 => {
+                let call = match read_u8(self.r)? {
+                    0 => GoCall::Call(self.call()?),
+                    1 => GoCall::Closure(self.call_closure()?),
+                    _ => return Err(invalid("Invalid go call")),
+                };
+                E::Go(Box::new(ast::Go {call, source_range: read_range(self.r)?}))
+            }
+            12 => E::Call(Box::new(self.call()?)),
+            13 => {
+                let args = self.exprs()?;
+                let fun = match self.ext()?.f {
+                    FnExt::Void(f) => FnVoidRef(f),
+                    _ => return Err(invalid("Expected external function without return value")),
+                };
+                E::CallVoid(Box::new(ast::CallVoid {args, fun, info: self.call_info()?}))
+            }
+            14 => {
+                let args = self.exprs()?;
+                let fun = match self.ext()?.f {
+                    FnExt::Return(f) => FnReturnRef(f),
+                    _ => return Err(invalid("Expected external function with return value")),
+                };
+                E::CallReturn(Box::new(ast::CallReturn {args, fun, info: self.call_info()?}))
+            }
+            15 => {
+                let args = self.exprs()?;
+                let ext = self.ext()?;
+                let fun = match ext.f {
+                    FnExt::Return(f) => FnReturnRef(f),
+                    _ => return Err(invalid("Expected external function with return value")),
+                };
+                E::CallLazy(Box::new(ast::CallLazy {
+                    args,
+                    fun,
+                    lazy_inv: ext.p.lazy,
+                    info: self.call_info()?,
+                }))
+            }
+            16 => {
+                let args = self.exprs()?;
+                let fun = self.loaded()?;
+                E::CallLoaded(Box::new(ast::CallLoaded {
+                    args,
+                    fun,
+                    info: self.call_info()?,
+                    custom_source: read_opt_str(self.r)?,
+                }))
+            }
+            17 => {
+                let left = self.expr()?;
+                let right = self.expr()?;
+                let fun = match self.ext()?.f {
+                    FnExt::BinOp(f) => FnBinOpRef(f),
+                    _ => return Err(invalid("Expected external binary operator")),
+                };
+                E::CallBinOp(Box::new(ast::CallBinOp {left, right, fun, info: self.call_info()?}))
+            }
+            18 => {
+                let arg = self.expr()?;
+                let fun = match self.ext()?.f {
+                    FnExt::UnOp(f) => FnUnOpRef(f),
+                    _ => return Err(invalid("Expected external unary operator")),
+                };
+                E::CallUnOp(Box::new(ast::CallUnOp {arg, fun, info: self.call_info()?}))
+            }
+            19 => E::Item(Box::new(self.item()?)),
+            20 => {
+                let op = match read_u8(self.r)? {
+                    0 => AssignOp::Assign,
+                    1 => AssignOp::Set,
+                    2 => AssignOp::Add,
+                    3 => AssignOp::Sub,
+                    4 => AssignOp::Mul,
+                    5 => AssignOp::Div,
+                    6 => AssignOp::Rem,
+                    7 => AssignOp::Pow,
+                    _ => return Err(invalid("Invalid assignment operator")),
+                };
+                E::Assign(Box::new(ast::Assign {
+                    op,
+                    left: self.expr()?,
+                    right: self.expr()?,
+                    source_range: read_range(self.r)?,
+                }))
+            }
+            21 => {
+                let args = self.exprs()?;
+                E::Vec4(Box::new(ast::Vec4 {args, source_range: read_range(self.r)?}))
+            }
+            22 => {
+                let args = self.exprs()?;
+                E::Mat4(Box::new(ast::Mat4 {args, source_range: read_range(self.r)?}))
+            }
+            23 => E::For(Box::new(ast::For {
+                init: self.expr()?,
+                cond: self.expr()?,
+                step: self.expr()?,
+                block: self.block()?,
+                label: read_opt_str(self.r)?,
+                source_range: read_range(self.r)?,
+            })),
+            24 => E::ForN(self.for_n()?),
+            25 => E::ForIn(self.for_in()?),
+            26 => E::Lock(Box::new(ast::Lock {
+                name: read_str(self.r)?,
+                mutex: self.expr()?,
+                block: self.block()?,
+                source_range: read_range(self.r)?,
+            })),
+            27 => E::Sum(self.for_n()?),
+            28 => E::SumIn(self.for_in()?),
+            29 => E::SumVec4(self.for_n()?),
+            30 => E::Prod(self.for_n()?),
+            31 => E::ProdIn(self.for_in()?),
+            32 => E::ProdVec4(self.for_n()?),
+            33 => E::Min(self.for_n()?),
+            34 => E::MinIn(self.for_in()?),
+            35 => E::Max(self.for_n()?),
+            36 => E::MaxIn(self.for_in()?),
+            37 => E::Sift(self.for_n()?),
+            38 => E::SiftIn(self.for_in()?),
+            39 => E::Any(self.for_n()?),
+            40 => E::AnyIn(self.for_in()?),
+            41 => E::All(self.for_n()?),
+            42 => E::AllIn(self.for_in()?),
+            43 => E::LinkFor(self.for_n()?),
+            44 => E::LinkIn(self.for_in()?),
+            45 => {
+                let cond = self.expr()?;
+                let true_block = self.block()?;
+                let else_if_conds = self.exprs()?;
+                let n = read_u64(self.r)?;
+                let mut else_if_blocks = vec![];
+                for _ in 0..n {else_if_blocks.push(self.block()?)};
+                E::If(Box::new(ast::If {
+                    cond,
+                    true_block,
+                    else_if_conds,
+                    else_if_blocks,
+                    else_block: self.opt_block()?,
+                    source_range: read_range(self.r)?,
+                }))
+            }
+            46 => {
+                let expr = self.expr()?;
+                let n = read_u64(self.r)?;
+                let mut arms = vec![];
+                for _ in 0..n {
+                    arms.push(ast::MatchArm {
+                        pattern: read_pattern(self.r)?,
+                        expr: self.expr()?,
+                        source_range: read_range(self.r)?,
+                    });
+                }
+                E::Match(Box::new(ast::Match {expr, arms, source_range: read_range(self.r)?}))
+            }
+            47 => E::Variant(Box::new(ast::Variant {
+                enum_name: read_str(self.r)?,
+                name: read_str(self.r)?,
+                args: self.exprs()?,
+                source_range: read_range(self.r)?,
+            })),
+            48 => {
+                let range = read_range(self.r)?;
+                E::Variable(Box::new((range, read_variable(self.r)?)))
+            }
+            49 => E::Try(Box::new(self.expr()?)),
+            50 => E::Swizzle(Box::new(ast::Swizzle {
+                sw0: read_u64(self.r)? as usize,
+                sw1: read_u64(self.r)? as usize,
+                sw2: read_opt_usize(self.r)?,
+                sw3: read_opt_usize(self.r)?,
+                expr: self.expr()?,
+                source_range: read_range(self.r)?,
+            })),
+            51 => E::Closure(Arc::new(ast::Closure {
+                file: self.file.clone(),
+                source: self.source.clone(),
+                args: self.args()?,
+                currents: self.currents()?,
+                expr: self.expr()?,
+                ret: read_type(self.r)?,
+                source_range: read_range(self.r)?,
+            })),
+            52 => E::CallClosure(Box::new(self.call_closure()?)),
+            53 => E::Grab(Box::new(ast::Grab {
+                level: read_u64(self.r)? as u16,
+                expr: self.expr()?,
+                source_range: read_range(self.r)?,
+            })),
+            54 => E::TryExpr(Box::new(ast::TryExpr {
+                expr: self.expr()?,
+                source_range: read_range(self.r)?,
+            })),
+            55 => E::In(Box::new(ast::In {
+                alias: read_opt_str(self.r)?,
+                name: read_str(self.r)?,
+                f_index: Cell::new(self.fn_index()?),
+                source_range: read_range(self.r)?,
+            })),
+            _ => return Err(invalid("Invalid expression")),
+        })
+    }
+
+    fn for_n(&mut self) -> io::Result<Box<ast::ForN>> {
+        Ok(Box::new(ast::ForN {
+            name: read_str(self.r)?,
+            start: self.opt_expr()?,
+            end: self.expr()?,
+            block: self.block()?,
+            label: read_opt_str(self.r)?,
+            par: read_bool(self.r)?,
+            source_range: read_range(self.r)?,
+        }))
+    }
+
+    fn for_in(&mut self) -> io::Result<Box<ast::ForIn>> {
+        Ok(Box::new(ast::ForIn {
+            name: read_str(self.r)?,
+            iter: self.expr()?,
+            block: self.block()?,
+            label: read_opt_str(self.r)?,
+            source_range: read_range(self.r)?,
+        }))
+    }
+
+    fn call(&mut self) -> io::Result<ast::Call> {
+        Ok(ast::Call {
+            args: self.exprs()?,
+            f_index: self.fn_index()?,
+            info: self.call_info()?,
+            custom_source: read_opt_str(self.r)?,
+        })
+    }
+
+    fn call_closure(&mut self) -> io::Result<ast::CallClosure> {
+        Ok(ast::CallClosure {
+            item: self.item()?,
+            args: self.exprs()?,
+            source_range: read_range(self.r)?,
+        })
+    }
+
+    fn call_info(&mut self) -> io::Result<Box<ast::CallInfo>> {
+        Ok(Box::new(ast::CallInfo {
+            name: read_str(self.r)?,
+            alias: read_opt_str(self.r)?,
+            source_range: read_range(self.r)?,
+        }))
+    }
+
+    fn item(&mut self) -> io::Result<ast::Item> {
+        let name = read_str(self.r)?;
+        let stack_id = read_opt_usize(self.r)?;
+        let static_stack_id = read_opt_usize(self.r)?;
+        let current = read_bool(self.r)?;
+        let try = read_bool(self.r)?;
+        let n = read_u64(self.r)?;
+        let mut ids = vec![];
+        for _ in 0..n {
+            ids.push(match read_u8(self.r)? {
+                0 => {
+                    let range = read_range(self.r)?;
+                    Id::String(range, read_str(self.r)?)
+                }
+                1 => {
+                    let range = read_range(self.r)?;
+                    Id::F64(range, f64::from_bits(read_u64(self.r)?))
+                }
+                2 => Id::Expression(self.expr()?),
+                _ => return Err(invalid("Invalid id")),
+            });
+        }
+        let n = read_u64(self.r)?;
+        let mut try_ids = vec![];
+        for _ in 0..n {try_ids.push(read_u64(self.r)? as usize)};
+        Ok(ast::Item {
+            name,
+            stack_id: Cell::new(stack_id),
+            static_stack_id: Cell::new(static_stack_id),
+            current,
+            try,
+            ids,
+            try_ids,
+            source_range: read_range(self.r)?,
+        })
+    }
+
+    fn fn_index(&mut self) -> io::Result<FnIndex> {
+        Ok(match read_u8(self.r)? {
+            0 => FnIndex::None,
+            1 => FnIndex::Loaded(self.loaded()?),
+            2 => match self.ext()?.f {
+                FnExt::Void(f) => FnIndex::Void(FnVoidRef(f)),
+                _ => return Err(invalid("Expected external function without return value")),
+            },
+            3 => match self.ext()?.f {
+                FnExt::Return(f) => FnIndex::Return(FnReturnRef(f)),
+                _ => return Err(invalid("Expected external function with return value")),
+            },
+            4 => {
+                let ext = self.ext()?;
+                match ext.f {
+                    FnExt::Return(f) => FnIndex::Lazy(FnReturnRef(f), ext.p.lazy),
+                    _ => return Err(invalid("Expected external function with return value")),
+                }
+            }
+            5 => match self.ext()?.f {
+                FnExt::BinOp(f) => FnIndex::BinOp(FnBinOpRef(f)),
+                _ => return Err(invalid("Expected external binary operator")),
+            },
+            6 => match self.ext()?.f {
+                FnExt::UnOp(f) => FnIndex::UnOp(FnUnOpRef(f)),
+                _ => return Err(invalid("Expected external unary operator")),
+            },
+            _ => return Err(invalid("Invalid function index")),
+        })
+    }
+
+    /// Reads the relative index of a loaded function, checking that it is in range.
+    fn loaded(&mut self) -> io::Result<isize> {
+        let ind = read_u64(self.r)? as i64 as isize;
+        let abs = (self.relative as isize).checked_add(ind);
+        match abs {
+            Some(abs) if abs >= 0 && (abs as usize) < self.len => Ok(ind),
+            _ => Err(invalid("Invalid function index")),
+        }
+    }
+
+    /// Reads the index of an external function in the module.
+    fn ext(&mut self) -> io::Result<&'a FnExternal> {
+        let ind = read_u64(self.r)?;
+        self.module.ext_prelude.get(ind as usize)
+            .ok_or_else(|| invalid("Invalid external function"))
+    }
+}
+
+/// Identifies the kind of an external function.
+fn ext_kind(f: FnExt) -> u8 {
+    match f {
+        FnExt::Void(_) => 0,
+        FnExt::Return(_) => 1,
+        FnExt::BinOp(_) => 2,
+        FnExt::UnOp(_) => 3,
+    }
+}
+
+/// Gets the address of an external function.
+fn ext_addr(f: FnExt) -> usize {
+    match f {
+        FnExt::Void(f) => f as usize,
+        FnExt::Return(f) => f as usize,
+        FnExt::BinOp(f) => f as usize,
+        FnExt::UnOp(f) => f as usize,
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {io::Error::new(io::ErrorKind::InvalidData, msg)}
+
+fn write_u64<W: Write>(w: &mut W, val: u64) -> io::Result<()> {w.write_all(&val.to_le_bytes())}
+
+fn write_bool<W: Write>(w: &mut W, val: bool) -> io::Result<()> {w.write_all(&[val as u8])}
+
+fn write_str<W: Write>(w: &mut W, val: &str) -> io::Result<()> {
+    write_u64(w, val.len() as u64)?;
+    w.write_all(val.as_bytes())
+}
+
+fn write_strs<W: Write>(w: &mut W, vals: &[Arc<String>]) -> io::Result<()> {
+    write_u64(w, vals.len() as u64)?;
+    for val in vals {write_str(w, val)?};
+    Ok(())
+}
+
+fn write_opt_str<W: Write>(w: &mut W, val: &Option<Arc<String>>) -> io::Result<()> {
+    match *val {
+        None => write_bool(w, false),
+        Some(ref val) => {
+            write_bool(w, true)?;
+            write_str(w, val)
+        }
+    }
+}
+
+fn write_opt_usize<W: Write>(w: &mut W, val: Option<usize>) -> io::Result<()> {
+    match val {
+        None => write_bool(w, false),
+        Some(val) => {
+            write_bool(w, true)?;
+            write_u64(w, val as u64)
+        }
+    }
+}
+
+fn write_range<W: Write>(w: &mut W, range: Range) -> io::Result<()> {
+    write_u64(w, range.offset as u64)?;
+    write_u64(w, range.length as u64)
+}
+
+fn write_lazy<W: Write>(w: &mut W, lazy: &Lazy) -> io::Result<()> {
+    match *lazy {
+        Lazy::Variable(ref v) => {
+            w.write_all(&[0])?;
+            write_variable(w, v)
+        }
+        Lazy::UnwrapOk => w.write_all(&[1]),
+        Lazy::UnwrapErr => w.write_all(&[2]),
+        Lazy::UnwrapSome => w.write_all(&[3]),
+    }
+}
+
+/// Writes a constant variable.
+///
+/// Returns an error for variables that can not be constants, such as secrets.
+fn write_variable<W: Write>(w: &mut W, v: &Variable) -> io::Result<()> {
+    match *v {
+        Variable::Bool(val, None) => {
+            w.write_all(&[0])?;
+            write_bool(w, val)
+        }
+        Variable::F64(val, None) => {
+            w.write_all(&[1])?;
+            write_u64(w, val.to_bits())
+        }
+        Variable::I64(val) => {
+            w.write_all(&[2])?;
+            write_u64(w, val as u64)
+        }
+        Variable::Str(ref val) => {
+            w.write_all(&[3])?;
+            write_str(w, val)
+        }
+        Variable::Bytes(ref val) => {
+            w.write_all(&[4])?;
+            write_u64(w, val.len() as u64)?;
+            w.write_all(val)
+        }
+        Variable::Vec4(val) => {
+            w.write_all(&[5])?;
+            for x in &val {w.write_all(&x.to_bits().to_le_bytes())?};
+            Ok(())
+        }
+        Variable::Mat4(ref val) => {
+            w.write_all(&[6])?;
+            for x in val.iter().flat_map(|col| col.iter()) {
+                w.write_all(&x.to_bits().to_le_bytes())?;
+            }
+            Ok(())
+        }
+        Variable::Array(ref arr) => {
+            w.write_all(&[7])?;
+            write_u64(w, arr.len() as u64)?;
+            for v in &**arr {write_variable(w, v)?};
+            Ok(())
+        }
+        Variable::Object(ref obj) => {
+            w.write_all(&[8])?;
+            write_u64(w, obj.len() as u64)?;
+            let mut keys: Vec<_> = obj.keys().collect();
+            keys.sort();
+            for key in keys {
+                write_str(w, key)?;
+                write_variable(w, &obj[key])?;
+            }
+            Ok(())
+        }
+        Variable::Link(ref link) => {
+            w.write_all(&[9])?;
+            let n: usize = link.slices.iter().map(|slice| (slice.end - slice.start) as usize).sum();
+            write_u64(w, n as u64)?;
+            for slice in &link.slices {
+                for i in slice.start..slice.end {write_variable(w, &slice.block.var(i))?};
+            }
+            Ok(())
+        }
+        Variable::Option(None) => w.write_all(&[10]),
+        Variable::Option(Some(ref v)) => {
+            w.write_all(&[11])?;
+            write_variable(w, v)
+        }
+        Variable::Return => w.write_all(&[12]),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Variable can not be written")),
+    }
+}
+
+fn write_pattern<W: Write>(w: &mut W, pattern: &Pattern) -> io::Result<()> {
+    match *pattern {
+        Pattern::Any => w.write_all(&[0]),
+        Pattern::Bind(ref name) => {
+            w.write_all(&[1])?;
+            write_str(w, name)
+        }
+        Pattern::F64(val) => {
+            w.write_all(&[2])?;
+            write_u64(w, val.to_bits())
+        }
+        Pattern::I64(val) => {
+            w.write_all(&[3])?;
+            write_u64(w, val as u64)
+        }
+        Pattern::Str(ref val) => {
+            w.write_all(&[4])?;
+            write_str(w, val)
+        }
+        Pattern::Bool(val) => {
+            w.write_all(&[5])?;
+            write_bool(w, val)
+        }
+        Pattern::Some(ref pat) => {
+            w.write_all(&[6])?;
+            write_pattern(w, pat)
+        }
+        Pattern::None => w.write_all(&[7]),
+        Pattern::Ok(ref pat) => {
+            w.write_all(&[8])?;
+            write_pattern(w, pat)
+        }
+        Pattern::Err(ref pat) => {
+            w.write_all(&[9])?;
+            write_pattern(w, pat)
+        }
+        Pattern::Object(ref fields) => {
+            w.write_all(&[10])?;
+            write_u64(w, fields.len() as u64)?;
+            for (name, pat) in fields {
+                write_str(w, name)?;
+                write_pattern(w, pat)?;
+            }
+            Ok(())
+        }
+        Pattern::Variant(ref enum_name, ref name, ref pats) => {
+            w.write_all(&[11])?;
+            write_str(w, enum_name)?;
+            write_str(w, name)?;
+            write_u64(w, pats.len() as u64)?;
+            for pat in pats {write_pattern(w, pat)?};
+            Ok(())
+        }
+    }
+}
+
+fn write_types<W: Write>(w: &mut W, tys: &[Type]) -> io::Result<()> {
+    write_u64(w, tys.len() as u64)?;
+    for ty in tys {write_type(w, ty)?};
+    Ok(())
+}
+
+/// Writes a function signature, except lazy invariants.
+fn write_dfn<W: Write>(w: &mut W, dfn: &Dfn) -> io::Result<()> {
+    write_u64(w, dfn.lts.len() as u64)?;
+    for lt in &dfn.lts {
+        match *lt {
+            Lt::Arg(ind) => {
+                w.write_all(&[0])?;
+                write_u64(w, ind as u64)?;
+            }
+            Lt::Return => w.write_all(&[1])?,
+            Lt::Default => w.write_all(&[2])?,
+        }
+    }
+    write_types(w, &dfn.tys)?;
+    write_type(w, &dfn.ret)?;
+    write_u64(w, dfn.ext.len() as u64)?;
+    for (vars, tys, ret) in &dfn.ext {
+        write_strs(w, vars)?;
+        write_types(w, tys)?;
+        write_type(w, ret)?;
+    }
+    Ok(())
+}
+
+fn write_type<W: Write>(w: &mut W, ty: &Type) -> io::Result<()> {
+    use Type::*;
+
+    let (tag, inner) = match *ty {
+        Unreachable => (0, None),
+        Void => (1, None),
+        Any => (2, None),
+        Bool => (3, None),
+        F64 => (4, None),
+        Vec4 => (5, None),
+        Mat4 => (6, None),
+        Str => (7, None),
+        Link => (8, None),
+        Object => (9, None),
+        Array(ref ty) => (10, Some(ty)),
+        Option(ref ty) => (11, Some(ty)),
+        Result(ref ty) => (12, Some(ty)),
+        Secret(ref ty) => (13, Some(ty)),
+        Thread(ref ty) => (14, Some(ty)),
+        In(ref ty) => (15, Some(ty)),
+        I64 => (18, None),
+        Bytes => (19, None),
+        Set(ref ty) => (21, Some(ty)),
+        Coroutine(ref ty) => (22, Some(ty)),
+        Out(ref ty) => (23, Some(ty)),
+        Atomic => (24, None),
+        Mutex(ref ty) => (25, Some(ty)),
+        Map(ref key, ref val) => {
+            w.write_all(&[20])?;
+            write_type(w, key)?;
+            return write_type(w, val);
+        }
+        AdHoc(ref name, ref ty) => {
+            w.write_all(&[16])?;
+            write_str(w, name)?;
+            return write_type(w, ty);
+        }
+        Closure(ref dfn) => {
+            w.write_all(&[17])?;
+            return write_dfn(w, dfn);
+        }
+    };
+    w.write_all(&[tag])?;
+    match inner {
+        Some(ty) => write_type(w, ty),
+        None => Ok(())
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<Arc<String>> {
+    let n = read_u64(r)?;
+    let mut buf = vec![];
+    r.take(n).read_to_end(&mut buf)?;
+    if buf.len() as u64 != n {return Err(invalid("Unexpected end of data"))};
+    String::from_utf8(buf).map(Arc::new).map_err(|_| invalid("Invalid UTF-8"))
+}
+
+fn read_bool<R: Read>(r: &mut R) -> io::Result<bool> {
+    match read_u8(r)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(invalid("Invalid bool")),
+    }
+}
+
+fn read_strs<R: Read>(r: &mut R) -> io::Result<Vec<Arc<String>>> {
+    let n = read_u64(r)?;
+    let mut vals = vec![];
+    for _ in 0..n {vals.push(read_str(r)?)};
+    Ok(vals)
+}
+
+fn read_opt_str<R: Read>(r: &mut R) -> io::Result<Option<Arc<String>>> {
+    Ok(if read_bool(r)? {Some(read_str(r)?)} else {None})
+}
+
+fn read_opt_usize<R: Read>(r: &mut R) -> io::Result<Option<usize>> {
+    Ok(if read_bool(r)? {Some(read_u64(r)? as usize)} else {None})
+}
+
+fn read_range<R: Read>(r: &mut R) -> io::Result<Range> {
+    let offset = read_u64(r)? as usize;
+    Ok(Range::new(offset, read_u64(r)? as usize))
+}
+
+fn read_lazy<R: Read>(r: &mut R) -> io::Result<Lazy> {
+    Ok(match read_u8(r)? {
+        0 => Lazy::Variable(read_variable(r)?),
+        1 => Lazy::UnwrapOk,
+        2 => Lazy::UnwrapErr,
+        3 => Lazy::UnwrapSome,
+        _ => return Err(invalid("Invalid lazy invariant")),
+    })
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(f32::from_bits(u32::from_le_bytes(buf)))
+}
+
+fn read_variable<R: Read>(r: &mut R) -> io::Result<Variable> {
+    Ok(match read_u8(r)? {
+        0 => Variable::bool(read_bool(r)?),
+        1 => Variable::f64(f64::from_bits(read_u64(r)?)),
+        2 => Variable::I64(read_u64(r)? as i64),
+        3 => Variable::Str(read_str(r)?),
+        4 => {
+            let n = read_u64(r)?;
+            let mut buf = vec![];
+            r.take(n).read_to_end(&mut buf)?;
+            if buf.len() as u64 != n {return Err(invalid("Unexpected end of data"))};
+            Variable::Bytes(Arc::new(buf))
+        }
+        5 => {
+            let mut val = [0.0; 4];
+            for x in &mut val {*x = read_f32(r)?};
+            Variable::Vec4(val)
+        }
+        6 => {
+            let mut val = [[0.0; 4]; 4];
+            for x in val.iter_mut().flat_map(|col| col.iter_mut()) {*x = read_f32(r)?};
+            Variable::Mat4(Box::new(val))
+        }
+        7 => {
+            let n = read_u64(r)?;
+            let mut arr = vec![];
+            for _ in 0..n {arr.push(read_variable(r)?)};
+            Variable::Array(Arc::new(arr))
+        }
+        8 => {
+            let n = read_u64(r)?;
+            let mut obj = HashMap::new();
+            for _ in 0..n {
+                let key = read_str(r)?;
+                obj.insert(key, read_variable(r)?);
+            }
+            Variable::Object(Arc::new(obj))
+        }
+        9 => {
+            let n = read_u64(r)?;
+            let mut link = Link::new();
+            for _ in 0..n {
+                link.push(&read_variable(r)?).map_err(|err| invalid(&err))?;
+            }
+            Variable::Link(Box::new(link))
+        }
+        10 => Variable::Option(None),
+        11 => Variable::Option(Some(Box::new(read_variable(r)?))),
+        12 => Variable::Return,
+        _ => return Err(invalid("Invalid variable")),
+    })
+}
+
+fn read_pattern<R: Read>(r: &mut R) -> io::Result<Pattern> {
+    Ok(match read_u8(r)? {
+        0 => Pattern::Any,
+        1 => Pattern::Bind(read_str(r)?),
+        2 => Pattern::F64(f64::from_bits(read_u64(r)?)),
+        3 => Pattern::I64(read_u64(r)? as i64),
+        4 => Pattern::Str(read_str(r)?),
+        5 => Pattern::Bool(read_bool(r)?),
+        6 => Pattern::Some(Box::new(read_pattern(r)?)),
+        7 => Pattern::None,
+        8 => Pattern::Ok(Box::new(read_pattern(r)?)),
+        9 => Pattern::Err(Box::new(read_pattern(r)?)),
+        10 => {
+            let n = read_u64(r)?;
+            let mut fields = vec![];
+            for _ in 0..n {
+                let name = read_str(r)?;
+                fields.push((name, read_pattern(r)?));
+            }
+            Pattern::Object(fields)
+        }
+        11 => {
+            let enum_name = read_str(r)?;
+            let name = read_str(r)?;
+            let n = read_u64(r)?;
+            let mut pats = vec![];
+            for _ in 0..n {pats.push(read_pattern(r)?)};
+            Pattern::Variant(enum_name, name, pats)
+        }
+        _ => return Err(invalid("Invalid pattern")),
+    })
+}
+
+fn read_types<R: Read>(r: &mut R) -> io::Result<Vec<Type>> {
+    let n = read_u64(r)?;
+    let mut tys = vec![];
+    for _ in 0..n {tys.push(read_type(r)?)};
+    Ok(tys)
+}
+
+fn read_type<R: Read>(r: &mut R) -> io::Result<Type> {
+    use Type::*;
+
+    Ok(match read_u8(r)? {
+        0 => Unreachable,
+        1 => Void,
+        2 => Any,
+        3 => Bool,
+        4 => F64,
+        5 => Vec4,
+        6 => Mat4,
+        7 => Str,
+        8 => Link,
+        9 => Object,
+        10 => Array(Box::new(read_type(r)?)),
+        11 => Option(Box::new(read_type(r)?)),
+        12 => Result(Box::new(read_type(r)?)),
+        13 => Secret(Box::new(read_type(r)?)),
+        14 => Thread(Box::new(read_type(r)?)),
+        15 => In(Box::new(read_type(r)?)),
+        16 => {
+            let name = read_str(r)?;
+            AdHoc(name, Box::new(read_type(r)?))
+        }
+        17 => Closure(Box::new(read_dfn(r)?)),
+        18 => I64,
+        19 => Bytes,
+        20 => {
+            let key = read_type(r)?;
+            Map(Box::new(key), Box::new(read_type(r)?))
+        }
+        21 => Set(Box::new(read_type(r)?)),
+        22 => Coroutine(Box::new(read_type(r)?)),
+        23 => Out(Box::new(read_type(r)?)),
+        24 => Atomic,
+        25 => Mutex(Box::new(read_type(r)?)),
+        _ => return Err(invalid("Invalid type")),
+    })
+}
+
+fn read_dfn<R: Read>(r: &mut R) -> io::Result<Dfn> {
+    let n = read_u64(r)?;
+    let mut lts = vec![];
+    for _ in 0..n {
+        lts.push(match read_u8(r)? {
+            0 => Lt::Arg(read_u64(r)? as usize),
+            1 => Lt::Return,
+            2 => Lt::Default,
+            _ => return Err(invalid("Invalid lifetime")),
+        });
+    }
+    let tys = read_types(r)?;
+    let ret = read_type(r)?;
+    let n = read_u64(r)?;
+    let mut ext = vec![];
+    for _ in 0..n {
+        let vars = read_strs(r)?;
+        let tys = read_types(r)?;
+        ext.push((vars, tys, read_type(r)?));
+    }
+    Ok(Dfn {lts, tys, ret, ext, lazy: LAZY_NO})
+}
@@ -0,0 +1,198 @@
+//! Debugging of Dyon programs.
+//!
+//! A `DebugHook` set on `Runtime::debug_hook` is called before each statement and expression.
+//! `Debugger` implements breakpoints and stepping on top of the hook,
+//! leaving the user interface to a callback.
+
+use std::sync::Arc;
+use range::Range;
+
+use write::{write_variable, EscapeString};
+use {Runtime, Variable};
+
+/// Called by the runtime before evaluating code.
+pub trait DebugHook: Send {
+    /// Called before evaluating a statement or expression.
+    ///
+    /// Returning an error stops the program with the error message.
+    fn before(&mut self, rt: &Runtime, event: &Event) -> Result<(), String>;
+}
+
+/// Describes code that is about to be evaluated.
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    /// The range in the source of the current function.
+    pub range: Range,
+    /// Whether the code is a statement in a block.
+    pub statement: bool,
+}
+
+/// Tells the debugger what to do after the program is paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Run until the next breakpoint.
+    Continue,
+    /// Pause at the next statement.
+    Into,
+    /// Pause at the next statement in the same or an outer function.
+    Over,
+    /// Pause at the next statement in an outer function.
+    Out,
+    /// Stop the program with an error.
+    Stop,
+}
+
+/// Stores breakpoints by file and line.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    list: Vec<(String, usize)>,
+}
+
+impl Breakpoints {
+    /// Creates an empty list of breakpoints.
+    pub fn new() -> Breakpoints {Breakpoints::default()}
+
+    /// Adds a breakpoint at a line, starting at 1.
+    ///
+    /// The file matches any file path ending with it.
+    pub fn add(&mut self, file: &str, line: usize) {
+        if !self.list.iter().any(|b| b.0 == file && b.1 == line) {
+            self.list.push((file.into(), line));
+        }
+    }
+
+    /// Removes a breakpoint, returning `true` if it existed.
+    pub fn remove(&mut self, file: &str, line: usize) -> bool {
+        let n = self.list.len();
+        self.list.retain(|b| b.0 != file || b.1 != line);
+        self.list.len() != n
+    }
+
+    /// Returns the breakpoints as file and line.
+    pub fn list(&self) -> &[(String, usize)] {&self.list}
+
+    /// Returns `true` if there is a breakpoint at a line in a file.
+    pub fn contains(&self, file: &str, line: usize) -> bool {
+        self.list.iter().any(|b| b.1 == line && file.ends_with(&b.0))
+    }
+}
+
+/// Describes where the program is paused.
+#[derive(Debug, Clone)]
+pub struct Pause {
+    /// The file of the current function.
+    pub file: Option<Arc<String>>,
+    /// The line, starting at 1.
+    pub line: usize,
+    /// The column, starting at 1.
+    pub column: usize,
+    /// The range of the statement in the source.
+    pub range: Range,
+    /// The depth of the call stack.
+    pub depth: usize,
+    /// Whether the program paused at a breakpoint.
+    pub breakpoint: bool,
+}
+
+/// Pauses the program at breakpoints and when stepping.
+///
+/// The program pauses only before statements.
+/// When paused, the callback is called to inspect the runtime and decide what to do next.
+pub struct Debugger<F> {
+    /// The breakpoints to pause at.
+    pub breakpoints: Breakpoints,
+    /// What to do until the next pause.
+    pub step: Step,
+    /// The call stack depth when the program was paused last.
+    depth: usize,
+    /// The last statement location, used to pause once per line.
+    last: Option<(usize, Option<Arc<String>>, usize)>,
+    /// Line starts of the last source used.
+    lines: Option<(Arc<String>, Vec<usize>)>,
+    on_pause: F,
+}
+
+impl<F> Debugger<F>
+    where F: FnMut(&mut Breakpoints, &Runtime, &Pause) -> Step + Send
+{
+    /// Creates a new debugger that pauses at the first statement.
+    pub fn new(on_pause: F) -> Debugger<F> {
+        Debugger {
+            breakpoints: Breakpoints::new(),
+            step: Step::Into,
+            depth: 0,
+            last: None,
+            lines: None,
+            on_pause,
+        }
+    }
+
+    /// Computes line and column, starting at 1, of an offset in the source.
+    fn line_column(&mut self, source: &Arc<String>, offset: usize) -> (usize, usize) {
+        let same = match self.lines {
+            Some((ref s, _)) => Arc::ptr_eq(s, source),
+            None => false,
+        };
+        if !same {
+            let mut starts = vec![0];
+            starts.extend(source.char_indices().filter(|c| c.1 == '\n').map(|c| c.0 + 1));
+            self.lines = Some((source.clone(), starts));
+        }
+        let starts = &self.lines.as_ref().unwrap().1;
+        let line = match starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let column = source[starts[line]..offset].chars().count();
+        (line + 1, column + 1)
+    }
+}
+
+impl<F> DebugHook for Debugger<F>
+    where F: FnMut(&mut Breakpoints, &Runtime, &Pause) -> Step + Send
+{
+    fn before(&mut self, rt: &Runtime, event: &Event) -> Result<(), String> {
+        if !event.statement || self.step == Step::Stop {return Ok(())};
+        let call = match rt.call_stack.last() {
+            Some(call) => call,
+            None => return Ok(()),
+        };
+        let depth = rt.call_stack.len();
+        let (line, column) = match rt.call_source(call) {
+            Some(source) => self.line_column(source, event.range.offset),
+            None => (0, 0),
+        };
+        let file = call.file().cloned();
+        let here = Some((depth, file.clone(), line));
+        if self.last == here {return Ok(())};
+        self.last = here;
+
+        let breakpoint = match file {
+            Some(ref file) => self.breakpoints.contains(file, line),
+            None => false,
+        };
+        let pause = breakpoint || match self.step {
+            Step::Continue | Step::Stop => false,
+            Step::Into => true,
+            Step::Over => depth <= self.depth,
+            Step::Out => depth < self.depth,
+        };
+        if !pause {return Ok(())};
+
+        let pause = Pause {file, line, column, range: event.range, depth, breakpoint};
+        self.step = (self.on_pause)(&mut self.breakpoints, rt, &pause);
+        self.depth = depth;
+        if self.step == Step::Stop {
+            Err("Stopped by debugger".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Formats a value for inspection.
+pub fn format_variable(rt: &Runtime, v: &Variable) -> String {
+    let mut buf: Vec<u8> = vec![];
+    write_variable(&mut buf, rt, v, EscapeString::Json, 0).unwrap();
+    String::from_utf8(buf).unwrap()
+}
@@ -84,6 +84,22 @@ fn expr(
         *read = read.consume(range.length);
         return link(read, strings, data);
     }
+    if let Some(range) = read.tag("map") {
+        // Map.
+        *read = read.consume(range.length);
+        return map(read, strings, data);
+    }
+    if let Some(range) = read.tag("set") {
+        // Set.
+        *read = read.consume(range.length);
+        return set(read, strings, data);
+    }
+    if let Some(range) = read.tag("bytes") {
+        // Bytes.
+        *read = read.consume(range.length);
+        opt_w(read);
+        return bytes(read, data);
+    }
     // Text.
     if let Some(range) = read.string() {
         match read.parse_string(range.length) {
@@ -105,6 +121,16 @@ fn expr(
     }
     // Number.
     if let Some(range) = read.number(&NUMBER_SETTINGS) {
+        if let Some(suffix) = read.consume(range.length).tag("i64") {
+            // Integers are parsed from text, since `f64` loses precision above 2^53.
+            return match read.raw_string(range.length).replace('_', "").parse() {
+                Ok(val) => {
+                    *read = read.consume(range.length + suffix.length);
+                    Ok(Variable::I64(val))
+                }
+                Err(_) => Err(error(range, "Expected integer in range of `i64`", data)),
+            }
+        }
         match read.parse_number(&NUMBER_SETTINGS, range.length) {
             Ok(val) => {
                 *read = read.consume(range.length);
@@ -139,9 +165,87 @@ fn expr(
             Err(error(read.start(), "Expected `)`", data))
         }
     }
+    // Enum variant.
+    let (range, _) = read.until_any_or_whitespace(SEPS);
+    if range.length > 0 {
+        let enum_name = Arc::new(read.raw_string(range.length));
+        *read = read.consume(range.length);
+        return variant(enum_name, read, strings, data);
+    }
     Err(error(read.start(), "Reached end of file", data))
 }
 
+fn bytes(read: &mut ReadToken, data: &str) -> Result<Variable, String> {
+    use super::from_hex;
+
+    if let Some(range) = read.string() {
+        match read.parse_string(range.length) {
+            Ok(s) => match from_hex(&s) {
+                Ok(bytes) => {
+                    *read = read.consume(range.length);
+                    Ok(Variable::Bytes(Arc::new(bytes)))
+                }
+                Err(err) => Err(error(range, &err, data)),
+            },
+            Err(err_range) => {
+                let (range, err) = err_range.decouple();
+                Err(error(range, &format!("{}", err), data))
+            }
+        }
+    } else {
+        Err(error(read.start(), "Expected hex string", data))
+    }
+}
+
+fn variant(
+    enum_name: Arc<String>,
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    data: &str
+) -> Result<Variable, String> {
+    use Variant;
+
+    if let Some(range) = read.tag("::") {
+        *read = read.consume(range.length);
+    } else {
+        return Err(error(read.start(), "Expected `::`", data));
+    }
+
+    let (range, _) = read.until_any_or_whitespace(SEPS);
+    if range.length == 0 {
+        return Err(error(range, "Expected variant", data));
+    }
+    let name = Arc::new(read.raw_string(range.length));
+    *read = read.consume(range.length);
+
+    opt_w(read);
+
+    if let Some(range) = read.tag("(") {
+        *read = read.consume(range.length);
+    } else {
+        return Err(error(read.start(), "Expected `(`", data));
+    }
+
+    let mut args = vec![];
+    let mut was_comma = false;
+    loop {
+        opt_w(read);
+
+        if let Some(range) = read.tag(")") {
+            *read = read.consume(range.length);
+            break;
+        }
+
+        if !args.is_empty() && !was_comma {
+            return Err(error(read.start(), "Expected `,`", data));
+        }
+
+        args.push(expr(read, strings, data)?);
+        was_comma = comma(read);
+    }
+    Ok(Variable::Variant(Box::new(Variant {enum_name, name, args})))
+}
+
 fn object(
     read: &mut ReadToken,
     strings: &mut Strings,
@@ -238,6 +342,94 @@ fn array(
     Ok(Variable::Array(Arc::new(res)))
 }
 
+fn map(
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    data: &str
+) -> Result<Variable, String> {
+    use std::collections::HashMap;
+
+    opt_w(read);
+
+    if let Some(range) = read.tag("{") {
+        *read = read.consume(range.length);
+    } else {
+        return Err(error(read.start(), "Expected `{`", data));
+    }
+
+    let mut res = HashMap::new();
+    let mut was_comma = false;
+    loop {
+        opt_w(read);
+
+        if let Some(range) = read.tag("}") {
+            *read = read.consume(range.length);
+            break;
+        }
+
+        if !res.is_empty() && !was_comma {
+            return Err(error(read.start(), "Expected `,`", data));
+        }
+
+        let start = read.start();
+        let key = expr(read, strings, data)?;
+        if !key.is_hashable() {
+            return Err(error(start, "Expected hashable key", data));
+        }
+        opt_w(read);
+        if let Some(range) = read.tag(":") {
+            *read = read.consume(range.length);
+        } else {
+            return Err(error(read.start(), "Expected `:`", data));
+        }
+        opt_w(read);
+        let val = expr(read, strings, data)?;
+        res.insert(key, val);
+        was_comma = comma(read);
+    }
+    Ok(Variable::Map(Arc::new(res)))
+}
+
+fn set(
+    read: &mut ReadToken,
+    strings: &mut Strings,
+    data: &str
+) -> Result<Variable, String> {
+    use std::collections::HashSet;
+
+    opt_w(read);
+
+    if let Some(range) = read.tag("{") {
+        *read = read.consume(range.length);
+    } else {
+        return Err(error(read.start(), "Expected `{`", data));
+    }
+
+    let mut res = HashSet::new();
+    let mut was_comma = false;
+    loop {
+        opt_w(read);
+
+        if let Some(range) = read.tag("}") {
+            *read = read.consume(range.length);
+            break;
+        }
+
+        if !res.is_empty() && !was_comma {
+            return Err(error(read.start(), "Expected `,`", data));
+        }
+
+        let start = read.start();
+        let val = expr(read, strings, data)?;
+        if !val.is_hashable() {
+            return Err(error(start, "Expected hashable value", data));
+        }
+        res.insert(val);
+        was_comma = comma(read);
+    }
+    Ok(Variable::Set(Arc::new(res)))
+}
+
 fn link(
     read: &mut ReadToken,
     strings: &mut Strings,
@@ -60,10 +60,15 @@ fn min_ref(v: &Variable, min: &mut Option<usize>) {
         Return => {}
         Bool(_, _) => {}
         F64(_, _) => {}
+        I64(_) => {}
         Vec4(_) => {}
         Mat4(_) => {}
         Str(_) => {}
+        Bytes(_) => {}
+        Map(_) => {}
+        Set(_) => {}
         Link(_) => {}
+        Variant(_) => {}
         UnsafeRef(_) => {}
         RustObject(_) => {}
         Option(_) => {}
@@ -74,12 +79,16 @@ fn min_ref(v: &Variable, min: &mut Option<usize>) {
                 min_ref(v, min);
             }
         }
-        Object(ref obj) => {
+        Object(ref obj) | Record(_, ref obj) => {
             for v in obj.values() {
                 min_ref(v, min);
             }
         }
         Closure(_, _) => {}
         In(_) => {}
+        Coroutine(_) => {}
+        Out(_) => {}
+        Atomic(_) => {}
+        Mutex(_) => {}
     }
 }
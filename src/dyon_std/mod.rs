@@ -1,12 +1,13 @@
 #![allow(non_snake_case)]
 
 use *;
+use runtime::pool::blocking;
 
 mod io;
 mod meta;
 mod data;
 mod lifetimechk;
-mod functions;
+pub(crate) mod functions;
 
 #[cfg(not(feature = "http"))]
 const HTTP_SUPPORT_DISABLED: &'static str = "Http support is disabled";
@@ -14,6 +15,9 @@ const HTTP_SUPPORT_DISABLED: &'static str = "Http support is disabled";
 #[cfg(not(feature = "file"))]
 const FILE_SUPPORT_DISABLED: &'static str = "File support is disabled";
 
+#[cfg(not(feature = "threading"))]
+const THREADING_SUPPORT_DISABLED: &'static str = "Threading support is disabled";
+
 pub(crate) fn and_also(rt: &mut Runtime) -> Result<Variable, String> {
     use Variable::*;
 
@@ -41,8 +45,9 @@ pub(crate) fn less(a: &Variable, b: &Variable) -> Result<Variable, String> {
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => Bool(a < b, sec.clone()),
+        (&I64(a), &I64(b)) => Variable::bool(a < b),
         (&Str(ref a), &Str(ref b)) => Variable::bool(a < b),
-        _ => return Err("Expected `f64` or `str`".into())
+        _ => return Err("Expected `f64`, `i64` or `str`".into())
     })
 }
 
@@ -51,8 +56,9 @@ pub(crate) fn less_or_equal(a: &Variable, b: &Variable) -> Result<Variable, Stri
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => Bool(a <= b, sec.clone()),
+        (&I64(a), &I64(b)) => Variable::bool(a <= b),
         (&Str(ref a), &Str(ref b)) => Variable::bool(a <= b),
-        _ => return Err("Expected `f64` or `str`".into())
+        _ => return Err("Expected `f64`, `i64` or `str`".into())
     })
 }
 
@@ -73,10 +79,16 @@ pub(crate) fn equal(a: &Variable, b: &Variable) -> Result<Variable, String> {
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => Bool(a == b, sec.clone()),
+        (&I64(a), &I64(b)) => Variable::bool(a == b),
         (&Str(ref a), &Str(ref b)) => Variable::bool(a == b),
+        (&Bytes(ref a), &Bytes(ref b)) => Variable::bool(a == b),
         (&Bool(a, ref sec), &Bool(b, _)) => Bool(a == b, sec.clone()),
         (&Vec4(a), &Vec4(b)) => Variable::bool(a == b),
-        (&Object(ref a), &Object(ref b)) => {
+        // Records compare by fields, like objects.
+        (&Object(ref a), &Object(ref b)) |
+        (&Object(ref a), &Record(_, ref b)) |
+        (&Record(_, ref a), &Object(ref b)) |
+        (&Record(_, ref a), &Record(_, ref b)) => {
             Variable::bool(a.len() == b.len() &&
             a.iter().all(|a| {
                 if let Some(b_val) = b.get(a.0) {
@@ -93,11 +105,22 @@ pub(crate) fn equal(a: &Variable, b: &Variable) -> Result<Variable, String> {
                     equal(a, b) {true} else {false}
             }))
         }
+        (&Map(ref a), &Map(ref b)) => Variable::bool(a == b),
+        (&Set(ref a), &Set(ref b)) => Variable::bool(a == b),
         (&Option(None), &Option(None)) => Variable::bool(true),
         (&Option(None), &Option(_)) => Variable::bool(false),
         (&Option(_), &Option(None)) => Variable::bool(false),
         (&Option(Some(ref a)), &Option(Some(ref b))) => equal(a, b)?,
-        _ => return Err("Expected `f64`, `str`, `bool`, `vec4`, `{}`, `[]` or `opt`".into())
+        (&Variant(ref a), &Variant(ref b)) => {
+            Variable::bool(a.enum_name == b.enum_name && a.name == b.name &&
+            a.args.len() == b.args.len() &&
+            a.args.iter().zip(b.args.iter()).all(|(a, b)| {
+                if let Ok(Variable::Bool(true, _)) =
+                    equal(a, b) {true} else {false}
+            }))
+        }
+        _ => return Err("Expected `f64`, `i64`, `str`, `bytes`, `bool`, `vec4`, `{}`, `map`, `set`, \
+                         `[]`, `opt` or enum".into())
     })
 }
 
@@ -107,11 +130,17 @@ pub(crate) fn not_equal(a: &Variable, b: &Variable) -> Result<Variable, String>
     })
 }
 
+/// Returns the result of an `i64` operation, or an error on overflow.
+fn checked_i64(val: Option<i64>, op: &str) -> Result<Variable, String> {
+    val.map(Variable::I64).ok_or_else(|| format!("Overflow in `{}` for `i64`", op))
+}
+
 pub(crate) fn add(a: &Variable, b: &Variable) -> Result<Variable, String> {
     use Variable::*;
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => F64(a + b, sec.clone()),
+        (&I64(a), &I64(b)) => checked_i64(a.checked_add(b), "+")?,
         (&Vec4(a), &Vec4(b)) => Vec4(vecmath::vec4_add(a, b)),
         (&Vec4(a), &F64(b, _)) | (&F64(b, _), &Vec4(a)) => {
             let b = b as f32;
@@ -134,8 +163,14 @@ pub(crate) fn add(a: &Variable, b: &Variable) -> Result<Variable, String> {
             res.push_str(b);
             Str(Arc::new(res))
         }
+        (&Bytes(ref a), &Bytes(ref b)) => {
+            let mut res = Vec::with_capacity(a.len() + b.len());
+            res.extend_from_slice(a);
+            res.extend_from_slice(b);
+            Bytes(Arc::new(res))
+        }
         (&Link(ref a), &Link(ref b)) => Link(Box::new(a.add(b))),
-        _ => return Err("Expected `f64`, `vec4`, `mat4`, `bool`, `str` or `link`".into())
+        _ => return Err("Expected `f64`, `i64`, `vec4`, `mat4`, `bool`, `str`, `bytes` or `link`".into())
     })
 }
 
@@ -144,6 +179,7 @@ pub(crate) fn sub(a: &Variable, b: &Variable) -> Result<Variable, String> {
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => F64(a - b, sec.clone()),
+        (&I64(a), &I64(b)) => checked_i64(a.checked_sub(b), "-")?,
         (&Vec4(a), &Vec4(b)) => Vec4(vecmath::vec4_sub(a, b)),
         (&Vec4(a), &F64(b, _)) => {
             let b = b as f32;
@@ -173,7 +209,7 @@ pub(crate) fn sub(a: &Variable, b: &Variable) -> Result<Variable, String> {
                 ]))
         }
         (&Bool(a, ref sec), &Bool(b, _)) => Bool(a && !b, sec.clone()),
-        _ => return Err("Expected `f64`, `vec4`, `mat4` or `bool`".into())
+        _ => return Err("Expected `f64`, `i64`, `vec4`, `mat4` or `bool`".into())
     })
 }
 
@@ -182,6 +218,7 @@ pub(crate) fn mul(a: &Variable, b: &Variable) -> Result<Variable, String> {
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => F64(a * b, sec.clone()),
+        (&I64(a), &I64(b)) => checked_i64(a.checked_mul(b), "*")?,
         (&Vec4(a), &Vec4(b)) => Vec4(vecmath::vec4_mul(a, b)),
         (&Vec4(a), &F64(b, _)) | (&F64(b, _), &Vec4(a)) => {
             let b = b as f32;
@@ -199,7 +236,7 @@ pub(crate) fn mul(a: &Variable, b: &Variable) -> Result<Variable, String> {
         }
         (&Mat4(ref a), &Vec4(b)) => Vec4(vecmath::col_mat4_transform(**a, b)),
         (&Bool(a, ref sec), &Bool(b, _)) => Bool(a && b, sec.clone()),
-        _ => return Err("Expected `f64`, `vec4`, `mat4` or `bool`".into())
+        _ => return Err("Expected `f64`, `i64`, `vec4`, `mat4` or `bool`".into())
     })
 }
 
@@ -208,6 +245,8 @@ pub(crate) fn div(a: &Variable, b: &Variable) -> Result<Variable, String> {
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => F64(a / b, sec.clone()),
+        (&I64(_), &I64(0)) => return Err("Division by zero".into()),
+        (&I64(a), &I64(b)) => checked_i64(a.checked_div(b), "/")?,
         (&Vec4(a), &Vec4(b)) => Vec4([a[0] / b[0], a[1] / b[1], a[2] / b[2], a[3] / b[3]]),
         (&Vec4(a), &F64(b, _)) => {
             let b = b as f32;
@@ -217,7 +256,7 @@ pub(crate) fn div(a: &Variable, b: &Variable) -> Result<Variable, String> {
             let a = a as f32;
             Vec4([a / b[0], a / b[1], a / b[2], a / b[3]])
         }
-        _ => return Err("Expected `f64` or `vec4`".into())
+        _ => return Err("Expected `f64`, `i64` or `vec4`".into())
     })
 }
 
@@ -226,6 +265,8 @@ pub(crate) fn rem(a: &Variable, b: &Variable) -> Result<Variable, String> {
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => F64(a % b, sec.clone()),
+        (&I64(_), &I64(0)) => return Err("Division by zero".into()),
+        (&I64(a), &I64(b)) => checked_i64(a.checked_rem(b), "%")?,
         (&Vec4(a), &Vec4(b)) => Vec4([a[0] % b[0], a[1] % b[1], a[2] % b[2], a[3] % b[3]]),
         (&Vec4(a), &F64(b, _)) => {
             let b = b as f32;
@@ -235,7 +276,7 @@ pub(crate) fn rem(a: &Variable, b: &Variable) -> Result<Variable, String> {
             let a = a as f32;
             Vec4([a % b[0], a % b[1], a % b[2], a % b[3]])
         }
-        _ => return Err("Expected `f64` or `vec4`".into())
+        _ => return Err("Expected `f64`, `i64` or `vec4`".into())
     })
 }
 
@@ -244,6 +285,9 @@ pub(crate) fn pow(a: &Variable, b: &Variable) -> Result<Variable, String> {
 
     Ok(match (a, b) {
         (&F64(a, ref sec), &F64(b, _)) => F64(a.powf(b), sec.clone()),
+        (&I64(_), &I64(b)) if b < 0 => return Err("Expected non-negative exponent for `i64`".into()),
+        (&I64(a), &I64(b)) => checked_i64(
+            if b > i64::from(u32::MAX) {None} else {a.checked_pow(b as u32)}, "^")?,
         (&Vec4(a), &Vec4(b)) => Vec4([a[0].powf(b[0]), a[1].powf(b[1]),
                                       a[2].powf(b[2]), a[3].powf(b[3])]),
         (&Vec4(a), &F64(b, _)) => {
@@ -255,7 +299,54 @@ pub(crate) fn pow(a: &Variable, b: &Variable) -> Result<Variable, String> {
             Vec4([a.powf(b[0]), a.powf(b[1]), a.powf(b[2]), a.powf(b[3])])
         }
         (&Bool(a, ref sec), &Bool(ref b, _)) => Bool(a ^ b, sec.clone()),
-        _ => return Err("Expected `f64`, `vec4` or `bool`".into())
+        _ => return Err("Expected `f64`, `i64`, `vec4` or `bool`".into())
+    })
+}
+
+pub(crate) fn bit_and(a: &Variable, b: &Variable) -> Result<Variable, String> {
+    Ok(match (a, b) {
+        (&Variable::I64(a), &Variable::I64(b)) => Variable::I64(a & b),
+        _ => return Err("Expected `i64`".into())
+    })
+}
+
+pub(crate) fn bit_or(a: &Variable, b: &Variable) -> Result<Variable, String> {
+    Ok(match (a, b) {
+        (&Variable::I64(a), &Variable::I64(b)) => Variable::I64(a | b),
+        _ => return Err("Expected `i64`".into())
+    })
+}
+
+pub(crate) fn xor(a: &Variable, b: &Variable) -> Result<Variable, String> {
+    use Variable::*;
+
+    Ok(match (a, b) {
+        (&I64(a), &I64(b)) => I64(a ^ b),
+        (&Bool(a, ref sec), &Bool(b, _)) => Bool(a ^ b, sec.clone()),
+        _ => return Err("Expected `i64` or `bool`".into())
+    })
+}
+
+/// Checks that a shift is less than the number of bits in `i64`.
+fn shift_i64(b: i64, op: &str) -> Result<u32, String> {
+    if !(0..64).contains(&b) {
+        Err(format!("Shift by `{}` is out of range in `{}` for `i64`", b, op))
+    } else {
+        Ok(b as u32)
+    }
+}
+
+pub(crate) fn shl(a: &Variable, b: &Variable) -> Result<Variable, String> {
+    Ok(match (a, b) {
+        (&Variable::I64(a), &Variable::I64(b)) => Variable::I64(a << shift_i64(b, "<<")?),
+        _ => return Err("Expected `i64`".into())
+    })
+}
+
+pub(crate) fn shr(a: &Variable, b: &Variable) -> Result<Variable, String> {
+    Ok(match (a, b) {
+        (&Variable::I64(a), &Variable::I64(b)) => Variable::I64(a >> shift_i64(b, ">>")?),
+        _ => return Err("Expected `i64`".into())
     })
 }
 
@@ -269,6 +360,7 @@ pub(crate) fn not(a: &Variable) -> Result<Variable, String> {
 pub(crate) fn neg(a: &Variable) -> Result<Variable, String> {
     Ok(match *a {
         Variable::F64(v, ref sec) => Variable::F64(-v, sec.clone()),
+        Variable::I64(v) => checked_i64(v.checked_neg(), "-")?,
         Variable::Vec4(v) => Variable::Vec4([-v[0], -v[1], -v[2], -v[3]]),
         Variable::Mat4(ref m) => Variable::Mat4(Box::new([
                 [-m[0][0], -m[0][1], -m[0][2], -m[0][3]],
@@ -276,7 +368,7 @@ pub(crate) fn neg(a: &Variable) -> Result<Variable, String> {
                 [-m[2][0], -m[2][1], -m[2][2], -m[2][3]],
                 [-m[3][0], -m[3][1], -m[3][2], -m[3][3]],
             ])),
-        _ => return Err("Expected `f64`, `vec4` or `mat4`".into())
+        _ => return Err("Expected `f64`, `i64`, `vec4` or `mat4`".into())
     })
 }
 
@@ -534,13 +626,28 @@ dyon_fn!{fn round(a: f64) -> f64 {a.round()}}
 dyon_fn!{fn abs(a: f64) -> f64 {a.abs()}}
 dyon_fn!{fn floor(a: f64) -> f64 {a.floor()}}
 dyon_fn!{fn ceil(a: f64) -> f64 {a.ceil()}}
+dyon_fn!{fn _f64(a: i64) -> f64 {a as f64}}
+
+pub(crate) fn _i64(rt: &mut Runtime) -> Result<Variable, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match *rt.resolve(&v) {
+        // Rounds toward zero, while `NaN` and numbers out of range are errors.
+        Variable::F64(x, _) if x.trunc() >= -9_223_372_036_854_775_808.0 &&
+                               x.trunc() < 9_223_372_036_854_775_808.0 => Variable::I64(x as i64),
+        Variable::F64(x, _) => return Err({
+            rt.arg_err_index.set(Some(0));
+            format!("Can not convert `{}` to `i64`", x)
+        }),
+        ref x => return Err(rt.expected_arg(0, x, "f64"))
+    })
+}
 dyon_fn!{fn sleep(v: f64) {
     use std::thread::sleep;
     use std::time::Duration;
 
     let secs = v as u64;
     let nanos = (v.fract() * 1.0e9) as u32;
-    sleep(Duration::new(secs, nanos));
+    blocking(|| sleep(Duration::new(secs, nanos)));
 }}
 
 pub(crate) fn head(rt: &mut Runtime) -> Result<Variable, String> {
@@ -594,7 +701,10 @@ dyon_fn!{fn tau() -> f64 {6.283_185_307_179_586}}
 pub(crate) fn len(a: &Variable) -> Result<Variable, String> {
     match a {
         &Variable::Array(ref arr) => Ok(Variable::f64(arr.len() as f64)),
-        _ => return Err("Expected array".into())
+        &Variable::Bytes(ref b) => Ok(Variable::f64(b.len() as f64)),
+        &Variable::Map(ref map) => Ok(Variable::f64(map.len() as f64)),
+        &Variable::Set(ref set) => Ok(Variable::f64(set.len() as f64)),
+        _ => return Err("Expected array, bytes, map or set".into())
     }
 }
 
@@ -692,11 +802,20 @@ pub(crate) fn insert(rt: &mut Runtime) -> Result<(), String> {
     let item = rt.stack.pop().expect(TINVOTS);
     let item = rt.resolve(&item).deep_clone(&rt.stack);
     let index = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = v {
+        if let Variable::Map(_) = rt.stack[ind] {
+            let key = hash_key(rt, &index, 1)?;
+            if let Variable::Map(ref mut map) = rt.stack[ind] {
+                Arc::make_mut(map).insert(key, item);
+            }
+            return Ok(());
+        }
+    }
     let index = match rt.resolve(&index) {
         &Variable::F64(index, _) => index,
         x => return Err(rt.expected_arg(1, x, "number"))
     };
-    let v = rt.stack.pop().expect(TINVOTS);
 
     if let Variable::Ref(ind) = v {
         if let Variable::Array(ref arr) = rt.stack[ind] {
@@ -714,13 +833,13 @@ pub(crate) fn insert(rt: &mut Runtime) -> Result<(), String> {
         if !ok {
             return Err({
                 rt.arg_err_index.set(Some(0));
-                "Expected reference to array".into()
+                "Expected reference to array or map".into()
             })
         }
     } else {
         return Err({
             rt.arg_err_index.set(Some(0));
-            "Expected reference to array".into()
+            "Expected reference to array or map".into()
         })
     }
     Ok(())
@@ -760,11 +879,26 @@ pub(crate) fn pop(rt: &mut Runtime) -> Result<Variable, String> {
 
 pub(crate) fn remove(rt: &mut Runtime) -> Result<Variable, String> {
     let index = rt.stack.pop().expect(TINVOTS);
+    let arr = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = arr {
+        match rt.stack[ind] {
+            Variable::Map(_) | Variable::Set(_) => {
+                let key = hash_key(rt, &index, 1)?;
+                return Ok(match rt.stack[ind] {
+                    Variable::Map(ref mut map) =>
+                        Variable::Option(Arc::make_mut(map).remove(&key).map(Box::new)),
+                    Variable::Set(ref mut set) =>
+                        Variable::bool(Arc::make_mut(set).remove(&key)),
+                    _ => unreachable!()
+                });
+            }
+            _ => {}
+        }
+    }
     let index = match rt.resolve(&index) {
         &Variable::F64(index, _) => index,
         x => return Err(rt.expected_arg(1, x, "number"))
     };
-    let arr = rt.stack.pop().expect(TINVOTS);
     if let Variable::Ref(ind) = arr {
         if let Variable::Array(ref arr) = rt.stack[ind] {
             let index = index as usize;
@@ -778,12 +912,12 @@ pub(crate) fn remove(rt: &mut Runtime) -> Result<Variable, String> {
         };
         return Err({
             rt.arg_err_index.set(Some(0));
-            "Expected reference to array".into()
+            "Expected reference to array, map or set".into()
         })
     } else {
         return Err({
             rt.arg_err_index.set(Some(0));
-            "Expected reference to array".into()
+            "Expected reference to array, map or set".into()
         })
     }
 }
@@ -988,14 +1122,20 @@ pub(crate) fn _typeof(rt: &mut Runtime) -> Result<Variable, String> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Str(match *rt.resolve(&v) {
         Str(_) => TEXT_TYPE.clone(),
+        Bytes(_) => BYTES_TYPE.clone(),
         F64(_, _) => F64_TYPE.clone(),
+        I64(_) => I64_TYPE.clone(),
         Vec4(_) => VEC4_TYPE.clone(),
         Mat4(_) => MAT4_TYPE.clone(),
         Return => RETURN_TYPE.clone(),
         Bool(_, _) => BOOL_TYPE.clone(),
         Object(_) => OBJECT_TYPE.clone(),
+        Record(ref name, _) => name.clone(),
+        Map(_) => MAP_TYPE.clone(),
+        Set(_) => SET_TYPE.clone(),
         Array(_) => ARRAY_TYPE.clone(),
         Link(_) => LINK_TYPE.clone(),
+        Variant(ref v) => v.enum_name.clone(),
         Ref(_) => REF_TYPE.clone(),
         UnsafeRef(_) => UNSAFE_REF_TYPE.clone(),
         RustObject(_) => RUST_OBJECT_TYPE.clone(),
@@ -1004,6 +1144,10 @@ pub(crate) fn _typeof(rt: &mut Runtime) -> Result<Variable, String> {
         Thread(_) => THREAD_TYPE.clone(),
         Closure(_, _) => CLOSURE_TYPE.clone(),
         In(_) => IN_TYPE.clone(),
+        Coroutine(_) => COROUTINE_TYPE.clone(),
+        Out(_) => OUT_TYPE.clone(),
+        Atomic(_) => ATOMIC_TYPE.clone(),
+        Mutex(_) => MUTEX_TYPE.clone(),
     }))
 }
 
@@ -1633,6 +1777,65 @@ pub(crate) fn save__string_file(_: &mut Runtime) -> Result<Variable, String> {
     Err(FILE_SUPPORT_DISABLED.into())
 }
 
+#[cfg(feature = "file")]
+dyon_fn!{fn save__bytes_file(b: Arc<Vec<u8>>, file: Arc<String>) -> Variable {
+    use std::fs::File;
+    use std::io::Write;
+
+    Variable::Result(match File::create(&**file) {
+        Ok(mut f) => {
+            match f.write_all(&b) {
+                Ok(_) => Ok(Box::new(Variable::Str(file))),
+                Err(err) => Err(Box::new(Error {
+                    message: Variable::Str(Arc::new(err.to_string().into())),
+                    trace: vec![]
+                }))
+            }
+        }
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err.to_string().into())),
+            trace: vec![]
+        }))
+    })
+}}
+
+#[cfg(not(feature = "file"))]
+pub(crate) fn save__bytes_file(_: &mut Runtime) -> Result<Variable, String> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "file")]
+dyon_fn!{fn load_bytes__file(file: Arc<String>) -> Variable {
+    use std::fs::File;
+    use std::io::Read;
+
+    Variable::Result(match File::open(&**file) {
+        Ok(mut f) => {
+            let mut b = vec![];
+            match f.read_to_end(&mut b) {
+                Ok(_) => {
+                    Ok(Box::new(Variable::Bytes(Arc::new(b))))
+                }
+                Err(err) => {
+                    Err(Box::new(Error {
+                        message: Variable::Str(Arc::new(err.to_string().into())),
+                        trace: vec![]
+                    }))
+                }
+            }
+        }
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err.to_string().into())),
+            trace: vec![]
+        }))
+    })
+}}
+
+#[cfg(not(feature = "file"))]
+pub(crate) fn load_bytes__file(_: &mut Runtime) -> Result<Variable, String> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
 #[cfg(feature = "file")]
 dyon_fn!{fn load_string__file(file: Arc<String>) -> Variable {
     use std::fs::File;
@@ -1679,27 +1882,30 @@ dyon_fn!{fn load_string__url(url: Arc<String>) -> Variable {
     })
 }}
 
-pub(crate) fn join__thread(rt: &mut Runtime) -> Result<Variable, String> {
-    let thread = rt.stack.pop().expect(TINVOTS);
+/// Converts the result of a thread to a Dyon result.
+fn thread_result(
+    res: Result<Result<Variable, String>, Box<dyn std::any::Any + Send>>
+) -> Result<Box<Variable>, Box<Error>> {
+    match res {
+        Ok(Ok(res)) => Ok(Box::new(res)),
+        Ok(Err(err)) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![]
+        })),
+        Err(_err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(
+                "Thread did not exit successfully".into())),
+            trace: vec![]
+        }))
+    }
+}
+
+/// Takes the handle of a thread and waits for its result.
+fn join_thread(rt: &mut Runtime, thread: Variable) -> Variable {
     let handle_res = Thread::invalidate_handle(rt, thread);
-    Ok(Variable::Result({
+    Variable::Result({
         match handle_res {
-            Ok(handle) => {
-                match handle.join() {
-                    Ok(res) => match res {
-                        Ok(res) => Ok(Box::new(res)),
-                        Err(err) => Err(Box::new(Error {
-                            message: Variable::Str(Arc::new(err)),
-                            trace: vec![]
-                        }))
-                    },
-                    Err(_err) => Err(Box::new(Error {
-                        message: Variable::Str(Arc::new(
-                            "Thread did not exit successfully".into())),
-                        trace: vec![]
-                    }))
-                }
-            }
+            Ok(handle) => thread_result(handle.join()),
             Err(err) => {
                 Err(Box::new(Error {
                     message: Variable::Str(Arc::new(err)),
@@ -1707,7 +1913,41 @@ pub(crate) fn join__thread(rt: &mut Runtime) -> Result<Variable, String> {
                 }))
             }
         }
-    }))
+    })
+}
+
+pub(crate) fn join__thread(rt: &mut Runtime) -> Result<Variable, String> {
+    let thread = rt.stack.pop().expect(TINVOTS);
+    Ok(join_thread(rt, thread))
+}
+
+pub(crate) fn join__thread_timeout(rt: &mut Runtime) -> Result<Variable, String> {
+    let timeout = rt.stack.pop().expect(TINVOTS);
+    let timeout = timeout_arg(rt, 1, &timeout)?;
+    let thread = rt.stack.pop().expect(TINVOTS);
+    let handle = match rt.resolve(&thread) {
+        &Variable::Thread(ref th) => th.handle.clone(),
+        x => return Err(rt.expected_arg(0, x, "thread"))
+    };
+    // Wait without taking the handle, such that other copies of the thread keep it.
+    let done = match handle {
+        None => return Err("The Thread has already been invalidated".into()),
+        Some(handle) => handle.lock()
+            .map(|handle| handle.wait_timeout(timeout))
+            .map_err(|err| format!("Can not lock Thread mutex:\n{}", err))?,
+    };
+    if !done {return Ok(Variable::Option(None))};
+    // The thread is finished, so joining returns immediately.
+    Ok(Variable::Option(Some(Box::new(join_thread(rt, thread)))))
+}
+
+pub(crate) fn cancel(rt: &mut Runtime) -> Result<(), String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::Thread(ref th) => th.cancel(),
+        x => return Err(rt.expected_arg(0, x, "thread"))
+    }
+    Ok(())
 }
 
 dyon_fn!{fn load_data__file(file: Arc<String>) -> Variable {
@@ -1849,7 +2089,7 @@ pub(crate) fn has(rt: &mut Runtime) -> Result<Variable, String> {
     };
     let obj = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::bool(match rt.resolve(&obj) {
-        &Variable::Object(ref obj) => obj.contains_key(&key),
+        &Variable::Object(ref obj) | &Variable::Record(_, ref obj) => obj.contains_key(&key),
         x => return Err(rt.expected_arg(0, x, "object"))
     }))
 }
@@ -1857,13 +2097,98 @@ pub(crate) fn has(rt: &mut Runtime) -> Result<Variable, String> {
 pub(crate) fn keys(rt: &mut Runtime) -> Result<Variable, String> {
     let obj = rt.stack.pop().expect(TINVOTS);
     Ok(Variable::Array(Arc::new(match rt.resolve(&obj) {
-        &Variable::Object(ref obj) => {
+        &Variable::Object(ref obj) | &Variable::Record(_, ref obj) => {
             obj.keys().map(|k| Variable::Str(k.clone())).collect()
         }
-        x => return Err(rt.expected_arg(0, x, "object"))
+        &Variable::Map(ref map) => map.keys().cloned().collect(),
+        x => return Err(rt.expected_arg(0, x, "object or map"))
+    })))
+}
+
+pub(crate) fn values(rt: &mut Runtime) -> Result<Variable, String> {
+    let obj = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Array(Arc::new(match rt.resolve(&obj) {
+        &Variable::Object(ref obj) | &Variable::Record(_, ref obj) => {
+            obj.values().map(|v| v.deep_clone(&rt.stack)).collect()
+        }
+        &Variable::Map(ref map) => map.values().cloned().collect(),
+        &Variable::Set(ref set) => set.iter().cloned().collect(),
+        x => return Err(rt.expected_arg(0, x, "object, map or set"))
     })))
 }
 
+/// Checks that a variable can be used as key in a map or value in a set.
+fn hash_key(rt: &Runtime, key: &Variable, arg: usize) -> Result<Variable, String> {
+    let key = rt.resolve(key).deep_clone(&rt.stack);
+    if key.is_hashable() {
+        Ok(key)
+    } else {
+        rt.arg_err_index.set(Some(arg));
+        Err(format!("{}\nExpected `bool`, `f64`, `i64`, `str`, `bytes`, `vec4` or `[]` as key, \
+                     found `{}`", rt.stack_trace(), key.typeof_var()))
+    }
+}
+
+dyon_fn!{fn map() -> Variable {Variable::Map(Arc::new(HashMap::new()))}}
+
+dyon_fn!{fn set() -> Variable {Variable::Set(Arc::new(HashSet::new()))}}
+
+pub(crate) fn insert_set(rt: &mut Runtime) -> Result<(), String> {
+    let item = rt.stack.pop().expect(TINVOTS);
+    let item = hash_key(rt, &item, 1)?;
+    let v = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = v {
+        if let Variable::Set(ref mut set) = rt.stack[ind] {
+            Arc::make_mut(set).insert(item);
+            return Ok(());
+        }
+    }
+    rt.arg_err_index.set(Some(0));
+    Err("Expected reference to set".into())
+}
+
+pub(crate) fn contains(rt: &mut Runtime) -> Result<Variable, String> {
+    let key = rt.stack.pop().expect(TINVOTS);
+    let key = hash_key(rt, &key, 1)?;
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::bool(match rt.resolve(&v) {
+        &Variable::Map(ref map) => map.contains_key(&key),
+        &Variable::Set(ref set) => set.contains(&key),
+        x => return Err(rt.expected_arg(0, x, "map or set"))
+    }))
+}
+
+pub(crate) fn get(rt: &mut Runtime) -> Result<Variable, String> {
+    let key = rt.stack.pop().expect(TINVOTS);
+    let key = hash_key(rt, &key, 1)?;
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Option(match rt.resolve(&v) {
+        &Variable::Map(ref map) => map.get(&key).map(|v| Box::new(v.clone())),
+        x => return Err(rt.expected_arg(0, x, "map"))
+    }))
+}
+
+/// Pops two sets from the runtime stack.
+fn pop_sets(rt: &mut Runtime) -> Result<(Set, Set), String> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let a = rt.stack.pop().expect(TINVOTS);
+    match (rt.resolve(&a), rt.resolve(&b)) {
+        (&Variable::Set(ref a), &Variable::Set(ref b)) => Ok((a.clone(), b.clone())),
+        (&Variable::Set(_), x) => Err(rt.expected_arg(1, x, "set")),
+        (x, _) => Err(rt.expected_arg(0, x, "set")),
+    }
+}
+
+pub(crate) fn union(rt: &mut Runtime) -> Result<Variable, String> {
+    let (a, b) = pop_sets(rt)?;
+    Ok(Variable::Set(Arc::new(a.union(&b).cloned().collect())))
+}
+
+pub(crate) fn intersect(rt: &mut Runtime) -> Result<Variable, String> {
+    let (a, b) = pop_sets(rt)?;
+    Ok(Variable::Set(Arc::new(a.intersection(&b).cloned().collect())))
+}
+
 pub(crate) fn chars(rt: &mut Runtime) -> Result<Variable, String> {
     let t = rt.stack.pop().expect(TINVOTS);
     let t = match rt.resolve(&t) {
@@ -1879,6 +2204,292 @@ pub(crate) fn chars(rt: &mut Runtime) -> Result<Variable, String> {
         .collect::<Vec<_>>())))
 }
 
+pub(crate) fn bytes(rt: &mut Runtime) -> Result<Variable, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(Variable::Bytes(Arc::new(match rt.resolve(&v) {
+        &Variable::Str(ref t) => t.as_bytes().to_vec(),
+        &Variable::Array(ref arr) => {
+            let mut res = Vec::with_capacity(arr.len());
+            for it in arr.iter() {
+                match rt.resolve(it) {
+                    &Variable::F64(x, _) if (0.0..=255.0).contains(&x) && x.fract() == 0.0 =>
+                        res.push(x as u8),
+                    &Variable::F64(x, _) => {
+                        rt.arg_err_index.set(Some(0));
+                        return Err(format!("Expected byte in range `0..256`, found `{}`", x))
+                    }
+                    x => return Err(rt.expected_arg(0, x, "f64"))
+                }
+            }
+            res
+        }
+        x => return Err(rt.expected_arg(0, x, "str or [f64]"))
+    })))
+}
+
+/// Pops bytes from the runtime stack.
+fn pop_bytes(rt: &mut Runtime, arg: usize) -> Result<Arc<Vec<u8>>, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::Bytes(ref b) => Ok(b.clone()),
+        x => Err(rt.expected_arg(arg, x, "bytes"))
+    }
+}
+
+/// Gets an index into bytes.
+///
+/// The index is allowed to be equal to `len`, which is used for ranges.
+fn byte_index(rt: &Runtime, v: &Variable, arg: usize, len: usize) -> Result<usize, String> {
+    match rt.resolve(v) {
+        &Variable::F64(x, _) if x >= 0.0 && x <= len as f64 && x.fract() == 0.0 => Ok(x as usize),
+        &Variable::F64(x, _) => {
+            rt.arg_err_index.set(Some(arg));
+            Err(format!("Out of bounds `{}`", x))
+        }
+        x => Err(rt.expected_arg(arg, x, "f64"))
+    }
+}
+
+pub(crate) fn byte(rt: &mut Runtime) -> Result<Variable, String> {
+    let ind = rt.stack.pop().expect(TINVOTS);
+    let b = pop_bytes(rt, 0)?;
+    let ind = byte_index(rt, &ind, 1, b.len())?;
+    match b.get(ind) {
+        Some(&x) => Ok(Variable::f64(f64::from(x))),
+        None => {
+            rt.arg_err_index.set(Some(1));
+            Err(format!("Out of bounds `{}`", ind))
+        }
+    }
+}
+
+pub(crate) fn slice__bytes_start_end(rt: &mut Runtime) -> Result<Variable, String> {
+    let end = rt.stack.pop().expect(TINVOTS);
+    let start = rt.stack.pop().expect(TINVOTS);
+    let b = pop_bytes(rt, 0)?;
+    let start = byte_index(rt, &start, 1, b.len())?;
+    let end = byte_index(rt, &end, 2, b.len())?;
+    if start > end {
+        rt.arg_err_index.set(Some(1));
+        return Err(format!("Expected start `{}` to be less or equal to end `{}`", start, end));
+    }
+    Ok(Variable::Bytes(Arc::new(b[start..end].to_vec())))
+}
+
+dyon_fn!{fn utf8(b: Arc<Vec<u8>>) -> Variable {
+    Variable::Result(match String::from_utf8((*b).clone()) {
+        Ok(s) => Ok(Box::new(Variable::Str(Arc::new(s)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err.to_string())),
+            trace: vec![]
+        }))
+    })
+}}
+
+/// Decodes hex text, e.g. `ff00`.
+pub(crate) fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text.as_bytes();
+    if digits.len() % 2 != 0 {
+        return Err("Expected an even number of hex digits".into());
+    }
+    let digit = |ch: u8| -> Result<u8, String> {
+        match ch {
+            b'0'..=b'9' => Ok(ch - b'0'),
+            b'a'..=b'f' => Ok(ch - b'a' + 10),
+            b'A'..=b'F' => Ok(ch - b'A' + 10),
+            _ => Err(format!("Expected hex digit, found `{}`", ch as char))
+        }
+    };
+    digits.chunks(2).map(|pair| Ok(digit(pair[0])? << 4 | digit(pair[1])?)).collect()
+}
+
+dyon_fn!{fn hex(b: Arc<Vec<u8>>) -> Arc<String> {
+    use std::fmt::Write;
+
+    let mut res = String::with_capacity(2 * b.len());
+    for x in b.iter() {
+        write!(res, "{:02x}", x).unwrap();
+    }
+    Arc::new(res)
+}}
+
+dyon_fn!{fn bytes__hex(text: Arc<String>) -> Variable {
+    Variable::Result(match from_hex(&text) {
+        Ok(b) => Ok(Box::new(Variable::Bytes(Arc::new(b)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![]
+        }))
+    })
+}}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+dyon_fn!{fn base64(b: Arc<Vec<u8>>) -> Arc<String> {
+    let mut res = String::with_capacity((b.len() + 2) / 3 * 4);
+    for chunk in b.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &x)| n | u32::from(x) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    Arc::new(res)
+}}
+
+/// Decodes base64 text with padding, e.g. `aGk=`.
+fn from_base64(text: &str) -> Result<Vec<u8>, String> {
+    let chars = text.as_bytes();
+    if chars.len() % 4 != 0 {
+        return Err("Expected base64 text with a length divisible by 4".into());
+    }
+    let mut res = Vec::with_capacity(chars.len() / 4 * 3);
+    for (i, chunk) in chars.chunks(4).enumerate() {
+        let last = i + 1 == chars.len() / 4;
+        let pad = chunk.iter().rev().take_while(|&&ch| ch == b'=').count();
+        if pad > 2 || pad > 0 && !last {
+            return Err("Expected `=` only at the end of base64 text".into());
+        }
+        let mut n = 0u32;
+        for &ch in &chunk[..4 - pad] {
+            let digit = match BASE64.iter().position(|&x| x == ch) {
+                Some(digit) => digit as u32,
+                None => return Err(format!("Expected base64 character, found `{}`", ch as char))
+            };
+            n = n << 6 | digit;
+        }
+        n <<= 6 * pad as u32;
+        res.extend_from_slice(&[(n >> 16) as u8, (n >> 8) as u8, n as u8][..3 - pad]);
+    }
+    Ok(res)
+}
+
+dyon_fn!{fn bytes__base64(text: Arc<String>) -> Variable {
+    Variable::Result(match from_base64(&text) {
+        Ok(b) => Ok(Box::new(Variable::Bytes(Arc::new(b)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Str(Arc::new(err)),
+            trace: vec![]
+        }))
+    })
+}}
+
+/// Pops an integer that is packed into bytes.
+fn pop_packed_int(rt: &mut Runtime, min: f64, max: f64, ty: &str) -> Result<f64, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::F64(x, _) if x >= min && x <= max && x.fract() == 0.0 => Ok(x),
+        &Variable::F64(x, _) => {
+            rt.arg_err_index.set(Some(0));
+            Err(format!("Expected integer in range of `{}`, found `{}`", ty, x))
+        }
+        x => Err(rt.expected_arg(0, x, "f64"))
+    }
+}
+
+/// Reads a fixed number of bytes at an offset.
+fn pop_packed<const N: usize>(rt: &mut Runtime) -> Result<[u8; N], String> {
+    let offset = rt.stack.pop().expect(TINVOTS);
+    let b = pop_bytes(rt, 0)?;
+    let offset = byte_index(rt, &offset, 1, b.len())?;
+    if offset + N > b.len() {
+        rt.arg_err_index.set(Some(1));
+        return Err(format!("Out of bounds `{}` when reading {} bytes from {} bytes",
+                           offset, N, b.len()));
+    }
+    let mut res = [0; N];
+    res.copy_from_slice(&b[offset..offset + N]);
+    Ok(res)
+}
+
+dyon_fn!{fn le_f64(x: f64) -> Variable {Variable::Bytes(Arc::new(x.to_le_bytes().to_vec()))}}
+dyon_fn!{fn be_f64(x: f64) -> Variable {Variable::Bytes(Arc::new(x.to_be_bytes().to_vec()))}}
+dyon_fn!{fn le_f32(x: f64) -> Variable {Variable::Bytes(Arc::new((x as f32).to_le_bytes().to_vec()))}}
+dyon_fn!{fn be_f32(x: f64) -> Variable {Variable::Bytes(Arc::new((x as f32).to_be_bytes().to_vec()))}}
+dyon_fn!{fn le_i64(x: i64) -> Variable {Variable::Bytes(Arc::new(x.to_le_bytes().to_vec()))}}
+dyon_fn!{fn be_i64(x: i64) -> Variable {Variable::Bytes(Arc::new(x.to_be_bytes().to_vec()))}}
+
+pub(crate) fn le_i32(rt: &mut Runtime) -> Result<Variable, String> {
+    let x = pop_packed_int(rt, f64::from(i32::MIN), f64::from(i32::MAX), "i32")? as i32;
+    Ok(Variable::Bytes(Arc::new(x.to_le_bytes().to_vec())))
+}
+
+pub(crate) fn be_i32(rt: &mut Runtime) -> Result<Variable, String> {
+    let x = pop_packed_int(rt, f64::from(i32::MIN), f64::from(i32::MAX), "i32")? as i32;
+    Ok(Variable::Bytes(Arc::new(x.to_be_bytes().to_vec())))
+}
+
+pub(crate) fn le_u32(rt: &mut Runtime) -> Result<Variable, String> {
+    let x = pop_packed_int(rt, 0.0, f64::from(u32::MAX), "u32")? as u32;
+    Ok(Variable::Bytes(Arc::new(x.to_le_bytes().to_vec())))
+}
+
+pub(crate) fn be_u32(rt: &mut Runtime) -> Result<Variable, String> {
+    let x = pop_packed_int(rt, 0.0, f64::from(u32::MAX), "u32")? as u32;
+    Ok(Variable::Bytes(Arc::new(x.to_be_bytes().to_vec())))
+}
+
+pub(crate) fn le_u16(rt: &mut Runtime) -> Result<Variable, String> {
+    let x = pop_packed_int(rt, 0.0, f64::from(u16::MAX), "u16")? as u16;
+    Ok(Variable::Bytes(Arc::new(x.to_le_bytes().to_vec())))
+}
+
+pub(crate) fn be_u16(rt: &mut Runtime) -> Result<Variable, String> {
+    let x = pop_packed_int(rt, 0.0, f64::from(u16::MAX), "u16")? as u16;
+    Ok(Variable::Bytes(Arc::new(x.to_be_bytes().to_vec())))
+}
+
+pub(crate) fn le_f64__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from_le_bytes(pop_packed(rt)?)))
+}
+
+pub(crate) fn be_f64__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from_be_bytes(pop_packed(rt)?)))
+}
+
+pub(crate) fn le_f32__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(f32::from_le_bytes(pop_packed(rt)?))))
+}
+
+pub(crate) fn be_f32__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(f32::from_be_bytes(pop_packed(rt)?))))
+}
+
+pub(crate) fn le_i64__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::I64(i64::from_le_bytes(pop_packed(rt)?)))
+}
+
+pub(crate) fn be_i64__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::I64(i64::from_be_bytes(pop_packed(rt)?)))
+}
+
+pub(crate) fn le_i32__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(i32::from_le_bytes(pop_packed(rt)?))))
+}
+
+pub(crate) fn be_i32__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(i32::from_be_bytes(pop_packed(rt)?))))
+}
+
+pub(crate) fn le_u32__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(u32::from_le_bytes(pop_packed(rt)?))))
+}
+
+pub(crate) fn be_u32__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(u32::from_be_bytes(pop_packed(rt)?))))
+}
+
+pub(crate) fn le_u16__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(u16::from_le_bytes(pop_packed(rt)?))))
+}
+
+pub(crate) fn be_u16__bytes_offset(rt: &mut Runtime) -> Result<Variable, String> {
+    Ok(Variable::f64(f64::from(u16::from_be_bytes(pop_packed(rt)?))))
+}
+
 dyon_fn!{fn now() -> f64 {
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -1901,7 +2512,7 @@ pub(crate) fn wait_next(rt: &mut Runtime) -> Result<Variable, String> {
     Ok(match rt.resolve(&v) {
         &Variable::In(ref mutex) => {
             match mutex.lock() {
-                Ok(x) => match x.recv() {
+                Ok(x) => match blocking(|| x.recv()) {
                     Ok(x) => Variable::Option(Some(Box::new(x))),
                     Err(_) => Variable::Option(None),
                 },
@@ -1930,3 +2541,213 @@ pub(crate) fn next(rt: &mut Runtime) -> Result<Variable, String> {
         x => return Err(rt.expected_arg(0, x, "in"))
     })
 }
+
+pub(crate) fn resume(rt: &mut Runtime) -> Result<Variable, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let co = match rt.resolve(&v) {
+        &Variable::Coroutine(ref co) => co.clone(),
+        x => return Err(rt.expected_arg(0, x, "coroutine"))
+    };
+    Ok(Variable::Option(rt.resume(&co)?.map(Box::new)))
+}
+
+pub(crate) fn is_done(rt: &mut Runtime) -> Result<Variable, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Coroutine(ref co) => Variable::bool(co.is_done()),
+        &Variable::Thread(ref th) => Variable::bool(th.is_done()?),
+        x => return Err(rt.expected_arg(0, x, "coroutine or thread"))
+    })
+}
+
+/// Creates an object with the sending and receiving end of a channel.
+fn channel_object(tx: Out, rx: ::std::sync::mpsc::Receiver<Variable>) -> Variable {
+    let mut obj = HashMap::new();
+    obj.insert(Arc::new("tx".into()), Variable::Out(tx));
+    obj.insert(Arc::new("rx".into()), Variable::In(Arc::new(Mutex::new(rx))));
+    Variable::Object(Arc::new(obj))
+}
+
+dyon_fn!{fn channel() -> Variable {
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    channel_object(Out::Unbounded(tx), rx)
+}}
+
+pub(crate) fn channel__capacity(rt: &mut Runtime) -> Result<Variable, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let cap = match rt.resolve(&v) {
+        &Variable::F64(val, _) if val >= 0.0 => val as usize,
+        x => return Err(rt.expected_arg(0, x, "non-negative number"))
+    };
+    let (tx, rx) = ::std::sync::mpsc::sync_channel(cap);
+    Ok(channel_object(Out::Bounded(tx), rx))
+}
+
+pub(crate) fn send(rt: &mut Runtime) -> Result<Variable, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    let tx = rt.stack.pop().expect(TINVOTS);
+    let tx = match rt.resolve(&tx) {
+        &Variable::Out(ref tx) => tx.clone(),
+        x => return Err(rt.expected_arg(0, x, "out"))
+    };
+    // Sending to a channel with capacity waits for free space.
+    Ok(Variable::bool(blocking(|| tx.send(v))))
+}
+
+/// Converts a timeout argument in seconds to a duration.
+fn timeout_arg(rt: &Runtime, arg: usize, v: &Variable) -> Result<std::time::Duration, String> {
+    use std::time::Duration;
+
+    match rt.resolve(v) {
+        &Variable::F64(val, _) => Duration::try_from_secs_f64(val).map_err(|_| {
+            rt.arg_err_index.set(Some(arg));
+            format!("{}\nExpected timeout from 0 to {} seconds, found `{}`",
+                rt.stack_trace(), Duration::MAX.as_secs(), val)
+        }),
+        x => Err(rt.expected_arg(arg, x, "number"))
+    }
+}
+
+pub(crate) fn recv__from_timeout(rt: &mut Runtime) -> Result<Variable, String> {
+    let timeout = rt.stack.pop().expect(TINVOTS);
+    let timeout = timeout_arg(rt, 1, &timeout)?;
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::In(ref mutex) => {
+            match mutex.lock() {
+                Ok(x) => Variable::Option(blocking(|| x.recv_timeout(timeout)).ok().map(Box::new)),
+                Err(err) =>
+                    return Err(format!("Can not lock In mutex:\n{}", err))
+            }
+        }
+        x => return Err(rt.expected_arg(0, x, "in"))
+    })
+}
+
+pub(crate) fn select(rt: &mut Runtime) -> Result<Variable, String> {
+    use std::sync::mpsc::TryRecvError;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    let v = rt.stack.pop().expect(TINVOTS);
+    let mut receivers = vec![];
+    match rt.resolve(&v) {
+        &Variable::Array(ref arr) => {
+            for x in arr.iter() {
+                match rt.resolve(x) {
+                    &Variable::In(ref mutex) => receivers.push(mutex.clone()),
+                    x => return Err(rt.expected_arg(0, x, "[in]"))
+                }
+            }
+        }
+        x => return Err(rt.expected_arg(0, x, "[in]"))
+    }
+    // Poll receivers in order until one has a value or all are disconnected.
+    loop {
+        let mut open = false;
+        for (i, mutex) in receivers.iter().enumerate() {
+            let res = match mutex.lock() {
+                Ok(x) => x.try_recv(),
+                Err(err) => return Err(format!("Can not lock In mutex:\n{}", err))
+            };
+            match res {
+                Ok(x) => {
+                    let mut obj = HashMap::new();
+                    obj.insert(Arc::new("index".into()), Variable::f64(i as f64));
+                    obj.insert(Arc::new("value".into()), x);
+                    return Ok(Variable::Option(Some(Box::new(
+                        Variable::Object(Arc::new(obj))))))
+                }
+                Err(TryRecvError::Empty) => open = true,
+                Err(TryRecvError::Disconnected) => {}
+            }
+        }
+        if !open {return Ok(Variable::Option(None))};
+        blocking(|| sleep(Duration::from_millis(1)));
+    }
+}
+
+#[cfg(feature = "threading")]
+dyon_fn!{fn atomic(x: f64) -> Variable {
+    use std::sync::atomic::AtomicU64;
+
+    Variable::Atomic(Arc::new(AtomicU64::new(x.to_bits())))
+}}
+
+#[cfg(not(feature = "threading"))]
+pub(crate) fn atomic(_: &mut Runtime) -> Result<Variable, String> {
+    Err(THREADING_SUPPORT_DISABLED.into())
+}
+
+pub(crate) fn load__atomic(rt: &mut Runtime) -> Result<Variable, String> {
+    use std::sync::atomic::Ordering;
+
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Atomic(ref a) => Variable::f64(f64::from_bits(a.load(Ordering::SeqCst))),
+        x => return Err(rt.expected_arg(0, x, "atomic"))
+    })
+}
+
+pub(crate) fn fetch_add(rt: &mut Runtime) -> Result<Variable, String> {
+    use std::sync::atomic::Ordering;
+
+    let x = rt.stack.pop().expect(TINVOTS);
+    let x = match rt.resolve(&x) {
+        &Variable::F64(x, _) => x,
+        x => return Err(rt.expected_arg(1, x, "number"))
+    };
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Atomic(ref a) => {
+            let prev = a.fetch_update(Ordering::SeqCst, Ordering::SeqCst,
+                |bits| Some((f64::from_bits(bits) + x).to_bits()));
+            // The closure always returns a value, so the update can not fail.
+            Variable::f64(f64::from_bits(prev.unwrap_or_else(|bits| bits)))
+        }
+        x => return Err(rt.expected_arg(0, x, "atomic"))
+    })
+}
+
+pub(crate) fn compare_exchange(rt: &mut Runtime) -> Result<Variable, String> {
+    use std::sync::atomic::Ordering;
+
+    let new = rt.stack.pop().expect(TINVOTS);
+    let new = match rt.resolve(&new) {
+        &Variable::F64(x, _) => x,
+        x => return Err(rt.expected_arg(2, x, "number"))
+    };
+    let current = rt.stack.pop().expect(TINVOTS);
+    let current = match rt.resolve(&current) {
+        &Variable::F64(x, _) => x,
+        x => return Err(rt.expected_arg(1, x, "number"))
+    };
+    let v = rt.stack.pop().expect(TINVOTS);
+    Ok(match rt.resolve(&v) {
+        &Variable::Atomic(ref a) => {
+            // Numbers are compared by bits, such that `NaN` can be exchanged.
+            Variable::Result(match a.compare_exchange(current.to_bits(), new.to_bits(),
+                                                      Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(prev) => Ok(Box::new(Variable::f64(f64::from_bits(prev)))),
+                Err(actual) => Err(Box::new(Error {
+                    message: Variable::f64(f64::from_bits(actual)),
+                    trace: vec![]
+                }))
+            })
+        }
+        x => return Err(rt.expected_arg(0, x, "atomic"))
+    })
+}
+
+#[cfg(feature = "threading")]
+pub(crate) fn mutex(rt: &mut Runtime) -> Result<Variable, String> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    Ok(Variable::Mutex(Arc::new(Mutex::new(v))))
+}
+
+#[cfg(not(feature = "threading"))]
+pub(crate) fn mutex(_: &mut Runtime) -> Result<Variable, String> {
+    Err(THREADING_SUPPORT_DISABLED.into())
+}
@@ -90,6 +90,26 @@ impl PopVariable for Arc<String> {
     }
 }
 
+impl PopVariable for Vec<u8> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let Variable::Bytes(ref b) = *var {
+            Ok((&**b).clone())
+        } else {
+            Err(rt.expected(var, "bytes"))
+        }
+    }
+}
+
+impl PopVariable for Arc<Vec<u8>> {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let Variable::Bytes(ref b) = *var {
+            Ok(b.clone())
+        } else {
+            Err(rt.expected(var, "bytes"))
+        }
+    }
+}
+
 impl PopVariable for u32 {
     fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
         if let Variable::F64(n, _) = *var {
@@ -110,6 +130,42 @@ impl PopVariable for usize {
     }
 }
 
+impl PopVariable for i64 {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let Variable::I64(n) = *var {
+            Ok(n)
+        } else {
+            Err(rt.expected(var, "i64"))
+        }
+    }
+}
+
+/// Uses the same bits as `i64`, such that large numbers become negative in Dyon.
+impl PopVariable for u64 {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let Variable::I64(n) = *var {
+            Ok(n as u64)
+        } else {
+            Err(rt.expected(var, "i64"))
+        }
+    }
+}
+
+impl PopVariable for i32 {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let Variable::I64(n) = *var {
+            if n < i64::from(i32::MIN) || n > i64::from(i32::MAX) {
+                Err(format!("{}\nExpected `i64` in range of `i32`, found `{}`",
+                            rt.stack_trace(), n))
+            } else {
+                Ok(n as i32)
+            }
+        } else {
+            Err(rt.expected(var, "i64"))
+        }
+    }
+}
+
 impl PopVariable for f32 {
     fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
         if let Variable::F64(n, _) = *var {
@@ -276,6 +332,19 @@ impl PushVariable for usize {
     fn push_var(&self) -> Variable { Variable::f64(*self as f64) }
 }
 
+impl PushVariable for i64 {
+    fn push_var(&self) -> Variable { Variable::I64(*self) }
+}
+
+/// Uses the same bits as `i64`, such that large numbers become negative in Dyon.
+impl PushVariable for u64 {
+    fn push_var(&self) -> Variable { Variable::I64(*self as i64) }
+}
+
+impl PushVariable for i32 {
+    fn push_var(&self) -> Variable { Variable::I64(i64::from(*self)) }
+}
+
 impl PushVariable for f32 {
     fn push_var(&self) -> Variable { Variable::f64(f64::from(*self)) }
 }
@@ -296,6 +365,14 @@ impl PushVariable for Arc<String> {
     fn push_var(&self) -> Variable { Variable::Str(self.clone()) }
 }
 
+impl PushVariable for Vec<u8> {
+    fn push_var(&self) -> Variable { Variable::Bytes(Arc::new(self.clone())) }
+}
+
+impl PushVariable for Arc<Vec<u8>> {
+    fn push_var(&self) -> Variable { Variable::Bytes(self.clone()) }
+}
+
 impl<T: PushVariable> PushVariable for Option<T> {
     fn push_var(&self) -> Variable {
         Variable::Option(self.as_ref().map(|v| Box::new(v.push_var())))
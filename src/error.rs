@@ -0,0 +1,190 @@
+//! Structured errors reported when loading or running Dyon programs.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use range::Range;
+
+/// An error reported when loading or running a Dyon program.
+///
+/// The `Display` impl writes the same message as the error strings
+/// used internally, such that it can be printed directly to the user.
+#[derive(Debug, Clone)]
+pub enum DyonError {
+    /// Syntax error reported by the parser.
+    Parse(ErrorInfo),
+    /// Error reported by the lifetime or type checker.
+    Check(ErrorInfo),
+    /// Error reported when running a program.
+    Runtime(ErrorInfo),
+    /// Could not read a source file.
+    Io(ErrorInfo),
+}
+
+impl DyonError {
+    /// Returns information about the error.
+    pub fn info(&self) -> &ErrorInfo {
+        match *self {
+            DyonError::Parse(ref info) |
+            DyonError::Check(ref info) |
+            DyonError::Runtime(ref info) |
+            DyonError::Io(ref info) => info
+        }
+    }
+
+    /// Returns the error message without location or call stack.
+    pub fn message(&self) -> &str {&self.info().message}
+}
+
+impl fmt::Display for DyonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.info().text)
+    }
+}
+
+impl Error for DyonError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {self.info().source()}
+}
+
+impl From<DyonError> for String {
+    fn from(err: DyonError) -> String {err.info().text.to_string()}
+}
+
+impl From<RuntimeError> for DyonError {
+    fn from(err: RuntimeError) -> DyonError {DyonError::Runtime(*err.0)}
+}
+
+/// An error reported while running a Dyon program.
+///
+/// Keeps the location and call stack of where the error happened,
+/// also when it is passed to another thread.
+/// External functions report errors as strings, which convert to errors without location.
+#[derive(Debug, Clone)]
+pub struct RuntimeError(Box<ErrorInfo>);
+
+impl RuntimeError {
+    /// Returns information about the error.
+    pub fn info(&self) -> &ErrorInfo {&self.0}
+
+    /// Returns the formatted error message.
+    pub fn text(&self) -> &str {&self.0.text}
+
+    pub(crate) fn info_mut(&mut self) -> &mut ErrorInfo {&mut self.0}
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.text)
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {self.0.source()}
+}
+
+impl From<ErrorInfo> for RuntimeError {
+    fn from(info: ErrorInfo) -> RuntimeError {RuntimeError(Box::new(info))}
+}
+
+impl From<String> for RuntimeError {
+    fn from(text: String) -> RuntimeError {ErrorInfo::from_text(text).into()}
+}
+
+impl<'a> From<&'a str> for RuntimeError {
+    fn from(text: &'a str) -> RuntimeError {String::from(text).into()}
+}
+
+impl From<RuntimeError> for String {
+    fn from(err: RuntimeError) -> String {err.0.text.into()}
+}
+
+/// Stores the location, message and call stack of an error.
+#[derive(Debug, Clone)]
+pub struct ErrorInfo {
+    /// The file where the error happened.
+    pub file: Option<Arc<String>>,
+    /// The location in source, if known.
+    pub location: Option<Location>,
+    /// The error message.
+    pub message: String,
+    /// The Dyon call stack, with the innermost call last.
+    pub call_stack: Vec<String>,
+    /// The formatted error message.
+    text: Box<str>,
+    /// The underlying error, if any.
+    ///
+    /// Boxed twice to keep errors small.
+    source: Option<Arc<Box<dyn Error + Send + Sync>>>,
+}
+
+impl ErrorInfo {
+    /// Creates error information from a formatted error message without location.
+    pub fn from_text(text: String) -> ErrorInfo {
+        ErrorInfo {
+            file: None,
+            location: None,
+            message: text.clone(),
+            call_stack: vec![],
+            text: text.into(),
+            source: None,
+        }
+    }
+
+    /// Creates error information with location in source.
+    pub(crate) fn new(
+        file: Option<Arc<String>>,
+        range: Range,
+        source: &str,
+        message: String,
+        text: String,
+    ) -> ErrorInfo {
+        ErrorInfo {
+            file,
+            location: Some(Location::new(range, source)),
+            message,
+            call_stack: vec![],
+            text: text.into(),
+            source: None,
+        }
+    }
+
+    /// Sets the underlying error.
+    pub(crate) fn with_source<E: Error + Send + Sync + 'static>(mut self, err: E) -> ErrorInfo {
+        self.source = Some(Arc::new(Box::new(err)));
+        self
+    }
+
+    /// Returns the formatted error message.
+    pub fn text(&self) -> &str {&self.text}
+
+    /// Returns the underlying error, if any.
+    pub fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|err| &***err as &(dyn Error + 'static))
+    }
+}
+
+/// Location of an error in source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// The range in source.
+    pub range: Range,
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// Column number in bytes, starting at 1.
+    pub column: usize,
+}
+
+impl Location {
+    /// Computes line and column from range in source.
+    pub fn new(range: Range, source: &str) -> Location {
+        let offset = ::std::cmp::min(range.offset, source.len());
+        let before = &source.as_bytes()[..offset];
+        let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+        let line_start = before.iter().rposition(|&c| c == b'\n').map(|i| i + 1).unwrap_or(0);
+        Location {
+            range,
+            line,
+            column: offset - line_start + 1,
+        }
+    }
+}
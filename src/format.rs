@@ -0,0 +1,1285 @@
+//! Source formatter for Dyon programs.
+//!
+//! Pretty-prints a module from the meta data of the parser, with canonical spacing.
+//! Comments are not part of the meta data, so they are recovered from the source.
+//! The output is parsed again and compared with the original meta data,
+//! such that formatting never changes the meaning of a program.
+
+use std::sync::Arc;
+use piston_meta::{json, MetaData};
+use range::Range;
+
+use {parse_str, DyonError, ErrorInfo};
+
+/// Which form to use for operators that have both an ASCII and a unicode form.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operators {
+    /// Keep the form used in source.
+    Preserve,
+    /// Use ASCII, e.g. `sum` and `and`.
+    Ascii,
+    /// Use unicode, e.g. `∑` and `∧`.
+    Unicode,
+}
+
+/// ASCII and unicode forms of the same operator.
+///
+/// The lazy `&&` and `||` have no unicode form, since `∧` and `∨` are eager.
+const OPERATORS: &[(&str, &str)] = &[
+    ("sum_vec4", "∑vec4"), ("prod_vec4", "∏vec4"),
+    ("sum", "∑"), ("prod", "∏"), ("any", "∃"), ("all", "∀"),
+    ("and", "∧"), ("or", "∨"), ("xor", "⊻"), ("*.", "·"), ("x", "⨯"),
+    ("!=", "¬="), ("!", "¬"),
+];
+
+/// Expressions that are parsed without parentheses on the left side of an operator.
+const LEXPR: &[&str] = &[
+    "closure", "object", "array", "array_fill",
+    "sum_in", "prod_in", "min_in", "max_in", "any_in", "all_in", "sift_in", "link_in",
+    "sum", "prod", "sum_vec4", "prod_vec4", "min", "max", "sift", "any", "all",
+    "vec4_un_loop", "link_for", "block", "mat4", "vec4", "link", "grab", "try_expr",
+    "not", "norm", "interp", "go", "call_closure", "named_call_closure",
+    "call", "named_call", "item",
+];
+
+/// Formats source of a Dyon module.
+///
+/// - file - The name of source file, used in error messages
+/// - source - The data of source file
+/// - operators - The form to use for operators
+pub fn format_str(file: &str, source: &str, operators: Operators) -> Result<String, DyonError> {
+    let data = parse_str(file, source)?;
+    let comments = comments(source, &data);
+    let mut formatter = Formatter {
+        source,
+        operators,
+        comments: &comments,
+        next_comment: 0,
+        out: String::new(),
+        indent: 0,
+        line_start: true,
+        pending_space: false,
+        after_comment: false,
+        item_start: 0,
+    };
+    formatter.document(&tree(&data));
+    let out = formatter.out;
+
+    // Check that the output means the same as the input.
+    let same = match parse_str(file, &out) {
+        Ok(new_data) => {
+            data.iter().map(|d| &d.data).eq(new_data.iter().map(|d| &d.data)) &&
+            comments.iter().map(|c| &source[c.start..c.end])
+                .eq(self::comments(&out, &new_data).iter().map(|c| &out[c.start..c.end]))
+        }
+        Err(_) => false
+    };
+    if same {
+        Ok(out)
+    } else {
+        Err(DyonError::Parse(ErrorInfo::from_text(format!(
+            "In `{}`:\nCould not format without changing the meaning of the program", file))))
+    }
+}
+
+/// Meta data grouped by nodes.
+enum Item {
+    Node(Node),
+    Bool(Arc<String>, bool, Range),
+    F64(Range),
+    Str(Arc<String>, Arc<String>, Range),
+}
+
+struct Node {
+    name: Arc<String>,
+    range: Range,
+    children: Vec<Item>,
+}
+
+impl Node {
+    fn start(&self) -> usize {self.range.offset}
+
+    fn end(&self) -> usize {self.range.offset + self.range.length}
+
+    fn node(&self, name: &str) -> Option<&Node> {
+        self.children.iter().filter_map(|ch| match *ch {
+            Item::Node(ref n) if *n.name == name => Some(n),
+            _ => None
+        }).next()
+    }
+
+    fn nodes<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'b Node> + 'b {
+        self.children.iter().filter_map(move |ch| match *ch {
+            Item::Node(ref n) if *n.name == name => Some(n),
+            _ => None
+        })
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.children.iter().filter_map(|ch| match *ch {
+            Item::Str(ref n, ref val, _) if **n == name => Some(&***val),
+            _ => None
+        }).next()
+    }
+
+    fn bool(&self, name: &str) -> bool {
+        self.children.iter().any(|ch| match *ch {
+            Item::Bool(ref n, val, _) => val && **n == name,
+            _ => false
+        })
+    }
+}
+
+fn tree(data: &[Range<MetaData>]) -> Vec<Item> {
+    let mut stack: Vec<Node> = vec![];
+    let mut top = vec![];
+    for d in data {
+        let item = match d.data {
+            MetaData::StartNode(ref name) => {
+                stack.push(Node {name: name.clone(), range: d.range(), children: vec![]});
+                continue;
+            }
+            MetaData::EndNode(_) => {
+                let mut node = stack.pop().unwrap();
+                node.range = d.range();
+                Item::Node(node)
+            }
+            MetaData::Bool(ref name, val) => Item::Bool(name.clone(), val, d.range()),
+            MetaData::F64(_, _) => Item::F64(d.range()),
+            MetaData::String(ref name, ref val) => Item::Str(name.clone(), val.clone(), d.range()),
+        };
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(item),
+            None => top.push(item),
+        }
+    }
+    top
+}
+
+struct Comment {
+    start: usize,
+    end: usize,
+    /// Whether there is only whitespace before the comment on the same line.
+    own_line: bool,
+}
+
+/// Finds comments in source, skipping strings.
+fn comments(source: &str, data: &[Range<MetaData>]) -> Vec<Comment> {
+    let strings: Vec<Range> = data.iter()
+        .filter(|d| matches!(d.data, MetaData::String(..)))
+        .map(|d| d.range())
+        .collect();
+    let bytes = source.as_bytes();
+    let mut res = vec![];
+    let mut i = 0;
+    let mut k = 0;
+    while i < bytes.len() {
+        while k < strings.len() && strings[k].offset + strings[k].length <= i {k += 1}
+        if k < strings.len() && strings[k].offset <= i {
+            i = strings[k].offset + strings[k].length;
+            continue;
+        }
+        let end = if bytes[i..].starts_with(b"//") {
+            let end = source[i..].find('\n').map(|n| i + n).unwrap_or(source.len());
+            i + source[i..end].trim_end().len()
+        } else if bytes[i..].starts_with(b"/*") {
+            let mut depth = 0;
+            let mut j = i;
+            while j < bytes.len() {
+                if bytes[j..].starts_with(b"/*") {
+                    depth += 1;
+                    j += 2;
+                } else if bytes[j..].starts_with(b"*/") {
+                    depth -= 1;
+                    j += 2;
+                    if depth == 0 {break};
+                } else {
+                    j += 1;
+                }
+            }
+            j
+        } else {
+            i += 1;
+            continue;
+        };
+        let line = source[..i].rfind('\n').map(|n| n + 1).unwrap_or(0);
+        res.push(Comment {
+            start: i,
+            end,
+            own_line: source[line..i].trim().is_empty(),
+        });
+        i = end;
+    }
+    res
+}
+
+/// Returns `true` if there is an empty line right before a position in source.
+fn blank_before(source: &str, pos: usize) -> bool {
+    let before = source[..pos].trim_end();
+    source[before.len()..pos].matches('\n').count() >= 2
+}
+
+/// Returns `true` if a key can be written without quotes.
+fn is_ident(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    operators: Operators,
+    comments: &'a [Comment],
+    next_comment: usize,
+    out: String,
+    indent: usize,
+    /// Whether nothing is written on the current line.
+    line_start: bool,
+    pending_space: bool,
+    after_comment: bool,
+    /// Where the last top level item starts in output.
+    item_start: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn write(&mut self, text: &str) {
+        if self.line_start {
+            for _ in 0..self.indent {self.out.push_str("    ")}
+            self.line_start = false;
+        } else if self.pending_space && !self.out.ends_with(' ') {
+            self.out.push(' ');
+        }
+        self.pending_space = false;
+        self.after_comment = false;
+        self.out.push_str(text);
+    }
+
+    fn write_range(&mut self, range: Range) {
+        let source = self.source;
+        self.write(&source[range.offset..range.offset + range.length]);
+    }
+
+    fn space(&mut self) {
+        if !self.line_start {self.pending_space = true}
+    }
+
+    fn newline(&mut self) {
+        if !self.line_start {
+            let len = self.out.trim_end_matches(' ').len();
+            self.out.truncate(len);
+            self.out.push('\n');
+            self.line_start = true;
+        }
+        self.pending_space = false;
+    }
+
+    fn blank_line(&mut self) {
+        if self.out.is_empty() {return};
+        self.newline();
+        if !self.out.ends_with("\n\n") {self.out.push('\n')}
+    }
+
+    /// Returns `true` if a position in source is preceded by a new line.
+    fn newline_before(&self, pos: usize) -> bool {
+        let before = self.source[..pos].trim_end();
+        self.source[before.len()..pos].contains('\n')
+    }
+
+    fn has_comment_before(&self, pos: usize) -> bool {
+        self.comments.get(self.next_comment).map(|c| c.start < pos).unwrap_or(false)
+    }
+
+    /// Writes comments that come before a position in source.
+    fn flush(&mut self, pos: usize) {
+        while self.has_comment_before(pos) {
+            let c = &self.comments[self.next_comment];
+            self.next_comment += 1;
+            let text = &self.source[c.start..c.end];
+            if c.own_line {
+                let open = {
+                    let out = self.out.trim_end();
+                    out.is_empty() || out.ends_with(|ch| "{[(".contains(ch))
+                };
+                if !open && blank_before(self.source, c.start) {
+                    self.blank_line();
+                } else {
+                    self.newline();
+                }
+            } else {
+                self.space();
+            }
+            self.write(text);
+            if c.own_line || text.starts_with("//") {
+                self.newline();
+            } else {
+                self.space();
+            }
+            self.after_comment = true;
+        }
+    }
+
+    /// Returns the operator form to use.
+    fn spell<'b>(&self, text: &'b str) -> &'b str {
+        for &(ascii, unicode) in OPERATORS {
+            match self.operators {
+                Operators::Ascii if text == unicode => return ascii,
+                Operators::Unicode if text == ascii => return unicode,
+                _ => {}
+            }
+        }
+        text
+    }
+
+    fn binop(&mut self, range: Range) {
+        self.flush(range.offset);
+        let source = self.source;
+        let op = self.spell(&source[range.offset..range.offset + range.length]);
+        // Powers are written without spaces, e.g. `x^2`.
+        if op == "^" {
+            self.write(op);
+        } else {
+            self.space();
+            self.write(op);
+            self.space();
+        }
+    }
+
+    /// Writes the keyword of a loop, in the form used in source.
+    fn keyword(&mut self, n: &Node, ascii: &str) {
+        let unicode = OPERATORS.iter().find(|op| op.0 == ascii).map(|op| op.1);
+        let mut pos = n.start();
+        for ch in &n.children {
+            if let Item::Str(ref name, _, range) = *ch {
+                if **name == "label" {pos = range.offset + range.length};
+            }
+        }
+        let rest = self.source[pos..].trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+        let text = match unicode {
+            Some(unicode) if rest.starts_with(unicode) => unicode,
+            _ => ascii
+        };
+        let text = self.spell(text);
+        self.write(text);
+    }
+
+    /// Separates top level items with an empty line.
+    ///
+    /// Items on a single line are kept together when they are in source.
+    fn top_level(&mut self, pos: usize) {
+        self.flush(pos);
+        if self.out.is_empty() {return};
+        let single_line = !self.out[self.item_start..].trim_end().contains('\n');
+        if (self.after_comment || single_line) && !blank_before(self.source, pos) {
+            self.newline();
+        } else {
+            self.blank_line();
+        }
+        self.item_start = self.out.len();
+    }
+
+    fn document(&mut self, items: &[Item]) {
+        for item in items {
+            let n = match *item {Item::Node(ref n) => n, _ => continue};
+            match &**n.name {
+                "ns" => {
+                    self.top_level(n.start());
+                    self.write("ns ");
+                    self.path(n, "name");
+                }
+                "uses" => {
+                    for (i, u) in n.nodes("use").enumerate() {
+                        if i == 0 {
+                            self.top_level(u.start());
+                        } else {
+                            self.flush(u.start());
+                            if blank_before(self.source, u.start()) {self.blank_line()} else {self.newline()}
+                        }
+                        self.use_decl(u);
+                    }
+                }
+                "fn" => {
+                    self.top_level(n.start());
+                    self.function(n);
+                }
+                "record" => {
+                    self.top_level(n.start());
+                    self.record(n);
+                }
+                "enum" => {
+                    self.top_level(n.start());
+                    self.enum_decl(n);
+                }
+                _ => {}
+            }
+        }
+        self.flush(self.source.len());
+        self.newline();
+    }
+
+    fn path(&mut self, n: &Node, name: &str) {
+        for (i, ch) in n.children.iter().enumerate() {
+            if let Item::Str(ref key, ref val, _) = *ch {
+                if **key != name {continue};
+                if i > 0 {self.write("::")};
+                self.write(val);
+            }
+        }
+    }
+
+    fn use_decl(&mut self, n: &Node) {
+        self.write("use ");
+        self.path(n, "name");
+        if n.string("use_fn").is_some() {
+            self.write("::{");
+            let mut first = true;
+            for ch in &n.children {
+                match *ch {
+                    Item::Str(ref key, ref val, _) if **key == "use_fn" => {
+                        if !first {self.write(", ")};
+                        self.write(val);
+                        first = false;
+                    }
+                    Item::Str(ref key, ref val, _) if **key == "use_fn_alias" => {
+                        self.write(" as ");
+                        self.write(val);
+                    }
+                    _ => {}
+                }
+            }
+            self.write("}");
+        }
+        self.write(" as ");
+        self.write(n.string("alias").unwrap_or(""));
+    }
+
+    fn function(&mut self, n: &Node) {
+        let block = n.node("block");
+        if block.is_some() {self.write("fn ")};
+        self.write(n.string("name").unwrap_or(""));
+        self.write("(");
+        self.args(n);
+        self.write(")");
+        self.currents(n);
+        if n.bool("returns") {
+            self.space();
+            self.write("->");
+            if let Some(ty) = n.node("ret_type") {
+                self.space();
+                self.ty(ty);
+            }
+        }
+        if let Some(block) = block {
+            self.space();
+            self.block(block);
+        }
+        if let Some(expr) = n.node("expr") {
+            self.space();
+            self.write("=");
+            self.space();
+            self.expr(expr);
+        }
+        for ty in n.nodes("ty") {
+            self.flush(ty.start());
+            self.newline();
+            self.fn_ty(ty);
+        }
+    }
+
+    fn record(&mut self, n: &Node) {
+        self.write("type ");
+        self.write(n.string("name").unwrap_or(""));
+        self.write(" = ");
+        let items: Vec<(usize, &Node)> = n.nodes("record_field").map(|f| (f.start(), f)).collect();
+        self.list("{", "}", ",", &items, n.end(), |f, field| {
+            f.write(field.string("name").unwrap_or(""));
+            f.write(": ");
+            if let Some(ty) = field.node("type") {f.ty(ty)};
+        });
+    }
+
+    fn enum_decl(&mut self, n: &Node) {
+        self.write("enum ");
+        self.write(n.string("name").unwrap_or(""));
+        self.space();
+        let items: Vec<(usize, &Node)> = n.nodes("enum_variant").map(|v| (v.start(), v)).collect();
+        self.list("{", "}", ",", &items, n.end(), |f, variant| {
+            f.write(variant.string("name").unwrap_or(""));
+            let args: Vec<&Node> = variant.nodes("variant_arg").collect();
+            if !args.is_empty() {
+                f.write("(");
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {f.write(", ")};
+                    if let Some(ty) = arg.node("type") {f.ty(ty)};
+                }
+                f.write(")");
+            }
+        });
+    }
+
+    fn args(&mut self, n: &Node) {
+        for (i, arg) in n.nodes("arg").enumerate() {
+            if i > 0 {self.write(", ")};
+            self.arg(arg);
+        }
+    }
+
+    fn arg(&mut self, n: &Node) {
+        self.flush(n.start());
+        if n.bool("mut") {self.write("mut ")};
+        self.write(n.string("name").unwrap_or(""));
+        let lifetime = n.string("lifetime");
+        let ty = n.node("type");
+        if lifetime.is_some() || ty.is_some() {self.write(": ")};
+        if let Some(lifetime) = lifetime {
+            self.write("'");
+            self.write(lifetime);
+        }
+        if let Some(ty) = ty {
+            self.space();
+            self.ty(ty);
+        }
+        let mut first = true;
+        for ch in &n.children {
+            let grab = match *ch {
+                Item::Bool(ref name, true, _) if name.ends_with("(_)") => Err(&***name),
+                Item::Node(ref grab) if *grab.name == "grab" => Ok(grab),
+                _ => continue
+            };
+            self.space();
+            self.write(if first {"=>"} else {"|"});
+            self.space();
+            match grab {
+                Ok(grab) => if let Some(expr) = grab.node("expr") {self.expr(expr)},
+                Err(name) => self.write(name),
+            }
+            first = false;
+        }
+    }
+
+    fn currents(&mut self, n: &Node) {
+        for (i, current) in n.nodes("current").enumerate() {
+            if i == 0 {
+                self.space();
+                self.write("~ ");
+            } else {
+                self.write(", ");
+            }
+            if current.bool("mut") {self.write("mut ")};
+            self.write(current.string("name").unwrap_or(""));
+            if let Some(ty) = current.node("type") {
+                self.write(": ");
+                self.ty(ty);
+            }
+        }
+    }
+
+    /// Writes an extra type signature of a function.
+    fn fn_ty(&mut self, n: &Node) {
+        let vars: Vec<&str> = n.children.iter().filter_map(|ch| match *ch {
+            Item::Str(ref name, ref val, _) if **name == "ty_var" => Some(&***val),
+            _ => None
+        }).collect();
+        if !vars.is_empty() {
+            self.write("all ");
+            self.write(&vars.join(", "));
+            self.write(" { ");
+        }
+        self.write("(");
+        for (i, arg) in n.nodes("ty_arg").enumerate() {
+            if i > 0 {self.write(", ")};
+            self.ty(arg);
+        }
+        self.write(") -> ");
+        if let Some(ret) = n.node("ty_ret") {self.ty(ret)};
+        if !vars.is_empty() {self.write(" }")};
+    }
+
+    fn ty(&mut self, n: &Node) {
+        for ch in &n.children {
+            match *ch {
+                Item::Bool(ref name, true, _) => self.write(match &***name {
+                    "opt_any" => "opt",
+                    "res_any" => "res",
+                    "thr_any" => "thr",
+                    "in_any" => "in",
+                    "co_any" => "co",
+                    "out_any" => "out",
+                    "mutex_any" => "mutex",
+                    "arr_any" => "[]",
+                    "obj_any" => "{}",
+                    "map_any" => "map",
+                    "set_any" => "set",
+                    "sec_bool" => "sec[bool]",
+                    "sec_f64" => "sec[f64]",
+                    x => x
+                }),
+                Item::Str(ref name, ref val, _) if **name == "ad_hoc" => self.write(val),
+                Item::Node(ref ty) => match &**ty.name {
+                    "opt" | "res" | "thr" | "in" | "set" | "co" | "out" | "mutex" => {
+                        self.write(&ty.name);
+                        self.write("[");
+                        self.ty(ty);
+                        self.write("]");
+                    }
+                    "arr" => {
+                        self.write("[");
+                        self.ty(ty);
+                        self.write("]");
+                    }
+                    "map_type" => {
+                        self.write("map[");
+                        if let Some(key) = ty.node("map_key") {self.ty(key)};
+                        self.write(", ");
+                        if let Some(val) = ty.node("map_val") {self.ty(val)};
+                        self.write("]");
+                    }
+                    "closure_type" => {
+                        self.write("\\(");
+                        for (i, arg) in ty.nodes("cl_arg").enumerate() {
+                            if i > 0 {self.write(", ")};
+                            self.ty(arg);
+                        }
+                        self.write(") -> ");
+                        if let Some(ret) = ty.node("cl_ret") {self.ty(ret)};
+                    }
+                    "ad_hoc_ty" => {
+                        self.space();
+                        self.ty(ty);
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// Writes items separated by `sep`.
+    ///
+    /// When the first item starts on a new line in source, the items are indented
+    /// and each item starts on a new line if it does so in source.
+    fn list<T: Copy, F>(
+        &mut self,
+        open: &str,
+        close: &str,
+        sep: &str,
+        items: &[(usize, T)],
+        end: usize,
+        mut f: F
+    )
+        where F: FnMut(&mut Formatter<'a>, T)
+    {
+        self.write(open);
+        let multi = items.first().map(|&(start, _)| self.newline_before(start)).unwrap_or(false);
+        if multi {self.indent += 1};
+        for (i, &(start, item)) in items.iter().enumerate() {
+            if i > 0 {self.write(sep)};
+            self.flush(start);
+            if multi && (i == 0 || self.newline_before(start)) {
+                self.newline();
+            } else if i > 0 {
+                self.space();
+            }
+            f(self, item);
+        }
+        self.flush(end);
+        if multi {
+            self.indent -= 1;
+            self.newline();
+        }
+        self.write(close);
+    }
+
+    fn block(&mut self, n: &Node) {
+        let exprs: Vec<&Node> = n.nodes("expr").collect();
+        let end = n.end();
+        self.write("{");
+        if exprs.len() == 1 && !self.newline_before(exprs[0].start()) {
+            let start = self.out.len();
+            self.space();
+            self.expr(exprs[0]);
+            self.flush(end);
+            // Closes nested blocks as `}}`.
+            if !self.out[start..].contains('\n') {self.space()};
+            self.write("}");
+            return;
+        }
+        if exprs.is_empty() && !self.has_comment_before(end) {
+            self.write("}");
+            return;
+        }
+        self.indent += 1;
+        for (i, expr) in exprs.iter().enumerate() {
+            self.flush(expr.start());
+            if i > 0 && blank_before(self.source, expr.start()) {
+                self.blank_line();
+            } else {
+                self.newline();
+            }
+            self.expr(expr);
+        }
+        self.flush(end);
+        self.indent -= 1;
+        self.newline();
+        self.write("}");
+    }
+
+    fn expr(&mut self, n: &Node) {self.wrapped(n, false)}
+
+    /// Writes an expression on the left side of an operator.
+    fn lexpr(&mut self, n: &Node) {self.wrapped(n, true)}
+
+    /// Writes the content of a node that wraps an expression.
+    fn wrapped(&mut self, n: &Node, lexpr: bool) {
+        self.flush(n.start());
+        let mut parens = lexpr && n.children.iter().any(|ch| match *ch {
+            Item::Node(ref ch) => !LEXPR.contains(&&**ch.name),
+            Item::Bool(ref name, _, _) => **name == "return_void",
+            _ => false
+        });
+        if parens {self.write("(")};
+        for ch in &n.children {
+            match *ch {
+                Item::Node(ref ch) => self.expr_node(ch),
+                Item::Bool(ref name, val, range) => {
+                    self.flush(range.offset);
+                    match &***name {
+                        "mut" => self.write("mut "),
+                        "bool" => self.write(if val {"true"} else {"false"}),
+                        "return_void" => self.write("return"),
+                        "try" => {
+                            if parens {
+                                self.write(")");
+                                parens = false;
+                            }
+                            self.write("?");
+                        }
+                        _ => {}
+                    }
+                }
+                Item::F64(range) => {
+                    self.flush(range.offset);
+                    self.write_range(range);
+                }
+                Item::Str(ref name, ref val, range) => {
+                    self.flush(range.offset);
+                    match &***name {
+                        "text" => self.write_range(range),
+                        "i64" => {
+                            self.write_range(range);
+                            self.write("i64");
+                        }
+                        "color" => {
+                            self.write("#");
+                            self.write(val);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if parens {self.write(")")};
+    }
+
+    fn label(&mut self, n: &Node) {
+        if let Some(label) = n.string("label") {
+            self.write("'");
+            self.write(label);
+            self.write(": ");
+        }
+    }
+
+    fn expr_node(&mut self, n: &Node) {
+        self.flush(n.start());
+        match &**n.name {
+            "add" | "mul" | "pow" | "compare" | "assign" => {
+                let mut first = true;
+                for ch in &n.children {
+                    match *ch {
+                        Item::Node(ref ch) => {
+                            match &**ch.name {
+                                "neg" => {
+                                    self.write("-");
+                                    if let Some(expr) = ch.node("expr") {self.expr(expr)};
+                                }
+                                "pow" => self.expr_node(ch),
+                                "left" => self.lexpr(ch),
+                                "expr" if *n.name == "mul" || *n.name == "pow" ||
+                                          *n.name == "compare" && first => self.lexpr(ch),
+                                _ => self.expr(ch),
+                            }
+                            first = false;
+                        }
+                        Item::Bool(_, _, range) => self.binop(range),
+                        _ => {}
+                    }
+                }
+            }
+            "return" => {
+                self.write("return ");
+                self.expr(n);
+            }
+            "yield" => {
+                self.write("yield ");
+                self.expr(n);
+            }
+            "block" => self.block(n),
+            "in" => {
+                self.write("in ");
+                if let Some(alias) = n.string("alias") {
+                    self.write(alias);
+                    self.write("::");
+                }
+                self.write(n.string("name").unwrap_or(""));
+            }
+            "closure" => {
+                self.write("\\(");
+                self.args(n);
+                self.write(")");
+                self.currents(n);
+                self.write(" = ");
+                if let Some(expr) = n.node("expr") {self.expr(expr)};
+            }
+            "object" => {
+                let items: Vec<(usize, &Node)> = n.nodes("key_value").map(|kv| (kv.start(), kv)).collect();
+                self.list("{", "}", ",", &items, n.end(), |f, kv| {
+                    let key = kv.string("key").unwrap_or("");
+                    if is_ident(key) {
+                        f.write(key);
+                    } else {
+                        let mut buf: Vec<u8> = vec![];
+                        json::write_string(&mut buf, key).unwrap();
+                        f.write(&String::from_utf8(buf).unwrap());
+                    }
+                    f.write(": ");
+                    if let Some(val) = kv.node("val") {f.expr(val)};
+                });
+            }
+            "array" => {
+                let items: Vec<(usize, &Node)> = n.nodes("array_item").map(|x| (x.start(), x)).collect();
+                self.list("[", "]", ",", &items, n.end(), |f, x| f.expr(x));
+            }
+            "array_fill" => {
+                self.write("[");
+                if let Some(fill) = n.node("fill") {self.expr(fill)};
+                self.write("; ");
+                if let Some(len) = n.node("n") {self.expr(len)};
+                self.write("]");
+            }
+            "for_in" | "sum_in" | "prod_in" | "min_in" | "max_in" | "any_in" | "all_in" |
+            "sift_in" | "link_in" => {
+                self.label(n);
+                let keyword = if *n.name == "for_in" {"for"} else {&n.name[..n.name.len() - 3]};
+                self.keyword(n, keyword);
+                self.write(" ");
+                self.write(n.string("name").unwrap_or(""));
+                self.write(" in ");
+                if let Some(iter) = n.node("iter") {self.expr(iter)};
+                self.space();
+                if *n.name == "link_in" {
+                    self.link_body(n);
+                } else if let Some(block) = n.node("block") {
+                    self.block(block);
+                }
+            }
+            "for_n" | "sum" | "prod" | "sum_vec4" | "prod_vec4" | "min" | "max" | "sift" |
+            "any" | "all" | "link_for" => {
+                self.label(n);
+                if n.bool("par") {self.write("par ")};
+                let keyword = match &**n.name {
+                    "for_n" => "for",
+                    "link_for" => "link",
+                    x => x
+                };
+                self.keyword(n, keyword);
+                let mut first = true;
+                let mut start = false;
+                for ch in &n.children {
+                    match *ch {
+                        Item::Str(ref name, ref val, _) if **name == "name" => {
+                            if !first {self.write(",")};
+                            self.write(" ");
+                            self.write(val);
+                            first = false;
+                            start = false;
+                        }
+                        Item::Node(ref ch) if *ch.name == "start" => {
+                            self.write(" [");
+                            self.expr(ch);
+                            self.write(", ");
+                            start = true;
+                        }
+                        Item::Node(ref ch) if *ch.name == "end" => {
+                            if start {
+                                self.expr(ch);
+                                self.write(")");
+                            } else {
+                                self.write(" ");
+                                self.expr(ch);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                self.space();
+                if *n.name == "link_for" {
+                    self.link_body(n);
+                } else if let Some(block) = n.node("block") {
+                    self.block(block);
+                }
+            }
+            "vec4_un_loop" => {
+                self.write("vec");
+                for &len in &["4", "3", "2"] {
+                    if n.bool(len) {self.write(len)};
+                }
+                self.write(" ");
+                self.write(n.string("name").unwrap_or(""));
+                self.write(" ");
+                if let Some(expr) = n.node("expr") {self.expr(expr)};
+            }
+            "for" => {
+                self.label(n);
+                self.write("for ");
+                if let Some(init) = n.node("init") {self.expr(init)};
+                self.write("; ");
+                if let Some(cond) = n.node("cond") {self.expr(cond)};
+                self.write("; ");
+                if let Some(step) = n.node("step") {self.expr(step)};
+                self.space();
+                if let Some(block) = n.node("block") {self.block(block)};
+            }
+            "loop" => {
+                self.label(n);
+                self.write("loop ");
+                if let Some(block) = n.node("block") {self.block(block)};
+            }
+            "lock" => {
+                self.write("lock ");
+                self.write(n.string("name").unwrap_or(""));
+                self.write(" := ");
+                if let Some(expr) = n.node("expr") {self.expr(expr)};
+                self.space();
+                if let Some(block) = n.node("block") {self.block(block)};
+            }
+            "if" => {
+                let mut prev_end = n.start();
+                for ch in &n.children {
+                    let ch = match *ch {Item::Node(ref ch) => ch, _ => continue};
+                    match &**ch.name {
+                        "cond" => {
+                            self.write("if ");
+                            self.expr(ch);
+                        }
+                        "else_if_cond" | "else_block" => {
+                            // Keep `else` on a new line when it is in source.
+                            let gap = &self.source[prev_end..ch.start()];
+                            if gap.find("else").map(|i| gap[..i].contains('\n')).unwrap_or(false) {
+                                self.newline();
+                            } else {
+                                self.space();
+                            }
+                            if *ch.name == "else_block" {
+                                self.write("else ");
+                                self.block(ch);
+                            } else {
+                                self.write("else if ");
+                                self.expr(ch);
+                            }
+                        }
+                        _ => {
+                            self.space();
+                            self.block(ch);
+                        }
+                    }
+                    prev_end = ch.end();
+                }
+            }
+            "match" => {
+                self.write("match ");
+                if let Some(expr) = n.node("expr") {self.expr(expr)};
+                self.space();
+                let arms: Vec<(usize, &Node)> = n.nodes("match_arm").map(|arm| (arm.start(), arm)).collect();
+                self.list("{", "}", ",", &arms, n.end(), |f, arm| {
+                    if let Some(pattern) = arm.node("pattern") {f.pattern(pattern)};
+                    f.write(" => ");
+                    if let Some(expr) = arm.node("expr") {f.expr(expr)};
+                });
+            }
+            "break" | "continue" => {
+                self.write(&n.name);
+                if let Some(label) = n.string("label") {
+                    self.write(" '");
+                    self.write(label);
+                }
+            }
+            "mat4" => {
+                let rows: Vec<(usize, &Node)> = n.children.iter().filter_map(|ch| match *ch {
+                    Item::Node(ref row) => Some((row.start(), row)),
+                    _ => None
+                }).collect();
+                // The first row must be followed by `;`.
+                let close = if rows.len() == 1 {";}"} else {"}"};
+                self.list("mat4 {", close, ";", &rows, n.end(), |f, row| {
+                    match row.node("vec4") {
+                        Some(vec4) if !row.bool("try") => f.components(vec4),
+                        _ => f.expr(row)
+                    }
+                });
+            }
+            "vec4" => {
+                let items: Vec<(usize, &Node)> = n.children.iter().filter_map(|ch| match *ch {
+                    Item::Node(ref x) => Some((x.start(), x)),
+                    _ => None
+                }).collect();
+                let close = if items.len() == 1 {",)"} else {")"};
+                self.list("(", close, ",", &items, n.end(), |f, x| f.expr(x));
+            }
+            "link" => {
+                self.write("link ");
+                self.link_body(n);
+            }
+            "interp" => {
+                self.write("$\"");
+                for ch in &n.children {
+                    match *ch {
+                        Item::Str(_, _, range) => self.write_range(range),
+                        Item::Bool(ref name, _, _) => self.write(match &***name {
+                            "interp_lbrace" => "{{",
+                            "interp_rbrace" => "}}",
+                            "interp_quote" => "\\\"",
+                            "interp_backslash" => "\\\\",
+                            "interp_newline" => "\\n",
+                            "interp_tab" => "\\t",
+                            _ => ""
+                        }),
+                        Item::Node(ref item) => {
+                            self.write("{");
+                            if let Some(expr) = item.node("expr") {self.expr(expr)};
+                            let width = item.string("width");
+                            let precision = item.string("precision");
+                            if width.is_some() || precision.is_some() {self.write(":")};
+                            if let Some(width) = width {self.write(width)};
+                            if let Some(precision) = precision {
+                                self.write(".");
+                                self.write(precision);
+                            }
+                            self.write("}");
+                        }
+                        Item::F64(_) => {}
+                    }
+                }
+                self.write("\"");
+            }
+            "grab" => {
+                self.write("grab ");
+                for ch in &n.children {
+                    if let Item::F64(range) = *ch {
+                        self.write("'");
+                        self.write_range(range);
+                        self.write(" ");
+                    }
+                }
+                if let Some(expr) = n.node("expr") {self.expr(expr)};
+            }
+            "try_expr" => {
+                self.write("try ");
+                if let Some(expr) = n.node("expr") {self.expr(expr)};
+            }
+            "not" => {
+                let not = if self.source[n.start()..].starts_with('¬') {"¬"} else {"!"};
+                let not = self.spell(not);
+                self.write(not);
+                if let Some(expr) = n.node("expr") {self.lexpr(expr)};
+            }
+            "norm" => {
+                self.write("|");
+                if let Some(expr) = n.node("expr") {self.expr(expr)};
+                self.write("|");
+            }
+            "go" => {
+                self.write("go ");
+                for ch in &n.children {
+                    if let Item::Node(ref call) = *ch {self.expr_node(call)};
+                }
+            }
+            "call" => {
+                if let Some(alias) = n.string("alias") {
+                    self.write(alias);
+                    self.write("::");
+                }
+                self.write(n.string("name").unwrap_or(""));
+                let args: Vec<(usize, &Node)> = n.nodes("call_arg").map(|x| (x.start(), x)).collect();
+                self.list("(", ")", ",", &args, n.end(), |f, x| f.expr(x));
+            }
+            "call_closure" => {
+                self.write("\\");
+                if let Some(item) = n.node("item") {self.expr_node(item)};
+                let args: Vec<(usize, &Node)> = n.nodes("call_arg").map(|x| (x.start(), x)).collect();
+                self.list("(", ")", ",", &args, n.end(), |f, x| f.expr(x));
+            }
+            "named_call" | "named_call_closure" => {
+                let mut words = vec![];
+                let mut args = vec![];
+                for ch in &n.children {
+                    match *ch {
+                        Item::Str(ref name, ref val, range) if **name == "word" =>
+                            words.push((range.offset, &***val)),
+                        Item::Node(ref arg) if *arg.name == "call_arg" => args.push(arg),
+                        _ => {}
+                    }
+                }
+                if *n.name == "named_call" {
+                    if let Some(alias) = n.string("alias") {
+                        self.write(alias);
+                        self.write("::");
+                    }
+                    if !words.is_empty() {
+                        let name = words.remove(0).1;
+                        self.write(name);
+                    }
+                } else {
+                    self.write("\\");
+                    if let Some(item) = n.node("item") {self.expr_node(item)};
+                }
+                let items: Vec<(usize, (&str, &Node))> = words.iter().zip(args)
+                    .map(|(&(start, word), arg)| (start, (word, arg))).collect();
+                self.list("(", ")", ",", &items, n.end(), |f, (word, arg)| {
+                    f.write(word);
+                    f.write(": ");
+                    f.expr(arg);
+                });
+            }
+            "item" => self.item(n),
+            "swizzle" => {
+                for ch in &n.children {
+                    match *ch {
+                        Item::Node(ref sw) if *sw.name != "expr" => {
+                            for &c in &["x", "y", "z", "w"] {
+                                if sw.bool(c) {self.write(c)};
+                            }
+                        }
+                        Item::Node(ref expr) => {
+                            self.write(" ");
+                            self.expr(expr);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn pattern(&mut self, n: &Node) {
+        if n.bool("object") {
+            let fields: Vec<(usize, &Node)> = n.nodes("pattern_field").map(|x| (x.start(), x)).collect();
+            self.list("{", "}", ",", &fields, n.end(), |f, field| {
+                f.write(field.string("key").unwrap_or(""));
+                f.write(": ");
+                f.pattern(field);
+            });
+            return;
+        }
+        for ch in &n.children {
+            match *ch {
+                Item::Node(ref ch) => {
+                    match &**ch.name {
+                        "pattern_some" => self.write("some("),
+                        "pattern_ok" => self.write("ok("),
+                        "pattern_err" => self.write("err("),
+                        "pattern_none" => {
+                            self.write("none()");
+                            continue;
+                        }
+                        "pattern_variant" => {
+                            self.write(ch.string("alias").unwrap_or(""));
+                            self.write("::");
+                            self.write(ch.string("name").unwrap_or(""));
+                            let args: Vec<(usize, &Node)> = ch.nodes("pattern_arg")
+                                .map(|x| (x.start(), x)).collect();
+                            self.list("(", ")", ",", &args, ch.end(), |f, arg| f.pattern(arg));
+                            continue;
+                        }
+                        _ => continue
+                    }
+                    self.pattern(ch);
+                    self.write(")");
+                }
+                Item::Bool(ref name, val, _) if **name == "bool" =>
+                    self.write(if val {"true"} else {"false"}),
+                Item::F64(range) => self.write_range(range),
+                Item::Str(ref name, _, range) if **name == "text" => self.write_range(range),
+                Item::Str(ref name, _, range) if **name == "i64" => {
+                    self.write_range(range);
+                    self.write("i64");
+                }
+                Item::Str(ref name, ref val, _) if **name == "name" => self.write(val),
+                _ => {}
+            }
+        }
+    }
+
+    /// Writes vector components separated by commas.
+    fn components(&mut self, n: &Node) {
+        let mut count = 0;
+        for ch in &n.children {
+            if let Item::Node(ref x) = *ch {
+                if count > 0 {self.write(", ")};
+                self.expr(x);
+                count += 1;
+            }
+        }
+        if count == 1 {self.write(",")};
+    }
+
+    /// Writes the body of a link expression or link loop.
+    fn link_body(&mut self, n: &Node) {
+        let link = if *n.name == "link" {Some(n)} else {
+            n.node("block").and_then(|b| b.node("expr")).and_then(|e| e.node("link"))
+        };
+        let items: Vec<(usize, &Node)> = link.into_iter()
+            .flat_map(|link| link.nodes("link_item"))
+            .map(|x| (x.start(), x))
+            .collect();
+        self.list("{", "}", "", &items, n.end(), |f, x| f.expr(x));
+    }
+
+    fn item(&mut self, n: &Node) {
+        for ch in &n.children {
+            match *ch {
+                Item::Bool(ref name, true, _) if **name == "current" => self.write("~"),
+                Item::Bool(ref name, true, _) if **name == "try_item" => self.write("?"),
+                Item::Str(ref name, ref val, _) if **name == "name" => self.write(val),
+                Item::Node(ref extra) if *extra.name == "item_extra" => {
+                    for ch in &extra.children {
+                        match *ch {
+                            Item::Str(_, ref id, _) => {
+                                if is_ident(id) {
+                                    self.write(".");
+                                    self.write(id);
+                                } else {
+                                    let mut buf: Vec<u8> = vec![];
+                                    json::write_string(&mut buf, id).unwrap();
+                                    self.write("[");
+                                    self.write(&String::from_utf8(buf).unwrap());
+                                    self.write("]");
+                                }
+                            }
+                            Item::F64(range) => {
+                                self.write("[");
+                                self.write_range(range);
+                                self.write("]");
+                            }
+                            Item::Node(ref id) => {
+                                self.write("[");
+                                self.expr(id);
+                                self.write("]");
+                            }
+                            Item::Bool(ref name, true, _) if **name == "try_id" => self.write("?"),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
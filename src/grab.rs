@@ -6,6 +6,7 @@ are constants.
 use std::sync::Arc;
 use ast;
 use runtime::{Flow, Runtime, Side};
+use RuntimeError;
 use Variable;
 
 #[derive(Debug)]
@@ -22,7 +23,7 @@ pub fn grab_expr(
     rt: &mut Runtime,
     expr: &ast::Expression,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     use ast::Expression as E;
 
     match *expr {
@@ -49,6 +50,13 @@ pub fn grab_expr(
                     x => return x,
                 }))), Flow::Continue))
         }
+        E::Yield(ref expr) => {
+            Ok((Grabbed::Expression(E::Yield(
+                Box::new(match grab_expr(level, rt, expr, side) {
+                    Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                    x => return x,
+                }))), Flow::Continue))
+        }
         E::Try(ref expr) => {
             Ok((Grabbed::Expression(E::Try(
                 Box::new(match grab_expr(level, rt, expr, side) {
@@ -145,23 +153,55 @@ pub fn grab_expr(
                 source_range: if_expr.source_range,
             }))), Flow::Continue))
         },
-        E::Go(ref go) => {
-            let call = &go.call;
-            Ok((Grabbed::Expression(E::Go(Box::new(ast::Go {
-                call: ast::Call {
-                    args: {
-                        let mut new_args = vec![];
-                        for arg in &call.args {
-                            new_args.push(match grab_expr(level, rt, arg, side) {
+        E::Match(ref match_expr) => {
+            Ok((Grabbed::Expression(E::Match(Box::new(ast::Match {
+                expr: match grab_expr(level, rt, &match_expr.expr, side) {
+                    Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                    x => return x,
+                },
+                arms: {
+                    let mut new_arms = vec![];
+                    for arm in &match_expr.arms {
+                        new_arms.push(ast::MatchArm {
+                            pattern: arm.pattern.clone(),
+                            expr: match grab_expr(level, rt, &arm.expr, side) {
                                 Ok((Grabbed::Expression(x), Flow::Continue)) => x,
                                 x => return x,
-                            });
+                            },
+                            source_range: arm.source_range,
+                        });
+                    }
+                    new_arms
+                },
+                source_range: match_expr.source_range,
+            }))), Flow::Continue))
+        },
+        E::Go(ref go) => {
+            Ok((Grabbed::Expression(E::Go(Box::new(ast::Go {
+                call: match go.call {
+                    ast::GoCall::Call(ref call) => ast::GoCall::Call(ast::Call {
+                        args: {
+                            let mut new_args = vec![];
+                            for arg in &call.args {
+                                new_args.push(match grab_expr(level, rt, arg, side) {
+                                    Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                                    x => return x,
+                                });
+                            }
+                            new_args
+                        },
+                        info: call.info.clone(),
+                        f_index: call.f_index.clone(),
+                        custom_source: call.custom_source.clone(),
+                    }),
+                    ast::GoCall::Closure(ref call) => {
+                        let expr = E::CallClosure(Box::new(call.clone()));
+                        match grab_expr(level, rt, &expr, side) {
+                            Ok((Grabbed::Expression(E::CallClosure(x)), Flow::Continue)) =>
+                                ast::GoCall::Closure(*x),
+                            x => return x,
                         }
-                        new_args
-                    },
-                    info: call.info.clone(),
-                    f_index: call.f_index.clone(),
-                    custom_source: call.custom_source.clone(),
+                    }
                 },
                 source_range: go.source_range,
             }))), Flow::Continue))
@@ -403,6 +443,31 @@ pub fn grab_expr(
                 source_range: link.source_range,
             }))), Flow::Continue))
         }
+        E::Interp(ref interp) => {
+            Ok((Grabbed::Expression(E::Interp(Box::new(ast::Interp {
+                parts: {
+                    let mut new_parts = vec![];
+                    for part in &interp.parts {
+                        new_parts.push(match *part {
+                            ast::InterpPart::Text(_) => part.clone(),
+                            ast::InterpPart::Item(ref item) => {
+                                ast::InterpPart::Item(Box::new(ast::InterpItem {
+                                    expr: match grab_expr(level, rt, &item.expr, side) {
+                                        Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                                        x => return x,
+                                    },
+                                    width: item.width,
+                                    precision: item.precision,
+                                    source_range: item.source_range,
+                                }))
+                            }
+                        });
+                    }
+                    new_parts
+                },
+                source_range: interp.source_range,
+            }))), Flow::Continue))
+        }
         E::Object(ref obj) => {
             Ok((Grabbed::Expression(E::Object(Box::new(ast::Object {
                 key_values: {
@@ -434,6 +499,23 @@ pub fn grab_expr(
                 source_range: arr.source_range,
             }))), Flow::Continue))
         }
+        E::Variant(ref variant) => {
+            Ok((Grabbed::Expression(E::Variant(Box::new(ast::Variant {
+                enum_name: variant.enum_name.clone(),
+                name: variant.name.clone(),
+                args: {
+                    let mut new_args = vec![];
+                    for arg in &variant.args {
+                        new_args.push(match grab_expr(level, rt, arg, side) {
+                            Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                            x => return x,
+                        });
+                    }
+                    new_args
+                },
+                source_range: variant.source_range,
+            }))), Flow::Continue))
+        }
         E::ArrayFill(ref arr_fill) => {
             Ok((Grabbed::Expression(E::ArrayFill(Box::new(ast::ArrayFill {
                 fill: match grab_expr(level, rt, &arr_fill.fill, side) {
@@ -484,6 +566,20 @@ pub fn grab_expr(
                 source_range: for_in_expr.source_range,
             }))), Flow::Continue))
         }
+        E::Lock(ref lock) => {
+            Ok((Grabbed::Expression(E::Lock(Box::new(ast::Lock {
+                name: lock.name.clone(),
+                mutex: match grab_expr(level, rt, &lock.mutex, side) {
+                    Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                    x => return x,
+                },
+                block: match grab_block(level, rt, &lock.block, side) {
+                    Ok((Grabbed::Block(x), Flow::Continue)) => x,
+                    x => return x,
+                },
+                source_range: lock.source_range,
+            }))), Flow::Continue))
+        }
         E::SumIn(ref for_in_expr) => {
             Ok((Grabbed::Expression(E::SumIn(Box::new(ast::ForIn {
                 name: for_in_expr.name.clone(),
@@ -637,7 +733,7 @@ fn grab_block(
     rt: &mut Runtime,
     block: &ast::Block,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     Ok((Grabbed::Block(ast::Block {
         expressions: {
             let mut new_expressions = vec![];
@@ -658,7 +754,7 @@ fn grab_item(
     rt: &mut Runtime,
     item: &ast::Item,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     Ok((Grabbed::Item(ast::Item {
         name: item.name.clone(),
         stack_id: item.stack_id.clone(),
@@ -691,7 +787,7 @@ fn grab_for_n(
     rt: &mut Runtime,
     for_n: &ast::ForN,
     side: Side,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), RuntimeError> {
     Ok((Grabbed::ForN(ast::ForN {
         name: for_n.name.clone(),
         start: match for_n.start {
@@ -712,6 +808,7 @@ fn grab_for_n(
             x => return x,
         },
         label: for_n.label.clone(),
+        par: for_n.par,
         source_range: for_n.source_range
     }), Flow::Continue))
 }
@@ -23,17 +23,26 @@ extern crate tree_mem_sort;
 
 use std::any::Any;
 use std::fmt;
-use std::thread::JoinHandle;
+use runtime::pool::JoinHandle;
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Sender, SyncSender};
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use range::Range;
-use piston_meta::{parse_errstr, syntax_errstr, MetaData, Syntax};
+use piston_meta::{parse, parse_errstr, syntax_errstr, MetaData, Syntax};
 
 pub mod ast;
 pub mod runtime;
 mod lifetime;
 mod prelude;
 pub mod embed;
+pub mod analysis;
+pub mod cache;
+pub mod debug;
+pub mod format;
+pub mod reload;
+pub mod repl;
 mod ty;
 mod link;
 pub mod macros;
@@ -41,18 +50,20 @@ mod vec4;
 mod mat4;
 mod write;
 mod module;
+mod error;
 
 mod grab;
 mod dyon_std;
 
-pub use runtime::Runtime;
+pub use runtime::{Limits, Runtime};
 pub use prelude::{Lt, Prelude, Dfn};
 pub use ty::Type;
 pub use link::Link;
 pub use vec4::Vec4;
 pub use mat4::Mat4;
 pub use ast::Lazy;
-pub use module::Module;
+pub use module::{Capabilities, Module};
+pub use error::{DyonError, ErrorInfo, Location, RuntimeError};
 
 /// A common error message when there is no value on the stack.
 pub const TINVOTS: &str = "There is no value on the stack";
@@ -72,6 +83,11 @@ lazy_static!{
     pub(crate) static ref DIV: Arc<String> = Arc::new("div".into());
     pub(crate) static ref REM: Arc<String> = Arc::new("rem".into());
     pub(crate) static ref POW: Arc<String> = Arc::new("pow".into());
+    pub(crate) static ref BIT_AND: Arc<String> = Arc::new("bit_and".into());
+    pub(crate) static ref BIT_OR: Arc<String> = Arc::new("bit_or".into());
+    pub(crate) static ref XOR: Arc<String> = Arc::new("xor".into());
+    pub(crate) static ref SHL: Arc<String> = Arc::new("shl".into());
+    pub(crate) static ref SHR: Arc<String> = Arc::new("shr".into());
     pub(crate) static ref DOT: Arc<String> = Arc::new("dot".into());
     pub(crate) static ref CROSS: Arc<String> = Arc::new("cross".into());
     pub(crate) static ref NOT: Arc<String> = Arc::new("not".into());
@@ -96,6 +112,10 @@ pub static LAZY_NO: LazyInvariant = &[];
 pub type Array = Arc<Vec<Variable>>;
 /// Type alias for Dyon objects.
 pub type Object = Arc<HashMap<Arc<String>, Variable>>;
+/// Type alias for Dyon maps.
+pub type Map = Arc<HashMap<Variable, Variable>>;
+/// Type alias for Dyon sets.
+pub type Set = Arc<HashSet<Variable>>;
 /// Type alias for Rust objects.
 pub type RustObject = Arc<Mutex<dyn Any>>;
 
@@ -113,14 +133,34 @@ pub struct Error {
 #[derive(Clone)]
 pub struct Thread {
     /// The handle of the thread.
-    pub handle: Option<Arc<Mutex<JoinHandle<Result<Variable, String>>>>>,
+    pub handle: Option<Arc<Mutex<JoinHandle>>>,
+    /// Checked by the runtime of the thread, which stops when set to `true`.
+    pub cancel: Arc<AtomicBool>,
 }
 
 impl Thread {
     /// Creates a new thread handle.
-    pub fn new(handle: JoinHandle<Result<Variable, String>>) -> Thread {
+    pub fn new(handle: JoinHandle, cancel: Arc<AtomicBool>) -> Thread {
         Thread {
-            handle: Some(Arc::new(Mutex::new(handle)))
+            handle: Some(Arc::new(Mutex::new(handle))),
+            cancel,
+        }
+    }
+
+    /// Asks the thread to stop.
+    ///
+    /// The thread stops with an error before evaluating the next expression.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the thread has finished.
+    pub fn is_done(&self) -> Result<bool, String> {
+        match self.handle {
+            None => Err("The Thread has already been invalidated".into()),
+            Some(ref handle) => handle.lock()
+                .map(|handle| handle.is_done())
+                .map_err(|err| format!("Can not lock Thread mutex:\n{}", err)),
         }
     }
 
@@ -129,13 +169,14 @@ impl Thread {
     pub fn invalidate_handle(
         rt: &mut Runtime,
         var: Variable
-    ) -> Result<JoinHandle<Result<Variable, String>>, String> {
+    ) -> Result<JoinHandle, String> {
 
         let thread = match var {
             Variable::Ref(ind) => {
                 use std::mem::replace;
 
-                match replace(&mut rt.stack[ind], Variable::Thread(Thread { handle: None })) {
+                let invalid = Thread {handle: None, cancel: Arc::new(AtomicBool::new(false))};
+                match replace(&mut rt.stack[ind], Variable::Thread(invalid)) {
                     Variable::Thread(th) => th,
                     x => return Err(rt.expected(&x, "Thread"))
                 }
@@ -161,6 +202,66 @@ impl fmt::Debug for Thread {
     }
 }
 
+/// Stores a coroutine handle.
+///
+/// Copies of the handle share the same suspended function.
+#[derive(Clone)]
+pub struct Coroutine {
+    pub(crate) state: Arc<Mutex<runtime::coroutine::State>>,
+}
+
+impl Coroutine {
+    /// Returns `true` if the coroutine function has returned.
+    pub fn is_done(&self) -> bool {
+        match self.state.lock() {
+            Ok(state) => state.is_done(),
+            Err(_) => true,
+        }
+    }
+}
+
+impl fmt::Debug for Coroutine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "coroutine")
+    }
+}
+
+/// Stores the sending end of a channel.
+#[derive(Clone)]
+pub enum Out {
+    /// Channel without capacity limit.
+    Unbounded(Sender<Variable>),
+    /// Channel that blocks when full.
+    Bounded(SyncSender<Variable>),
+}
+
+impl Out {
+    /// Sends a value, returning `false` if the receiver is dropped.
+    pub fn send(&self, v: Variable) -> bool {
+        match *self {
+            Out::Unbounded(ref tx) => tx.send(v).is_ok(),
+            Out::Bounded(ref tx) => tx.send(v).is_ok(),
+        }
+    }
+}
+
+impl fmt::Debug for Out {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "out")
+    }
+}
+
+/// Value of an enum variant, e.g. `Shape::Circle(2)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// The name of the enum.
+    pub enum_name: Arc<String>,
+    /// The name of the variant.
+    pub name: Arc<String>,
+    /// The payload values.
+    pub args: Vec<Variable>,
+}
+
 /// Prevents unsafe references from being accessed outside library.
 #[derive(Debug, Clone)]
 pub struct UnsafeRef(*mut Variable);
@@ -191,18 +292,30 @@ pub enum Variable {
     Bool(bool, Option<Box<Vec<Variable>>>),
     /// F64.
     F64(f64, Option<Box<Vec<Variable>>>),
+    /// I64.
+    I64(i64),
     /// 4D vector.
     Vec4([f32; 4]),
     /// 4D matrix.
     Mat4(Box<[[f32; 4]; 4]>),
     /// Text.
     Str(Arc<String>),
+    /// Byte buffer.
+    Bytes(Arc<Vec<u8>>),
     /// Array.
     Array(Array),
     /// Object.
     Object(Object),
+    /// Object created as a declared record type, with the name of the record.
+    Record(Arc<String>, Object),
+    /// Map with hashable keys.
+    Map(Map),
+    /// Set of hashable values.
+    Set(Set),
     /// Link.
     Link(Box<Link>),
+    /// Enum variant.
+    Variant(Box<Variant>),
     /// Unsafe reference.
     UnsafeRef(UnsafeRef),
     /// Rust object.
@@ -219,6 +332,18 @@ pub enum Variable {
     Closure(Arc<ast::Closure>, Box<ClosureEnvironment>),
     /// In-type.
     In(Arc<Mutex<::std::sync::mpsc::Receiver<Variable>>>),
+    /// Coroutine handle.
+    Coroutine(Coroutine),
+    /// Sending end of a channel.
+    Out(Out),
+    /// Atomic number, stored as the bits of a `f64`.
+    ///
+    /// Copies share the same number, also between threads.
+    Atomic(Arc<AtomicU64>),
+    /// Value guarded by a lock.
+    ///
+    /// Copies share the same value, also between threads.
+    Mutex(Arc<Mutex<Variable>>),
 }
 
 /// This is requires because `UnsafeRef(*mut Variable)` can not be sent across threads.
@@ -244,14 +369,20 @@ impl Variable {
 
         match *self {
             Str(_) => TEXT_TYPE.clone(),
+            Bytes(_) => BYTES_TYPE.clone(),
             F64(_, _) => F64_TYPE.clone(),
+            I64(_) => I64_TYPE.clone(),
             Vec4(_) => VEC4_TYPE.clone(),
             Mat4(_) => MAT4_TYPE.clone(),
             Return => RETURN_TYPE.clone(),
             Bool(_, _) => BOOL_TYPE.clone(),
             Object(_) => OBJECT_TYPE.clone(),
+            Record(ref name, _) => name.clone(),
+            Map(_) => MAP_TYPE.clone(),
+            Set(_) => SET_TYPE.clone(),
             Array(_) => ARRAY_TYPE.clone(),
             Link(_) => LINK_TYPE.clone(),
+            Variant(ref v) => v.enum_name.clone(),
             Ref(_) => REF_TYPE.clone(),
             UnsafeRef(_) => UNSAFE_REF_TYPE.clone(),
             RustObject(_) => RUST_OBJECT_TYPE.clone(),
@@ -260,6 +391,23 @@ impl Variable {
             Thread(_) => THREAD_TYPE.clone(),
             Closure(_, _) => CLOSURE_TYPE.clone(),
             In(_) => IN_TYPE.clone(),
+            Coroutine(_) => COROUTINE_TYPE.clone(),
+            Out(_) => OUT_TYPE.clone(),
+            Atomic(_) => ATOMIC_TYPE.clone(),
+            Mutex(_) => MUTEX_TYPE.clone(),
+        }
+    }
+
+    /// Returns `true` if the variable can be used as key in a map or value in a set.
+    ///
+    /// This is `bool`, `f64`, `i64`, `str`, `bytes`, `vec4` and arrays of these.
+    pub fn is_hashable(&self) -> bool {
+        use Variable::*;
+
+        match *self {
+            Bool(_, _) | F64(_, _) | I64(_) | Str(_) | Bytes(_) | Vec4(_) => true,
+            Array(ref arr) => arr.iter().all(|it| it.is_hashable()),
+            _ => false
         }
     }
 
@@ -268,18 +416,15 @@ impl Variable {
 
         match *self {
             F64(_, _) => self.clone(),
+            I64(_) => self.clone(),
             Vec4(_) => self.clone(),
             Mat4(_) => self.clone(),
             Return => self.clone(),
             Bool(_, _) => self.clone(),
             Str(_) => self.clone(),
-            Object(ref obj) => {
-                let mut res = obj.clone();
-                for val in Arc::make_mut(&mut res).values_mut() {
-                    *val = val.deep_clone(stack);
-                }
-                Object(res)
-            }
+            Bytes(_) => self.clone(),
+            Object(ref obj) => Object(deep_clone_object(obj, stack)),
+            Record(ref name, ref obj) => Record(name.clone(), deep_clone_object(obj, stack)),
             Array(ref arr) => {
                 let mut res = arr.clone();
                 for it in Arc::make_mut(&mut res) {
@@ -287,7 +432,12 @@ impl Variable {
                 }
                 Array(res)
             }
+            // Keys and values are deep cloned when inserted, so they do not contain references.
+            Map(_) => self.clone(),
+            Set(_) => self.clone(),
             Link(_) => self.clone(),
+            // Variant payloads always use deep clone, so they do not contain references.
+            Variant(_) => self.clone(),
             Ref(ind) => {
                 stack[ind].deep_clone(stack)
             }
@@ -303,6 +453,10 @@ impl Variable {
             Thread(_) => self.clone(),
             Closure(_, _) => self.clone(),
             In(_) => self.clone(),
+            Coroutine(_) => self.clone(),
+            Out(_) => self.clone(),
+            Atomic(_) => self.clone(),
+            Mutex(_) => self.clone(),
         }
     }
 }
@@ -312,10 +466,23 @@ impl PartialEq for Variable {
         match (self, other) {
             (&Variable::Return, _) => false,
             (&Variable::Bool(a, _), &Variable::Bool(b, _)) => a == b,
-            (&Variable::F64(a, _), &Variable::F64(b, _)) => a == b,
+            (&Variable::F64(a, _), &Variable::F64(b, _)) => total_eq_f64(a, b),
+            (&Variable::I64(a), &Variable::I64(b)) => a == b,
             (&Variable::Str(ref a), &Variable::Str(ref b)) => a == b,
-            (&Variable::Object(ref a), &Variable::Object(ref b)) => a == b,
+            (&Variable::Bytes(ref a), &Variable::Bytes(ref b)) => a == b,
+            (&Variable::Vec4(ref a), &Variable::Vec4(ref b)) =>
+                a.iter().zip(b.iter()).all(|(&a, &b)| total_eq_f64(a.into(), b.into())),
+            (&Variable::Mat4(ref a), &Variable::Mat4(ref b)) =>
+                a.iter().flat_map(|col| col.iter()).zip(b.iter().flat_map(|col| col.iter()))
+                    .all(|(&a, &b)| total_eq_f64(a.into(), b.into())),
+            (&Variable::Object(ref a), &Variable::Object(ref b)) |
+            (&Variable::Object(ref a), &Variable::Record(_, ref b)) |
+            (&Variable::Record(_, ref a), &Variable::Object(ref b)) |
+            (&Variable::Record(_, ref a), &Variable::Record(_, ref b)) => a == b,
+            (&Variable::Map(ref a), &Variable::Map(ref b)) => a == b,
+            (&Variable::Set(ref a), &Variable::Set(ref b)) => a == b,
             (&Variable::Array(ref a), &Variable::Array(ref b)) => a == b,
+            (&Variable::Variant(ref a), &Variable::Variant(ref b)) => a == b,
             (&Variable::Ref(_), _) => false,
             (&Variable::UnsafeRef(_), _) => false,
             (&Variable::RustObject(_), _) => false,
@@ -324,6 +491,51 @@ impl PartialEq for Variable {
     }
 }
 
+/// Deep clones the values of an object.
+fn deep_clone_object(obj: &Object, stack: &[Variable]) -> Object {
+    let mut res = obj.clone();
+    for val in Arc::make_mut(&mut res).values_mut() {
+        *val = val.deep_clone(stack);
+    }
+    res
+}
+
+/// Equality is total for hashable variables, see `Variable::is_hashable`.
+impl Eq for Variable {}
+
+/// Compares numbers such that `NaN` equals itself.
+fn total_eq_f64(a: f64, b: f64) -> bool {
+    a == b || a.is_nan() && b.is_nan()
+}
+
+/// Hashes a number consistently with `total_eq_f64`.
+fn hash_f64<H: Hasher>(a: f64, state: &mut H) {
+    let a = if a == 0.0 {0.0} else if a.is_nan() {::std::f64::NAN} else {a};
+    a.to_bits().hash(state)
+}
+
+impl Hash for Variable {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use Variable::*;
+
+        ::std::mem::discriminant(self).hash(state);
+        match *self {
+            Bool(a, _) => a.hash(state),
+            F64(a, _) => hash_f64(a, state),
+            I64(a) => a.hash(state),
+            Str(ref a) => a.hash(state),
+            Bytes(ref a) => a.hash(state),
+            Vec4(a) => for &x in &a {hash_f64(x.into(), state)},
+            Array(ref arr) => {
+                arr.len().hash(state);
+                for it in arr.iter() {it.hash(state)}
+            }
+            // Other variables are not used as keys.
+            _ => {}
+        }
+    }
+}
+
 /// Refers to a function.
 #[derive(Clone, Copy, Debug)]
 pub enum FnIndex {
@@ -469,7 +681,7 @@ impl Clone for FnExternal {
 }
 
 /// Runs a program using a source file.
-pub fn run(source: &str) -> Result<(), String> {
+pub fn run(source: &str) -> Result<(), DyonError> {
     let mut module = Module::new();
     load(source, &mut module)?;
     let mut runtime = runtime::Runtime::new();
@@ -478,7 +690,7 @@ pub fn run(source: &str) -> Result<(), String> {
 }
 
 /// Runs a program from a string.
-pub fn run_str(source: &str, d: Arc<String>) -> Result<(), String> {
+pub fn run_str(source: &str, d: Arc<String>) -> Result<(), DyonError> {
     let mut module = Module::new();
     load_str(source, d, &mut module)?;
     let mut runtime = runtime::Runtime::new();
@@ -520,35 +732,48 @@ impl Call {
     }
 
     /// Run call without any return value.
-    pub fn run(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<(), String> {
+    pub fn run(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<(), DyonError> {
         runtime.call_str(&self.name, &self.args, module)
     }
 
     /// Run call with return value.
-    pub fn run_ret<T: embed::PopVariable>(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<T, String> {
+    pub fn run_ret<T: embed::PopVariable>(
+        &self,
+        runtime: &mut Runtime,
+        module: &Arc<Module>
+    ) -> Result<T, DyonError> {
         let val = runtime.call_str_ret(&self.name, &self.args, module)?;
         T::pop_var(runtime, runtime.resolve(&val))
+            .map_err(|err| DyonError::Runtime(ErrorInfo::from_text(err)))
     }
 
     /// Convert return value to a Vec4 convertible type.
-    pub fn run_vec4<T: embed::ConvertVec4>(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<T, String> {
+    pub fn run_vec4<T: embed::ConvertVec4>(
+        &self,
+        runtime: &mut Runtime,
+        module: &Arc<Module>
+    ) -> Result<T, DyonError> {
         let val = runtime.call_str_ret(&self.name, &self.args, module)?;
         match runtime.resolve(&val) {
             &Variable::Vec4(val) => Ok(T::from(val)),
-            x => Err(runtime.expected(x, "vec4"))
+            x => Err(DyonError::Runtime(ErrorInfo::from_text(runtime.expected(x, "vec4"))))
         }
     }
 }
 
 /// Loads source from file.
-pub fn load(source: &str, module: &mut Module) -> Result<(), String> {
+pub fn load(source: &str, module: &mut Module) -> Result<(), DyonError> {
     use std::fs::File;
     use std::io::Read;
 
-    let mut data_file = File::open(source).map_err(|err|
-        format!("Could not open `{}`, {}", source, err))?;
+    let io_error = |action: &str, err: ::std::io::Error| {
+        let mut info = ErrorInfo::from_text(format!("Could not {} `{}`, {}", action, source, err));
+        info.file = Some(Arc::new(source.into()));
+        DyonError::Io(info.with_source(err))
+    };
+    let mut data_file = File::open(source).map_err(|err| io_error("open", err))?;
     let mut data = Arc::new(String::new());
-    data_file.read_to_string(Arc::make_mut(&mut data)).unwrap();
+    data_file.read_to_string(Arc::make_mut(&mut data)).map_err(|err| io_error("read", err))?;
     load_str(source, data, module)
 }
 
@@ -581,24 +806,47 @@ pub(crate) fn check_str(
     let prelude = Arc::new(Prelude::from_module(module));
 
     let mut nodes = vec![];
-    let _ = lifetime::check_core(&mut nodes, &check_data, &prelude);
+    let _ = lifetime::check_core(&mut nodes, &check_data, &prelude, &mut vec![]);
     Ok(nodes)
 }
 
+/// Parses source into meta data.
+///
+/// - source - The name of source file
+/// - d - The data of source file
+pub(crate) fn parse_str(source: &str, d: &str) -> Result<Vec<Range<MetaData>>, DyonError> {
+    use piston_meta::ParseErrorHandler;
+
+    let syntax_rules = SYNTAX_RULES.as_ref()
+        .map_err(|err| DyonError::Parse(ErrorInfo::from_text(err.clone())))?;
+
+    let mut data = vec![];
+    parse(syntax_rules, d, &mut data).map_err(|range_err| {
+        let range = range_err.range();
+        let message = range_err.data.to_string();
+        let mut buf: Vec<u8> = vec![];
+        ParseErrorHandler::new(d).write(&mut buf, range_err).unwrap();
+        let text = format!("In `{}:`\n{}", source, String::from_utf8(buf).unwrap());
+        DyonError::Parse(ErrorInfo::new(Some(Arc::new(source.into())), range, d, message, text))
+    })?;
+    Ok(data)
+}
+
 /// Loads a source from string.
 ///
 /// - source - The name of source file
 /// - d - The data of source file
 /// - module - The module to load the source
-pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(), String> {
+///
+/// When the module has a cache directory, the source is loaded from the cache if unchanged.
+pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(), DyonError> {
     use std::thread;
 
-    let syntax_rules = SYNTAX_RULES.as_ref().map_err(|err| err.clone())?;
+    if let Some(dir) = module.cache_dir.clone() {
+        return cache::load_str_cached(&dir, source, d, module);
+    }
 
-    let mut data = vec![];
-    parse_errstr(syntax_rules, &d, &mut data).map_err(
-        |err| format!("In `{}:`\n{}", source, err)
-    )?;
+    let data = parse_str(source, &d)?;
 
     let check_data = data.clone();
     let prelude = Arc::new(Prelude::from_module(module));
@@ -615,30 +863,54 @@ pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(),
 
     // Check that lifetime checking succeeded.
     match handle.join().unwrap() {
-        Ok(refined_rets) => {
-            for (name, ty) in &refined_rets {
-                if let FnIndex::Loaded(f_index) = module.find_function(name, 0) {
-                    let f = &mut module.functions[f_index as usize];
-                    f.ret = ty.clone();
-                }
-            }
-        }
-        Err(err_msg) => {
-            use std::io::Write;
-            use piston_meta::ParseErrorHandler;
+        Ok(refined_rets) => refine_returns(module, refined_rets.iter()),
+        Err(err_msg) => return Err(check_error(source, &d, err_msg)),
+    }
+    name_record_returns(module);
 
-            let (range, msg) = err_msg.decouple();
+    check_ignored_meta_data(conv_res, source, &d, &data, &ignored)
+}
 
-            let mut buf: Vec<u8> = vec![];
-            writeln!(&mut buf, "In `{}`:\n", source).unwrap();
-            ParseErrorHandler::new(&d)
-                .write_msg(&mut buf, range, &msg)
-                .unwrap();
-            return Err(String::from_utf8(buf).unwrap())
+/// Sets return types of loaded functions refined by the type checker.
+pub(crate) fn refine_returns<'a, I>(module: &mut Module, refined_rets: I)
+    where I: Iterator<Item = (&'a Arc<String>, &'a Type)>
+{
+    for (name, ty) in refined_rets {
+        if let FnIndex::Loaded(f_index) = module.find_function(name, 0) {
+            let f = &mut module.functions[f_index as usize];
+            f.ret = ty.clone();
         }
     }
+}
 
-    check_ignored_meta_data(conv_res, source, &d, &data, &ignored)
+/// Looks up the records returned by loaded functions.
+///
+/// Objects returned from these functions are named after the record.
+/// Objects stored in locals or passed as arguments stay unnamed until returned.
+pub(crate) fn name_record_returns(module: &mut Module) {
+    for i in 0..module.functions.len() {
+        let record = match module.functions[i].ret {
+            Type::AdHoc(ref name, _) => module.find_record(name).map(|r| r.name.clone()),
+            _ => None
+        };
+        module.functions[i].ret_record = record;
+    }
+}
+
+/// Converts an error from the lifetime or type checker to a structured error.
+pub(crate) fn check_error(source: &str, d: &str, err_msg: Range<String>) -> DyonError {
+    use std::io::Write;
+    use piston_meta::ParseErrorHandler;
+
+    let (range, msg) = err_msg.decouple();
+
+    let mut buf: Vec<u8> = vec![];
+    writeln!(&mut buf, "In `{}`:\n", source).unwrap();
+    ParseErrorHandler::new(d)
+        .write_msg(&mut buf, range, &msg)
+        .unwrap();
+    let text = String::from_utf8(buf).unwrap();
+    DyonError::Check(ErrorInfo::new(Some(Arc::new(source.into())), range, d, msg, text))
 }
 
 /// Loads a source from meta data.
@@ -648,7 +920,7 @@ pub fn load_meta(
     d: Arc<String>,
     data: &[Range<MetaData>],
     module: &mut Module
-) -> Result<(), String> {
+) -> Result<(), DyonError> {
     // Convert to AST.
     let mut ignored = vec![];
     let conv_res = ast::convert(Arc::new(source.into()), d.clone(), &data, &mut ignored, module);
@@ -662,7 +934,7 @@ fn check_ignored_meta_data(
     d: &Arc<String>,
     data: &[Range<MetaData>],
     ignored: &[Range],
-) -> Result<(), String> {
+) -> Result<(), DyonError> {
     use piston_meta::json;
 
     if !ignored.is_empty() || conv_res.is_err() {
@@ -686,14 +958,24 @@ fn check_ignored_meta_data(
         if let Err(()) = conv_res {
             writeln!(&mut buf, "Conversion error").unwrap();
         }
-        return Err(String::from_utf8(buf).unwrap());
+        let text = String::from_utf8(buf).unwrap();
+        let file = Some(Arc::new(source.to_string()));
+        return Err(DyonError::Parse(if ignored.is_empty() {
+            let mut info = ErrorInfo::from_text(text);
+            info.file = file;
+            info.message = "Conversion error".into();
+            info
+        } else {
+            let range = data[ignored[0].iter()][0].range();
+            ErrorInfo::new(file, range, d, "Could not understand this".into(), text)
+        }));
     }
 
     Ok(())
 }
 
 /// Reports and error to standard output.
-pub fn error(res: Result<(), String>) -> bool {
+pub fn error<E: fmt::Display>(res: Result<(), E>) -> bool {
     match res {
         Err(err) => {
             println!();
@@ -754,10 +1036,798 @@ mod tests {
         assert_eq!(size_of::<ast::Expression>(), 16);
     }
 
+    #[test]
+    fn bytecode() {
+        use std::sync::Arc;
+        use super::*;
+
+        let mut module = Module::new();
+        load("source/bytecode/vm.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        let expected = rt.call_str_ret("test", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+        rt.bytecode = true;
+        let found = rt.call_str_ret("test", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(format!("{:?}", found), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn error_location() {
+        use std::sync::Arc;
+        use super::*;
+
+        let mut module = Module::new();
+        let err = load_str("parse.dyon", Arc::new("fn main() {\n    x := \n}".into()),
+                           &mut module).unwrap_err();
+        assert!(matches!(err, DyonError::Parse(_)));
+        assert_eq!(err.info().location.map(|loc| loc.line), Some(3));
+        assert!(err.to_string().starts_with("In `parse.dyon:`\n"));
+
+        let mut module = Module::new();
+        let err = load_str("check.dyon", Arc::new("fn main() {\n    println(y)\n}".into()),
+                           &mut module).unwrap_err();
+        assert!(matches!(err, DyonError::Check(_)));
+        let loc = err.info().location.unwrap();
+        assert_eq!((loc.line, loc.column), (2, 13));
+        assert_eq!(err.info().file.as_ref().map(|f| &***f), Some("check.dyon"));
+
+        let mut module = Module::new();
+        load_str("runtime.dyon", Arc::new(
+            "fn main() {foo()}\nfn foo() {\n    x := [1]\n    y := x[3]\n}".into()
+        ), &mut module).unwrap_or_else(|err| panic!("{}", err));
+        let err = Runtime::new().run(&Arc::new(module)).unwrap_err();
+        assert!(matches!(err, DyonError::Runtime(_)));
+        assert_eq!(err.info().location.map(|loc| loc.line), Some(4));
+        assert_eq!(err.message(), "Out of bounds `3`");
+        assert_eq!(err.info().call_stack, vec!["main (runtime.dyon)", "foo (runtime.dyon)"]);
+
+        // Errors from `par` chunks keep their location.
+        let mut module = Module::new();
+        load_str("par.dyon", Arc::new(
+            "fn main() {foo()}\nfn foo() {\n    x := [1, 2, 3]\n    \
+             y := par sum i 4 {x[i]}\n}".into()
+        ), &mut module).unwrap_or_else(|err| panic!("{}", err));
+        let mut rt = Runtime::new();
+        rt.threads = 4;
+        let err = rt.run(&Arc::new(module)).unwrap_err();
+        assert_eq!(err.info().location.map(|loc| loc.line), Some(4));
+        assert_eq!(err.message(), "Out of bounds `3`");
+        assert_eq!(err.info().call_stack, vec!["main (par.dyon)", "foo (par.dyon)"]);
+
+        // Errors work with the standard library, keeping the cause of I/O errors.
+        use std::error::Error;
+
+        let err = load("source/missing.dyon", &mut Module::new()).unwrap_err();
+        assert!(matches!(err, DyonError::Io(_)));
+        let cause = err.source().and_then(|err| err.downcast_ref::<::std::io::Error>());
+        assert_eq!(cause.map(|err| err.kind()), Some(::std::io::ErrorKind::NotFound));
+        let err: Box<dyn Error> = RuntimeError::from("Out of bounds").into();
+        assert_eq!(err.to_string(), "Out of bounds");
+        assert!(err.source().is_none());
+    }
+
+    #[test]
+    fn analysis() {
+        use std::sync::Arc;
+        use super::*;
+        use analysis::Analysis;
+
+        let source = "fn main() {\n    x := foo(2)\n    println(x)\n}\n\
+                      fn foo(a: f64) -> f64 { return a + 1 }\n";
+        let module = Module::new();
+        let analysis = Analysis::new("main.dyon", Arc::new(source.into()), &module);
+        assert!(analysis.error().is_none());
+        let call = source.find("foo(2)").unwrap();
+        let def = analysis.definition(call + 1).unwrap();
+        assert_eq!(def.range.offset, source.find("fn foo").unwrap());
+        assert_eq!(analysis.hover(call).unwrap(), "fn foo(a: f64) -> f64");
+        assert_eq!(analysis.hover(source.find("println").unwrap()).unwrap(),
+                   "fn println(any) -> void");
+        let names = analysis.completions("printl");
+        assert_eq!(names, vec![Arc::new("println".to_string())]);
+
+        let analysis = Analysis::new("main.dyon", Arc::new("fn main() {foo()}".into()), &module);
+        assert!(matches!(analysis.error(), Some(&DyonError::Check(_))));
+    }
+
+    #[test]
+    fn match_warnings() {
+        use std::sync::Arc;
+        use super::*;
+        use analysis::Analysis;
+
+        let source = "fn foo(x: opt[f64]) -> f64 {\n    return clone(match x {some(y) => y})\n}\n\
+                      fn bar(x: res[f64]) -> f64 {\n    return clone(match x {ok(y) => y, _ => 0})\n}\n";
+        let module = Module::new();
+        let analysis = Analysis::new("main.dyon", Arc::new(source.into()), &module);
+        assert!(analysis.error().is_none());
+        let warnings = analysis.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].offset, source.find("match x {some").unwrap());
+        assert_eq!(warnings[0].data, "Match over `opt[f64]` is not exhaustive:\nMissing `none()`");
+    }
+
+    #[test]
+    fn records() {
+        use std::sync::Arc;
+        use super::*;
+
+        let source = "type Player = {name: str, hp: f64}\n\
+                      fn new_player() -> Player {\n    return {name: \"Ann\", hp: 3}\n}\n\
+                      fn kind() -> str {\n    return typeof(new_player())\n}\n\
+                      fn get(key: str) -> f64 {\n    p := new_player()\n    return clone(p[key])\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        let kind = rt.call_str_ret("kind", &[], &module).unwrap();
+        assert_eq!(kind, Variable::Str(Arc::new("Player".into())));
+        match rt.call_str_ret("new_player", &[], &module).unwrap() {
+            Variable::Record(name, obj) => {
+                assert_eq!(*name, "Player");
+                assert_eq!(obj.len(), 2);
+            }
+            x => panic!("Expected record, got {:?}", x),
+        }
+        let hp = rt.call_str_ret("get", &[Variable::Str(Arc::new("hp".into()))], &module).unwrap();
+        assert_eq!(hp, Variable::f64(3.0));
+        match rt.call_str_ret("get", &[Variable::Str(Arc::new("mp".into()))], &module) {
+            Err(DyonError::Runtime(info)) => {
+                assert_eq!(info.message, "Record `Player` has no field `mp`")
+            }
+            x => panic!("Expected runtime error, got {:?}", x),
+        }
+
+        // Records are named when loading from cache.
+        let dir = std::env::temp_dir().join(format!("dyon-records-{}", std::process::id()));
+        for _ in 0..2 {
+            let mut module = Module::new();
+            module.set_cache_dir(Some(dir.clone()));
+            load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+            let kind = Runtime::new().call_str_ret("kind", &[], &Arc::new(module)).unwrap();
+            assert_eq!(kind, Variable::Str(Arc::new("Player".into())));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn enums() {
+        use std::sync::Arc;
+        use super::*;
+
+        let source = "enum Shape {Circle(f64), Empty}\n\
+                      fn circle(r: f64) -> Shape {\n    return Shape::Circle(r)\n}\n\
+                      fn kind() -> str {\n    s := Shape::Empty()\n    s = circle(1)\n    return typeof(s)\n}\n\
+                      fn text() -> str {\n    return str([circle(2), Shape::Empty()])\n}\n\
+                      fn load() -> [] {\n    return unwrap(load_data(string: text()))\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        let kind = rt.call_str_ret("kind", &[], &module).unwrap();
+        assert_eq!(kind, Variable::Str(Arc::new("Shape".into())));
+        let circle = rt.call_str_ret("circle", &[Variable::f64(2.0)], &module).unwrap();
+        assert_eq!(circle, Variable::Variant(Box::new(Variant {
+            enum_name: Arc::new("Shape".into()),
+            name: Arc::new("Circle".into()),
+            args: vec![Variable::f64(2.0)],
+        })));
+        let text = rt.call_str_ret("text", &[], &module).unwrap();
+        assert_eq!(text, Variable::Str(Arc::new("[Shape::Circle(2), Shape::Empty()]".into())));
+        match rt.call_str_ret("load", &[], &module).unwrap() {
+            Variable::Array(ref arr) => assert_eq!(arr[0], circle),
+            x => panic!("Expected array, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn i64_numbers() {
+        use std::sync::Arc;
+        use embed::{PopVariable, PushVariable};
+        use super::*;
+
+        let source = "fn double(a: i64) -> i64 {\n    return a * 2i64\n}\n\
+                      fn overflow() -> i64 {\n    return 9_223_372_036_854_775_807i64 + 1i64\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        let x = rt.call_str_ret("double", &[Variable::I64(1 << 60)], &module).unwrap();
+        assert_eq!(i64::pop_var(&rt, &x), Ok(1 << 61));
+        let x = rt.call_str_ret("double", &[u64::MAX.push_var()], &module).unwrap();
+        assert_eq!(u64::pop_var(&rt, &x), Ok(u64::MAX - 1));
+        let x = rt.call_str_ret("double", &[i32::MAX.push_var()], &module).unwrap();
+        assert!(i32::pop_var(&rt, &x).is_err());
+        assert!(rt.call_str_ret("overflow", &[], &module).is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        use std::sync::Arc;
+        use embed::{PopVariable, PushVariable};
+        use super::*;
+
+        let source = "fn header(b: bytes) -> bytes {\n    return slice(bytes: b, start: 0, end: 2)\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        let data: Vec<u8> = vec![0x89, b'P', b'N', b'G'];
+        let x = rt.call_str_ret("header", &[data.push_var()], &module).unwrap();
+        assert_eq!(Vec::<u8>::pop_var(&rt, &x), Ok(vec![0x89, b'P']));
+        assert!(Vec::<u8>::pop_var(&rt, &Variable::f64(1.0)).is_err());
+    }
+
+    #[test]
+    fn coroutines() {
+        use std::sync::Arc;
+        use super::*;
+
+        let source = "fn count(n: f64) -> co[f64] {\n    for i n { yield i * 10 }\n}\n\
+                      fn collect() -> [f64] {\n    c := count(3)\n    a := [unwrap(resume(c))]\n    \
+                      for x in c { push(mut a, x) }\n    push(mut a, if is_done(c) { 1 } else { 0 })\n    \
+                      return clone(a)\n}\n\
+                      fn start() -> {\n    return count(2)\n}\n\
+                      fn fail() -> co[f64] {\n    yield 1\n    yield unwrap(err(\"fail\"))\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        for &bytecode in &[false, true] {
+            rt.bytecode = bytecode;
+            let x = rt.call_str_ret("collect", &[], &module).unwrap();
+            assert_eq!(format!("{:?}", rt.resolve(&x)), "Array([F64(0.0, None), F64(10.0, None), \
+                       F64(20.0, None), F64(1.0, None)])");
+        }
+        match rt.call_str_ret("start", &[], &module).unwrap() {
+            Variable::Coroutine(co) => assert!(!co.is_done()),
+            x => panic!("Expected coroutine, found {:?}", x),
+        }
+        // Resuming leaves the stacks as they were, also when the coroutine fails.
+        let co = match rt.call_str_ret("fail", &[], &module).unwrap() {
+            Variable::Coroutine(co) => co,
+            x => panic!("Expected coroutine, found {:?}", x),
+        };
+        let lens = |rt: &Runtime| (rt.stack.len(), rt.local_stack.len(),
+                                   rt.current_stack.len(), rt.call_stack.len());
+        let before = lens(&rt);
+        assert!(rt.resume(&co).unwrap().is_some());
+        assert_eq!(lens(&rt), before);
+        assert!(rt.resume(&co).is_err());
+        assert_eq!(lens(&rt), before);
+        assert!(co.is_done());
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn channels() {
+        run("source/syntax/channel.dyon").unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn recv_timeout() {
+        use std::sync::Arc;
+        use super::*;
+
+        for timeout in &["-1", "1e300", "1/0"] {
+            let source = format!("fn main() {{\n    ch := channel()\n    \
+                                  _ := recv(from: ch.rx, timeout: {})\n}}", timeout);
+            let mut module = Module::new();
+            load_str("main.dyon", Arc::new(source), &mut module).unwrap();
+            match Runtime::new().run(&Arc::new(module)) {
+                Err(DyonError::Runtime(info)) =>
+                    assert!(info.message.starts_with("Expected timeout from 0 to"), "{}", info.message),
+                x => panic!("Expected runtime error, got {:?}", x),
+            }
+        }
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn join_timeout() {
+        use std::sync::Arc;
+        use super::*;
+
+        let run_timeout = |timeout: &str| {
+            let source = format!("fn f() -> f64 {{ return 42 }}\n\
+                                  fn main() {{\n    t := go f()\n    \
+                                  x := join(thread: t, timeout: {})\n    \
+                                  if unwrap(unwrap(x)) != 42 {{ _ := unwrap(err(\"wrong\")) }}\n}}", timeout);
+            let mut module = Module::new();
+            load_str("main.dyon", Arc::new(source), &mut module).unwrap();
+            Runtime::new().run(&Arc::new(module))
+        };
+        for timeout in &["-1", "1e300", "1/0"] {
+            match run_timeout(timeout) {
+                Err(DyonError::Runtime(info)) =>
+                    assert!(info.message.starts_with("Expected timeout from 0 to"), "{}", info.message),
+                x => panic!("Expected runtime error, got {:?}", x),
+            }
+        }
+        // Too far in the future for a deadline.
+        run_timeout("1e19").unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn thread_pool() {
+        use std::sync::Arc;
+        use super::*;
+
+        let source = "fn fib(n: f64) -> f64 {\n    if n < 2 { return clone(n) }\n    \
+                      a := go fib(n - 1)\n    b := go fib(n - 2)\n    \
+                      return unwrap(join(thread: a)) + unwrap(join(thread: b))\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        // Tasks join other tasks inside the only worker.
+        rt.threads = 1;
+        let x = rt.call_str_ret("fib", &[Variable::f64(12.0)], &module).unwrap();
+        assert_eq!(rt.resolve(&x), &Variable::f64(144.0));
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn go_blocking() {
+        use std::sync::Arc;
+        use super::*;
+
+        let mut module = Module::new();
+        load("source/syntax/go_blocking.dyon", &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        // Tasks block the only worker while waiting for tasks queued after them.
+        rt.threads = 1;
+        rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn go_closures() {
+        run("source/syntax/go_closure.dyon").unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn thread_cancel() {
+        use std::sync::Arc;
+        use super::*;
+
+        let mut module = Module::new();
+        load("source/syntax/thread_cancel.dyon", &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        // Both the AST walker and the bytecode VM check for cancellation.
+        for &bytecode in &[false, true] {
+            rt.bytecode = bytecode;
+            rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+        }
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn locks() {
+        use std::sync::Arc;
+        use super::*;
+
+        let mut module = Module::new();
+        load("source/syntax/lock.dyon", &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        // Run with several workers so updates happen concurrently.
+        rt.threads = 4;
+        rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    #[test]
+    fn par_loops() {
+        use std::sync::Arc;
+        use super::*;
+
+        let mut module = Module::new();
+        load("source/syntax/par.dyon", &mut module).unwrap();
+        let module = Arc::new(module);
+        let mut rt = Runtime::new();
+        // Merging of chunks gives the same result for any number of threads.
+        for &threads in &[1, 3, 8] {
+            rt.threads = threads;
+            rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+        }
+
+        // Chunks do not depend on the number of threads, so rounding errors are the same.
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new("fn f() -> f64 {\n    \
+            return par sum i 10000 { 1 / (i + 1) }\n}".into()), &mut module).unwrap();
+        let module = Arc::new(module);
+        let sums: Vec<Variable> = [1, 2, 3, 8].iter().map(|&threads| {
+            rt.threads = threads;
+            rt.call_str_ret("f", &[], &module).unwrap_or_else(|err| panic!("{}", err))
+        }).collect();
+        assert!(sums.iter().all(|sum| *sum == sums[0]), "{:?}", sums);
+    }
+
+    #[test]
+    fn hash_keys() {
+        use std::collections::HashSet;
+        use std::sync::Arc;
+        use super::*;
+
+        assert_eq!(Variable::Vec4([1.0, 2.0, 0.0, 0.0]), Variable::Vec4([1.0, 2.0, 0.0, 0.0]));
+        assert_eq!(Variable::f64(::std::f64::NAN), Variable::f64(::std::f64::NAN));
+        let mut set = HashSet::new();
+        set.insert(Variable::f64(0.0));
+        set.insert(Variable::f64(-0.0));
+        set.insert(Variable::Array(Arc::new(vec![Variable::f64(1.0), Variable::bool(true)])));
+        set.insert(Variable::Array(Arc::new(vec![Variable::f64(1.0), Variable::bool(true)])));
+        assert_eq!(set.len(), 2);
+        assert!(!Variable::Object(Arc::new(HashMap::new())).is_hashable());
+    }
+
+    #[test]
+    fn repl() {
+        use repl::Repl;
+
+        let mut repl = Repl::new();
+        let mut eval = |input: &str| repl.eval(input).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(eval("x := 2"), "");
+        assert_eq!(eval("x + 1"), "3");
+        assert_eq!(eval("fn sq(a: f64) -> f64 { return a * a }"), "Defined sq");
+        assert_eq!(eval("sq(x)"), "4");
+        assert_eq!(eval("fn sq(a: f64) -> f64 { return a * a * a }"), "Defined sq");
+        assert_eq!(eval("x += 1"), "");
+        assert_eq!(eval("sq(x)"), "27");
+        assert_eq!(eval(":type sq(x) > 1"), "bool");
+        assert_eq!(eval(":functions sq"), "sq(a: f64) -> f64\nsqrt(arg0: f64) -> f64");
+        assert!(repl.eval("println(y)").is_err());
+
+        let mut repl = Repl::new();
+        let mut eval = |input: &str| repl.eval(input).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(eval("type Point = {x: f64, y: f64}"), "Defined Point");
+        assert_eq!(eval("fn origin() -> Point { return {x: 0, y: 0} }"), "Defined origin");
+        assert_eq!(eval("p := origin()"), "");
+        assert_eq!(eval("p.x + p.y"), "0");
+        assert_eq!(eval(":type origin()"), "Point {}");
+        assert!(repl.eval("type Point = {x: f64, y: f64, z: f64}").is_err());
+        assert_eq!(repl.eval(":type origin()").unwrap(), "Point {}");
+
+        let mut repl = Repl::new();
+        let mut eval = |input: &str| repl.eval(input).unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(eval("enum Shape {Circle(f64), Empty}"), "Defined Shape");
+        assert_eq!(eval("fn area(s: Shape) -> f64 {\n\
+                         return match s {Shape::Circle(r) => 3 * r * r, Shape::Empty() => 0}\n\
+                         }"), "Defined area");
+        assert_eq!(eval("area(Shape::Circle(2))"), "12");
+        assert_eq!(eval(":type Shape::Empty()"), "Shape {}");
+        assert!(repl.eval("enum Shape {Circle(f64)}").is_err());
+        assert_eq!(repl.eval("area(Shape::Empty())").unwrap(), "0");
+    }
+
+    #[test]
+    fn format() {
+        use std::fs;
+        use std::path::Path;
+        use format::{format_str, Operators};
+
+        let source = "fn main() {\n// Sum.\nx:=∑ i 3 {i}   // Trailing.\n\n\n  \
+                      if x>1 and true {println(x)}\n}\n";
+        let ascii = format_str("main.dyon", source, Operators::Ascii).unwrap();
+        assert_eq!(ascii, "fn main() {\n    // Sum.\n    x := sum i 3 { i } // Trailing.\n\n    \
+                           if x > 1 and true { println(x) }\n}\n");
+        let unicode = format_str("main.dyon", &ascii, Operators::Unicode).unwrap();
+        assert_eq!(unicode, ascii.replace("sum", "∑").replace("and", "∧"));
+
+        // Formatting is idempotent.
+        // Sources with syntax errors can not be formatted.
+        const SYNTAX_ERRORS: &[&str] = &[
+            "source/syntax/add_fail_1.dyon",
+            "source/syntax/compare_fail_1.dyon",
+            "source/syntax/div_fail_1.dyon",
+            "source/syntax/hello_world.dyon",
+            "source/syntax/return_void_3.dyon",
+            "source/syntax/secret_fail.dyon",
+            "source/syntax/try_fail_1.dyon",
+            "source/syntax/try_fail_2.dyon",
+        ];
+        fn check_dir(dir: &Path) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    check_dir(&path);
+                } else if path.extension().map(|ext| ext == "dyon").unwrap_or(false) {
+                    let file = path.to_str().unwrap().replace('\\', "/");
+                    let source = fs::read_to_string(&path).unwrap();
+                    match format_str(&file, &source, Operators::Preserve) {
+                        Ok(text) => {
+                            assert!(!SYNTAX_ERRORS.contains(&&*file), "Expected syntax error in {}", file);
+                            assert_eq!(format_str(&file, &text, Operators::Preserve).unwrap(), text,
+                                       "Formatting {} is not idempotent", file);
+                        }
+                        Err(err) => assert!(SYNTAX_ERRORS.contains(&&*file), "{}", err),
+                    }
+                }
+            }
+        }
+        check_dir(Path::new("source"));
+    }
+
+    #[test]
+    fn debugger() {
+        use std::sync::{Arc, Mutex};
+        use debug::{format_variable, Debugger, Step};
+        use super::*;
+
+        let source = "fn foo(a) -> {\n    b := a + 1\n    return clone(b)\n}\n\n\
+                      fn main() {\n    x := 2\n    y := foo(x)\n    z := y\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let module = Arc::new(module);
+
+        // Returns paused lines with locals, answering with steps in order.
+        let run = |steps: Vec<Step>, breakpoint: Option<usize>| {
+            let log = Arc::new(Mutex::new(vec![]));
+            let log2 = log.clone();
+            let mut steps = steps.into_iter();
+            let mut debugger = Debugger::new(move |_: &mut _, rt: &Runtime, pause: &debug::Pause| {
+                let locals: Vec<String> = rt.locals().iter()
+                    .map(|&(ref name, v)| format!("{}={}", name, format_variable(rt, v)))
+                    .collect();
+                log2.lock().unwrap().push(format!("{} {}", pause.line, locals.join(",")));
+                steps.next().unwrap_or(Step::Continue)
+            });
+            if let Some(line) = breakpoint {
+                debugger.breakpoints.add("main.dyon", line);
+                debugger.step = Step::Continue;
+            }
+            let mut rt = Runtime::new();
+            rt.debug_hook = Some(Box::new(debugger));
+            let res = rt.run(&module);
+            let log = log.lock().unwrap().clone();
+            (res.is_ok(), log)
+        };
+
+        assert_eq!(run(vec![Step::Over, Step::Over, Step::Over], None),
+                   (true, vec!["7 ".into(), "8 x=2".into(), "9 x=2,y=3".into()]));
+        assert_eq!(run(vec![Step::Over, Step::Into, Step::Out], None),
+                   (true, vec!["7 ".into(), "8 x=2".into(), "2 a=2".into(), "9 x=2,y=3".into()]));
+        assert_eq!(run(vec![Step::Into], Some(3)),
+                   (true, vec!["3 a=2,b=3".into(), "9 x=2,y=3".into()]));
+        assert_eq!(run(vec![Step::Stop], None), (false, vec!["7 ".into()]));
+
+        // Breakpoints in coroutine functions.
+        let source = "fn f() -> co {\n    x := 1\n    yield x\n}\n\n\
+                      fn main() {\n    _ := resume(f())\n}\n";
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let lines = Arc::new(Mutex::new(vec![]));
+        let lines2 = lines.clone();
+        let mut debugger = Debugger::new(move |_: &mut _, _: &Runtime, pause: &debug::Pause| {
+            lines2.lock().unwrap().push(pause.line);
+            Step::Continue
+        });
+        debugger.breakpoints.add("main.dyon", 3);
+        debugger.step = Step::Continue;
+        let mut rt = Runtime::new();
+        rt.debug_hook = Some(Box::new(debugger));
+        rt.run(&Arc::new(module)).unwrap();
+        assert_eq!(*lines.lock().unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn limits() {
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+        use super::*;
+
+        let run_limited = |source: &str, limits: Limits| {
+            let mut module = Module::new();
+            load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+            let mut rt = Runtime::new();
+            rt.limits = limits;
+            match rt.run(&Arc::new(module)) {
+                Err(DyonError::Runtime(info)) => (info.message, info.call_stack),
+                x => panic!("Expected runtime error, got {:?}", x.err()),
+            }
+        };
+
+        let (msg, call_stack) = run_limited("fn main() { loop {} }",
+            Limits {fuel: Some(1000), ..Limits::default()});
+        assert_eq!(msg, "Out of fuel, evaluated the maximum number of expressions");
+        assert_eq!(call_stack, vec!["main (main.dyon)".to_string()]);
+
+        let (msg, call_stack) = run_limited("fn f(x) -> { return f(x + 1) }\nfn main() { _ := f(0) }",
+            Limits {call_depth: Some(100), ..Limits::default()});
+        assert_eq!(msg, "Exceeded maximum call stack depth of 100");
+        assert_eq!(call_stack.len(), 101);
+
+        let source = format!("fn main() {{\n{}}}", "    a := 0\n".repeat(20));
+        let (msg, _) = run_limited(&source, Limits {stack_len: Some(10), ..Limits::default()});
+        assert_eq!(msg, "Exceeded maximum stack length of 10");
+
+        let (msg, _) = run_limited("fn f() { loop { try loop {} } }\nfn main() { f() }",
+            Limits {deadline: Some(Instant::now() + Duration::from_millis(10)),
+                    ..Limits::default()});
+        assert_eq!(msg, "Exceeded deadline");
+
+        // Coroutine functions run with the bytecode VM.
+        let (msg, _) = run_limited("fn f() -> co { loop {} }\nfn main() { _ := resume(f()) }",
+            Limits {fuel: Some(1000), ..Limits::default()});
+        assert!(msg.contains("Out of fuel, evaluated the maximum number of expressions"));
+    }
+
+    #[cfg(feature = "threading")]
+    #[test]
+    fn limits_threads() {
+        use std::sync::Arc;
+        use super::*;
+
+        // Every thread uses less fuel than the limit, but not all together.
+        let run_limited = |source: &str| {
+            let mut module = Module::new();
+            load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+            let mut rt = Runtime::new();
+            rt.threads = 4;
+            rt.limits = Limits {fuel: Some(2000), ..Limits::default()};
+            match rt.run(&Arc::new(module)) {
+                Err(DyonError::Runtime(info)) => info.message,
+                x => panic!("Expected runtime error, got {:?}", x.err()),
+            }
+        };
+
+        let msg = run_limited("fn work() -> f64 {\n    return sum i 400 { i }\n}\n\
+                               fn main() {\n    t := sift i 8 { go work() }\n    \
+                               for i 8 { _ := unwrap(join(thread: pop(mut t))) }\n}\n");
+        assert!(msg.contains("Out of fuel"), "{}", msg);
+
+        let msg = run_limited("fn main() {\n    _ := par sum i 4000 { i }\n}\n");
+        assert!(msg.contains("Out of fuel"), "{}", msg);
+    }
+
+    #[test]
+    fn capabilities() {
+        use std::sync::Arc;
+        use super::*;
+
+        let source = Arc::new("fn main() { println(read_line()) }".to_string());
+        let mut module = Module::with_capabilities(Capabilities::none());
+        match load_str("main.dyon", source.clone(), &mut module) {
+            Err(DyonError::Check(info)) => assert_eq!(info.message,
+                "Function `read_line` is not allowed by the capabilities of the module"),
+            x => panic!("Expected check error, got {:?}", x.err()),
+        }
+        let mut module = Module::with_capabilities(Capabilities {stdin: false, ..Capabilities::all()});
+        assert!(load_str("main.dyon", source.clone(), &mut module).is_err());
+        let mut module = Module::with_capabilities(Capabilities {stdin: true, ..Capabilities::none()});
+        assert!(load_str("main.dyon", source, &mut module).is_ok());
+
+        // Virtualize the file system.
+        fn virtual_load_string(rt: &mut Runtime) -> Result<Variable, String> {
+            let file: Arc<String> = rt.pop()?;
+            Ok(Variable::Result(Ok(Box::new(Variable::Str(
+                Arc::new(format!("Contents of {}", file)))))))
+        }
+        let mut module = Module::with_capabilities(Capabilities::none());
+        module.add_str("load_string__file", virtual_load_string,
+                       Dfn::nl(vec![Type::Str], Type::Result(Box::new(Type::Str))));
+        let source = "fn main() -> { return unwrap(load_string__file(\"a.txt\")) }";
+        load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap();
+        let val = Runtime::new().call_str_ret("main", &[], &Arc::new(module)).unwrap();
+        match val {
+            Variable::Str(ref s) => assert_eq!(&**s, "Contents of a.txt"),
+            x => panic!("Expected string, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn cache() {
+        use std::fs;
+        use std::sync::Arc;
+        use cache::Compiled;
+        use super::*;
+
+        let source = Arc::new("fn sq(x: f64) -> { return x * x }\n\
+                               fn f() -> { return \\(x) = sq(x) }\n\
+                               fn main() {\n    g := f()\n    a := {x: \\g(3), y: [1, 2]}\n    \
+                               for i 2 { a.y[i] += i }\n    \
+                               if a.x == 9 { _ := $\"{a.y[1]}\" }\n}".to_string());
+        let module = Module::new();
+        let compiled = Compiled::compile("main.dyon", &source, &module).unwrap();
+        let mut buf = vec![];
+        compiled.write(&mut buf, &module).unwrap();
+        let read = Compiled::read(&mut &buf[..], "main.dyon", &source, &module).unwrap();
+        let mut buf2 = vec![];
+        read.write(&mut buf2, &module).unwrap();
+        assert_eq!(buf, buf2);
+        assert!(Compiled::read(&mut &buf[1..], "main.dyon", &source, &module).is_err());
+        assert!(Compiled::read(&mut &buf[..], "main.dyon", &source, &Module::empty()).is_err());
+        let mut loaded = Module::new();
+        read.load(&mut loaded);
+        Runtime::new().run(&Arc::new(loaded)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("dyon-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for _ in 0..2 {
+            let mut module = Module::new();
+            module.set_cache_dir(Some(dir.clone()));
+            load_str("main.dyon", source.clone(), &mut module).unwrap();
+            Runtime::new().run(&Arc::new(module)).unwrap();
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Errors are not cached.
+        let mut module = Module::new();
+        module.set_cache_dir(Some(dir.clone()));
+        assert!(load_str("main.dyon", Arc::new("fn main() { x }".into()), &mut module).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reload() {
+        use std::sync::Arc;
+        use super::*;
+
+        let source = |f: &str| Arc::new(format!("{}\n\
+            fn g() -> {{\n    a := f()\n    reload()\n    return a + f()\n}}\n", f));
+        fn reload(rt: &mut Runtime) -> Result<(), String> {
+            let mut module = rt.module.clone();
+            let source = Arc::new("fn f() -> { return 10 }\n\
+                fn g() -> {\n    a := f()\n    reload()\n    return a + f()\n}\n".to_string());
+            let reload = rt.reload_str(&mut module, "main.dyon", source).map_err(|e| e.to_string())?;
+            assert_eq!(reload.changed, vec![Arc::new("f".to_string())]);
+            Ok(())
+        }
+        let call = |module: &Arc<Module>, name: &str| {
+            match Runtime::new().call_str_ret(name, &[], module) {
+                Ok(Variable::F64(x, _)) => x,
+                x => panic!("Expected number, got {:?}", x),
+            }
+        };
+
+        let mut module = Module::new();
+        module.add_str("reload", reload, Dfn::nl(vec![], Type::Void));
+        load_str("main.dyon", source("fn f() -> { return 1 }"), &mut module).unwrap();
+        let mut module = Arc::new(module);
+        let mut rt = Runtime::new();
+
+        // Running code uses the reloaded function.
+        assert_eq!(call(&module, "g"), 11.0);
+
+        let reload = rt.reload_str(&mut module, "main.dyon", source("fn f() -> { return 2 }"))
+            .unwrap();
+        assert_eq!(reload.changed, vec![Arc::new("f".to_string())]);
+        assert!(!reload.needs_restart());
+        assert_eq!(call(&module, "f"), 2.0);
+
+        // Reloads that fail checking are rejected.
+        assert!(rt.reload_str(&mut module, "main.dyon", source("fn f() -> { return x }")).is_err());
+        assert!(rt.reload_str(&mut module, "other.dyon", source("fn f() -> { return 3 }")).is_err());
+        assert_eq!(call(&module, "f"), 2.0);
+
+        let reload = rt.reload_str(&mut module, "main.dyon",
+            source("fn f() -> f64 { return 3 }\nfn h() {}")).unwrap();
+        assert_eq!(reload.signature_changed, vec![Arc::new("f".to_string())]);
+        assert_eq!(reload.added, vec![Arc::new("h".to_string())]);
+        assert!(reload.needs_restart());
+        assert_eq!(call(&module, "f"), 3.0);
+    }
+
     fn run_bench(source: &str) {
         run(source).unwrap_or_else(|err| panic!("{}", err));
     }
 
+    fn run_bench_bytecode(source: &str) {
+        use std::sync::Arc;
+        use super::*;
+
+        let mut module = Module::new();
+        load(source, &mut module).unwrap_or_else(|err| panic!("{}", err));
+        let mut rt = Runtime::new();
+        rt.bytecode = true;
+        rt.run(&Arc::new(module)).unwrap_or_else(|err| panic!("{}", err));
+    }
+
     #[bench]
     fn bench_add(b: &mut Bencher) {
         b.iter(|| run_bench("source/bench/add.dyon"));
@@ -798,6 +1868,11 @@ mod tests {
         b.iter(|| run_bench("source/bench/n_body.dyon"));
     }
 
+    #[bench]
+    fn bench_n_body_bytecode(b: &mut Bencher) {
+        b.iter(|| run_bench_bytecode("source/bench/n_body.dyon"));
+    }
+
     #[bench]
     fn bench_len(b: &mut Bencher) {
         b.iter(|| run_bench("source/bench/len.dyon"));
@@ -828,6 +1903,7 @@ mod tests {
         b.iter(|| run_bench("source/bench/threads_no_go.dyon"));
     }
 
+    #[cfg(feature = "threading")]
     #[bench]
     fn bench_threads_go(b: &mut Bencher) {
         b.iter(|| run_bench("source/bench/threads_go.dyon"));
@@ -848,6 +1924,7 @@ mod tests {
         b.iter(|| run_bench("source/bench/push_link_for.dyon"));
     }
 
+    #[cfg(feature = "threading")]
     #[bench]
     fn bench_push_link_go(b: &mut Bencher) {
         b.iter(|| run_bench("source/bench/push_link_go.dyon"));
@@ -5,6 +5,11 @@ pub enum Kind {
     Uses,
     Use,
     Fn,
+    Record,
+    RecordField,
+    Enum,
+    EnumVariant,
+    VariantArg,
     Arg,
     Current,
     Block,
@@ -15,12 +20,14 @@ pub enum Kind {
     Val,
     Call,
     CallArg,
+    Variant,
     Assign,
     Left,
     Right,
     Item,
     ItemExtra,
     Return,
+    Yield,
     Object,
     Array,
     ArrayItem,
@@ -31,6 +38,7 @@ pub enum Kind {
     For,
     ForN,
     ForIn,
+    Lock,
     Sum,
     SumIn,
     SumVec4,
@@ -58,6 +66,16 @@ pub enum Kind {
     Step,
     Compare,
     If,
+    Match,
+    MatchArm,
+    Pattern,
+    PatternSome,
+    PatternNone,
+    PatternOk,
+    PatternErr,
+    PatternField,
+    PatternVariant,
+    PatternArg,
     TrueBlock,
     ElseBlock,
     Loop,
@@ -93,6 +111,8 @@ pub enum Kind {
     LinkFor,
     LinkIn,
     LinkItem,
+    Interp,
+    InterpItem,
     Closure,
     CallClosure,
     ClosureType,
@@ -113,6 +133,11 @@ impl Kind {
             "uses" => Kind::Uses,
             "use" => Kind::Use,
             "fn" => Kind::Fn,
+            "record" => Kind::Record,
+            "record_field" => Kind::RecordField,
+            "enum" => Kind::Enum,
+            "enum_variant" => Kind::EnumVariant,
+            "variant_arg" => Kind::VariantArg,
             "arg" => Kind::Arg,
             "current" => Kind::Current,
             "block" => Kind::Block,
@@ -130,6 +155,7 @@ impl Kind {
             "item" => Kind::Item,
             "item_extra" => Kind::ItemExtra,
             "return" => Kind::Return,
+            "yield" => Kind::Yield,
             "object" => Kind::Object,
             "array" => Kind::Array,
             "array_item" => Kind::ArrayItem,
@@ -167,6 +193,16 @@ impl Kind {
             "step" => Kind::Step,
             "compare" => Kind::Compare,
             "if" => Kind::If,
+            "match" => Kind::Match,
+            "match_arm" => Kind::MatchArm,
+            "pattern" => Kind::Pattern,
+            "pattern_some" => Kind::PatternSome,
+            "pattern_none" => Kind::PatternNone,
+            "pattern_ok" => Kind::PatternOk,
+            "pattern_err" => Kind::PatternErr,
+            "pattern_field" => Kind::PatternField,
+            "pattern_variant" => Kind::PatternVariant,
+            "pattern_arg" => Kind::PatternArg,
             "true_block" => Kind::TrueBlock,
             "else_block" => Kind::ElseBlock,
             "loop" => Kind::Loop,
@@ -193,6 +229,7 @@ impl Kind {
             "ret_type" => Kind::RetType,
             "return_void" => Kind::ReturnVoid,
             #[cfg(feature = "threading")] "go" => Kind::Go,
+            #[cfg(feature = "threading")] "lock" => Kind::Lock,
             "swizzle" => Kind::Swizzle,
             "sw0" => Kind::Sw0,
             "sw1" => Kind::Sw1,
@@ -202,6 +239,8 @@ impl Kind {
             "link_for" => Kind::LinkFor,
             "link_in" => Kind::LinkIn,
             "link_item" => Kind::LinkItem,
+            "interp" => Kind::Interp,
+            "interp_item" => Kind::InterpItem,
             "closure" => Kind::Closure,
             "call_closure" => Kind::CallClosure,
             "named_call_closure" => Kind::CallClosure,
@@ -248,6 +287,17 @@ impl Kind {
         }
     }
 
+    /// A pattern in a match arm can bind variables.
+    pub fn is_pattern(self) -> bool {
+        use self::Kind::*;
+
+        match self {
+            Pattern | PatternSome | PatternNone | PatternOk |
+            PatternErr | PatternField | PatternVariant | PatternArg => true,
+            _ => false
+        }
+    }
+
     pub fn is_block(self) -> bool {
         use self::Kind::*;
 
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use std::collections::{HashMap, HashSet};
 use self::piston_meta::MetaData;
 use self::range::Range;
-use self::kind::Kind;
+pub(crate) use self::kind::Kind;
 use self::node::convert_meta_data;
 pub(crate) use self::node::Node;
 use self::lt::{arg_lifetime, compare_lifetimes, Lifetime};
@@ -28,14 +28,16 @@ pub fn check(
     prelude: &Prelude,
 ) -> Result<HashMap<Arc<String>, Type>, Range<String>> {
     let mut nodes: Vec<Node> = vec![];
-    check_core(&mut nodes, data, prelude)
+    check_core(&mut nodes, data, prelude, &mut vec![])
 }
 
 // Core lifetime and type check.
+// Warnings from type checking are pushed to `warnings`.
 pub(crate) fn check_core(
     nodes: &mut Vec<Node>,
     data: &[Range<MetaData>],
-    prelude: &Prelude
+    prelude: &Prelude,
+    warnings: &mut Vec<Range<String>>
 ) -> Result<HashMap<Arc<String>, Type>, Range<String>> {
 
     convert_meta_data(nodes, data)?;
@@ -66,6 +68,7 @@ pub(crate) fn check_core(
                 alias: None,
                 mutable: false,
                 try: false,
+                par: false,
                 grab_level: 0,
                 source: nodes[i].source,
                 start: nodes[i].start,
@@ -125,6 +128,11 @@ pub(crate) fn check_core(
                 Cross => crate::CROSS.clone(),
                 AndAlso => crate::AND_ALSO.clone(),
                 OrElse => crate::OR_ELSE.clone(),
+                BitAnd => crate::BIT_AND.clone(),
+                BitOr => crate::BIT_OR.clone(),
+                Xor => crate::XOR.clone(),
+                Shl => crate::SHL.clone(),
+                Shr => crate::SHR.clone(),
                 Less => crate::LESS.clone(),
                 LessOrEqual => crate::LESS_OR_EQUAL.clone(),
                 Greater => crate::GREATER.clone(),
@@ -324,7 +332,8 @@ pub(crate) fn check_core(
         'search: loop {
             if nodes[parent].kind.is_decl_loop() ||
                nodes[parent].kind.is_decl_un_loop() ||
-               nodes[parent].kind.is_in_loop() {
+               nodes[parent].kind.is_in_loop() ||
+               nodes[parent].kind == Kind::Lock {
                 let my_name = nodes[i].name().unwrap();
                 for name in &nodes[parent].names {
                     if name == my_name {
@@ -334,6 +343,16 @@ pub(crate) fn check_core(
                 }
             }
 
+            if nodes[parent].kind == Kind::MatchArm {
+                let my_name = nodes[i].name().unwrap();
+                if let Some(pattern) = nodes[parent].find_child_by_kind(nodes, Kind::Pattern) {
+                    if let Some(j) = find_pattern_bind(nodes, pattern, my_name) {
+                        it = Some(j);
+                        break 'search;
+                    }
+                }
+            }
+
             let me = nodes[parent].children.binary_search(&child)
                 .expect("Expected parent to contain child");
             let children = &nodes[parent].children[..me];
@@ -551,6 +570,50 @@ pub(crate) fn check_core(
         }
     }
 
+    // Check for duplicate records and fields.
+    let mut record_names: Vec<&Arc<String>> = vec![];
+    for node in nodes.iter() {
+        if node.kind != Kind::Record {continue};
+        let name = node.name().expect("Expected name");
+        if record_names.contains(&name) {
+            return Err(node.source.wrap(format!("Duplicate record `{}`", name)));
+        }
+        record_names.push(name);
+        for (j, &a) in node.children.iter().enumerate() {
+            let field = nodes[a].name().expect("Expected name");
+            if node.children[..j].iter().any(|&b| nodes[b].name() == Some(field)) {
+                return Err(nodes[a].source.wrap(
+                    format!("Duplicate field `{}` in record `{}`", field, name)));
+            }
+        }
+    }
+
+    // Check for duplicate enums and variants,
+    // and build a map from enum names to variants with number of arguments.
+    let mut enums: HashMap<Arc<String>, Vec<(Arc<String>, usize)>> = HashMap::new();
+    for e in &prelude.enums {
+        enums.insert(e.name.clone(), e.variants.iter().map(|v| (v.0.clone(), v.1.len())).collect());
+    }
+    let mut enum_names: Vec<&Arc<String>> = vec![];
+    for node in nodes.iter() {
+        if node.kind != Kind::Enum {continue};
+        let name = node.name().expect("Expected name");
+        if enum_names.contains(&name) {
+            return Err(node.source.wrap(format!("Duplicate enum `{}`", name)));
+        }
+        enum_names.push(name);
+        let mut variants: Vec<(Arc<String>, usize)> = vec![];
+        for &a in &node.children {
+            let variant = nodes[a].name().expect("Expected name");
+            if variants.iter().any(|v| v.0 == *variant) {
+                return Err(nodes[a].source.wrap(
+                    format!("Duplicate variant `{}` in enum `{}`", variant, name)));
+            }
+            variants.push((variant.clone(), nodes[a].children.len()));
+        }
+        enums.insert(name.clone(), variants);
+    }
+
     let mut use_lookup: UseLookup = UseLookup::new();
     for node in nodes.iter() {
         if node.kind == Kind::Uses {
@@ -594,6 +657,20 @@ pub(crate) fn check_core(
             if let Some(&FnAlias::Loaded(i)) = use_lookup.aliases.get(alias).and_then(|map| map.get(&name)) {
                 node.lts = prelude.list[i].lts.clone();
                 continue;
+            } else if let (false, Some(variants)) =
+                (use_lookup.aliases.contains_key(alias), enums.get(alias)) {
+                // Enum variants are constructed like calls, e.g. `Shape::Circle(2)`.
+                match variants.iter().find(|v| v.0 == name) {
+                    Some(&(_, args)) if args == n => {
+                        node.kind = Kind::Variant;
+                        continue;
+                    }
+                    Some(&(_, args)) => return Err(node.source.wrap(
+                        format!("{}::{}: Expected {} arguments, found {}",
+                        alias, name, args, n))),
+                    None => return Err(node.source.wrap(
+                        format!("Could not find variant `{}::{}`", alias, name))),
+                }
             } else {
                 return Err(node.source.wrap(
                     format!("Could not find function `{}::{}`", alias, name)));
@@ -612,6 +689,10 @@ pub(crate) fn check_core(
                     }
                     continue;
                 }
+                if prelude.denied.contains(&name) {
+                    return Err(node.source.wrap(format!(
+                        "Function `{}` is not allowed by the capabilities of the module", name)));
+                }
                 let suggestions = suggestions(&**name, &function_lookup, prelude);
                 return Err(node.source.wrap(
                     format!("Could not find function `{}`{}", name, suggestions)));
@@ -627,6 +708,25 @@ pub(crate) fn check_core(
         node.declaration = Some(functions[i]);
     }
 
+    // Check variant patterns.
+    for node in nodes.iter() {
+        if node.kind != Kind::PatternVariant {continue};
+        let alias = node.alias.as_ref().expect("Expected alias");
+        let name = node.name().expect("Expected name");
+        let variants = match enums.get(alias) {
+            None => return Err(node.source.wrap(format!("Could not find enum `{}`", alias))),
+            Some(variants) => variants
+        };
+        let n = node.children.len();
+        match variants.iter().find(|v| v.0 == *name) {
+            Some(&(_, args)) if args == n => {}
+            Some(&(_, args)) => return Err(node.source.wrap(
+                format!("{}::{}: Expected {} arguments, found {}", alias, name, args, n))),
+            None => return Err(node.source.wrap(
+                format!("Could not find variant `{}::{}`", alias, name))),
+        }
+    }
+
     // Check in-nodes.
     for &c in &ins {
         let node = &mut nodes[c];
@@ -648,6 +748,10 @@ pub(crate) fn check_core(
             None => {
                 // Check whether it is a prelude function.
                 if prelude.functions.get(&name).is_some() {continue};
+                if prelude.denied.contains(&name) {
+                    return Err(node.source.wrap(format!(
+                        "Function `{}` is not allowed by the capabilities of the module", name)));
+                }
                 let suggestions = suggestions(&**name, &function_lookup, prelude);
                 return Err(node.source.wrap(
                     format!("Could not find function `{}`{}", name, suggestions)));
@@ -836,6 +940,34 @@ pub(crate) fn check_core(
         }
     }
 
+    // Check that `go` closures do not use current objects,
+    // since these are references to variables on the stack of the thread.
+    for (c, _) in nodes.iter().enumerate().filter(|&(_, n)| n.kind == Kind::CallClosure) {
+        if let Some(parent) = nodes[c].parent {
+            if nodes[parent].kind != Kind::Go { continue }
+        } else {
+            continue;
+        }
+        let item = match nodes[c].find_child_by_kind(&nodes, Kind::Item) {
+            Some(item) if !nodes[item].item_ids() => item,
+            _ => continue,
+        };
+        // Find the closure that the variable is declared with.
+        let mut n = match nodes[item].declaration
+            .and_then(|decl| locals.iter().find(|&&(_, it)| it == decl)) {
+            Some(&(a, _)) => nodes[a].children[1],
+            None => continue,
+        };
+        while nodes[n].kind != Kind::Closure && nodes[n].children.len() == 1 {
+            n = nodes[n].children[0];
+        }
+        if nodes[n].kind == Kind::Closure &&
+           nodes[n].find_child_by_kind(&nodes, Kind::Current).is_some() {
+            return Err(nodes[item].source.wrap(
+                "Can not use `go` because the closure uses current objects".to_string()));
+        }
+    }
+
     // Check that calls satisfy the lifetime constraints of arguments.
     for &c in &calls {
         let call = &nodes[c];
@@ -1000,7 +1132,62 @@ pub(crate) fn check_core(
         }
     }
 
-    typecheck::run(nodes, prelude, &use_lookup)?;
+    // Collect functions that mutate current objects, directly or through calls.
+    let mut mut_currents: HashSet<usize> = functions.iter().cloned()
+        .filter(|&f| nodes[f].children.iter()
+            .any(|&ch| nodes[ch].kind == Kind::Current && nodes[ch].mutable))
+        .collect();
+    loop {
+        let n = mut_currents.len();
+        for &c in &calls {
+            if let Some(decl) = nodes[c].declaration {
+                if !mut_currents.contains(&decl) { continue }
+                let mut f = c;
+                while let Some(parent) = nodes[f].parent { f = parent; }
+                mut_currents.insert(f);
+            }
+        }
+        if mut_currents.len() == n { break; }
+    }
+
+    // Check that parallel loops do not mutate variables declared outside the loop.
+    for (p, _) in nodes.iter().enumerate().filter(|&(_, n)| n.par) {
+        let inside = |mut i: usize| {
+            while let Some(parent) = nodes[i].parent {
+                if parent == p { return true; }
+                i = parent;
+            }
+            false
+        };
+        let outside = |i: usize| nodes[i].declaration.map(|decl| !inside(decl)).unwrap_or(false);
+        let mut mutated = mutated_locals.iter().chain(assigned_locals.iter())
+            .filter(|&&(a, i)| inside(a) && outside(i))
+            .map(|&(_, i)| i)
+            .chain(calls.iter()
+                .filter(|&&c| inside(c))
+                .flat_map(|&c| nodes[c].children.iter())
+                .filter(|&&n| nodes[n].kind == Kind::CallArg && nodes[n].mutable)
+                // Item is 2 levels down inside call_arg/item
+                .filter_map(|&n| nodes[n].children.first().cloned())
+                .filter(|&i| nodes[i].kind == Kind::Item && outside(i)));
+        if let Some(i) = mutated.next() {
+            return Err(nodes[i].source.wrap(
+                format!("Can not mutate `{}` inside `par` loop, \
+                    because it is not declared in the loop body", nodes[i].name().unwrap())
+            ));
+        }
+        // Each chunk works on a copy of the current objects.
+        if let Some(&c) = calls.iter().find(|&&c| inside(c) &&
+            nodes[c].declaration.map(|d| mut_currents.contains(&d)).unwrap_or(false))
+        {
+            return Err(nodes[c].source.wrap(
+                format!("Can not call `{}` inside `par` loop, \
+                    because it mutates current objects", nodes[c].name().unwrap())
+            ));
+        }
+    }
+
+    typecheck::run(nodes, prelude, &use_lookup, warnings)?;
 
     // Copy refined return types to use in AST.
     let mut refined_rets: HashMap<Arc<String>, Type> = HashMap::new();
@@ -1013,6 +1200,17 @@ pub(crate) fn check_core(
     Ok(refined_rets)
 }
 
+// Search for the pattern node that binds a variable in a match arm.
+fn find_pattern_bind(nodes: &[Node], i: usize, name: &Arc<String>) -> Option<usize> {
+    // The name of a variant pattern is the name of the variant.
+    if nodes[i].kind != Kind::PatternVariant &&
+       nodes[i].names.iter().any(|n| n == name) { return Some(i); }
+    nodes[i].children.iter()
+        .filter(|&&ch| nodes[ch].kind.is_pattern())
+        .filter_map(|&ch| find_pattern_bind(nodes, ch, name))
+        .next()
+}
+
 // Search for suggestions using matching function signature.
 // Meant to be put last in error message.
 fn suggestions(
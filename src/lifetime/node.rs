@@ -23,6 +23,8 @@ pub(crate) struct Node {
     pub mutable: bool,
     /// Whether there is a `?` operator used on the node.
     pub try: bool,
+    /// Whether the loop runs in parallel.
+    pub par: bool,
     /// The grab level.
     pub grab_level: u16,
     /// The range in source.
@@ -78,6 +80,7 @@ impl Node {
             alias: None,
             mutable: false,
             try: false,
+            par: false,
             grab_level: 0,
             source: nodes[old_left].source,
             start: nodes[old_left].start,
@@ -98,6 +101,7 @@ impl Node {
             alias: None,
             mutable: false,
             try: false,
+            par: false,
             grab_level: 0,
             source: nodes[old_right].source,
             start: nodes[old_right].start,
@@ -179,8 +183,10 @@ impl Node {
             Pow | Sum | SumIn | Prod | ProdIn | SumVec4 | Min | MinIn | Max | MaxIn |
             Any | AnyIn | All | AllIn | LinkIn |
             Vec4 | Mat4 | Vec4UnLoop | Swizzle |
-            Assign | For | ForN | ForIn | Link | LinkFor |
-            Closure | CallClosure | Grab | TryExpr | Norm | In => false,
+            Assign | For | ForN | ForIn | Lock | Link | LinkFor | Interp | Yield |
+            Closure | CallClosure | Grab | TryExpr | Norm | In |
+            // A variant deep clones its payload.
+            Variant => false,
             Add | Mul | Compare => self.children.len() == 1,
             _ => true
         }
@@ -199,6 +205,17 @@ impl Node {
                     return arg_lifetime(declaration, &arg, nodes, arg_names);
                 } else if arg.kind == Kind::Current {
                     return Some(Lifetime::Current(declaration));
+                } else if arg.kind.is_pattern() {
+                    // Variables bound by a pattern live as long as the matched value.
+                    let mut parent = arg.parent;
+                    while let Some(p) = parent {
+                        if nodes[p].kind == Kind::Match {
+                            return nodes[p].find_child_by_kind(nodes, Kind::Expr)
+                                .and_then(|expr| nodes[expr].lifetime(nodes, arg_names));
+                        }
+                        parent = nodes[p].parent;
+                    }
+                    return None;
                 } else {
                     return Some(Lifetime::Local(declaration));
                 }
@@ -245,13 +262,16 @@ impl Node {
                 (_, Kind::LinkFor) => {}
                 (_, Kind::LinkIn) => {}
                 (_, Kind::LinkItem) => {}
+                (_, Kind::Interp) => {}
                 (_, Kind::ReturnVoid) => {}
+                (_, Kind::Yield) => {}
                 (_, Kind::Swizzle) => {}
                 (_, Kind::Loop) => {}
                 (_, Kind::Go) => {}
                 (_, Kind::For) => {}
                 (_, Kind::ForN) => {}
                 (_, Kind::ForIn) => {}
+                (_, Kind::Lock) => {}
                 (_, Kind::Break) => {}
                 (_, Kind::Continue) => {}
                 (_, Kind::Sift) => {}
@@ -283,6 +303,7 @@ impl Node {
                 (_, Kind::Add) => {}
                 (_, Kind::Mul) => {}
                 (_, Kind::Call) => {}
+                (_, Kind::Variant) => {}
                 (_, Kind::In) => {}
                 (_, Kind::Closure) => {}
                 (_, Kind::CallClosure) => {}
@@ -300,6 +321,10 @@ impl Node {
                 }
                 (_, Kind::Left) => {}
                 (_, Kind::Right) => {}
+                (Kind::Match, Kind::Expr) => {
+                    // The matched value is used through bound variables.
+                    continue
+                }
                 (_, Kind::Expr) => {}
                 (_, Kind::Return) => {}
                 (_, Kind::Array) => {}
@@ -308,6 +333,9 @@ impl Node {
                 (_, Kind::Pow) => {}
                 (_, Kind::Block) => {}
                 (_, Kind::If) => {}
+                (_, Kind::Match) => {}
+                (_, Kind::MatchArm) => {}
+                (_, Kind::Pattern) => { continue }
                 (_, Kind::TrueBlock) => {}
                 (_, Kind::ElseIfBlock) => {}
                 (_, Kind::ElseBlock) => {}
@@ -402,11 +430,12 @@ pub(crate) fn convert_meta_data(
                     Kind::Sum | Kind::SumIn | Kind::Prod | Kind::ProdIn => Some(Type::F64),
                     Kind::Swizzle => Some(Type::F64),
                     Kind::Link | Kind::LinkFor => Some(Type::Link),
+                    Kind::Interp => Some(Type::Str),
                     Kind::Any | Kind::AnyIn | Kind::All | Kind::AllIn =>
                         Some(Type::Secret(Box::new(Type::Bool))),
                     Kind::Min | Kind::MinIn | Kind::Max | Kind::MaxIn =>
                         Some(Type::Secret(Box::new(Type::F64))),
-                    Kind::For | Kind::ForN => Some(Type::Void),
+                    Kind::For | Kind::ForN | Kind::Yield => Some(Type::Void),
                     Kind::TyArg | Kind::TyRet => {
                         // Parse extra type information.
                         let convert = Convert::new(&data[i..]);
@@ -422,6 +451,12 @@ pub(crate) fn convert_meta_data(
                 };
 
                 let parent = parents.last().map(|i| *i);
+                if let Some(parent) = parent {
+                    // A computed id has no name.
+                    if kind == Kind::Id && nodes[parent].kind == Kind::ItemExtra {
+                        nodes[parent].names.push(Arc::new(String::new()));
+                    }
+                }
                 parents.push(nodes.len());
                 nodes.push(Node {
                     kind,
@@ -430,6 +465,7 @@ pub(crate) fn convert_meta_data(
                     ty,
                     mutable: false,
                     try: false,
+                    par: false,
                     grab_level: 0,
                     source: Range::empty(0),
                     parent,
@@ -460,6 +496,14 @@ pub(crate) fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].alias = Some(val.clone());
                     }
+                    "i64" => {
+                        if val.replace('_', "").parse::<i64>().is_err() {
+                            return Err(d.range().wrap(
+                                "Expected integer in range of `i64`".to_string()));
+                        }
+                        let i = *parents.last().unwrap();
+                        nodes[i].ty = Some(Type::I64);
+                    }
                     "name" => {
                         let i = *parents.last().unwrap();
                         nodes[i].names.push(val.clone());
@@ -491,11 +535,30 @@ pub(crate) fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::Vec4);
                     }
+                    "width" | "precision" => {
+                        // Formatting panics on larger values.
+                        if val.parse::<u16>().is_err() {
+                            return Err(d.range().wrap(format!(
+                                "Expected {} from 0 to {}, found `{}`", n, u16::MAX, val)));
+                        }
+                        if &***n == "precision" {
+                            // Use names as a way of storing precision of embedded expressions.
+                            let i = *parents.last().unwrap();
+                            nodes[i].names.push(val.clone());
+                        }
+                    }
                     "ty_var" => {
                         // Use names as a way of storing type variables.
                         let i = *parents.last().unwrap();
                         nodes[i].names.push(val.clone());
                     }
+                    "id" | "key" => {
+                        // Use names as a way of storing object keys.
+                        let i = *parents.last().unwrap();
+                        if nodes[i].kind == Kind::ItemExtra || nodes[i].kind == Kind::KeyValue {
+                            nodes[i].names.push(val.clone());
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -537,6 +600,10 @@ pub(crate) fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].mutable = _val;
                     }
+                    "par" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].par = _val;
+                    }
                     "try" | "try_item" => {
                         let i = *parents.last().unwrap();
                         nodes[i].try = _val;
@@ -545,6 +612,10 @@ pub(crate) fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::Bool);
                     }
+                    "try_id" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].names.push(Arc::new("?".into()));
+                    }
                     "returns" => {
                         // Assuming this will be overwritten when
                         // type is parsed or inferred.
@@ -589,6 +660,26 @@ pub(crate) fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].binops.push(BinOp::AndAlso);
                     }
+                    "&" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitAnd);
+                    }
+                    "|" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitOr);
+                    }
+                    "xor" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Xor);
+                    }
+                    "<<" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Shl);
+                    }
+                    ">>" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Shr);
+                    }
                     "+" => {
                         let i = *parents.last().unwrap();
                         nodes[i].binops.push(BinOp::Add);
@@ -630,6 +721,10 @@ pub(crate) fn convert_meta_data(
             }
             MetaData::F64(ref n, val) => {
                 match &***n {
+                    "id" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].names.push(Arc::new(String::new()));
+                    }
                     "num" => {
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::F64);
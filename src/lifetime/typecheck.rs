@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use range::Range;
 use super::node::Node;
 use super::kind::Kind;
@@ -44,9 +45,47 @@ mod refine;
 /// The type propagation step uses this assumption without checking the whole `if` expression.
 /// After type propagation, all blocks in the `if` expression should have some type information,
 /// but no further propagation is necessary, so it only need to check for consistency.
-pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) -> Result<(), Range<String>> {
+///
+/// Warnings are reported for code that is accepted, but probably not what was intended,
+/// e.g. a `match` over an option or result that does not cover all cases.
+pub(crate) fn run(
+    nodes: &mut Vec<Node>,
+    prelude: &Prelude,
+    use_lookup: &UseLookup,
+    warnings: &mut Vec<Range<String>>
+) -> Result<(), Range<String>> {
     use std::collections::HashMap;
 
+    // Collect declared records, where records in the checked source shadow loaded ones.
+    let mut records: Records = HashMap::new();
+    for r in &prelude.records {
+        records.insert(r.name.clone(), r.fields.clone());
+    }
+    for node in nodes.iter() {
+        if node.kind != Kind::Record {continue};
+        let fields = node.children.iter()
+            .map(|&ch| (nodes[ch].name().unwrap().clone(),
+                        nodes[ch].ty.clone().unwrap_or(Type::Any)))
+            .collect();
+        records.insert(node.name().unwrap().clone(), fields);
+    }
+
+    // Collect declared enums, where enums in the checked source shadow loaded ones.
+    let mut enums: Enums = HashMap::new();
+    for e in &prelude.enums {
+        enums.insert(e.name.clone(), e.variants.clone());
+    }
+    for node in nodes.iter() {
+        if node.kind != Kind::Enum {continue};
+        let variants = node.children.iter()
+            .map(|&ch| (nodes[ch].name().unwrap().clone(),
+                        nodes[ch].children.iter()
+                            .map(|&arg| nodes[arg].ty.clone().unwrap_or(Type::Any))
+                            .collect()))
+            .collect();
+        enums.insert(node.name().unwrap().clone(), variants);
+    }
+
     // Keep an extra todo-list for nodes that are affected by type refinement.
     let mut todo: Vec<usize> = (0..nodes.len()).collect();
     // Keep an extra delay-errors map for nodes that should not report an error after all,
@@ -332,10 +371,14 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
                 }
                 Kind::Item => {
                     if nodes[i].item_ids() {
-                        todo.push(i);
-                        continue 'node;
-                    }
-                    if let Some(decl) = nodes[i].declaration {
+                        // The type of a record field is known from the declaration.
+                        if let Some(ty) = field_type(i, nodes, &records)? {
+                            this_ty = Some(ty);
+                        } else {
+                            todo.push(i);
+                            continue 'node;
+                        }
+                    } else if let Some(decl) = nodes[i].declaration {
                         match nodes[decl].kind {
                             Kind::Sum | Kind::Min | Kind::Max |
                             Kind::Any | Kind::All | Kind::Sift |
@@ -353,6 +396,36 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
                                 this_ty = Some(nodes[i].inner_type(nodes[decl].ty.as_ref()
                                     .unwrap_or(&Type::Any)));
                             }
+                            Kind::Lock => {
+                                // Infer type of the guarded value from the mutex.
+                                let mutex_ty = nodes[decl].find_child_by_kind(nodes, Kind::Expr)
+                                    .and_then(|expr| nodes[expr].children.first())
+                                    .and_then(|&ch| nodes[ch].ty.as_ref());
+                                match mutex_ty {
+                                    None => {
+                                        todo.push(i);
+                                        continue 'node;
+                                    }
+                                    Some(&Type::Mutex(ref ty)) =>
+                                        this_ty = Some(nodes[i].inner_type(ty)),
+                                    Some(_) => this_ty = Some(Type::Any),
+                                }
+                            }
+                            Kind::ForIn | Kind::SumIn | Kind::ProdIn |
+                            Kind::MinIn | Kind::MaxIn | Kind::AnyIn |
+                            Kind::AllIn | Kind::SiftIn | Kind::LinkIn => {
+                                // Infer type of items from the iterated value.
+                                let iter_ty = nodes[decl].find_child_by_kind(nodes, Kind::Iter)
+                                    .and_then(|iter| nodes[iter].children.first())
+                                    .and_then(|&ch| nodes[ch].ty.as_ref());
+                                match iter_ty {
+                                    None => {
+                                        todo.push(i);
+                                        continue 'node;
+                                    }
+                                    Some(ty) => this_ty = Some(nodes[i].inner_type(&item_type(ty))),
+                                }
+                            }
                             _ => {
                                 if let Some(ref ty) = nodes[decl].ty {
                                     this_ty = Some(nodes[i].inner_type(ty));
@@ -471,6 +544,18 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
                         }
                     }
                 }
+                Kind::Lock => {
+                    // Infer type from body.
+                    let ch = if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Block) {
+                        ch
+                    } else {
+                        todo.push(i);
+                        continue 'node;
+                    };
+                    if let Some(ref ty) = nodes[ch].ty {
+                        this_ty = Some(ty.clone());
+                    }
+                }
                 Kind::Sift => {
                     // Infer type from body.
                     let ch = if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Block) {
@@ -523,6 +608,54 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
 
                     this_ty = Some(true_type);
                 }
+                Kind::Match => {
+                    // The type of a match is inferred from the first arm.
+                    let arm = match nodes[i].find_child_by_kind(nodes, Kind::MatchArm) {
+                        None => {
+                            todo.push(i);
+                            continue 'node;
+                        }
+                        Some(arm) => arm
+                    };
+                    match nodes[arm].ty.as_ref().map(|ty| nodes[i].inner_type(ty)) {
+                        None => {
+                            todo.push(i);
+                            continue 'node;
+                        }
+                        Some(arm_type) => this_ty = Some(arm_type),
+                    }
+                }
+                Kind::MatchArm => {
+                    match nodes[i].find_child_by_kind(nodes, Kind::Expr)
+                        .and_then(|ch| nodes[ch].ty.clone()) {
+                        None => {
+                            todo.push(i);
+                            continue 'node;
+                        }
+                        Some(ty) => this_ty = Some(ty),
+                    }
+                }
+                Kind::Pattern | Kind::PatternSome | Kind::PatternOk |
+                Kind::PatternErr | Kind::PatternField |
+                Kind::PatternVariant | Kind::PatternArg => {
+                    // The type of a pattern is the type of the value it matches.
+                    match matched_type(i, nodes, &enums) {
+                        None => {
+                            todo.push(i);
+                            continue 'node;
+                        }
+                        Some(ty) => {
+                            if nodes[i].ty.as_ref() == Some(&ty) {continue 'node};
+                            this_ty = Some(ty);
+                        }
+                    }
+                }
+                Kind::Variant => {
+                    // The type of a variant is the enum it belongs to.
+                    let ty = Type::AdHoc(nodes[i].alias.clone().unwrap(), Box::new(Type::Object));
+                    if nodes[i].ty.as_ref() == Some(&ty) {continue 'node};
+                    this_ty = Some(ty);
+                }
                 Kind::Arg => {
                     if nodes[i].ty.is_none() {
                         this_ty = Some(Type::Any);
@@ -624,6 +757,16 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
                         }
                     }
 
+                    // Coroutine functions yield values instead of returning them.
+                    let ty = if let Type::Coroutine(_) = *ty {
+                        if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Current) {
+                            return Err(nodes[ch].source.wrap(
+                                "Type mismatch (#3000):\n\
+                                    Coroutine functions can not use current objects".to_string()));
+                        }
+                        &Type::Void
+                    } else { ty };
+
                     // Check all return statements.
                     let mut found_return = false;
                     check_fn(i, nodes, ty, &mut found_return)?;
@@ -661,6 +804,19 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
             Kind::If => {
                 check_if(i, nodes)?
             }
+            Kind::Match => {
+                check_match(i, nodes, warnings)?
+            }
+            Kind::Variant => {
+                check_variant(i, nodes, &enums)?
+            }
+            Kind::Object => {
+                if let Some(Type::AdHoc(name, _)) = expected_type(i, nodes, prelude, use_lookup) {
+                    if let Some(fields) = records.get(&name) {
+                        check_record(i, nodes, &name, fields, &records)?
+                    }
+                }
+            }
             Kind::Assign => {
                 use ast::AssignOp;
 
@@ -680,6 +836,24 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
                             }
                         }
                     }
+                    Some(AssignOp::Assign) | Some(AssignOp::Set) => {
+                        // Check the type of a record field.
+                        let left = nodes[i].find_child_by_kind(nodes, Kind::Left).unwrap();
+                        let right = nodes[i].find_child_by_kind(nodes, Kind::Right).unwrap();
+                        let item = nodes[left].children[0];
+                        if nodes[item].item_ids() {
+                            if let (Some(ref item_ty), Some(ref right_ty)) =
+                                (&nodes[item].ty, &nodes[right].ty) {
+                                if !item_ty.goes_with(right_ty) {
+                                    return Err(nodes[right].source.wrap(
+                                        format!("Type mismatch (#2400):\n\
+                                        Expected `{}`, found `{}`",
+                                            item_ty.description(), right_ty.description())
+                                    ))
+                                }
+                            }
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -715,6 +889,79 @@ pub(crate) fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLook
                     }
                 }
             }
+            Kind::Yield => {
+                // Find function and check the type of items.
+                // The bytecode VM can only suspend inside blocks, `if` and sequential loops.
+                let mut p = i;
+                let mut supported = true;
+                let fn_ty = loop {
+                    p = match nodes[p].parent {
+                        None => break None,
+                        Some(p) => p
+                    };
+                    match nodes[p].kind {
+                        Kind::Fn => break nodes[p].ty.as_ref(),
+                        Kind::Closure => break None,
+                        Kind::Expr | Kind::Block | Kind::If | Kind::TrueBlock |
+                        Kind::ElseIfBlock | Kind::ElseBlock | Kind::For | Kind::ForN |
+                        Kind::ForIn | Kind::Loop => {}
+                        Kind::Sum | Kind::SumVec4 | Kind::Sift if !nodes[p].par => {}
+                        _ => supported = false,
+                    }
+                };
+                let item_ty = match fn_ty {
+                    Some(&Type::Coroutine(ref item_ty)) => item_ty,
+                    _ => return Err(nodes[i].source.wrap(
+                        "Type mismatch (#2800):\n\
+                            `yield` requires `-> co` on function".to_string()))
+                };
+                if !supported {
+                    return Err(nodes[i].source.wrap(
+                        "`yield` is not supported inside this expression".to_string()));
+                }
+                if let Some(&ch) = nodes[i].children.first() {
+                    if let Some(ref ty) = nodes[ch].ty {
+                        if !item_ty.goes_with(ty) {
+                            return Err(nodes[ch].source.wrap(
+                                format!("Type mismatch (#2900):\nExpected `{}`, found `{}`",
+                                    item_ty.description(), ty.description())));
+                        }
+                    }
+                }
+            }
+            Kind::Lock => {
+                if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Expr) {
+                    let expr_type = nodes[ch].ty.as_ref().map(|ty| nodes[ch].inner_type(&ty));
+                    if let Some(ref ty) = expr_type {
+                        if !Type::mutex().goes_with(ty) {
+                            return Err(nodes[ch].source.wrap(
+                                format!("Type mismatch (#3100):\nExpected `mutex`, found `{}`",
+                                    ty.description())));
+                        }
+                    }
+                }
+            }
+            Kind::InterpItem => {
+                if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Expr) {
+                    let expr_type = nodes[ch].ty.as_ref().map(|ty| nodes[ch].inner_type(&ty));
+                    match expr_type {
+                        Some(Type::Void) => {
+                            return Err(nodes[ch].source.wrap(
+                                "Type mismatch (#2600):\n\
+                                    Expected something, found `void`".to_string()));
+                        }
+                        // Precision is stored as name.
+                        Some(ref ty) if !nodes[i].names.is_empty() &&
+                                        !Type::F64.goes_with(ty) => {
+                            return Err(nodes[ch].source.wrap(
+                                format!("Type mismatch (#2700):\n\
+                                    Expected `f64` when using precision, found `{}`",
+                                    ty.description())));
+                        }
+                        _ => {}
+                    }
+                }
+            }
             Kind::Swizzle => {
                 if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Expr) {
                     let expr_type = nodes[ch].ty.as_ref().map(|ty| nodes[ch].inner_type(&ty));
@@ -837,3 +1084,317 @@ fn check_if(n: usize, nodes: &[Node]) -> Result<(), Range<String>> {
 
     Ok(())
 }
+
+fn check_match(
+    n: usize,
+    nodes: &[Node],
+    warnings: &mut Vec<Range<String>>
+) -> Result<(), Range<String>> {
+    let arms: Vec<usize> = nodes[n].children.iter().cloned()
+        .filter(|&ch| nodes[ch].kind == Kind::MatchArm).collect();
+
+    // The type of a match is inferred from the first arm.
+    if let Some(ref first_type) = nodes[n].ty {
+        for &arm in arms.iter().skip(1) {
+            if let Some(ref arm_type) = nodes[arm].ty {
+                if !arm_type.goes_with(first_type) {
+                    return Err(nodes[arm].source.wrap(
+                        format!("Type mismatch (#1800):\nExpected `{}`, found `{}`",
+                            first_type.description(), arm_type.description())));
+                }
+            }
+        }
+    }
+
+    let patterns: Vec<usize> = arms.iter()
+        .filter_map(|&arm| nodes[arm].find_child_by_kind(nodes, Kind::Pattern)).collect();
+    for &p in &patterns {
+        check_pattern(p, nodes)?;
+    }
+
+    // Warn when a match over an option or result does not cover all cases.
+    let ty = match nodes[n].find_child_by_kind(nodes, Kind::Expr)
+        .and_then(|ch| nodes[ch].ty.as_ref()) {
+        None => return Ok(()),
+        Some(ty) => ty
+    };
+    let cases: &[(Kind, &str)] = match *ty {
+        Type::Option(_) => &[(Kind::PatternSome, "some(_)"), (Kind::PatternNone, "none()")],
+        Type::Result(_) => &[(Kind::PatternOk, "ok(_)"), (Kind::PatternErr, "err(_)")],
+        _ => return Ok(()),
+    };
+    if patterns.iter().any(|&p| binds_all(p, nodes)) {return Ok(())};
+    let missing: Vec<&str> = cases.iter()
+        .filter(|&&(kind, _)| !patterns.iter().any(|&p| {
+            nodes[p].find_child_by_kind(nodes, kind)
+                .map(|ch| kind == Kind::PatternNone || binds_all(ch, nodes))
+                .unwrap_or(false)
+        }))
+        .map(|&(_, case)| case)
+        .collect();
+    if !missing.is_empty() {
+        warnings.push(nodes[n].source.wrap(
+            format!("Match over `{}` is not exhaustive:\nMissing `{}`",
+                ty.description(), missing.join("`, `"))));
+    }
+    Ok(())
+}
+
+/// Checks that a pattern can match the type of the matched value.
+fn check_pattern(n: usize, nodes: &[Node]) -> Result<(), Range<String>> {
+    let ty = match nodes[n].ty {
+        None | Some(Type::Any) => return Ok(()),
+        Some(ref ty) => ty
+    };
+    for &ch in &nodes[n].children {
+        let expected = match nodes[ch].kind {
+            Kind::PatternSome | Kind::PatternNone => Type::Option(Box::new(Type::Any)),
+            Kind::PatternOk | Kind::PatternErr => Type::Result(Box::new(Type::Any)),
+            Kind::PatternField => Type::Object,
+            Kind::PatternVariant => match nodes[ch].alias {
+                None => continue,
+                Some(ref alias) => Type::AdHoc(alias.clone(), Box::new(Type::Object))
+            },
+            _ => continue,
+        };
+        if !expected.goes_with(ty) {
+            return Err(nodes[ch].source.wrap(
+                format!("Type mismatch (#1900):\nExpected `{}`, found `{}`",
+                    expected.description(), ty.description())));
+        }
+        if nodes[ch].kind == Kind::PatternVariant {
+            for &arg in &nodes[ch].children {
+                check_pattern(arg, nodes)?;
+            }
+        } else {
+            check_pattern(ch, nodes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if a pattern matches any value, e.g. `_` or a variable.
+fn binds_all(n: usize, nodes: &[Node]) -> bool {
+    !nodes[n].names.is_empty()
+}
+
+/// Gets the type of the value matched by a pattern.
+fn matched_type(n: usize, nodes: &[Node], enums: &Enums) -> Option<Type> {
+    let parent = nodes[n].parent?;
+    if nodes[n].kind == Kind::PatternArg {
+        // Use the declared type of the variant payload.
+        let j = nodes[parent].children.iter().position(|&ch| ch == n)?;
+        let variants = enums.get(nodes[parent].alias.as_ref()?)?;
+        let name = nodes[parent].name()?;
+        return Some(variants.iter().find(|v| v.0 == *name)
+            .and_then(|v| v.1.get(j).cloned())
+            .unwrap_or(Type::Any));
+    }
+    let parent_ty = if nodes[n].kind == Kind::Pattern {
+        let match_expr = nodes[parent].parent?;
+        let expr = nodes[match_expr].find_child_by_kind(nodes, Kind::Expr)?;
+        nodes[expr].ty.as_ref()?
+    } else {
+        nodes[parent].ty.as_ref()?
+    };
+    Some(match (nodes[n].kind, parent_ty) {
+        (Kind::Pattern, ty) => ty.clone(),
+        (Kind::PatternSome, &Type::Option(ref ty)) |
+        (Kind::PatternOk, &Type::Result(ref ty)) => (**ty).clone(),
+        _ => Type::Any,
+    })
+}
+
+/// Maps record names to declared fields.
+type Records = ::std::collections::HashMap<Arc<String>, Vec<(Arc<String>, Type)>>;
+
+/// Maps enum names to declared variants with payload types.
+type Enums = ::std::collections::HashMap<Arc<String>, Vec<(Arc<String>, Vec<Type>)>>;
+
+/// Checks the payload of a variant against the declared types.
+fn check_variant(n: usize, nodes: &[Node], enums: &Enums) -> Result<(), Range<String>> {
+    let alias = nodes[n].alias.as_ref().unwrap();
+    let name = nodes[n].name().unwrap();
+    let tys = match enums.get(alias).and_then(|variants| variants.iter().find(|v| v.0 == *name)) {
+        None => return Ok(()),
+        Some(v) => &v.1
+    };
+    let args = nodes[n].children.iter().filter(|&&ch| nodes[ch].kind == Kind::CallArg);
+    for (ty, &arg) in tys.iter().zip(args) {
+        if let Some(ref arg_ty) = nodes[arg].ty {
+            if !ty.goes_with(arg_ty) {
+                return Err(nodes[arg].source.wrap(
+                    format!("Type mismatch (#2500):\nExpected `{}` for `{}::{}`, found `{}`",
+                        ty.description(), alias, name, arg_ty.description())));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Gets the type of an item accessing fields of a record.
+///
+/// Returns `None` if the type can not be known from the declaration.
+fn field_type(i: usize, nodes: &[Node], records: &Records) -> Result<Option<Type>, Range<String>> {
+    let decl = match nodes[i].declaration {
+        None => return Ok(None),
+        Some(decl) => decl
+    };
+    let mut ty = match nodes[decl].ty {
+        None => return Ok(None),
+        Some(ref ty) => nodes[i].inner_type(ty)
+    };
+    let extra = match nodes[i].find_child_by_kind(nodes, Kind::ItemExtra) {
+        None => return Ok(None),
+        Some(extra) => extra
+    };
+    for id in &nodes[extra].names {
+        if **id == "?" {
+            ty = match ty {
+                Type::Option(ty) | Type::Result(ty) => *ty,
+                _ => return Ok(None)
+            };
+            continue;
+        }
+        let (name, fields) = match ty {
+            Type::AdHoc(ref name, _) => match records.get(name) {
+                None => return Ok(None),
+                Some(fields) => (name.clone(), fields)
+            },
+            _ => return Ok(None)
+        };
+        // Computed ids are not known before running.
+        if id.is_empty() {return Ok(None)};
+        ty = match fields.iter().find(|f| f.0 == *id) {
+            None => return Err(nodes[i].source.wrap(
+                format!("Type mismatch (#2000):\nRecord `{}` has no field `{}`", name, id))),
+            Some(f) => f.1.clone()
+        };
+    }
+    Ok(Some(ty))
+}
+
+/// Gets the type expected of an expression from the surrounding code.
+///
+/// This is the return type of a function, the type of an argument
+/// or the type of a record field assigned to.
+fn expected_type(
+    i: usize,
+    nodes: &[Node],
+    prelude: &Prelude,
+    use_lookup: &UseLookup
+) -> Option<Type> {
+    let parent = nodes[i].parent?;
+    match nodes[parent].kind {
+        Kind::Return => {
+            let mut f = parent;
+            while nodes[f].kind != Kind::Fn {
+                if nodes[f].kind == Kind::Closure {return None};
+                f = nodes[f].parent?;
+            }
+            nodes[f].ty.clone()
+        }
+        Kind::Expr => {
+            // The last expression in the block of a function is returned.
+            let grand_parent = nodes[parent].parent?;
+            let f = match nodes[grand_parent].kind {
+                Kind::Fn => grand_parent,
+                Kind::Block if nodes[grand_parent].children.last() == Some(&parent) => {
+                    nodes[grand_parent].parent?
+                }
+                _ => return None
+            };
+            if nodes[f].kind != Kind::Fn {return None};
+            nodes[f].ty.clone()
+        }
+        Kind::Right => {
+            // The type of a record field assigned to.
+            let assign = nodes[parent].parent?;
+            let left = nodes[assign].find_child_by_kind(nodes, Kind::Left)?;
+            let item = *nodes[left].children.first()?;
+            if !nodes[item].item_ids() {return None};
+            nodes[item].ty.clone()
+        }
+        Kind::CallArg => {
+            use ast::FnAlias;
+
+            let call = nodes[parent].parent?;
+            if nodes[call].kind != Kind::Call {return None};
+            let j = nodes[call].children.iter().filter(|&&ch| nodes[ch].kind == Kind::CallArg)
+                .position(|&ch| ch == parent)?;
+            if let Some(decl) = nodes[call].declaration {
+                let arg = *nodes[decl].children.get(j)?;
+                if nodes[arg].kind != Kind::Arg {return None};
+                nodes[arg].ty.clone()
+            } else if let Some(ref alias) = nodes[call].alias {
+                match use_lookup.aliases.get(alias).and_then(|map| map.get(nodes[call].name()?)) {
+                    Some(&FnAlias::Loaded(f)) => prelude.list[f].tys.get(j).cloned(),
+                    _ => None
+                }
+            } else {
+                let &f = prelude.functions.get(nodes[call].name()?)?;
+                prelude.list[f].tys.get(j).cloned()
+            }
+        }
+        _ => None
+    }
+}
+
+/// Checks the fields of an object constructing a record.
+fn check_record(
+    n: usize,
+    nodes: &[Node],
+    name: &Arc<String>,
+    fields: &[(Arc<String>, Type)],
+    records: &Records
+) -> Result<(), Range<String>> {
+    let key_values: Vec<usize> = nodes[n].children.iter().cloned()
+        .filter(|&ch| nodes[ch].kind == Kind::KeyValue).collect();
+    for &kv in &key_values {
+        let key = match nodes[kv].names.first() {
+            None => continue,
+            Some(key) => key
+        };
+        let field_ty = match fields.iter().find(|f| f.0 == *key) {
+            None => return Err(nodes[kv].source.wrap(
+                format!("Type mismatch (#2100):\nRecord `{}` has no field `{}`", name, key))),
+            Some(f) => &f.1
+        };
+        let val = match nodes[kv].find_child_by_kind(nodes, Kind::Val) {
+            None => continue,
+            Some(val) => val
+        };
+        if let Type::AdHoc(ref inner_name, _) = *field_ty {
+            if let Some(inner_fields) = records.get(inner_name) {
+                if let Some(obj) = nodes[val].find_child_by_kind(nodes, Kind::Object) {
+                    check_record(obj, nodes, inner_name, inner_fields, records)?;
+                    continue;
+                }
+            }
+        }
+        if let Some(ref val_ty) = nodes[val].ty {
+            if !field_ty.goes_with(val_ty) {
+                return Err(nodes[val].source.wrap(
+                    format!("Type mismatch (#2200):\nExpected `{}` for field `{}`, found `{}`",
+                        field_ty.description(), key, val_ty.description())));
+            }
+        }
+    }
+    for f in fields {
+        if !key_values.iter().any(|&kv| nodes[kv].names.first() == Some(&f.0)) {
+            return Err(nodes[n].source.wrap(
+                format!("Type mismatch (#2300):\nRecord `{}` is missing field `{}`", name, f.0)));
+        }
+    }
+    Ok(())
+}
+
+/// Gets the type of items when iterating over a value of some type.
+fn item_type(ty: &Type) -> Type {
+    match *ty {
+        Type::In(ref ty) | Type::Array(ref ty) | Type::Coroutine(ref ty) => (**ty).clone(),
+        Type::Object => Type::Object,
+        Type::Str => Type::Str,
+        _ => Type::Any,
+    }
+}
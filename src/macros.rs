@@ -350,7 +350,8 @@ macro_rules! dyon_obj {
                 fn pop_var(rt: &$crate::Runtime, var: &$crate::Variable) -> Result<Self, String> {
                     use dyon::embed::obj_field;
                     let var = rt.resolve(var);
-                    if let &$crate::Variable::Object(ref obj) = var {
+                    if let &$crate::Variable::Object(ref obj) |
+                           &$crate::Variable::Record(_, ref obj) = var {
                         Ok($t {
                             $(
                                 $f: obj_field(rt, obj, stringify!($f))?
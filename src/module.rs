@@ -1,11 +1,90 @@
+use std::path::PathBuf;
+
 use super::*;
+use error::{ErrorInfo, RuntimeError};
+
+/// Capabilities of the standard library that give access to the outside world.
+///
+/// Functions that require a denied capability are not added to the module,
+/// such that calling them fails when loading.
+/// An embedder can virtualize a capability by adding functions with the same names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Read and write files.
+    pub filesystem: bool,
+    /// Read from the network.
+    pub network: bool,
+    /// Read from standard input.
+    pub stdin: bool,
+    /// Read the arguments of the process.
+    pub args: bool,
+    /// Load modules dynamically.
+    pub modules: bool,
+    /// Pause the current thread.
+    pub sleep: bool,
+}
+
+impl Capabilities {
+    /// Allows everything.
+    pub fn all() -> Capabilities {
+        Capabilities {
+            filesystem: true,
+            network: true,
+            stdin: true,
+            args: true,
+            modules: true,
+            sleep: true,
+        }
+    }
+
+    /// Denies everything.
+    pub fn none() -> Capabilities {
+        Capabilities {
+            filesystem: false,
+            network: false,
+            stdin: false,
+            args: false,
+            modules: false,
+            sleep: false,
+        }
+    }
+
+    /// Returns `true` if a standard library function is allowed.
+    pub fn allows(&self, name: &str) -> bool {
+        match name {
+            "load__meta_file" | "save__string_file" | "load_string__file" |
+            "save__bytes_file" | "load_bytes__file" |
+            "load_data__file" | "save__data_file" => self.filesystem,
+            "load__meta_url" | "load_string__url" => self.network,
+            "download__url_file" => self.network && self.filesystem,
+            "read_line" | "read_number" => self.stdin,
+            "args_os" => self.args,
+            "load" | "load__source_imports" => self.modules && self.filesystem,
+            "module__in_string_imports" => self.modules,
+            "sleep" => self.sleep,
+            _ => true
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {Capabilities::all()}
+}
 
 /// Stores functions for a Dyon module.
 #[derive(Clone)]
 pub struct Module {
     pub(crate) functions: Vec<ast::Function>,
+    /// Declared record types.
+    pub(crate) records: Vec<ast::Record>,
+    /// Declared enum types.
+    pub(crate) enums: Vec<ast::Enum>,
     pub(crate) ext_prelude: Vec<FnExternal>,
     pub(crate) register_namespace: Arc<Vec<Arc<String>>>,
+    /// Standard library functions denied by capabilities.
+    pub(crate) denied: Vec<Arc<String>>,
+    /// Directory of compiled sources, see `Module::set_cache_dir`.
+    pub(crate) cache_dir: Option<PathBuf>,
 }
 
 impl Default for Module {
@@ -17,16 +96,38 @@ impl Module {
     pub fn empty() -> Module {
         Module {
             functions: vec![],
+            records: vec![],
+            enums: vec![],
             ext_prelude: vec![],
             register_namespace: Arc::new(vec![]),
+            denied: vec![],
+            cache_dir: None,
         }
     }
 
+    /// Sets the directory where compiled sources are cached.
+    ///
+    /// When set, `load` and `load_str` skip parsing, checking and conversion of unchanged sources.
+    /// A source is recompiled when its text or the functions in the module change.
+    pub fn set_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.cache_dir = dir;
+    }
+
     /// Import external prelude from other module.
     pub fn import_ext_prelude(&mut self, other: &Module) {
         for f in &other.ext_prelude {
             self.ext_prelude.push(f.clone());
         }
+        self.import_denied(other);
+    }
+
+    /// Import names of functions denied by capabilities.
+    fn import_denied(&mut self, other: &Module) {
+        for name in &other.denied {
+            if !self.denied.contains(name) {
+                self.denied.push(name.clone());
+            }
+        }
     }
 
     /// Import external prelude and loaded functions from module.
@@ -43,10 +144,20 @@ impl Module {
         for f in &other.functions {
             self.functions.push(f.clone())
         }
+        for r in &other.records {
+            self.register_record(r.clone());
+        }
+        for e in &other.enums {
+            self.register_enum(e.clone());
+        }
+        self.import_denied(other);
     }
 
     /// Creates a new module with standard library.
-    pub fn new() -> Module {
+    pub fn new() -> Module {Module::with_capabilities(Capabilities::all())}
+
+    /// Creates a new module with the parts of the standard library allowed by capabilities.
+    pub fn with_capabilities(capabilities: Capabilities) -> Module {
         use Type::*;
         use dyon_std::*;
 
@@ -59,6 +170,7 @@ impl Module {
             ext: vec![
                 (vec![], vec![Secret(Box::new(F64)), F64], Secret(Box::new(Bool))),
                 (vec![], vec![F64; 2], Bool),
+                (vec![], vec![I64; 2], Bool),
                 (vec![], vec![Str; 2], Bool),
             ],
             lazy: LAZY_NO
@@ -70,6 +182,7 @@ impl Module {
             ext: vec![
                 (vec![], vec![Secret(Box::new(F64)), F64], Secret(Box::new(Bool))),
                 (vec![], vec![F64; 2], Bool),
+                (vec![], vec![I64; 2], Bool),
                 (vec![], vec![Str; 2], Bool),
             ],
             lazy: LAZY_NO
@@ -81,6 +194,7 @@ impl Module {
             ext: vec![
                 (vec![], vec![Secret(Box::new(F64)), F64], Secret(Box::new(Bool))),
                 (vec![], vec![F64; 2], Bool),
+                (vec![], vec![I64; 2], Bool),
                 (vec![], vec![Str; 2], Bool),
             ],
             lazy: LAZY_NO
@@ -92,6 +206,7 @@ impl Module {
             ext: vec![
                 (vec![], vec![Secret(Box::new(F64)), F64], Secret(Box::new(Bool))),
                 (vec![], vec![F64; 2], Bool),
+                (vec![], vec![I64; 2], Bool),
                 (vec![], vec![Str; 2], Bool),
             ],
             lazy: LAZY_NO
@@ -103,12 +218,16 @@ impl Module {
             ext: vec![
                 (vec![], vec![Secret(Box::new(F64)), F64], Secret(Box::new(Bool))),
                 (vec![], vec![F64; 2], Bool),
+                (vec![], vec![I64; 2], Bool),
                 (vec![], vec![Str; 2], Bool),
+                (vec![], vec![Bytes; 2], Bool),
                 (vec![], vec![Secret(Box::new(Bool)), Bool], Secret(Box::new(Bool))),
                 (vec![], vec![Bool; 2], Bool),
                 (vec![], vec![Vec4; 2], Bool),
                 (vec![], vec![Type::object(), Type::object()], Bool),
                 (vec![], vec![Type::array(), Type::array()], Bool),
+                (vec![], vec![Type::map(), Type::map()], Bool),
+                (vec![], vec![Type::set(), Type::set()], Bool),
                 (vec![], vec![Type::option(), Type::option()], Bool),
             ],
             lazy: LAZY_NO
@@ -120,12 +239,16 @@ impl Module {
             ext: vec![
                 (vec![], vec![Secret(Box::new(F64)), F64], Secret(Box::new(Bool))),
                 (vec![], vec![F64; 2], Bool),
+                (vec![], vec![I64; 2], Bool),
                 (vec![], vec![Str; 2], Bool),
+                (vec![], vec![Bytes; 2], Bool),
                 (vec![], vec![Secret(Box::new(Bool)), Bool], Secret(Box::new(Bool))),
                 (vec![], vec![Bool; 2], Bool),
                 (vec![], vec![Vec4; 2], Bool),
                 (vec![], vec![Type::object(), Type::object()], Bool),
                 (vec![], vec![Type::array(), Type::array()], Bool),
+                (vec![], vec![Type::map(), Type::map()], Bool),
+                (vec![], vec![Type::set(), Type::set()], Bool),
                 (vec![], vec![Type::option(), Type::option()], Bool),
             ],
             lazy: LAZY_NO
@@ -156,6 +279,7 @@ impl Module {
             ret: Any,
             ext: vec![
                 Type::all_ext(vec![F64, F64], F64),
+                Type::all_ext(vec![I64, I64], I64),
                 Type::all_ext(vec![Vec4, Vec4], Vec4),
                 Type::all_ext(vec![Vec4, F64], Vec4),
                 Type::all_ext(vec![F64, Vec4], Vec4),
@@ -164,6 +288,7 @@ impl Module {
                 Type::all_ext(vec![Mat4, F64], Mat4),
                 Type::all_ext(vec![Bool, Bool], Bool),
                 Type::all_ext(vec![Str, Str], Str),
+                Type::all_ext(vec![Bytes, Bytes], Bytes),
                 Type::all_ext(vec![Link, Link], Link),
             ],
             lazy: LAZY_NO
@@ -174,6 +299,7 @@ impl Module {
             ret: Any,
             ext: vec![
                 Type::all_ext(vec![F64, F64], F64),
+                Type::all_ext(vec![I64, I64], I64),
                 Type::all_ext(vec![Vec4, Vec4], Vec4),
                 Type::all_ext(vec![Vec4, F64], Vec4),
                 Type::all_ext(vec![F64, Vec4], Vec4),
@@ -190,6 +316,7 @@ impl Module {
             ret: Any,
             ext: vec![
                 (vec![], vec![F64, F64], F64),
+                (vec![], vec![I64, I64], I64),
                 (vec![], vec![Vec4, Vec4], Vec4),
                 (vec![], vec![Vec4, F64], Vec4),
                 (vec![], vec![F64, Vec4], Vec4),
@@ -207,6 +334,7 @@ impl Module {
             ret: Any,
             ext: vec![
                 (vec![], vec![F64, F64], F64),
+                (vec![], vec![I64, I64], I64),
                 (vec![], vec![Vec4, Vec4], Vec4),
                 (vec![], vec![Vec4, F64], Vec4),
                 (vec![], vec![F64, Vec4], Vec4),
@@ -219,6 +347,7 @@ impl Module {
             ret: Any,
             ext: vec![
                 (vec![], vec![F64, F64], F64),
+                (vec![], vec![I64, I64], I64),
                 (vec![], vec![Vec4, Vec4], Vec4),
                 (vec![], vec![Vec4, F64], Vec4),
                 (vec![], vec![F64, Vec4], Vec4),
@@ -231,6 +360,7 @@ impl Module {
             ret: Any,
             ext: vec![
                 (vec![], vec![F64, F64], F64),
+                (vec![], vec![I64, I64], I64),
                 (vec![], vec![Vec4, Vec4], Vec4),
                 (vec![], vec![Vec4, F64], Vec4),
                 (vec![], vec![F64, Vec4], Vec4),
@@ -238,6 +368,20 @@ impl Module {
             ],
             lazy: LAZY_NO
         });
+        m.add_binop(crate::BIT_AND.clone(), bit_and, Dfn::nl(vec![I64; 2], I64));
+        m.add_binop(crate::BIT_OR.clone(), bit_or, Dfn::nl(vec![I64; 2], I64));
+        m.add_binop(crate::XOR.clone(), xor, Dfn {
+            lts: vec![Lt::Default; 2],
+            tys: vec![Any; 2],
+            ret: Any,
+            ext: vec![
+                (vec![], vec![I64, I64], I64),
+                Type::all_ext(vec![Bool, Bool], Bool),
+            ],
+            lazy: LAZY_NO
+        });
+        m.add_binop(crate::SHL.clone(), shl, Dfn::nl(vec![I64; 2], I64));
+        m.add_binop(crate::SHR.clone(), shr, Dfn::nl(vec![I64; 2], I64));
         m.add_unop(crate::NOT.clone(), not, Dfn {
             lts: vec![Lt::Default],
             tys: vec![Any],
@@ -252,6 +396,7 @@ impl Module {
             lts: vec![Lt::Default], tys: vec![Any], ret: Any,
             ext: vec![
                 (vec![], vec![F64], F64),
+                (vec![], vec![I64], I64),
                 (vec![], vec![Vec4], Vec4),
                 (vec![], vec![Mat4], Mat4),
             ],
@@ -314,6 +459,8 @@ impl Module {
         m.add_str("abs", abs, Dfn::nl(vec![F64], F64));
         m.add_str("floor", floor, Dfn::nl(vec![F64], F64));
         m.add_str("ceil", ceil, Dfn::nl(vec![F64], F64));
+        m.add_str("i64", _i64, Dfn::nl(vec![F64], I64));
+        m.add_str("f64", _f64, Dfn::nl(vec![I64], F64));
         m.add_str("sleep", sleep, Dfn::nl(vec![F64], Void));
         m.add_str("random", random, Dfn::nl(vec![], F64));
         m.add_str("tau", tau, Dfn::nl(vec![], F64));
@@ -350,10 +497,44 @@ impl Module {
                   Dfn::nl(vec![Type::Str; 2], Type::Result(Box::new(Str))));
         m.add_str("load_string__file", load_string__file,
                   Dfn::nl(vec![Str], Type::Result(Box::new(Str))));
+        m.add_str("save__bytes_file", save__bytes_file,
+                  Dfn::nl(vec![Bytes, Str], Type::Result(Box::new(Str))));
+        m.add_str("load_bytes__file", load_bytes__file,
+                  Dfn::nl(vec![Str], Type::Result(Box::new(Bytes))));
         m.add_str("load_string__url", load_string__url,
                   Dfn::nl(vec![Str], Type::Result(Box::new(Str))));
         m.add_str("join__thread", join__thread,
                   Dfn::nl(vec![Type::thread()], Type::Result(Box::new(Any))));
+        m.add_str("join__thread_timeout", join__thread_timeout,
+                  Dfn::nl(vec![Type::thread(), F64],
+                          Type::Option(Box::new(Type::Result(Box::new(Any))))));
+        m.add_str("cancel", cancel, Dfn::nl(vec![Type::thread()], Void));
+        m.add_str("resume", resume, Dfn::nl(vec![Type::coroutine()], Type::option()));
+        m.add_str("is_done", is_done, Dfn {
+            lts: vec![Lt::Default],
+            tys: vec![Any],
+            ret: Bool,
+            ext: vec![
+                (vec![], vec![Type::coroutine()], Bool),
+                (vec![], vec![Type::thread()], Bool),
+            ],
+            lazy: LAZY_NO
+        });
+        m.add_str("channel", channel, Dfn::nl(vec![], Object));
+        m.add_str("channel__capacity", channel__capacity, Dfn::nl(vec![F64], Object));
+        m.add_str("send", send, Dfn::nl(vec![Type::out(), Any], Bool));
+        m.add_str("recv", wait_next, Dfn::nl(vec![Type::in_ty()], Type::option()));
+        m.add_str("try_recv", next, Dfn::nl(vec![Type::in_ty()], Type::option()));
+        m.add_str("recv__from_timeout", recv__from_timeout,
+                  Dfn::nl(vec![Type::in_ty(), F64], Type::option()));
+        m.add_str("select", select,
+                  Dfn::nl(vec![Type::Array(Box::new(Type::in_ty()))], Type::option()));
+        m.add_str("atomic", atomic, Dfn::nl(vec![F64], Type::Atomic));
+        m.add_str("load__atomic", load__atomic, Dfn::nl(vec![Type::Atomic], F64));
+        m.add_str("fetch_add", fetch_add, Dfn::nl(vec![Type::Atomic, F64], F64));
+        m.add_str("compare_exchange", compare_exchange,
+                  Dfn::nl(vec![Type::Atomic, F64, F64], Type::Result(Box::new(F64))));
+        m.add_str("mutex", mutex, Dfn::nl(vec![Any], Type::mutex()));
         m.add_str("load_data__file", load_data__file,
                   Dfn::nl(vec![Str], Type::Result(Box::new(Any))));
         m.add_str("load_data__string", load_data__string,
@@ -389,7 +570,18 @@ impl Module {
         m.add_str("tail", tail, Dfn::nl(vec![Link], Link));
         m.add_str("neck", neck, Dfn::nl(vec![Link], Link));
         m.add_str("is_empty", is_empty, Dfn::nl(vec![Link], Bool));
-        m.add_unop_str("len", len, Dfn::nl(vec![Type::array()], F64));
+        m.add_unop_str("len", len, Dfn {
+            lts: vec![Lt::Default],
+            tys: vec![Any],
+            ret: F64,
+            ext: vec![
+                (vec![], vec![Type::array()], F64),
+                (vec![], vec![Bytes], F64),
+                (vec![], vec![Type::map()], F64),
+                (vec![], vec![Type::set()], F64),
+            ],
+            lazy: LAZY_NO
+        });
         m.add_str("push_ref(mut,_)", push_ref, Dfn {
             lts: vec![Lt::Default, Lt::Arg(0)],
             tys: vec![Type::array(), Any],
@@ -407,11 +599,15 @@ impl Module {
         m.add_str("push(mut,_)", push, Dfn::nl(vec![Type::array(), Any], Void));
         m.add_str("insert(mut,_,_)", insert, Dfn {
             lts: vec![Lt::Default; 3],
-            tys: vec![Type::array(), F64, Any],
+            tys: vec![Any; 3],
             ret: Void,
-            ext: vec![],
+            ext: vec![
+                (vec![], vec![Type::array(), F64, Any], Void),
+                (vec![], vec![Type::map(), Any, Any], Void),
+            ],
             lazy: LAZY_NO
         });
+        m.add_str("insert(mut,_)", insert_set, Dfn::nl(vec![Type::set(), Any], Void));
         m.add_str("pop(mut)", pop, Dfn {
             lts: vec![Lt::Return],
             tys: vec![Type::array()],
@@ -421,9 +617,13 @@ impl Module {
         });
         m.add_str("remove(mut,_)", remove, Dfn {
             lts: vec![Lt::Return, Lt::Default],
-            tys: vec![Type::array(), F64],
+            tys: vec![Any; 2],
             ret: Any,
-            ext: vec![],
+            ext: vec![
+                (vec![], vec![Type::array(), F64], Any),
+                (vec![], vec![Type::map(), Any], Type::option()),
+                (vec![], vec![Type::set(), Any], Bool),
+            ],
             lazy: LAZY_NO
         });
         m.add_str("reverse(mut)", reverse, Dfn::nl(vec![Type::array()], Void));
@@ -446,12 +646,94 @@ impl Module {
         m.add_str("errstr__string_start_len_msg",
             errstr__string_start_len_msg, Dfn::nl(vec![Str, F64, F64, Str], Str));
         m.add_str("has", has, Dfn::nl(vec![Object, Str], Bool));
-        m.add_str("keys", keys, Dfn::nl(vec![Object], Type::Array(Box::new(Str))));
+        m.add_str("keys", keys, Dfn {
+            lts: vec![Lt::Default],
+            tys: vec![Any],
+            ret: Type::array(),
+            ext: vec![
+                (vec![], vec![Object], Type::Array(Box::new(Str))),
+                (vec![], vec![Type::map()], Type::array()),
+            ],
+            lazy: LAZY_NO
+        });
+        m.add_str("values", values, Dfn {
+            lts: vec![Lt::Default],
+            tys: vec![Any],
+            ret: Type::array(),
+            ext: vec![
+                (vec![], vec![Object], Type::array()),
+                (vec![], vec![Type::map()], Type::array()),
+                (vec![], vec![Type::set()], Type::array()),
+            ],
+            lazy: LAZY_NO
+        });
+        m.add_str("map", map, Dfn::nl(vec![], Type::map()));
+        m.add_str("set", set, Dfn::nl(vec![], Type::set()));
+        m.add_str("contains", contains, Dfn {
+            lts: vec![Lt::Default; 2],
+            tys: vec![Any; 2],
+            ret: Bool,
+            ext: vec![
+                (vec![], vec![Type::map(), Any], Bool),
+                (vec![], vec![Type::set(), Any], Bool),
+            ],
+            lazy: LAZY_NO
+        });
+        m.add_str("get", get, Dfn::nl(vec![Type::map(), Any], Type::option()));
+        m.add_str("union", union, Dfn::nl(vec![Type::set(); 2], Type::set()));
+        m.add_str("intersect", intersect, Dfn::nl(vec![Type::set(); 2], Type::set()));
         m.add_str("chars", chars, Dfn::nl(vec![Str], Type::Array(Box::new(Str))));
+        m.add_str("bytes", bytes, Dfn {
+            lts: vec![Lt::Default],
+            tys: vec![Any],
+            ret: Bytes,
+            ext: vec![
+                (vec![], vec![Str], Bytes),
+                (vec![], vec![Type::Array(Box::new(F64))], Bytes),
+            ],
+            lazy: LAZY_NO
+        });
+        m.add_str("byte", byte, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("slice__bytes_start_end", slice__bytes_start_end,
+                  Dfn::nl(vec![Bytes, F64, F64], Bytes));
+        m.add_str("utf8", utf8, Dfn::nl(vec![Bytes], Type::Result(Box::new(Str))));
+        m.add_str("hex", hex, Dfn::nl(vec![Bytes], Str));
+        m.add_str("bytes__hex", bytes__hex, Dfn::nl(vec![Str], Type::Result(Box::new(Bytes))));
+        m.add_str("base64", base64, Dfn::nl(vec![Bytes], Str));
+        m.add_str("bytes__base64", bytes__base64,
+                  Dfn::nl(vec![Str], Type::Result(Box::new(Bytes))));
+        m.add_str("le_f64", le_f64, Dfn::nl(vec![F64], Bytes));
+        m.add_str("be_f64", be_f64, Dfn::nl(vec![F64], Bytes));
+        m.add_str("le_f32", le_f32, Dfn::nl(vec![F64], Bytes));
+        m.add_str("be_f32", be_f32, Dfn::nl(vec![F64], Bytes));
+        m.add_str("le_i64", le_i64, Dfn::nl(vec![I64], Bytes));
+        m.add_str("be_i64", be_i64, Dfn::nl(vec![I64], Bytes));
+        m.add_str("le_i32", le_i32, Dfn::nl(vec![F64], Bytes));
+        m.add_str("be_i32", be_i32, Dfn::nl(vec![F64], Bytes));
+        m.add_str("le_u32", le_u32, Dfn::nl(vec![F64], Bytes));
+        m.add_str("be_u32", be_u32, Dfn::nl(vec![F64], Bytes));
+        m.add_str("le_u16", le_u16, Dfn::nl(vec![F64], Bytes));
+        m.add_str("be_u16", be_u16, Dfn::nl(vec![F64], Bytes));
+        m.add_str("le_f64__bytes_offset", le_f64__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("be_f64__bytes_offset", be_f64__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("le_f32__bytes_offset", le_f32__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("be_f32__bytes_offset", be_f32__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("le_i64__bytes_offset", le_i64__bytes_offset, Dfn::nl(vec![Bytes, F64], I64));
+        m.add_str("be_i64__bytes_offset", be_i64__bytes_offset, Dfn::nl(vec![Bytes, F64], I64));
+        m.add_str("le_i32__bytes_offset", le_i32__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("be_i32__bytes_offset", be_i32__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("le_u32__bytes_offset", le_u32__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("be_u32__bytes_offset", be_u32__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("le_u16__bytes_offset", le_u16__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
+        m.add_str("be_u16__bytes_offset", be_u16__bytes_offset, Dfn::nl(vec![Bytes, F64], F64));
         m.add_str("wait_next", wait_next, Dfn::nl(vec![Type::in_ty()], Any));
         m.add_str("next", next, Dfn::nl(vec![Type::in_ty()], Type::option()));
 
         m.no_ns();
+        let (allowed, denied) = m.ext_prelude.into_iter()
+            .partition(|f| capabilities.allows(&f.name));
+        m.ext_prelude = allowed;
+        m.denied = denied.into_iter().map(|f: FnExternal| f.name).collect();
         m
     }
 
@@ -472,6 +754,28 @@ impl Module {
         self.functions.push(function);
     }
 
+    /// Adds a record type, replacing a record with the same name.
+    pub(crate) fn register_record(&mut self, record: ast::Record) {
+        self.records.retain(|r| r.name != record.name);
+        self.records.push(record);
+    }
+
+    /// Finds a record type by name.
+    pub fn find_record(&self, name: &str) -> Option<&ast::Record> {
+        self.records.iter().find(|r| **r.name == *name)
+    }
+
+    /// Adds an enum type, replacing an enum with the same name.
+    pub(crate) fn register_enum(&mut self, enum_decl: ast::Enum) {
+        self.enums.retain(|e| e.name != enum_decl.name);
+        self.enums.push(enum_decl);
+    }
+
+    /// Finds an enum type by name.
+    pub fn find_enum(&self, name: &str) -> Option<&ast::Enum> {
+        self.enums.iter().find(|e| **e.name == *name)
+    }
+
     /// Find function relative another function index.
     pub fn find_function(&self, name: &Arc<String>, relative: usize) -> FnIndex {
         for (i, f) in self.functions.iter().enumerate().rev() {
@@ -498,28 +802,42 @@ impl Module {
         FnIndex::None
     }
 
-    /// Generates an error message.
-    pub(crate) fn error(&self, range: Range, msg: &str, rt: &Runtime) -> String {
+    /// Generates an error with the call stack of the runtime.
+    pub(crate) fn error(&self, range: Range, msg: &str, rt: &Runtime) -> RuntimeError {
         let fnindex = if let Some(x) = rt.call_stack.last() {x.index}
                       else {return msg.into()};
-        self.error_fnindex(range, msg, fnindex)
+        let mut err = self.error_fnindex(range, msg, fnindex);
+        rt.set_call_stack(&mut err);
+        err
     }
 
     /// Generates an error with a function index.
-    pub(crate) fn error_fnindex(&self, range: Range, msg: &str, fnindex: usize) -> String {
-        let source = &self.functions[fnindex].source;
-        self.error_source(range, msg, source)
+    pub(crate) fn error_fnindex(&self, range: Range, msg: &str, fnindex: usize) -> RuntimeError {
+        let f = &self.functions[fnindex];
+        self.error_file(range, msg, &f.source, Some(f.file.clone()))
+    }
+
+    /// Generates an error with a source.
+    pub(crate) fn error_source(&self, range: Range, msg: &str, source: &Arc<String>) -> RuntimeError {
+        self.error_file(range, msg, source, None)
     }
 
-    /// Generates an error message with a source.
-    pub(crate) fn error_source(&self, range: Range, msg: &str, source: &Arc<String>) -> String {
+    /// Generates an error with the location where it happened.
+    fn error_file(
+        &self,
+        range: Range,
+        msg: &str,
+        source: &Arc<String>,
+        file: Option<Arc<String>>
+    ) -> RuntimeError {
         use piston_meta::ParseErrorHandler;
 
         let mut w: Vec<u8> = vec![];
         ParseErrorHandler::new(source)
             .write_msg(&mut w, range, msg)
             .unwrap();
-        String::from_utf8(w).unwrap()
+        let text = String::from_utf8(w).unwrap();
+        ErrorInfo::new(file, range, source, msg.into(), text).into()
     }
 
     /// Adds a new external prelude function.
@@ -93,6 +93,12 @@ pub struct Prelude {
     pub(crate) functions: HashMap<Arc<String>, usize>,
     pub(crate) list: Vec<Dfn>,
     pub(crate) namespaces: Vec<(Arc<Vec<Arc<String>>>, Arc<String>)>,
+    /// Standard library functions denied by capabilities.
+    pub(crate) denied: Vec<Arc<String>>,
+    /// Record types declared in loaded sources.
+    pub(crate) records: Vec<ast::Record>,
+    /// Enum types declared in loaded sources.
+    pub(crate) enums: Vec<ast::Enum>,
 }
 
 impl Default for Prelude {
@@ -114,6 +120,9 @@ impl Prelude {
             functions: HashMap::new(),
             list: vec![],
             namespaces: vec![],
+            denied: vec![],
+            records: vec![],
+            enums: vec![],
         }
     }
 
@@ -126,6 +135,9 @@ impl Prelude {
         for f in &module.functions {
             prelude.insert(f.namespace.clone(), f.name.clone(), Dfn::new(f));
         }
+        prelude.denied = module.denied.clone();
+        prelude.records = module.records.clone();
+        prelude.enums = module.enums.clone();
         prelude
     }
 }
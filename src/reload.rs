@@ -0,0 +1,107 @@
+//! Hot reloading of sources.
+//!
+//! A module is reloaded by loading its sources again in the same order,
+//! with new text for the changed source.
+//! External functions are kept, such that the reloaded module has the same prelude.
+
+use std::sync::Arc;
+
+use ast;
+use {load_str, DyonError, ErrorInfo, Module};
+
+/// Describes which functions changed when reloading a source.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reload {
+    /// Functions with a changed body.
+    pub changed: Vec<Arc<String>>,
+    /// Functions with changed arguments or return type.
+    pub signature_changed: Vec<Arc<String>>,
+    /// Functions that were added.
+    pub added: Vec<Arc<String>>,
+    /// Functions that were removed.
+    pub removed: Vec<Arc<String>>,
+    /// Whether the same functions are in a different order.
+    pub reordered: bool,
+}
+
+impl Reload {
+    /// Returns `true` if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && !self.needs_restart()
+    }
+
+    /// Returns `true` if running code can not continue with the reloaded module.
+    ///
+    /// This happens when functions are added, removed, reordered or change signature.
+    pub fn needs_restart(&self) -> bool {
+        !self.signature_changed.is_empty() || !self.added.is_empty() ||
+        !self.removed.is_empty() || self.reordered
+    }
+}
+
+/// Returns a copy of a module where a source file is loaded with new text.
+///
+/// Returns an error if the file is not loaded in the module,
+/// or if the new text fails to parse or check.
+pub(crate) fn reload_str(
+    module: &Module,
+    file: &str,
+    source: Arc<String>
+) -> Result<(Module, Reload), DyonError> {
+    // Functions loaded from the same source share the text.
+    let mut sources: Vec<(Arc<String>, Arc<String>)> = vec![];
+    for f in &module.functions {
+        match sources.last() {
+            Some((_, s)) if Arc::ptr_eq(s, &f.source) => {}
+            _ => sources.push((f.file.clone(), f.source.clone())),
+        }
+    }
+    if !sources.iter().any(|s| **s.0 == *file) {
+        let mut info = ErrorInfo::from_text(format!("Could not find loaded file `{}`", file));
+        info.file = Some(Arc::new(file.into()));
+        return Err(DyonError::Io(info));
+    }
+
+    let mut new_module = module.clone();
+    new_module.functions.clear();
+    new_module.records.clear();
+    new_module.enums.clear();
+    for (f, s) in sources {
+        let s = if *f == file {source.clone()} else {s};
+        load_str(&f, s, &mut new_module)?;
+    }
+
+    let mut reload = Reload::default();
+    for f in &new_module.functions {
+        match module.functions.iter().find(|old| old.name == f.name) {
+            None => reload.added.push(f.name.clone()),
+            Some(old) if !same_signature(old, f) => reload.signature_changed.push(f.name.clone()),
+            Some(old) if body(old) != body(f) => reload.changed.push(f.name.clone()),
+            Some(_) => {}
+        }
+    }
+    for f in &module.functions {
+        if !new_module.functions.iter().any(|new| new.name == f.name) {
+            reload.removed.push(f.name.clone());
+        }
+    }
+    reload.reordered = reload.added.is_empty() && reload.removed.is_empty() &&
+                       !same_layout(module, &new_module);
+    Ok((new_module, reload))
+}
+
+fn same_signature(a: &ast::Function, b: &ast::Function) -> bool {
+    a.ret == b.ret && a.args.len() == b.args.len() &&
+    a.args.iter().zip(&b.args).all(|(a, b)| {
+        a.ty == b.ty && a.mutable == b.mutable && a.lifetime == b.lifetime
+    })
+}
+
+fn body(f: &ast::Function) -> &str {
+    &f.source[f.source_range.offset..f.source_range.offset + f.source_range.length]
+}
+
+fn same_layout(a: &Module, b: &Module) -> bool {
+    a.functions.len() == b.functions.len() &&
+    a.functions.iter().zip(&b.functions).all(|(a, b)| a.name == b.name)
+}
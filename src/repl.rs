@@ -0,0 +1,281 @@
+//! Interactive evaluation of Dyon code.
+//!
+//! Keeps a single runtime and module alive between inputs.
+//! Functions, records and enums can be defined incrementally, and locals declared
+//! at the top level of an input are available in the next inputs.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use lifetime::Kind;
+use write::{write_variable, EscapeString};
+use {check_str, load, load_str, DyonError, ErrorInfo, Module, Runtime, Type, Variable};
+
+/// The file name used for code typed in the REPL.
+const REPL_FILE: &str = "repl";
+
+/// Stores the state of an interactive session.
+pub struct Repl {
+    runtime: Runtime,
+    /// The module without declarations made in the REPL.
+    base: Module,
+    /// The base module with declarations made in the REPL.
+    module: Module,
+    /// Source of each declaration made in the REPL, by kind and name.
+    defs: Vec<(Decl, Arc<String>, String)>,
+    /// Locals kept between inputs.
+    locals: Vec<(Arc<String>, Variable)>,
+}
+
+/// The kind of a declaration made in the REPL.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Decl {
+    Record,
+    Enum,
+    Function,
+}
+
+impl Default for Repl {
+    fn default() -> Repl {Repl::new()}
+}
+
+impl Repl {
+    /// Creates a new REPL with the standard library.
+    pub fn new() -> Repl {Repl::with_module(Module::new())}
+
+    /// Creates a new REPL with the functions in a module.
+    pub fn with_module(module: Module) -> Repl {
+        Repl {
+            runtime: Runtime::new(),
+            base: module.clone(),
+            module,
+            defs: vec![],
+            locals: vec![],
+        }
+    }
+
+    /// Evaluates a line of input and returns the text to print.
+    ///
+    /// Declarations are added to the module, replacing declarations with same name.
+    /// Other input is evaluated, printing the result of expressions.
+    /// Commands start with `:`, see `:help`.
+    pub fn eval(&mut self, input: &str) -> Result<String, DyonError> {
+        let input = input.trim();
+        if input.starts_with(':') {
+            let (cmd, arg) = match input.find(char::is_whitespace) {
+                Some(i) => (&input[..i], input[i..].trim()),
+                None => (input, ""),
+            };
+            return match cmd {
+                ":type" => self.type_of(arg),
+                ":functions" => Ok(self.functions(arg)),
+                ":load" => self.load(arg),
+                ":locals" => Ok(self.locals()),
+                ":help" => Ok(HELP.into()),
+                _ => Err(DyonError::Runtime(ErrorInfo::from_text(
+                    format!("Unknown command `{}`, type `:help` for a list of commands", cmd))))
+            };
+        }
+        if input.is_empty() {return Ok(String::new())};
+
+        // Try loading input as function declarations.
+        let mut module = self.module.clone();
+        let n = module.functions.len();
+        match load_str(REPL_FILE, Arc::new(input.into()), &mut module) {
+            Ok(()) => return self.define(input, &module, n),
+            Err(DyonError::Parse(_)) => {}
+            Err(err) => return Err(err),
+        }
+        self.run(input)
+    }
+
+    /// Adds or replaces declarations and reloads the module.
+    ///
+    /// The input is loaded into `module`, which had `n` functions before.
+    fn define(&mut self, input: &str, module: &Module, n: usize) -> Result<String, DyonError> {
+        let mut decls = vec![];
+        // Types replace those with same name, so new types differ from the old ones.
+        for r in module.records.iter().filter(|r| !self.module.records.contains(r)) {
+            decls.push((Decl::Record, r.name.clone(), r.source_range));
+        }
+        for e in module.enums.iter().filter(|e| !self.module.enums.contains(e)) {
+            decls.push((Decl::Enum, e.name.clone(), e.source_range));
+        }
+        for f in &module.functions[n..] {
+            decls.push((Decl::Function, f.name.clone(), f.source_range));
+        }
+        let mut defs = self.defs.clone();
+        let mut names = vec![];
+        for (decl, name, range) in decls {
+            let text = input[range.offset..range.offset + range.length].to_string();
+            match defs.iter_mut().find(|def| def.0 == decl && def.1 == name) {
+                Some(def) => def.2 = text,
+                None => defs.push((decl, name.clone(), text)),
+            }
+            names.push(name.to_string());
+        }
+        let mut module = self.base.clone();
+        load_str(REPL_FILE, Arc::new(source(&defs)), &mut module)?;
+        self.defs = defs;
+        self.module = module;
+        Ok(format!("Defined {}", names.join(", ")))
+    }
+
+    /// Runs input as statements or as an expression.
+    fn run(&mut self, input: &str) -> Result<String, DyonError> {
+        let mut module = self.module.clone();
+        let expr = match load_str(REPL_FILE, Arc::new(self.wrap(input, false)), &mut module) {
+            Ok(()) => false,
+            Err(err) => {
+                // Try as an expression, but report errors of the statements.
+                module = self.module.clone();
+                match load_str(REPL_FILE, Arc::new(self.wrap(input, true)), &mut module) {
+                    Ok(()) => true,
+                    Err(_) => return Err(err),
+                }
+            }
+        };
+
+        let f_index = module.functions.len() - 1;
+        let args = self.locals.iter().map(|l| l.1.clone()).collect();
+        let (val, locals) = self.runtime.call_keep_locals(&Arc::new(module), f_index, args)?;
+        self.locals = locals;
+        match val {
+            Some(ref val) if expr => {
+                let mut buf: Vec<u8> = vec![];
+                write_variable(&mut buf, &self.runtime, val, EscapeString::Json, 0).unwrap();
+                Ok(String::from_utf8(buf).unwrap())
+            }
+            _ => Ok(String::new())
+        }
+    }
+
+    /// Generates a function with locals as arguments.
+    ///
+    /// An expression is returned, with arguments outliving the return value.
+    fn wrap(&self, input: &str, expr: bool) -> String {
+        let mut s = String::from("fn __repl__(");
+        for (i, (name, v)) in self.locals.iter().enumerate() {
+            if i > 0 {s.push_str(", ")};
+            s.push_str("mut ");
+            s.push_str(name);
+            if expr || type_of_variable(v).is_some() {s.push_str(": ")};
+            if expr {s.push_str("'return ")};
+            if let Some(ty) = type_of_variable(v) {s.push_str(ty)};
+        }
+        if expr {
+            s.push_str(") -> {\nreturn ");
+        } else {
+            s.push_str(") {\n");
+        }
+        s.push_str(input);
+        s.push_str("\n}\n");
+        s
+    }
+
+    /// Returns the type of an expression inferred by the type checker.
+    fn type_of(&self, expr: &str) -> Result<String, DyonError> {
+        let src = self.wrap(expr, true);
+        let mut module = self.module.clone();
+        load_str(REPL_FILE, Arc::new(src.clone()), &mut module)?;
+        let nodes = check_str(REPL_FILE, Arc::new(src), &self.module)
+            .map_err(|err| DyonError::Parse(ErrorInfo::from_text(err)))?;
+        let ty = nodes.iter().rev()
+            .find(|n| n.kind == Kind::Return)
+            .and_then(|n| n.ty.clone().or_else(|| {
+                n.children.first().and_then(|&ch| nodes[ch].ty.clone())
+            }))
+            .unwrap_or(Type::Any);
+        Ok(ty.description())
+    }
+
+    /// Lists functions starting with a prefix.
+    fn functions(&self, prefix: &str) -> String {
+        use dyon_std::functions::list_functions;
+
+        let mut lines = vec![];
+        for f in list_functions(&self.module) {
+            let obj = match f {Variable::Object(obj) => obj, _ => continue};
+            let field = |key: &str| match obj.get(&Arc::new(key.into())) {
+                Some(Variable::Str(s)) => s.to_string(),
+                _ => String::new()
+            };
+            let name = field("name");
+            if !name.starts_with(prefix) {continue};
+            let mut args = vec![];
+            if let Some(Variable::Array(arr)) = obj.get(&Arc::new("arguments".into())) {
+                for arg in arr.iter() {
+                    if let Variable::Object(ref arg) = *arg {
+                        let name = arg.get(&Arc::new("name".into()));
+                        let takes = arg.get(&Arc::new("takes".into()));
+                        if let (Some(Variable::Str(name)), Some(Variable::Str(takes))) =
+                               (name, takes) {
+                            args.push(format!("{}: {}", name, takes));
+                        }
+                    }
+                }
+            }
+            lines.push(format!("{}({}) -> {}", name, args.join(", "), field("returns")));
+        }
+        lines.sort();
+        lines.dedup();
+        lines.join("\n")
+    }
+
+    /// Loads functions from a file into the session.
+    fn load(&mut self, file: &str) -> Result<String, DyonError> {
+        let mut base = self.base.clone();
+        load(file, &mut base)?;
+        let mut module = base.clone();
+        load_str(REPL_FILE, Arc::new(source(&self.defs)), &mut module)?;
+        let n = base.functions.len() - self.base.functions.len();
+        self.base = base;
+        self.module = module;
+        Ok(format!("Loaded {} function{} from `{}`", n, if n == 1 {""} else {"s"}, file))
+    }
+
+    /// Lists locals with their values.
+    fn locals(&self) -> String {
+        let mut lines = vec![];
+        for (name, v) in &self.locals {
+            let mut buf: Vec<u8> = vec![];
+            write!(&mut buf, "{} := ", name).unwrap();
+            write_variable(&mut buf, &self.runtime, v, EscapeString::Json, 0).unwrap();
+            lines.push(String::from_utf8(buf).unwrap());
+        }
+        lines.join("\n")
+    }
+}
+
+/// Text printed by the `:help` command.
+const HELP: &str = "\
+:type <expr>         Show the type of an expression
+:functions [prefix]  List available functions
+:load <file>         Load functions from a file
+:locals              List locals
+:help                Show this help";
+
+/// Joins the source of declarations, with types before functions.
+fn source(defs: &[(Decl, Arc<String>, String)]) -> String {
+    let mut s = String::new();
+    for def in defs.iter().filter(|def| def.0 != Decl::Function)
+        .chain(defs.iter().filter(|def| def.0 == Decl::Function))
+    {
+        s.push_str(&def.2);
+        s.push('\n');
+    }
+    s
+}
+
+/// Returns the type syntax of a value, if it is known to the type checker.
+fn type_of_variable(v: &Variable) -> Option<&'static str> {
+    match *v {
+        Variable::F64(_, None) => Some("f64"),
+        Variable::Bool(_, None) => Some("bool"),
+        Variable::Str(_) => Some("str"),
+        Variable::Vec4(_) => Some("vec4"),
+        Variable::Mat4(_) => Some("mat4"),
+        Variable::Link(_) => Some("link"),
+        _ => None
+    }
+}
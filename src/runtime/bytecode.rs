@@ -0,0 +1,1304 @@
+//! Bytecode compiler and virtual machine for loaded functions.
+//!
+//! A function block is lowered to a flat list of instructions operating on an
+//! operand stack, while locals, arguments and current objects stay on the
+//! runtime stack. This means static stack ids, references and current objects
+//! behave exactly as in the tree walker.
+//!
+//! Expressions that have no dedicated instructions are evaluated by the tree
+//! walker, so the compiler is free to support a subset of the language.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use range::Range;
+
+use ast::{self, AssignOp, Expression};
+use {FnBinOpRef, FnReturnRef, FnUnOpRef, FnVoidRef, LazyInvariant};
+use {RuntimeError, Variable};
+use TINVOTS;
+use super::{Flow, FlowResult, Runtime, Side};
+use super::coroutine::Frame;
+use super::for_in::Iter;
+
+/// Stores the bytecode of a function block.
+#[derive(Debug)]
+pub struct Chunk {
+    ops: Vec<Op>,
+    /// Expressions evaluated by the tree walker.
+    exprs: Vec<Expression>,
+    /// Call sites, used for function info and error reporting.
+    sites: Vec<Site>,
+    /// Loops that can be exited with `break` or `continue`.
+    loops: Vec<Loop>,
+    /// Items looked up by id.
+    items: Vec<ast::Item>,
+    /// Keys of object literals.
+    objects: Vec<Vec<Arc<String>>>,
+    /// Argument ranges of vec4 literals.
+    vec4s: Vec<[Range; 4]>,
+    /// Closure calls.
+    closure_calls: Vec<ast::CallClosure>,
+}
+
+// Required because the `Sync` impl of `Variable` is unsafe.
+unsafe impl Sync for Chunk {}
+
+#[derive(Debug)]
+struct Site {
+    info: ast::CallInfo,
+    fun: isize,
+    custom_source: Option<Arc<String>>,
+    args: Vec<Range>,
+}
+
+#[derive(Debug)]
+struct Loop {
+    label: Option<Arc<String>>,
+    parent: Option<usize>,
+    break_pc: usize,
+    continue_pc: usize,
+    /// Number of marks when entering the loop body.
+    marks: usize,
+    /// Operand stack depth when entering the loop body.
+    depth: usize,
+    /// Number of pending loaded calls when entering the loop body.
+    frames: usize,
+    /// Number of for-in iterators when entering the loop body.
+    iters: usize,
+}
+
+/// The value computed from the body of a for-n loop.
+#[derive(Clone, Copy, PartialEq)]
+enum Reduce {
+    Nothing,
+    Sum,
+    SumVec4,
+    Sift,
+}
+
+#[derive(Debug)]
+enum Op {
+    /// Pushes a constant.
+    Const(Variable),
+    /// Pushes a reference to a local variable by static stack id.
+    Local(usize),
+    /// Looks up an item by ids, with the number of computed ids on the runtime stack.
+    Item(usize, usize),
+    /// Creates an object from the values on the operand stack.
+    Object(usize, usize),
+    /// Creates an array from the values on the operand stack.
+    Array(usize),
+    /// Creates a vec4 from the arguments on the runtime stack.
+    Vec4(usize),
+    /// Evaluates an expression with the tree walker into the accumulator.
+    Eval(usize, Option<usize>),
+    /// Moves the accumulator to the operand stack.
+    ///
+    /// Reports an error when there is no value.
+    /// The flag tells whether to include the stack trace.
+    Need(Range, &'static str, bool),
+    /// Reports an error when the accumulator has a value.
+    NeedNone(Range, &'static str),
+    /// Moves the top operand to the accumulator.
+    Value,
+    /// Sets the accumulator to nothing.
+    Void,
+    /// Removes the top operand.
+    Pop,
+    /// Checks limits and calls the debug hook before a statement.
+    Statement(Range),
+    /// Moves the top operand to the runtime stack as an argument.
+    PushArg,
+    /// Moves the accumulator to the runtime stack as an argument, if it has a value.
+    AccArg,
+    Binary(FnBinOpRef, usize),
+    Unary(FnUnOpRef, usize),
+    CallVoid(FnVoidRef, usize),
+    CallReturn(FnReturnRef, usize),
+    /// Checks argument of external function against lazy invariant.
+    ///
+    /// Jumps to the address with the value when the invariant holds.
+    LazyArg(LazyInvariant, usize, usize),
+    /// Prepares the stack for calling a loaded function.
+    CallBegin(usize),
+    /// Checks the accumulator against lazy invariant of loaded function,
+    /// then pushes it to the runtime stack.
+    LoadedArg(usize, usize, usize),
+    /// Calls a loaded function with arguments on the runtime stack.
+    CallLoaded(usize),
+    /// Prepares the stack for calling the closure in the top operand.
+    ClosureBegin(usize),
+    /// Calls the closure in the top operand with arguments on the runtime stack.
+    CallClosure(usize),
+    /// Assigns the top operand to the left expression.
+    Assign(AssignOp, usize),
+    /// Assigns the top operand to an item,
+    /// with the number of computed ids on the runtime stack.
+    AssignItem(AssignOp, usize, usize),
+    Jump(usize),
+    /// Jumps when the top operand is `false`.
+    ///
+    /// The flag tells whether to resolve references.
+    JumpIfFalse(usize, Range, &'static str, bool),
+    /// Stores the length of stacks.
+    Mark,
+    /// Truncates stack, locals and current objects and removes mark.
+    Unmark,
+    /// Truncates stack and locals and removes mark.
+    UnmarkLocals,
+    /// Truncates stack and locals to the last mark.
+    Reset,
+    /// Removes mark without truncating.
+    DropMark,
+    /// Checks that the top operand is a number.
+    Number(Range),
+    /// Declares the counter of a for-n loop, leaving the end on the operand stack.
+    ForNInit(Arc<String>),
+    /// Jumps when the counter reaches the end.
+    ForNCond(usize, Range),
+    /// Increments the counter, truncates to the last mark and jumps.
+    ForNNext(usize, Range),
+    /// Adds the accumulator to the sum below the end of a for-n loop.
+    SumAdd(Range),
+    /// Adds the accumulator to the vec4 sum below the end of a for-n loop.
+    SumVec4Add(Range),
+    /// Pushes the accumulator to the array below the end of a for-n loop.
+    SiftPush(Range),
+    /// Creates an iterator from the top operand and declares the item of a for-in loop.
+    ForInInit(Arc<String>, Range),
+    /// Stores the next item, truncates to the last mark or jumps when there are no more.
+    ForInNext(usize, Range),
+    /// Removes the iterator of a for-in loop.
+    PopIter,
+    /// Suspends a coroutine with the top operand.
+    Yield,
+    /// Jumps to the break or continue address of a loop.
+    Exit(usize, bool),
+    /// Break or continue to a label outside the function.
+    Escape(bool, Option<Arc<String>>),
+    Return,
+    ReturnVoid,
+    End,
+}
+
+impl Op {
+    /// Returns the change in operand stack depth.
+    fn effect(&self) -> isize {
+        use self::Op::*;
+
+        match *self {
+            Const(_) | Local(_) | Item(..) | Vec4(_) | Need(..) | CallReturn(..) => 1,
+            Value | Pop | PushArg | Binary(..) | LazyArg(..) | CallClosure(_) |
+            Assign(..) | AssignItem(..) | JumpIfFalse(..) | ForNInit(_) | ForInInit(..) |
+            Yield | Return => -1,
+            Object(_, n) | Array(n) => 1 - n as isize,
+            _ => 0,
+        }
+    }
+}
+
+/// Stores registers of the virtual machine.
+///
+/// These are shared between calls to avoid allocating for every call.
+#[derive(Default)]
+pub(crate) struct Registers {
+    pub(crate) operands: Vec<Variable>,
+    /// Stack, local and current stack lengths.
+    pub(crate) marks: Vec<(usize, usize, usize)>,
+    /// Stack, local and current stack lengths of pending loaded calls.
+    pub(crate) frames: Vec<(usize, usize, usize)>,
+    /// Iterators of for-in loops.
+    pub(crate) iters: Vec<Iter>,
+    /// Set when a coroutine yields.
+    pub(crate) suspended: Option<Box<Frame>>,
+}
+
+// Required because the `Send` impl of `Variable` is unsafe.
+unsafe impl Send for Registers {}
+
+/// Stores the lengths of registers when running a chunk.
+#[derive(Clone, Copy)]
+pub(crate) struct Base {
+    pub(crate) operands: usize,
+    pub(crate) marks: usize,
+    pub(crate) frames: usize,
+    pub(crate) iters: usize,
+}
+
+impl Registers {
+    /// Returns the current lengths of registers.
+    pub(crate) fn base(&self) -> Base {
+        Base {
+            operands: self.operands.len(),
+            marks: self.marks.len(),
+            frames: self.frames.len(),
+            iters: self.iters.len(),
+        }
+    }
+
+    /// Truncates registers to lengths.
+    pub(crate) fn truncate(&mut self, base: Base) {
+        self.operands.truncate(base.operands);
+        self.marks.truncate(base.marks);
+        self.frames.truncate(base.frames);
+        self.iters.truncate(base.iters);
+    }
+}
+
+struct Compiler {
+    chunk: Chunk,
+    depth: usize,
+    marks: usize,
+    frames: usize,
+    iters: usize,
+    current_loop: Option<usize>,
+}
+
+impl Chunk {
+    /// Compiles a function block.
+    pub fn compile(block: &ast::Block) -> Chunk {
+        let mut c = Compiler {
+            chunk: Chunk {
+                ops: vec![],
+                exprs: vec![],
+                sites: vec![],
+                loops: vec![],
+                items: vec![],
+                objects: vec![],
+                vec4s: vec![],
+                closure_calls: vec![],
+            },
+            depth: 0,
+            marks: 0,
+            frames: 0,
+            iters: 0,
+            current_loop: None,
+        };
+        c.block(block);
+        c.emit(Op::End);
+        c.chunk
+    }
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op) -> usize {
+        self.depth = (self.depth as isize + op.effect()) as usize;
+        match op {
+            Op::Mark => self.marks += 1,
+            Op::Unmark | Op::UnmarkLocals | Op::DropMark => self.marks -= 1,
+            Op::CallBegin(_) | Op::ClosureBegin(_) => self.frames += 1,
+            Op::CallLoaded(_) | Op::CallClosure(_) => self.frames -= 1,
+            Op::ForInInit(..) => self.iters += 1,
+            Op::PopIter => self.iters -= 1,
+            _ => {}
+        }
+        self.chunk.ops.push(op);
+        self.chunk.ops.len() - 1
+    }
+
+    fn pc(&self) -> usize { self.chunk.ops.len() }
+
+    fn patch(&mut self, ind: usize, target: usize) {
+        match self.chunk.ops[ind] {
+            Op::Jump(ref mut pc) |
+            Op::JumpIfFalse(ref mut pc, ..) |
+            Op::LazyArg(_, _, ref mut pc) |
+            Op::LoadedArg(_, _, ref mut pc) |
+            Op::ForNCond(ref mut pc, _) |
+            Op::ForInNext(ref mut pc, _) => *pc = target,
+            _ => panic!("Expected jump instruction"),
+        }
+    }
+
+    fn site(
+        &mut self,
+        info: &ast::CallInfo,
+        fun: isize,
+        custom_source: &Option<Arc<String>>,
+        args: Vec<Range>
+    ) -> usize {
+        self.chunk.sites.push(Site {
+            info: info.clone(),
+            fun,
+            custom_source: custom_source.clone(),
+            args,
+        });
+        self.chunk.sites.len() - 1
+    }
+
+    /// Falls back to the tree walker.
+    fn eval(&mut self, expr: &Expression) {
+        self.chunk.exprs.push(expr.clone());
+        let ind = self.chunk.exprs.len() - 1;
+        let current_loop = self.current_loop;
+        self.emit(Op::Eval(ind, current_loop));
+    }
+
+    /// Returns `true` if the expression always pushes one operand.
+    fn is_value(expr: &Expression) -> bool {
+        match *expr {
+            Expression::Variable(_) | Expression::CallBinOp(_) |
+            Expression::CallUnOp(_) | Expression::CallReturn(_) |
+            Expression::CallLazy(_) | Expression::Array(_) => true,
+            Expression::Object(ref obj) => Compiler::is_object(obj),
+            Expression::Vec4(ref vec4) => Compiler::is_vec4(vec4),
+            // Parallel loops are evaluated by the tree walker.
+            Expression::Sum(ref for_n_expr) |
+            Expression::SumVec4(ref for_n_expr) |
+            Expression::Sift(ref for_n_expr) => !for_n_expr.par,
+            Expression::Item(ref item) => Compiler::is_local(item) || Compiler::is_lookup(item),
+            _ => false
+        }
+    }
+
+    fn is_local(item: &ast::Item) -> bool {
+        item.ids.is_empty() && !item.try && item.static_stack_id.get().is_some()
+    }
+
+    fn is_lookup(item: &ast::Item) -> bool {
+        !item.ids.is_empty() && item.static_stack_id.get().is_some()
+    }
+
+    /// Returns `true` if an item can be assigned to without the tree walker.
+    fn is_assignable(item: &ast::Item, op: AssignOp) -> bool {
+        (!item.ids.is_empty() || op != AssignOp::Assign) &&
+        !item.try && item.static_stack_id.get().is_some()
+    }
+
+    /// Objects with duplicate keys are evaluated by the tree walker,
+    /// which reports the error after evaluating the values before it.
+    fn is_object(obj: &ast::Object) -> bool {
+        obj.key_values.iter().enumerate()
+            .all(|(i, kv)| obj.key_values[..i].iter().all(|prev| prev.0 != kv.0))
+    }
+
+    /// Swizzled arguments push several values, so these are evaluated by the tree walker.
+    fn is_vec4(vec4: &ast::Vec4) -> bool {
+        vec4.args.len() == 4 && !vec4.args.iter().any(Compiler::is_swizzle)
+    }
+
+    fn is_closure_call(call: &ast::CallClosure) -> bool {
+        (Compiler::is_local(&call.item) || Compiler::is_lookup(&call.item)) &&
+        !call.args.iter().any(Compiler::is_swizzle)
+    }
+
+    fn is_swizzle(expr: &Expression) -> bool {
+        matches!(*expr, Expression::Swizzle(_))
+    }
+
+    /// Compiles expression that pushes one operand.
+    fn value(&mut self, expr: &Expression, msg: &'static str) {
+        if Compiler::is_value(expr) {
+            self.expr(expr);
+        } else {
+            self.expr(expr);
+            self.emit(Op::Need(expr.source_range(), msg, true));
+        }
+    }
+
+    /// Compiles expression that stores its result in the accumulator.
+    fn stmt(&mut self, expr: &Expression) {
+        self.expr(expr);
+        if Compiler::is_value(expr) {
+            self.emit(Op::Value);
+        }
+    }
+
+    /// Returns `true` if the expression never changes the runtime stack.
+    fn is_simple(expr: &Expression) -> bool {
+        match *expr {
+            Expression::Variable(_) => true,
+            Expression::Item(ref item) => Compiler::is_local(item),
+            Expression::CallBinOp(ref call) =>
+                Compiler::is_simple(&call.left) && Compiler::is_simple(&call.right),
+            Expression::CallUnOp(ref call) => Compiler::is_simple(&call.arg),
+            _ => false
+        }
+    }
+
+    fn block(&mut self, block: &ast::Block) {
+        // Blocks of simple expressions do not need truncating the stack.
+        let simple = block.expressions.iter().all(Compiler::is_simple);
+        if !simple {
+            self.emit(Op::Mark);
+        }
+        if block.expressions.is_empty() {
+            self.emit(Op::Void);
+        }
+        for e in &block.expressions {
+            self.emit(Op::Statement(e.source_range()));
+            self.stmt(e);
+        }
+        if !simple {
+            self.emit(Op::Unmark);
+        }
+    }
+
+    fn enter_loop(&mut self, label: &Option<Arc<String>>) -> (usize, Option<usize>) {
+        self.chunk.loops.push(Loop {
+            label: label.clone(),
+            parent: self.current_loop,
+            break_pc: 0,
+            continue_pc: 0,
+            marks: self.marks,
+            depth: self.depth,
+            frames: self.frames,
+            iters: self.iters,
+        });
+        let ind = self.chunk.loops.len() - 1;
+        let parent = self.current_loop;
+        self.current_loop = Some(ind);
+        (ind, parent)
+    }
+
+    fn find_loop(&self, label: &Option<Arc<String>>) -> Option<usize> {
+        let mut ind = self.current_loop;
+        while let Some(i) = ind {
+            let l = &self.chunk.loops[i];
+            if label.is_none() || l.label == *label { return Some(i); }
+            ind = l.parent;
+        }
+        None
+    }
+
+    /// Compiles an expression.
+    ///
+    /// Value expressions push one operand,
+    /// others store their result in the accumulator.
+    fn expr(&mut self, expr: &Expression) {
+        match *expr {
+            Expression::Variable(ref range_var) => {
+                self.emit(Op::Const(range_var.1.clone()));
+            }
+            Expression::Item(ref item)
+            if Compiler::is_local(item) || Compiler::is_lookup(item) => self.item(item),
+            Expression::Object(ref obj) if Compiler::is_object(obj) => {
+                for (_, value) in &obj.key_values {
+                    self.value(value, "Expected something");
+                }
+                self.chunk.objects.push(obj.key_values.iter().map(|kv| kv.0.clone()).collect());
+                let ind = self.chunk.objects.len() - 1;
+                self.emit(Op::Object(ind, obj.key_values.len()));
+            }
+            Expression::Array(ref arr) => {
+                for item in &arr.items {
+                    self.value(item, "Expected something");
+                }
+                self.emit(Op::Array(arr.items.len()));
+            }
+            Expression::Vec4(ref vec4) if Compiler::is_vec4(vec4) => {
+                for arg in &vec4.args {
+                    self.stmt(arg);
+                    self.emit(Op::AccArg);
+                }
+                let ranges = [vec4.args[0].source_range(), vec4.args[1].source_range(),
+                              vec4.args[2].source_range(), vec4.args[3].source_range()];
+                self.chunk.vec4s.push(ranges);
+                let ind = self.chunk.vec4s.len() - 1;
+                self.emit(Op::Vec4(ind));
+            }
+            Expression::CallClosure(ref call) if Compiler::is_closure_call(call) => {
+                self.item(&call.item);
+                self.chunk.closure_calls.push((**call).clone());
+                let ind = self.chunk.closure_calls.len() - 1;
+                self.emit(Op::ClosureBegin(ind));
+                for arg in &call.args {
+                    self.stmt(arg);
+                    self.emit(Op::AccArg);
+                }
+                self.emit(Op::CallClosure(ind));
+            }
+            Expression::Block(ref block) => self.block(block),
+            Expression::Return(ref ret) => {
+                self.value(ret, "Expected something");
+                self.emit(Op::Return);
+            }
+            Expression::ReturnVoid(_) => {
+                self.emit(Op::ReturnVoid);
+            }
+            Expression::Yield(ref expr) => {
+                self.value(expr, "Expected something");
+                self.emit(Op::Yield);
+                self.emit(Op::Void);
+            }
+            Expression::Break(ref b) => {
+                match self.find_loop(&b.label) {
+                    Some(ind) => self.emit(Op::Exit(ind, false)),
+                    None => self.emit(Op::Escape(false, b.label.clone())),
+                };
+            }
+            Expression::Continue(ref b) => {
+                match self.find_loop(&b.label) {
+                    Some(ind) => self.emit(Op::Exit(ind, true)),
+                    None => self.emit(Op::Escape(true, b.label.clone())),
+                };
+            }
+            Expression::CallBinOp(ref call) => {
+                let msg = "Expected something. Expression did not return a value.";
+                self.value(&call.left, msg);
+                self.value(&call.right, msg);
+                let args = vec![call.left.source_range(), call.right.source_range()];
+                let site = self.site(&call.info, 0, &None, args);
+                self.emit(Op::Binary(call.fun, site));
+            }
+            Expression::CallUnOp(ref call) => {
+                self.value(&call.arg, "Expected something. Expression did not return a value.");
+                let site = self.site(&call.info, 0, &None, vec![call.arg.source_range()]);
+                self.emit(Op::Unary(call.fun, site));
+            }
+            Expression::CallVoid(ref call) => {
+                let site = self.args(&call.args, &call.info);
+                self.emit(Op::CallVoid(call.fun, site));
+                self.emit(Op::Void);
+            }
+            Expression::CallReturn(ref call) => {
+                let site = self.args(&call.args, &call.info);
+                self.emit(Op::CallReturn(call.fun, site));
+            }
+            Expression::CallLazy(ref call) => {
+                let ranges = call.args.iter().map(|arg| arg.source_range()).collect();
+                let site = self.site(&call.info, 0, &None, ranges);
+                let mut exits = vec![];
+                for (i, arg) in call.args.iter().enumerate() {
+                    self.value(arg, "Expected something. Expression did not return a value.");
+                    if call.lazy_inv.get(i).map(|lz| !lz.is_empty()).unwrap_or(false) {
+                        exits.push(self.emit(Op::LazyArg(call.lazy_inv, i, 0)));
+                    } else {
+                        self.emit(Op::PushArg);
+                    }
+                }
+                self.emit(Op::CallReturn(call.fun, site));
+                let end = self.pc();
+                for ind in exits { self.patch(ind, end); }
+            }
+            Expression::CallLoaded(ref call) => {
+                let ranges = call.args.iter().map(|arg| arg.source_range()).collect();
+                let site = self.site(&call.info, call.fun, &call.custom_source, ranges);
+                self.emit(Op::CallBegin(site));
+                let mut exits = vec![];
+                for (i, arg) in call.args.iter().enumerate() {
+                    self.stmt(arg);
+                    exits.push(self.emit(Op::LoadedArg(site, i, 0)));
+                }
+                self.emit(Op::CallLoaded(site));
+                let end = self.pc();
+                for ind in exits { self.patch(ind, end); }
+            }
+            Expression::Assign(ref assign) => {
+                self.value(&assign.right, "Expected something from the right side");
+                match assign.left {
+                    Expression::Item(ref item) if Compiler::is_assignable(item, assign.op) => {
+                        let n = self.ids(item);
+                        self.chunk.items.push((**item).clone());
+                        let ind = self.chunk.items.len() - 1;
+                        self.emit(Op::AssignItem(assign.op, ind, n));
+                    }
+                    _ => {
+                        self.chunk.exprs.push(assign.left.clone());
+                        let ind = self.chunk.exprs.len() - 1;
+                        self.emit(Op::Assign(assign.op, ind));
+                    }
+                }
+                self.emit(Op::Void);
+            }
+            Expression::If(ref if_expr) => self.if_expr(if_expr),
+            Expression::For(ref for_expr) => self.for_expr(for_expr),
+            Expression::ForN(ref for_n_expr) => self.for_n_expr(for_n_expr, Reduce::Nothing),
+            Expression::ForIn(ref for_in_expr) => self.for_in_expr(for_in_expr),
+            Expression::Sum(ref for_n_expr) if !for_n_expr.par =>
+                self.for_n_expr(for_n_expr, Reduce::Sum),
+            Expression::SumVec4(ref for_n_expr) if !for_n_expr.par =>
+                self.for_n_expr(for_n_expr, Reduce::SumVec4),
+            Expression::Sift(ref for_n_expr) if !for_n_expr.par =>
+                self.for_n_expr(for_n_expr, Reduce::Sift),
+            _ => self.eval(expr),
+        }
+    }
+
+    /// Compiles an item that pushes one operand.
+    fn item(&mut self, item: &ast::Item) {
+        if Compiler::is_local(item) {
+            self.emit(Op::Local(item.static_stack_id.get().unwrap()));
+        } else {
+            let n = self.ids(item);
+            self.chunk.items.push(item.clone());
+            let ind = self.chunk.items.len() - 1;
+            self.emit(Op::Item(ind, n));
+        }
+    }
+
+    /// Pushes computed ids of an item to the runtime stack, returning their number.
+    fn ids(&mut self, item: &ast::Item) -> usize {
+        let mut n = 0;
+        for id in &item.ids {
+            if let ast::Id::Expression(ref expr) = *id {
+                self.value(expr, "Expected something for index");
+                self.emit(Op::PushArg);
+                n += 1;
+            }
+        }
+        n
+    }
+
+    fn args(&mut self, args: &[Expression], info: &ast::CallInfo) -> usize {
+        for arg in args {
+            self.value(arg, "Expected something. Expression did not return a value.");
+            self.emit(Op::PushArg);
+        }
+        let ranges = args.iter().map(|arg| arg.source_range()).collect();
+        self.site(info, 0, &None, ranges)
+    }
+
+    fn if_expr(&mut self, if_expr: &ast::If) {
+        let mut ends = vec![];
+        self.value(&if_expr.cond, "Expected bool from if condition");
+        let mut next = self.emit(Op::JumpIfFalse(0, if_expr.cond.source_range(),
+                                                 "Expected bool from if condition", true));
+        self.block(&if_expr.true_block);
+        ends.push(self.emit(Op::Jump(0)));
+        for (cond, body) in if_expr.else_if_conds.iter()
+            .zip(if_expr.else_if_blocks.iter()) {
+            let pc = self.pc();
+            self.patch(next, pc);
+            self.value(cond, "Expected bool from else if condition");
+            next = self.emit(Op::JumpIfFalse(0, cond.source_range(),
+                                             "Expected bool from else if condition", true));
+            self.block(body);
+            ends.push(self.emit(Op::Jump(0)));
+        }
+        let pc = self.pc();
+        self.patch(next, pc);
+        if let Some(ref block) = if_expr.else_block {
+            self.block(block);
+        } else {
+            self.emit(Op::Void);
+        }
+        let end = self.pc();
+        for ind in ends { self.patch(ind, end); }
+    }
+
+    fn for_expr(&mut self, for_expr: &ast::For) {
+        self.emit(Op::Mark);
+        self.stmt(&for_expr.init);
+        self.emit(Op::NeedNone(for_expr.init.source_range(), "Expected nothing from for init"));
+        self.emit(Op::Mark);
+        let cond = self.pc();
+        self.value(&for_expr.cond, "Expected bool from for condition");
+        let exit = self.emit(Op::JumpIfFalse(0, for_expr.cond.source_range(),
+                                             "Expected bool", false));
+        let (ind, parent) = self.enter_loop(&for_expr.label);
+        self.block(&for_expr.block);
+        self.current_loop = parent;
+        self.stmt(&for_expr.step);
+        self.emit(Op::NeedNone(for_expr.step.source_range(), "Expected nothing from for step"));
+        self.emit(Op::Reset);
+        self.emit(Op::Jump(cond));
+        // When continuing the loop, the stack is not truncated.
+        let continue_pc = self.pc();
+        self.stmt(&for_expr.step);
+        self.emit(Op::NeedNone(for_expr.step.source_range(), "Expected nothing from for step"));
+        self.emit(Op::Jump(cond));
+        let break_pc = self.pc();
+        self.patch(exit, break_pc);
+        self.emit(Op::DropMark);
+        self.emit(Op::UnmarkLocals);
+        self.emit(Op::Void);
+        self.chunk.loops[ind].break_pc = break_pc;
+        self.chunk.loops[ind].continue_pc = continue_pc;
+    }
+
+    fn for_in_expr(&mut self, for_in_expr: &ast::ForIn) {
+        self.emit(Op::Mark);
+        self.value(&for_in_expr.iter, "Expected in-type or collection from for iter");
+        self.emit(Op::ForInInit(for_in_expr.name.clone(), for_in_expr.iter.source_range()));
+        self.emit(Op::Mark);
+        let cond = self.emit(Op::ForInNext(0, for_in_expr.source_range));
+        let (ind, parent) = self.enter_loop(&for_in_expr.label);
+        self.block(&for_in_expr.block);
+        self.current_loop = parent;
+        self.emit(Op::Jump(cond));
+        let break_pc = self.pc();
+        self.patch(cond, break_pc);
+        self.emit(Op::DropMark);
+        self.emit(Op::UnmarkLocals);
+        self.emit(Op::PopIter);
+        self.emit(Op::Void);
+        self.chunk.loops[ind].break_pc = break_pc;
+        self.chunk.loops[ind].continue_pc = cond;
+    }
+
+    fn for_n_expr(&mut self, for_n_expr: &ast::ForN, reduce: Reduce) {
+        self.emit(Op::Mark);
+        match reduce {
+            Reduce::Nothing => {}
+            Reduce::Sum => {self.emit(Op::Const(Variable::f64(0.0)));}
+            Reduce::SumVec4 => {self.emit(Op::Const(Variable::Vec4([0.0; 4])));}
+            Reduce::Sift => {self.emit(Op::Const(Variable::Array(Arc::new(vec![]))));}
+        }
+        if let Some(ref start) = for_n_expr.start {
+            self.value(start, "Expected number from for start");
+            self.emit(Op::Number(start.source_range()));
+        } else {
+            self.emit(Op::Const(Variable::f64(0.0)));
+        }
+        self.value(&for_n_expr.end, "Expected number from for end");
+        self.emit(Op::Number(for_n_expr.end.source_range()));
+        self.emit(Op::ForNInit(for_n_expr.name.clone()));
+        self.emit(Op::Mark);
+        let cond = self.emit(Op::ForNCond(0, for_n_expr.source_range));
+        let (ind, parent) = self.enter_loop(&for_n_expr.label);
+        self.block(&for_n_expr.block);
+        let range = for_n_expr.block.source_range;
+        match reduce {
+            Reduce::Nothing => {}
+            Reduce::Sum => {self.emit(Op::SumAdd(range));}
+            Reduce::SumVec4 => {self.emit(Op::SumVec4Add(range));}
+            Reduce::Sift => {self.emit(Op::SiftPush(range));}
+        }
+        self.current_loop = parent;
+        let continue_pc = self.emit(Op::ForNNext(cond, for_n_expr.source_range));
+        let break_pc = self.pc();
+        self.patch(cond, break_pc);
+        self.emit(Op::DropMark);
+        self.emit(Op::UnmarkLocals);
+        // Remove end of range.
+        self.emit(Op::Pop);
+        if reduce == Reduce::Nothing {
+            self.emit(Op::Void);
+        }
+        self.chunk.loops[ind].break_pc = break_pc;
+        self.chunk.loops[ind].continue_pc = continue_pc;
+    }
+}
+
+impl Runtime {
+    fn site_error(&self, err: String, site: &Site) -> RuntimeError {
+        let range = if let Some(ind) = self.arg_err_index.get() {
+            self.arg_err_index.set(None);
+            site.args.get(ind).cloned().unwrap_or(site.info.source_range)
+        } else {
+            site.info.source_range
+        };
+        self.module.error(range, &err, self)
+    }
+
+    fn lazy_value(&self, x: &Variable, lazy: &[ast::Lazy]) -> Option<Variable> {
+        use ast::Lazy;
+
+        for lz in lazy {
+            match *lz {
+                Lazy::Variable(ref val) => {
+                    if self.resolve(x) == val {return Some(x.clone())}
+                }
+                Lazy::UnwrapOk => {
+                    if let Variable::Result(Ok(ref x)) = *self.resolve(x) {
+                        return Some((**x).clone())
+                    }
+                }
+                Lazy::UnwrapErr => {
+                    if let Variable::Result(Err(ref x)) = *self.resolve(x) {
+                        return Some(x.message.clone())
+                    }
+                }
+                Lazy::UnwrapSome => {
+                    if let Variable::Option(Some(ref x)) = *self.resolve(x) {
+                        return Some((**x).clone())
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Checks for cancellation and limits when repeating a loop.
+    fn check_loop(&mut self, range: Range) -> Result<(), RuntimeError> {
+        if self.cancelled() {
+            return Err(self.module.error(range,
+                &format!("{}\nThread was cancelled", self.stack_trace()), self));
+        }
+        if self.limits.any() {
+            self.check_limits(range)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the stack id of an item, with computed ids starting at a stack length.
+    fn item_stack_id(&self, item: &ast::Item, start: usize) -> usize {
+        let stack_id = start - item.static_stack_id.get().unwrap();
+        if let Variable::Ref(ref_id) = self.stack[stack_id] {
+            ref_id
+        } else {
+            stack_id
+        }
+    }
+
+    /// Executes bytecode of a function block.
+    ///
+    /// Has the same semantics as evaluating the block with the tree walker.
+    pub(crate) fn run_chunk(&mut self, chunk: &Chunk) -> FlowResult {
+        let base = self.vm.base();
+        let res = self.run_chunk_registers(chunk, 0, base);
+        self.vm.truncate(base);
+        res
+    }
+
+    /// Jumps to the break or continue address of a loop.
+    fn exit_loop(&mut self, chunk: &Chunk, ind: usize, cont: bool, base: Base) -> usize {
+        let l = &chunk.loops[ind];
+        // Truncate the same way as leaving the blocks inside the loop.
+        if let Some(&(st, lc, cu)) = self.vm.marks.get(base.marks + l.marks) {
+            self.stack.truncate(st);
+            self.local_stack.truncate(lc);
+            self.current_stack.truncate(cu);
+        }
+        self.vm.truncate(Base {
+            operands: base.operands + l.depth,
+            marks: base.marks + l.marks,
+            frames: base.frames + l.frames,
+            iters: base.iters + l.iters,
+        });
+        if cont { l.continue_pc } else { l.break_pc }
+    }
+
+    /// Runs a chunk from an address, with registers above the base belonging to the chunk.
+    pub(crate) fn run_chunk_registers(
+        &mut self,
+        chunk: &Chunk,
+        mut pc: usize,
+        base: Base
+    ) -> FlowResult {
+        let mut acc: Option<Variable> = None;
+        loop {
+            match chunk.ops[pc] {
+                Op::Const(ref v) => self.vm.operands.push(v.clone()),
+                Op::Local(id) => {
+                    let stack_id = self.stack.len() - id;
+                    let stack_id = if let Variable::Ref(ref_id) = self.stack[stack_id] {
+                        ref_id
+                    } else {
+                        stack_id
+                    };
+                    self.vm.operands.push(Variable::Ref(stack_id));
+                }
+                Op::Item(ind, n) => {
+                    let item = &chunk.items[ind];
+                    let start = self.stack.len() - n;
+                    let stack_id = self.item_stack_id(item, start);
+                    match self.item_ids(item, stack_id, start, Side::Right)? {
+                        (x, Flow::Return) => return Ok((x, Flow::Return)),
+                        (x, _) => self.vm.operands.push(x.expect(TINVOTS)),
+                    }
+                }
+                Op::Object(ind, n) => {
+                    let values = self.vm.operands.split_off(self.vm.operands.len() - n);
+                    let object: HashMap<_, _> = chunk.objects[ind].iter().cloned()
+                        .zip(values).collect();
+                    self.vm.operands.push(Variable::Object(Arc::new(object)));
+                }
+                Op::Array(n) => {
+                    let array = self.vm.operands.split_off(self.vm.operands.len() - n);
+                    self.vm.operands.push(Variable::Array(Arc::new(array)));
+                }
+                Op::Vec4(ind) => {
+                    let ranges = &chunk.vec4s[ind];
+                    let mut v = [0.0; 4];
+                    for i in (0..4).rev() {
+                        let x = self.stack.pop().expect(TINVOTS);
+                        v[i] = match *self.resolve(&x) {
+                            Variable::F64(val, _) => val as f32,
+                            ref x => return self.err(ranges[i], &self.expected(x, "number"))
+                        };
+                    }
+                    self.vm.operands.push(Variable::Vec4(v));
+                }
+                Op::Eval(ind, current_loop) => {
+                    match self.expression(&chunk.exprs[ind], Side::Right)? {
+                        (x, Flow::Continue) => acc = x,
+                        (x, Flow::Return) => return Ok((x, Flow::Return)),
+                        (_, flow) => {
+                            let (cont, label) = match flow {
+                                Flow::Break(label) => (false, label),
+                                Flow::ContinueLoop(label) => (true, label),
+                                _ => unreachable!()
+                            };
+                            let mut ind = current_loop;
+                            while let Some(i) = ind {
+                                let l = &chunk.loops[i];
+                                if label.is_none() || l.label == label { break; }
+                                ind = l.parent;
+                            }
+                            match ind {
+                                Some(i) => {
+                                    pc = self.exit_loop(chunk, i, cont, base);
+                                    continue;
+                                }
+                                None => return Ok((None, if cont {
+                                    Flow::ContinueLoop(label)
+                                } else {
+                                    Flow::Break(label)
+                                })),
+                            }
+                        }
+                    }
+                }
+                Op::Need(range, msg, trace) => {
+                    match acc.take() {
+                        Some(x) => self.vm.operands.push(x),
+                        None => if trace {
+                            return self.err(range, msg)
+                        } else {
+                            return Err(self.module.error(range, msg, self))
+                        }
+                    }
+                }
+                Op::NeedNone(range, msg) => {
+                    if acc.is_some() { return self.err(range, msg) }
+                }
+                Op::Value => acc = self.vm.operands.pop(),
+                Op::Void => acc = None,
+                Op::Pop => { self.vm.operands.pop(); }
+                Op::Statement(range) => {
+                    if self.limits.any() {
+                        self.check_limits(range)?;
+                    }
+                    if self.debug_hook.is_some() {
+                        self.debug(range, true)?;
+                    }
+                }
+                Op::PushArg => {
+                    let x = self.vm.operands.pop().expect(TINVOTS);
+                    self.stack.push(x);
+                }
+                Op::AccArg => {
+                    if let Some(x) = acc.take() { self.stack.push(x) }
+                }
+                Op::Binary(fun, site) => {
+                    let right = self.vm.operands.pop().expect(TINVOTS);
+                    let left = self.vm.operands.pop().expect(TINVOTS);
+                    let v = (fun.0)(self.resolve(&left), self.resolve(&right))
+                        .map_err(|err| self.site_error(err, &chunk.sites[site]))?;
+                    self.vm.operands.push(v);
+                }
+                Op::Unary(fun, site) => {
+                    let arg = self.vm.operands.pop().expect(TINVOTS);
+                    let v = (fun.0)(self.resolve(&arg))
+                        .map_err(|err| self.site_error(err, &chunk.sites[site]))?;
+                    self.vm.operands.push(v);
+                }
+                Op::CallVoid(fun, site) => {
+                    (fun.0)(self).map_err(|err| self.site_error(err, &chunk.sites[site]))?;
+                }
+                Op::CallReturn(fun, site) => {
+                    let v = (fun.0)(self).map_err(|err| self.site_error(err, &chunk.sites[site]))?;
+                    self.vm.operands.push(v);
+                }
+                Op::LazyArg(lazy_inv, i, end) => {
+                    let x = self.vm.operands.pop().expect(TINVOTS);
+                    match self.lazy_value(&x, lazy_inv[i]) {
+                        Some(v) => {
+                            self.vm.operands.push(v);
+                            pc = end;
+                            continue;
+                        }
+                        None => self.stack.push(x),
+                    }
+                }
+                Op::CallBegin(site) => {
+                    let relative = self.call_stack.last().map(|c| c.index).unwrap_or(0);
+                    let new_index = (chunk.sites[site].fun + relative as isize) as usize;
+                    if self.module.functions[new_index].returns() {
+                        // Add return value before arguments on the stack.
+                        self.stack.push(Variable::Return);
+                    }
+                    self.vm.frames.push((self.stack.len(), self.local_stack.len(),
+                                 self.current_stack.len()));
+                }
+                Op::LoadedArg(site, i, end) => {
+                    if let Some(x) = acc.take() {
+                        let relative = self.call_stack.last().map(|c| c.index).unwrap_or(0);
+                        let new_index = (chunk.sites[site].fun + relative as isize) as usize;
+                        let v = self.module.functions[new_index].lazy_inv.get(i)
+                            .and_then(|lz| self.lazy_value(&x, lz));
+                        match v {
+                            Some(v) => {
+                                self.vm.frames.pop();
+                                acc = Some(v);
+                                pc = end;
+                                continue;
+                            }
+                            None => self.stack.push(x),
+                        }
+                    }
+                }
+                Op::CallLoaded(site) => {
+                    let (st, lc, cu) = self.vm.frames.pop().expect(TINVOTS);
+                    let site = &chunk.sites[site];
+                    let relative = self.call_stack.last().map(|c| c.index).unwrap_or(0);
+                    let new_index = (site.fun + relative as isize) as usize;
+                    // Copy the module to avoid problems with borrow checker.
+                    let mod_copy = self.module.clone();
+                    let f = &mod_copy.functions[new_index];
+                    acc = self.call_loaded_body(f, new_index, &site.info,
+                                                &site.custom_source, st, lc, cu)?.0;
+                }
+                Op::ClosureBegin(ind) => {
+                    let call = &chunk.closure_calls[ind];
+                    let f = {
+                        let x = self.vm.operands.last().expect(TINVOTS);
+                        match *self.resolve(x) {
+                            ref x @ Variable::Closure(..) => x.clone(),
+                            ref x => return self.err(call.source_range,
+                                                     &self.expected(x, "closure"))
+                        }
+                    };
+                    let returns = match f {
+                        Variable::Closure(ref f, _) => {
+                            if call.arg_len() != f.args.len() {
+                                return Err(self.module.error(call.source_range,
+                                    &format!("{}\nExpected {} arguments but found {}",
+                                    self.stack_trace(),
+                                    f.args.len(),
+                                    call.arg_len()), self));
+                            }
+                            f.returns()
+                        }
+                        _ => unreachable!()
+                    };
+                    *self.vm.operands.last_mut().unwrap() = f;
+                    if returns {
+                        // Add return value before arguments on the stack.
+                        self.stack.push(Variable::Return);
+                    }
+                    self.vm.frames.push((self.stack.len(), self.local_stack.len(),
+                                 self.current_stack.len()));
+                }
+                Op::CallClosure(ind) => {
+                    let (st, lc, cu) = self.vm.frames.pop().expect(TINVOTS);
+                    let (f, env) = match self.vm.operands.pop() {
+                        Some(Variable::Closure(f, env)) => (f, env),
+                        _ => unreachable!()
+                    };
+                    acc = self.call_closure_body(&chunk.closure_calls[ind], f, &env,
+                                                 st, lc, cu)?.0;
+                }
+                Op::Assign(op, ind) => {
+                    let b = self.vm.operands.pop().expect(TINVOTS);
+                    if let (x, Flow::Return) = self.assign_value(op, &chunk.exprs[ind], b)? {
+                        return Ok((x, Flow::Return));
+                    }
+                }
+                Op::AssignItem(op, ind, n) => {
+                    let b = self.vm.operands.pop().expect(TINVOTS);
+                    let item = &chunk.items[ind];
+                    let start = self.stack.len() - n;
+                    let stack_id = self.item_stack_id(item, start);
+                    if op == AssignOp::Assign {
+                        // Shallow clone references, like the tree walker.
+                        let v = match b {
+                            Variable::Ref(ind) => self.stack[ind].clone(),
+                            x => x
+                        };
+                        match self.item_ids(item, stack_id, start, Side::LeftInsert(true))? {
+                            (Some(Variable::UnsafeRef(r)), Flow::Continue) => unsafe { *r.0 = v },
+                            (x, Flow::Return) => return Ok((x, Flow::Return)),
+                            _ => panic!("Expected unsafe reference")
+                        }
+                    } else {
+                        let a = if item.ids.is_empty() {
+                            Variable::Ref(stack_id)
+                        } else {
+                            match self.item_ids(item, stack_id, start, Side::LeftInsert(false))? {
+                                (x, Flow::Return) => return Ok((x, Flow::Return)),
+                                (x, _) => x.expect(TINVOTS),
+                            }
+                        };
+                        self.assign_ref(op, item.source_range, a, b)?;
+                    }
+                }
+                Op::Jump(target) => {
+                    pc = target;
+                    continue;
+                }
+                Op::JumpIfFalse(target, range, msg, resolve) => {
+                    // Check loops that do not evaluate statements.
+                    self.check_loop(range)?;
+                    let cond = self.vm.operands.pop().expect(TINVOTS);
+                    let val = {
+                        let cond = if resolve { self.resolve(&cond) } else { &cond };
+                        match *cond {
+                            Variable::Bool(val, _) => val,
+                            _ => return self.err(range, msg)
+                        }
+                    };
+                    if !val {
+                        pc = target;
+                        continue;
+                    }
+                }
+                Op::Mark => self.vm.marks.push((self.stack.len(), self.local_stack.len(),
+                                        self.current_stack.len())),
+                Op::Unmark => {
+                    let (st, lc, cu) = self.vm.marks.pop().expect(TINVOTS);
+                    self.stack.truncate(st);
+                    self.local_stack.truncate(lc);
+                    self.current_stack.truncate(cu);
+                }
+                Op::UnmarkLocals => {
+                    let (st, lc, _) = self.vm.marks.pop().expect(TINVOTS);
+                    self.stack.truncate(st);
+                    self.local_stack.truncate(lc);
+                }
+                Op::Reset => {
+                    let (st, lc, _) = *self.vm.marks.last().expect(TINVOTS);
+                    self.stack.truncate(st);
+                    self.local_stack.truncate(lc);
+                }
+                Op::DropMark => { self.vm.marks.pop(); }
+                Op::Number(range) => {
+                    let val = match *self.resolve(self.vm.operands.last().expect(TINVOTS)) {
+                        Variable::F64(val, _) => val,
+                        ref x => return Err(self.module.error(range,
+                                            &self.expected(x, "number"), self))
+                    };
+                    *self.vm.operands.last_mut().unwrap() = Variable::f64(val);
+                }
+                Op::ForNInit(ref name) => {
+                    let end = self.vm.operands.pop().expect(TINVOTS);
+                    let start = self.vm.operands.pop().expect(TINVOTS);
+                    // Initialize counter.
+                    self.local_stack.push((name.clone(), self.stack.len()));
+                    self.stack.push(start);
+                    self.vm.operands.push(end);
+                }
+                Op::ForNCond(target, range) => {
+                    self.check_loop(range)?;
+                    let st = self.vm.marks.last().expect(TINVOTS).0;
+                    let end = match *self.vm.operands.last().expect(TINVOTS) {
+                        Variable::F64(val, _) => val,
+                        _ => unreachable!()
+                    };
+                    match self.stack[st - 1] {
+                        Variable::F64(val, _) => {
+                            if val < end {} else {
+                                pc = target;
+                                continue;
+                            }
+                        }
+                        ref x => return Err(self.module.error(range,
+                                            &self.expected(x, "number"), self))
+                    }
+                }
+                Op::ForNNext(target, range) => {
+                    let (st, lc, _) = *self.vm.marks.last().expect(TINVOTS);
+                    if let Variable::F64(ref mut val, _) = self.stack[st - 1] {
+                        *val += 1.0;
+                    } else {
+                        return Err(self.module.error(range,
+                                   &self.expected(&self.stack[st - 1], "number"), self))
+                    }
+                    self.stack.truncate(st);
+                    self.local_stack.truncate(lc);
+                    pc = target;
+                    continue;
+                }
+                Op::SumAdd(range) => {
+                    let val = match acc.take() {
+                        Some(x) => match *self.resolve(&x) {
+                            Variable::F64(val, _) => val,
+                            ref x => return Err(self.module.error(range,
+                                                &self.expected(x, "number"), self))
+                        },
+                        None => return Err(self.module.error(range,
+                                           "Expected `number`", self))
+                    };
+                    let n = self.vm.operands.len();
+                    if let Variable::F64(ref mut sum, _) = self.vm.operands[n - 2] {
+                        *sum += val;
+                    }
+                }
+                Op::SumVec4Add(range) => {
+                    let val = match acc.take() {
+                        Some(x) => match *self.resolve(&x) {
+                            Variable::Vec4(val) => val,
+                            ref x => return Err(self.module.error(range,
+                                                &self.expected(x, "vec4"), self))
+                        },
+                        None => return Err(self.module.error(range,
+                                           "Expected `vec4`", self))
+                    };
+                    let n = self.vm.operands.len();
+                    if let Variable::Vec4(ref mut sum) = self.vm.operands[n - 2] {
+                        for i in 0..4 {
+                            sum[i] += val[i]
+                        }
+                    }
+                }
+                Op::SiftPush(range) => {
+                    let x = match acc.take() {
+                        Some(x) => x,
+                        None => return Err(self.module.error(range,
+                                           "Expected variable", self))
+                    };
+                    let n = self.vm.operands.len();
+                    if let Variable::Array(ref mut arr) = self.vm.operands[n - 2] {
+                        Arc::make_mut(arr).push(x);
+                    }
+                }
+                Op::ForInInit(ref name, range) => {
+                    let v = self.vm.operands.pop().expect(TINVOTS);
+                    let iter = match Iter::new(self.resolve(&v)) {
+                        Some(iter) => iter,
+                        None => return Err(self.module.error(range,
+                            &self.expected(self.resolve(&v), "in, array, object, string or link"),
+                            self))
+                    };
+                    self.vm.iters.push(iter);
+                    // Initialize item, which is set before running the loop body.
+                    self.local_stack.push((name.clone(), self.stack.len()));
+                    self.stack.push(Variable::Return);
+                }
+                Op::ForInNext(target, range) => {
+                    self.check_loop(range)?;
+                    let (st, lc, _) = *self.vm.marks.last().expect(TINVOTS);
+                    self.stack.truncate(st);
+                    self.local_stack.truncate(lc);
+                    // Take out the iterator, since getting the next item might run code.
+                    let mut iter = self.vm.iters.pop().expect(TINVOTS);
+                    let item = iter.next(self);
+                    self.vm.iters.push(iter);
+                    match item {
+                        Ok(Some(x)) => self.stack[st - 1] = x,
+                        Ok(None) => {
+                            pc = target;
+                            continue;
+                        }
+                        Err(err) => return Err(self.module.error(range, err.text(), self)),
+                    }
+                }
+                Op::PopIter => { self.vm.iters.pop(); }
+                Op::Yield => {
+                    let v = self.vm.operands.pop().expect(TINVOTS);
+                    let v = self.resolve(&v).deep_clone(&self.stack);
+                    self.suspend(pc + 1, base);
+                    return Ok((Some(v), Flow::Return));
+                }
+                Op::Exit(ind, cont) => {
+                    pc = self.exit_loop(chunk, ind, cont, base);
+                    continue;
+                }
+                Op::Escape(cont, ref label) => {
+                    return Ok((None, if cont {
+                        Flow::ContinueLoop(label.clone())
+                    } else {
+                        Flow::Break(label.clone())
+                    }))
+                }
+                Op::Return => return Ok((self.vm.operands.pop(), Flow::Return)),
+                Op::ReturnVoid => return Ok((None, Flow::Return)),
+                Op::End => return Ok((acc, Flow::Continue)),
+            }
+            pc += 1;
+        }
+    }
+}
+
@@ -0,0 +1,234 @@
+//! Coroutines that suspend at `yield` and are resumed later.
+//!
+//! A coroutine function runs with the bytecode VM.
+//! When it yields, the segments of the runtime stacks that belong to the function
+//! are moved into a suspended frame together with the registers of the VM.
+//! Stack indices are stored relative to the start of the segment,
+//! such that the frame can be resumed at any stack depth.
+
+use super::*;
+use super::bytecode::Base;
+use super::for_in::Iter;
+
+use std::mem::replace;
+use std::sync::Mutex;
+
+use Coroutine;
+
+/// The state of a coroutine.
+pub(crate) enum State {
+    /// Waiting to be resumed.
+    Suspended(Box<Frame>),
+    /// Currently running.
+    Running,
+    /// Returned from the function.
+    Done,
+}
+
+impl State {
+    /// Returns `true` if the coroutine function has returned.
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(*self, State::Done)
+    }
+}
+
+// Required because the `Send` impl of `Variable` is unsafe.
+unsafe impl Send for State {}
+
+/// Stores a suspended coroutine function.
+pub(crate) struct Frame {
+    /// The module of the function.
+    module: Arc<Module>,
+    fn_name: Arc<String>,
+    /// The index of the function in module.
+    index: usize,
+    file: Option<Arc<String>>,
+    /// Where to continue in the bytecode.
+    pc: usize,
+    /// Stack segment, starting with the return slot.
+    stack: Vec<Variable>,
+    local_stack: Vec<(Arc<String>, usize)>,
+    current_stack: Vec<(Arc<String>, usize)>,
+    operands: Vec<Variable>,
+    marks: Vec<(usize, usize, usize)>,
+    frames: Vec<(usize, usize, usize)>,
+    iters: Vec<Iter>,
+}
+
+/// Makes a variable relative to the start of a stack segment.
+///
+/// References below the segment are replaced by a copy of the value.
+fn relative(v: Variable, st: usize, stack: &[Variable]) -> Variable {
+    match v {
+        Variable::Ref(ind) if ind >= st => Variable::Ref(ind - st),
+        Variable::Ref(ind) => stack[ind].deep_clone(stack),
+        x => x
+    }
+}
+
+/// Makes a variable absolute after moving a stack segment to a new start.
+fn absolute(v: Variable, st: usize) -> Variable {
+    match v {
+        Variable::Ref(ind) => Variable::Ref(ind + st),
+        x => x
+    }
+}
+
+impl Runtime {
+    /// Creates a coroutine from a function call.
+    ///
+    /// The arguments are moved from the stack, after the return slot.
+    pub(crate) fn create_coroutine(
+        &mut self,
+        f: &ast::Function,
+        index: usize,
+        name: &Arc<String>,
+        st: usize,
+        lc: usize,
+    ) -> Variable {
+        let mut stack = vec![Variable::Return];
+        for i in st..self.stack.len() {
+            stack.push(self.resolve(&self.stack[i]).deep_clone(&self.stack));
+        }
+        self.stack.truncate(st - 1);
+        self.local_stack.truncate(lc);
+
+        let mut local_stack = vec![(RETURN_TYPE.clone(), 0)];
+        for (i, arg) in f.args.iter().enumerate() {
+            local_stack.push((arg.name.clone(), i + 1));
+        }
+        Variable::Coroutine(Coroutine {
+            state: Arc::new(Mutex::new(State::Suspended(Box::new(Frame {
+                module: self.module.clone(),
+                fn_name: name.clone(),
+                index,
+                file: Some(f.file.clone()),
+                pc: 0,
+                stack,
+                local_stack,
+                current_stack: vec![],
+                operands: vec![],
+                marks: vec![],
+                frames: vec![],
+                iters: vec![],
+            }))))
+        })
+    }
+
+    /// Moves the running coroutine function into a suspended frame.
+    ///
+    /// The frame is picked up by `Runtime::resume` after the VM returns.
+    pub(crate) fn suspend(&mut self, pc: usize, base: Base) {
+        let call = self.call_stack.pop().expect(TINVOTS);
+        // Include the return slot.
+        let st = call.stack_len - 1;
+        let (lc, cu) = (call.local_len, call.current_len);
+
+        let mut stack = Vec::with_capacity(self.stack.len() - st);
+        for i in st..self.stack.len() {
+            let v = replace(&mut self.stack[i], Variable::Return);
+            stack.push(relative(v, st, &self.stack));
+        }
+        self.stack.truncate(st);
+        let operands = self.vm.operands.drain(base.operands..).collect::<Vec<_>>()
+            .into_iter().map(|v| relative(v, st, &self.stack)).collect();
+        let rebase = |&(a, b, c): &(usize, usize, usize)| (a - st, b - lc, c - cu);
+        let marks = self.vm.marks[base.marks..].iter().map(rebase).collect();
+        let frames = self.vm.frames[base.frames..].iter().map(rebase).collect();
+
+        self.vm.suspended = Some(Box::new(Frame {
+            module: self.module.clone(),
+            fn_name: call.fn_name,
+            index: call.index,
+            file: call.file,
+            pc,
+            stack,
+            local_stack: self.local_stack.drain(lc..).map(|(n, i)| (n, i - st)).collect(),
+            current_stack: self.current_stack.drain(cu..).map(|(n, i)| (n, i - st)).collect(),
+            operands,
+            marks,
+            frames,
+            iters: self.vm.iters.drain(base.iters..).collect(),
+        }));
+    }
+
+    /// Resumes a coroutine until it yields or returns.
+    ///
+    /// Returns the yielded value, or `None` when the coroutine is done.
+    pub(crate) fn resume(&mut self, co: &Coroutine) -> Result<Option<Variable>, RuntimeError> {
+        let frame = {
+            let mut state = co.state.lock()
+                .map_err(|err| format!("Can not lock coroutine mutex:\n{}", err))?;
+            match replace(&mut *state, State::Running) {
+                State::Suspended(frame) => frame,
+                State::Running => return Err("Coroutine is already running".into()),
+                State::Done => {
+                    *state = State::Done;
+                    return Ok(None)
+                }
+            }
+        };
+        let frame = *frame;
+
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let cu = self.current_stack.len();
+        let cs = self.call_stack.len();
+        self.stack.extend(frame.stack.into_iter().map(|v| absolute(v, st)));
+        self.local_stack.extend(frame.local_stack.into_iter().map(|(n, i)| (n, i + st)));
+        self.current_stack.extend(frame.current_stack.into_iter().map(|(n, i)| (n, i + st)));
+        self.call_stack.push(Call {
+            fn_name: frame.fn_name.clone(),
+            index: frame.index,
+            file: frame.file,
+            stack_len: st + 1,
+            local_len: lc,
+            current_len: cu,
+        });
+
+        let base = self.vm.base();
+        let rebase = |(a, b, c): (usize, usize, usize)| (a + st, b + lc, c + cu);
+        self.vm.operands.extend(frame.operands.into_iter().map(|v| absolute(v, st)));
+        self.vm.marks.extend(frame.marks.into_iter().map(rebase));
+        self.vm.frames.extend(frame.frames.into_iter().map(rebase));
+        self.vm.iters.extend(frame.iters);
+
+        let old_module = replace(&mut self.module, frame.module.clone());
+        let res = self.run_chunk_registers(
+            frame.module.functions[frame.index].bytecode(), frame.pc, base);
+        self.vm.truncate(base);
+        self.module = old_module;
+
+        let suspended = self.vm.suspended.take();
+        let mut state = co.state.lock()
+            .map_err(|err| format!("Can not lock coroutine mutex:\n{}", err))?;
+        let (v, flow) = match res {
+            Ok(x) => x,
+            Err(err) => {
+                *state = State::Done;
+                // Remove the function and the calls it made, such that the caller can continue.
+                self.call_stack.truncate(cs);
+                self.stack.truncate(st);
+                self.local_stack.truncate(lc);
+                self.current_stack.truncate(cu);
+                return Err(err)
+            }
+        };
+        if let Some(frame) = suspended {
+            *state = State::Suspended(frame);
+            return Ok(v);
+        }
+
+        *state = State::Done;
+        drop(state);
+        self.pop_fn(frame.fn_name);
+        self.stack.truncate(st);
+        match flow {
+            Flow::Break(None) => Err("Can not break from function".into()),
+            Flow::ContinueLoop(None) => Err("Can not continue from function".into()),
+            Flow::Break(Some(ref label)) | Flow::ContinueLoop(Some(ref label)) =>
+                Err(format!("There is no loop labeled `{}`", label).into()),
+            _ => Ok(None)
+        }
+    }
+}
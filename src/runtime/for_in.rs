@@ -1,33 +1,106 @@
 use super::*;
 
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::vec;
+
+use {Array, Coroutine, Link};
+
+/// Iterates over the items of an in-type or a collection.
+pub(crate) enum Iter {
+    /// Receives items from an in-type until there are no more.
+    In(Arc<Mutex<Receiver<Variable>>>),
+    /// Array items by value.
+    Array(Array, usize),
+    /// Items computed ahead, used by objects, strings and links.
+    Values(vec::IntoIter<Variable>),
+    /// Resumes a coroutine until it is done.
+    Coroutine(Coroutine),
+}
+
+impl Iter {
+    /// Creates an iterator from a value, returning `None` if the value can not be iterated.
+    pub(crate) fn new(v: &Variable) -> Option<Iter> {
+        Some(match *v {
+            Variable::In(ref val) => Iter::In(val.clone()),
+            Variable::Array(ref arr) => Iter::Array(arr.clone(), 0),
+            Variable::Object(ref obj) | Variable::Record(_, ref obj) => {
+                let key = Arc::new("key".to_string());
+                let value = Arc::new("value".to_string());
+                let mut keys: Vec<&Arc<String>> = obj.keys().collect();
+                keys.sort();
+                let items: Vec<Variable> = keys.into_iter().map(|k| {
+                    let mut item = HashMap::new();
+                    item.insert(key.clone(), Variable::Str(k.clone()));
+                    item.insert(value.clone(), obj[k].clone());
+                    Variable::Object(item.into())
+                }).collect();
+                Iter::Values(items.into_iter())
+            }
+            Variable::Str(ref s) => {
+                let items: Vec<Variable> = s.chars()
+                    .map(|c| Variable::Str(Arc::new(c.to_string()))).collect();
+                Iter::Values(items.into_iter())
+            }
+            Variable::Link(ref link) => Iter::Values(link_items(link).into_iter()),
+            Variable::Coroutine(ref co) => Iter::Coroutine(co.clone()),
+            _ => return None,
+        })
+    }
+
+    /// Gets the next item.
+    pub(crate) fn next(&mut self, rt: &mut Runtime) -> Result<Option<Variable>, RuntimeError> {
+        match *self {
+            Iter::In(ref val) => match val.lock() {
+                Ok(x) => Ok(x.try_recv().ok()),
+                Err(err) => Err(format!("Can not lock In mutex:\n{}", err).into()),
+            },
+            Iter::Array(ref arr, ref mut i) => {
+                let item = arr.get(*i).cloned();
+                *i += 1;
+                Ok(item)
+            }
+            Iter::Values(ref mut values) => Ok(values.next()),
+            Iter::Coroutine(ref co) => rt.resume(co),
+        }
+    }
+}
+
+fn link_items(link: &Link) -> Vec<Variable> {
+    let mut items = vec![];
+    for slice in &link.slices {
+        for i in slice.start..slice.end {
+            items.push(slice.block.var(i));
+        }
+    }
+    items
+}
+
 macro_rules! iter(
     ($rt:ident, $for_in_expr:ident) => {{
         let iter = match $rt.expression(&$for_in_expr.iter, Side::Right)? {
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             (Some(x), Flow::Continue) => x,
             _ => return Err($rt.module.error($for_in_expr.iter.source_range(),
-                &format!("{}\nExpected in-type from for iter",
+                &format!("{}\nExpected in-type or collection from for iter",
                     $rt.stack_trace()), $rt))
         };
-        match $rt.resolve(&iter) {
-            &Variable::In(ref val) => val.clone(),
-            x => return Err($rt.module.error($for_in_expr.iter.source_range(),
-                            &$rt.expected(x, "in"), $rt))
+        let v = $rt.resolve(&iter);
+        match Iter::new(v) {
+            Some(iter) => iter,
+            None => return Err($rt.module.error($for_in_expr.iter.source_range(),
+                            &$rt.expected(v, "in, array, object, string or link"), $rt))
         }
     }};
 );
 
+// Gets the first item, returning a default value when there are no items.
 macro_rules! iter_val(
-    ($iter:ident, $rt:ident, $for_in_expr:ident) => {
-        match $iter.lock() {
-            Ok(x) => match x.try_recv() {
-                Ok(x) => x,
-                Err(_) => return Ok((None, Flow::Continue)),
-            },
-            Err(err) => {
-                return Err($rt.module.error($for_in_expr.source_range,
-                &format!("Can not lock In mutex:\n{}", err.to_string()), $rt));
-            }
+    ($iter:ident, $rt:ident, $for_in_expr:ident, $default:expr) => {
+        match $iter.next($rt) {
+            Ok(Some(x)) => x,
+            Ok(None) => return Ok(($default, Flow::Continue)),
+            Err(err) => return Err($rt.module.error($for_in_expr.source_range, err.text(), $rt)),
         }
     };
 );
@@ -64,15 +137,10 @@ macro_rules! continue_(
 
 macro_rules! iter_val_inc(
     ($iter:ident, $rt:ident, $for_in_expr:ident) => {
-        match $iter.lock() {
-            Ok(x) => match x.try_recv() {
-                Ok(x) => x,
-                Err(_) => break,
-            },
-            Err(err) => {
-                return Err($rt.module.error($for_in_expr.source_range,
-                &format!("Can not lock In mutex:\n{}", err.to_string()), $rt));
-            }
+        match $iter.next($rt) {
+            Ok(Some(x)) => x,
+            Ok(None) => break,
+            Err(err) => return Err($rt.module.error($for_in_expr.source_range, err.text(), $rt)),
         }
     };
 );
@@ -81,13 +149,13 @@ impl Runtime {
     pub(crate) fn for_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr, None);
 
         // Initialize counter.
         self.local_stack.push((for_in_expr.name.clone(), self.stack.len()));
@@ -116,13 +184,13 @@ impl Runtime {
     pub(crate) fn sum_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr, Some(Variable::f64(0.0)));
 
         let mut sum = 0.0;
 
@@ -160,13 +228,13 @@ impl Runtime {
     pub(crate) fn prod_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr, Some(Variable::f64(1.0)));
 
         let mut prod = 1.0;
 
@@ -204,13 +272,13 @@ impl Runtime {
     pub(crate) fn min_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr, Some(Variable::f64(f64::NAN)));
 
         let mut min = ::std::f64::NAN;
         let mut sec = None;
@@ -264,13 +332,13 @@ impl Runtime {
     pub(crate) fn max_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr, Some(Variable::f64(f64::NAN)));
 
         let mut max = ::std::f64::NAN;
         let mut sec = None;
@@ -324,13 +392,13 @@ impl Runtime {
     pub(crate) fn any_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr, Some(Variable::bool(false)));
 
         let mut any = false;
         let mut sec = None;
@@ -386,13 +454,13 @@ impl Runtime {
     pub(crate) fn all_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr, Some(Variable::bool(true)));
 
         let mut all = true;
         let mut sec = None;
@@ -448,20 +516,18 @@ impl Runtime {
     pub(crate) fn link_for_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
-        use Link;
-
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         fn sub_link_for_in_expr(
             res: &mut Link,
             rt: &mut Runtime,
             for_in_expr: &ast::ForIn
-        ) -> Result<(Option<Variable>, Flow), String> {
+        ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
             let prev_st = rt.stack.len();
             let prev_lc = rt.local_stack.len();
 
-            let iter = iter!(rt, for_in_expr);
-            let iter_val = iter_val!(iter, rt, for_in_expr);
+            let mut iter = iter!(rt, for_in_expr);
+            let iter_val = iter_val!(iter, rt, for_in_expr, None);
 
             // Initialize counter.
             rt.local_stack.push((for_in_expr.name.clone(), rt.stack.len()));
@@ -579,14 +645,15 @@ impl Runtime {
     pub(crate) fn sift_in_expr(
         &mut self,
         for_in_expr: &ast::ForIn
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
 
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut res: Vec<Variable> = vec![];
 
-        let iter = iter!(self, for_in_expr);
-        let iter_val = iter_val!(iter, self, for_in_expr);
+        let mut iter = iter!(self, for_in_expr);
+        let iter_val = iter_val!(iter, self, for_in_expr,
+            Some(Variable::Array(vec![].into())));
 
         // Initialize counter.
         self.local_stack.push((for_in_expr.name.clone(), self.stack.len()));
@@ -110,7 +110,7 @@ impl Runtime {
     pub(crate) fn for_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -144,7 +144,7 @@ impl Runtime {
     pub(crate) fn sum_n_expr(
         &mut self,
         for_n_expr: &ast::ForN,
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut sum = 0.0;
@@ -189,7 +189,7 @@ impl Runtime {
     pub(crate) fn prod_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut prod = 1.0;
@@ -234,7 +234,7 @@ impl Runtime {
     pub(crate) fn min_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -293,7 +293,7 @@ impl Runtime {
     pub(crate) fn max_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -353,7 +353,7 @@ impl Runtime {
     pub(crate) fn any_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -414,7 +414,7 @@ impl Runtime {
     pub(crate) fn all_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -475,14 +475,14 @@ impl Runtime {
     pub(crate) fn link_for_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         use Link;
 
         fn sub_link_for_n_expr(
             res: &mut Link,
             rt: &mut Runtime,
             for_n_expr: &ast::ForN
-        ) -> Result<(Option<Variable>, Flow), String> {
+        ) -> Result<(Option<Variable>, Flow), RuntimeError> {
             let prev_st = rt.stack.len();
             let prev_lc = rt.local_stack.len();
 
@@ -585,7 +585,7 @@ impl Runtime {
     pub(crate) fn sift_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut res: Vec<Variable> = vec![];
@@ -624,7 +624,7 @@ impl Runtime {
     pub(crate) fn sum_vec4_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut sum: [f32; 4] = [0.0; 4];
@@ -673,7 +673,7 @@ impl Runtime {
     pub(crate) fn prod_vec4_n_expr(
         &mut self,
         for_n_expr: &ast::ForN
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), RuntimeError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut prod: [f32; 4] = [1.0; 4];
@@ -1,24 +1,48 @@
 //! Dyon runtime.
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::cell::Cell;
 use std::collections::HashMap;
+use std::time::Instant;
 use rand;
 use range::Range;
 
 use ast;
+use debug::{self, DebugHook};
 use embed;
+use error::{DyonError, RuntimeError};
+use reload::{self, Reload};
 
 use FnIndex;
 use Module;
+use Type;
 use Variable;
 use UnsafeRef;
 use TINVOTS;
 
+#[macro_use]
 mod for_n;
 mod for_in;
+mod par;
+pub(crate) mod bytecode;
+pub(crate) mod coroutine;
+pub mod pool;
 
-type FlowResult = Result<(Option<Variable>, Flow), String>;
+type FlowResult = Result<(Option<Variable>, Flow), RuntimeError>;
+type Locals = Vec<(Arc<String>, Variable)>;
+
+thread_local! {
+    /// Mutexes locked by `lock` expressions on this thread.
+    ///
+    /// A joined task can run on the joining thread, so this is shared by the runtimes on a thread.
+    static LOCKED: ::std::cell::RefCell<Vec<usize>> = const { ::std::cell::RefCell::new(vec![]) };
+}
+
+/// Returns `true` if a `lock` expression on this thread holds a mutex.
+pub(crate) fn holds_lock() -> bool {
+    LOCKED.with(|l| !l.borrow().is_empty())
+}
 
 /// Which side an expression is evaluated.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -59,7 +83,9 @@ pub struct Call {
 
 lazy_static! {
     pub(crate) static ref TEXT_TYPE: Arc<String> = Arc::new("string".into());
+    pub(crate) static ref BYTES_TYPE: Arc<String> = Arc::new("bytes".into());
     pub(crate) static ref F64_TYPE: Arc<String> = Arc::new("number".into());
+    pub(crate) static ref I64_TYPE: Arc<String> = Arc::new("i64".into());
     pub(crate) static ref VEC4_TYPE: Arc<String> = Arc::new("vec4".into());
     pub(crate) static ref MAT4_TYPE: Arc<String> = Arc::new("mat4".into());
     pub(crate) static ref RETURN_TYPE: Arc<String> = Arc::new("return".into());
@@ -67,6 +93,8 @@ lazy_static! {
     pub(crate) static ref OBJECT_TYPE: Arc<String> = Arc::new("object".into());
     pub(crate) static ref LINK_TYPE: Arc<String> = Arc::new("link".into());
     pub(crate) static ref ARRAY_TYPE: Arc<String> = Arc::new("array".into());
+    pub(crate) static ref MAP_TYPE: Arc<String> = Arc::new("map".into());
+    pub(crate) static ref SET_TYPE: Arc<String> = Arc::new("set".into());
     pub(crate) static ref UNSAFE_REF_TYPE: Arc<String> = Arc::new("unsafe_ref".into());
     pub(crate) static ref REF_TYPE: Arc<String> = Arc::new("ref".into());
     pub(crate) static ref RUST_OBJECT_TYPE: Arc<String> = Arc::new("rust_object".into());
@@ -75,9 +103,40 @@ lazy_static! {
     pub(crate) static ref THREAD_TYPE: Arc<String> = Arc::new("thread".into());
     pub(crate) static ref CLOSURE_TYPE: Arc<String> = Arc::new("closure".into());
     pub(crate) static ref IN_TYPE: Arc<String> = Arc::new("in".into());
+    pub(crate) static ref COROUTINE_TYPE: Arc<String> = Arc::new("coroutine".into());
+    pub(crate) static ref OUT_TYPE: Arc<String> = Arc::new("out".into());
+    pub(crate) static ref ATOMIC_TYPE: Arc<String> = Arc::new("atomic".into());
+    pub(crate) static ref MUTEX_TYPE: Arc<String> = Arc::new("mutex".into());
     pub(crate) static ref MAIN: Arc<String> = Arc::new("main".into());
 }
 
+/// Limits execution of untrusted code.
+///
+/// When a limit is exceeded, the program stops with a runtime error.
+/// Threads started with `go` and the chunks of `par` loops inherit the limits,
+/// and share the remaining fuel with the runtime that started them.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// The remaining number of expressions that can be evaluated.
+    pub fuel: Option<u64>,
+    /// The maximum depth of the call stack.
+    ///
+    /// Set this to avoid a stack overflow caused by deep recursion.
+    pub call_depth: Option<usize>,
+    /// The maximum length of the stack.
+    pub stack_len: Option<usize>,
+    /// The time when the program is stopped.
+    pub deadline: Option<Instant>,
+}
+
+impl Limits {
+    /// Returns `true` if any limit is set.
+    pub fn any(&self) -> bool {
+        self.fuel.is_some() || self.call_depth.is_some() ||
+        self.stack_len.is_some() || self.deadline.is_some()
+    }
+}
+
 /// Stores data needed for running a Dyon program.
 pub struct Runtime {
     /// Stores the current module in use.
@@ -96,6 +155,40 @@ pub struct Runtime {
     pub(crate) rng: rand::rngs::StdRng,
     /// External functions can choose to report an error on an argument.
     pub arg_err_index: Cell<Option<usize>>,
+    /// Whether to execute loaded functions with the bytecode VM.
+    ///
+    /// When disabled, the runtime walks the AST directly.
+    pub bytecode: bool,
+    /// Called before evaluating each statement and expression.
+    ///
+    /// Loaded functions are executed by walking the AST while a hook is set.
+    /// Coroutine functions always run with the bytecode VM,
+    /// which calls the hook before statements only.
+    pub debug_hook: Option<Box<dyn DebugHook>>,
+    /// Limits execution, such as the number of evaluated expressions.
+    ///
+    /// Loaded functions are executed by walking the AST while a limit is set.
+    /// Coroutine functions always run with the bytecode VM,
+    /// which checks the limits before statements and loop iterations.
+    pub limits: Limits,
+    /// The number of worker threads used by `go`.
+    ///
+    /// When zero, the number of threads is the available parallelism.
+    pub threads: usize,
+    /// Stops the runtime with an error when set to `true`.
+    ///
+    /// Threads started with `go` get a new flag, which is set by `cancel`.
+    /// The flag is checked before evaluating each expression.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// The remaining fuel shared with other threads, and its value when last synchronized.
+    ///
+    /// Used instead of the fuel in `limits` while the two values agree.
+    shared_fuel: Option<(Arc<AtomicU64>, u64)>,
+    /// Counts expressions since the deadline was checked.
+    ticks: u32,
+    vm: bytecode::Registers,
+    /// Shared with runtimes of tasks, created on first use.
+    pool: Option<Arc<pool::Pool>>,
 }
 
 impl Default for Runtime {
@@ -121,13 +214,13 @@ fn item_lookup(
     expr_j: &mut usize,
     insert: bool, // Whether to insert key in object.
     last: bool,   // Whether it is the last property.
-) -> Result<*mut Variable, String> {
+) -> Result<*mut Variable, RuntimeError> {
     use ast::Id;
     use std::collections::hash_map::Entry;
 
     unsafe {
         match *var {
-            Variable::Object(ref mut obj) => {
+            Variable::Object(ref mut obj) | Variable::Record(_, ref mut obj) => {
                 let id = match *prop {
                     Id::String(_, ref id) => id.clone(),
                     Id::Expression(_) => {
@@ -160,9 +253,13 @@ fn item_lookup(
                             // Insert a key to overwrite with new value.
                             vac.insert(Variable::Return)
                         } else {
+                            let msg = match *var {
+                                Variable::Record(ref name, _) =>
+                                    format!("Record `{}` has no field `{}`", name, id),
+                                _ => format!("Object has no key `{}`", id),
+                            };
                             return Err(module.error_fnindex(prop.source_range(),
-                                &format!("{}\nObject has no key `{}`",
-                                    stack_trace(call_stack), id),
+                                &format!("{}\n{}", stack_trace(call_stack), msg),
                                     call_stack.last().unwrap().index));
                         }
                     }
@@ -311,6 +408,27 @@ impl Runtime {
             current_stack: vec![],
             rng: rand::rngs::StdRng::from_entropy(),
             arg_err_index: Cell::new(None),
+            bytecode: false,
+            debug_hook: None,
+            limits: Limits::default(),
+            threads: 0,
+            cancel: None,
+            shared_fuel: None,
+            ticks: 0,
+            vm: bytecode::Registers::default(),
+            pool: None,
+        }
+    }
+
+    /// Returns the thread pool, creating a new one if the number of threads changed.
+    fn pool(&mut self) -> Arc<pool::Pool> {
+        match self.pool {
+            Some(ref pool) if self.threads == 0 || pool.size() == self.threads => pool.clone(),
+            _ => {
+                let pool = Arc::new(pool::Pool::new(self.threads));
+                self.pool = Some(pool.clone());
+                pool
+            }
         }
     }
 
@@ -485,11 +603,94 @@ impl Runtime {
         Err(self.module.error(range, &format!("{}\n{}", self.stack_trace(), msg), self))
     }
 
+    /// Calls the debug hook before evaluating code.
+    fn debug(&mut self, range: Range, statement: bool) -> Result<(), RuntimeError> {
+        if let Some(mut hook) = self.debug_hook.take() {
+            let res = hook.before(self, &debug::Event {range, statement});
+            self.debug_hook = Some(hook);
+            if let Err(msg) = res {
+                return Err(self.module.error(range,
+                    &format!("{}\n{}", self.stack_trace(), msg), self));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an error if a limit of execution is exceeded.
+    fn check_limits(&mut self, range: Range) -> Result<(), RuntimeError> {
+        let msg = match self.limits {
+            Limits {fuel: Some(0), ..} =>
+                "Out of fuel, evaluated the maximum number of expressions".into(),
+            Limits {call_depth: Some(n), ..} if self.call_stack.len() > n =>
+                format!("Exceeded maximum call stack depth of {}", n),
+            Limits {stack_len: Some(n), ..} if self.stack.len() > n =>
+                format!("Exceeded maximum stack length of {}", n),
+            Limits {deadline: Some(deadline), ..} => {
+                // Checking the time is slow, so it is done once per 1024 expressions,
+                // and for every expression when the deadline has passed.
+                self.ticks += 1;
+                if self.ticks < 1024 || Instant::now() < deadline {
+                    if self.ticks >= 1024 {self.ticks = 0};
+                    String::new()
+                } else {
+                    "Exceeded deadline".into()
+                }
+            }
+            _ => String::new()
+        };
+        if !msg.is_empty() {
+            return Err(self.module.error(range,
+                &format!("{}\n{}", self.stack_trace(), msg), self));
+        }
+        if let Some(ref mut fuel) = self.limits.fuel {
+            *fuel = match self.shared_fuel {
+                // Ignore the shared fuel when the limits were changed after sharing.
+                Some((ref shared, ref mut last)) if *last == *fuel => {
+                    let prev = shared.fetch_update(Ordering::SeqCst, Ordering::SeqCst,
+                        |x| x.checked_sub(1));
+                    *last = prev.map(|x| x - 1).unwrap_or(0);
+                    *last
+                }
+                _ => *fuel - 1
+            };
+        }
+        Ok(())
+    }
+
+    /// Shares the remaining fuel with threads started by this runtime.
+    pub(crate) fn share_fuel(&mut self) {
+        self.shared_fuel = match (self.limits.fuel, self.shared_fuel.take()) {
+            (Some(fuel), Some((shared, last))) if fuel == last => Some((shared, last)),
+            (Some(fuel), _) => Some((Arc::new(AtomicU64::new(fuel)), fuel)),
+            (None, _) => None,
+        };
+    }
+
+    /// Returns `true` if the thread is cancelled.
+    #[inline(always)]
+    pub(crate) fn cancelled(&self) -> bool {
+        match self.cancel {
+            Some(ref cancel) => cancel.load(Ordering::Relaxed),
+            None => false
+        }
+    }
+
     pub(crate) fn expression(&mut self, expr: &ast::Expression, side: Side) -> FlowResult {
         use ast::Expression::*;
 
+        if self.cancelled() {
+            return self.err(expr.source_range(), "Thread was cancelled");
+        }
+
+        if self.limits.any() {
+            self.check_limits(expr.source_range())?;
+        }
+        if self.debug_hook.is_some() {
+            self.debug(expr.source_range(), false)?;
+        }
         match *expr {
             Link(ref link) => self.link(link),
+            Interp(ref interp) => self.interp(interp),
             Object(ref obj) => self.object(obj),
             Array(ref arr) => self.array(arr),
             ArrayFill(ref array_fill) => self.array_fill(array_fill),
@@ -503,6 +704,9 @@ impl Runtime {
                 Ok((Some(x), Flow::Return))
             }
             ReturnVoid(_) => Ok((None, Flow::Return)),
+            // Only the bytecode of a coroutine function can be suspended.
+            Yield(ref expr) => self.err(expr.source_range(),
+                "`yield` is not supported inside this expression"),
             Break(ref b) => Ok((None, Flow::Break(b.label.clone()))),
             Continue(ref b) => Ok((None, Flow::ContinueLoop(b.label.clone()))),
             Go(ref go) => self.go(go),
@@ -528,6 +732,10 @@ impl Runtime {
             For(ref for_expr) => self.for_expr(for_expr),
             ForN(ref for_n_expr) => self.for_n_expr(for_n_expr),
             ForIn(ref for_in_expr) => self.for_in_expr(for_in_expr),
+            Lock(ref lock) => self.lock(lock),
+            Sum(ref for_n_expr) | Prod(ref for_n_expr) | Min(ref for_n_expr) |
+            Max(ref for_n_expr) | Any(ref for_n_expr) | All(ref for_n_expr) |
+            Sift(ref for_n_expr) if for_n_expr.par => self.par_n_expr(expr),
             Sum(ref for_n_expr) => self.sum_n_expr(for_n_expr),
             SumIn(ref sum_in_expr) => self.sum_in_expr(sum_in_expr),
             SumVec4(ref for_n_expr) => self.sum_vec4_n_expr(for_n_expr),
@@ -547,6 +755,8 @@ impl Runtime {
             LinkFor(ref for_n_expr) => self.link_for_n_expr(for_n_expr),
             LinkIn(ref for_in_expr) => self.link_for_in_expr(for_in_expr),
             If(ref if_expr) => self.if_expr(if_expr),
+            Match(ref match_expr) => self.match_expr(match_expr),
+            Variant(ref variant) => self.variant(variant),
             Variable(ref range_var) => Ok((Some(range_var.1.clone()), Flow::Continue)),
             Try(ref expr) => self.try(expr, side),
             Swizzle(ref sw) => {
@@ -604,7 +814,7 @@ impl Runtime {
                 self.current_stack.truncate(cu);
                 Ok((
                     Some(Variable::Result(Err(Box::new(Error {
-                        message: Variable::Str(Arc::new(err)),
+                        message: Variable::Str(Arc::new(err.into())),
                         trace: vec![],
                     }
                     )))),
@@ -745,14 +955,18 @@ impl Runtime {
                 };
                 err.trace.push(self.module.error(expr.source_range(),
                     &format!("In function `{}`{}",
-                    &call.fn_name, file), self));
+                    &call.fn_name, file), self).into());
                 Ok((Some(Variable::Result(Err(err))), Flow::Return))
             }
         }
     }
 
     /// Run `main` function in a module.
-    pub fn run(&mut self, module: &Arc<Module>) -> Result<(), String> {
+    pub fn run(&mut self, module: &Arc<Module>) -> Result<(), DyonError> {
+        self.run_main(module).map_err(|err| self.runtime_error(err))
+    }
+
+    fn run_main(&mut self, module: &Arc<Module>) -> Result<(), RuntimeError> {
         use std::mem::replace;
 
         let old_module = replace(&mut self.module, module.clone());
@@ -801,6 +1015,9 @@ impl Runtime {
         let lc = self.local_stack.len();
         let cu = self.current_stack.len();
         for e in &block.expressions {
+            if self.debug_hook.is_some() {
+                self.debug(e.source_range(), true)?;
+            }
             expect = match self.expression(e, Side::Right)? {
                 (x, Flow::Continue) => x,
                 x => {
@@ -820,21 +1037,42 @@ impl Runtime {
 
     /// Start a new thread and return the handle.
     pub fn go(&mut self, go: &ast::Go) -> FlowResult {
-        use std::thread::{self, JoinHandle};
         use Thread;
 
-        let n = go.call.args.len();
-        let mut stack = vec![];
-        let relative = self.call_stack.last().map(|c| c.index).unwrap();
-        let mut fake_call = ast::Call {
-            f_index: self.module.find_function(&go.call.info.name, relative),
-            args: Vec::with_capacity(n),
-            custom_source: None,
-            info: go.call.info.clone(),
+        // Find the closure before evaluating the arguments, like a closure call.
+        let closure = match go.call {
+            ast::GoCall::Call(_) => None,
+            ast::GoCall::Closure(ref call) => {
+                let item = match self.item(&call.item, Side::Right)? {
+                    (Some(x), Flow::Continue) => x,
+                    (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                    _ => return self.err(call.item.source_range,
+                                    "Expected something. \
+                                    Check that item returns a value.")
+                };
+                let (f, env) = match self.resolve(&item) {
+                    &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
+                    x => return self.err(call.source_range, &self.expected(x, "closure"))
+                };
+                if !f.currents.is_empty() {
+                    return self.err(call.source_range,
+                        "Can not use `go` with a closure that uses current objects");
+                }
+                // Deep clone the closure, such that no part of it is shared between threads.
+                Some((Arc::new((*f).clone()), env))
+            }
+        };
+
+        let args = match go.call {
+            ast::GoCall::Call(ref call) => &call.args,
+            ast::GoCall::Closure(ref call) => &call.args,
         };
+        let n = args.len();
+        let mut stack = vec![];
+        let mut fake_args = Vec::with_capacity(n);
         // Evaluate the arguments and put a deep clone on the new stack.
         // This prevents the arguments from containing any reference to other variables.
-        for (i, arg) in go.call.args.iter().enumerate() {
+        for (i, arg) in args.iter().enumerate() {
             let v = match self.expression(arg, Side::Right)? {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
@@ -843,11 +1081,31 @@ impl Runtime {
                                 Expression did not return a value.")
             };
             stack.push(v.deep_clone(&self.stack));
-            fake_call.args.push(ast::Expression::Variable(Box::new((
-                go.call.args[i].source_range(), Variable::Ref(n-i-1)))));
+            fake_args.push(ast::Expression::Variable(Box::new((
+                args[i].source_range(), Variable::Ref(n-i-1)))));
         }
         stack.reverse();
 
+        let fake_call = match go.call {
+            ast::GoCall::Call(ref call) => {
+                let relative = self.call_stack.last().map(|c| c.index).unwrap();
+                ast::GoCall::Call(ast::Call {
+                    f_index: self.module.find_function(&call.info.name, relative),
+                    args: fake_args,
+                    custom_source: None,
+                    info: call.info.clone(),
+                })
+            }
+            ast::GoCall::Closure(ref call) => ast::GoCall::Closure(ast::CallClosure {
+                item: call.item.clone(),
+                args: fake_args,
+                source_range: call.source_range,
+            }),
+        };
+
+        let pool = self.pool();
+        self.share_fuel();
+        let cancel = Arc::new(AtomicBool::new(false));
         let last_call = self.call_stack.last().unwrap();
         let new_rt = Runtime {
             module: self.module.clone(),
@@ -866,20 +1124,85 @@ impl Runtime {
             }],
             rng: self.rng.clone(),
             arg_err_index: Cell::new(None),
+            bytecode: self.bytecode,
+            debug_hook: None,
+            limits: self.limits.clone(),
+            threads: self.threads,
+            cancel: Some(cancel.clone()),
+            shared_fuel: self.shared_fuel.clone(),
+            ticks: 0,
+            vm: bytecode::Registers::default(),
+            pool: Some(pool.clone()),
         };
-        let handle: JoinHandle<Result<Variable, String>> = thread::spawn(move || {
+        let handle = pool.spawn(move || {
             let mut new_rt = new_rt;
             let fake_call = fake_call;
             let loader = false;
-            Ok(match new_rt.call_internal(&fake_call, loader) {
-                Err(err) => return Err(err),
+            let res = match (fake_call, closure) {
+                (ast::GoCall::Closure(call), Some((f, env))) =>
+                    new_rt.call_closure_value(&call, f, env),
+                (ast::GoCall::Call(call), _) => new_rt.call_internal(&call, loader),
+                _ => return Err("Expected closure".into()),
+            };
+            Ok(match res {
+                Err(err) => return Err(err.into()),
                 Ok((None, _)) => {
                     new_rt.stack.pop().expect(TINVOTS)
                 }
                 Ok((Some(x), _)) => x,
             }.deep_clone(&new_rt.stack))
         });
-        Ok((Some(Variable::Thread(Thread::new(handle))), Flow::Continue))
+        Ok((Some(Variable::Thread(Thread::new(handle, cancel))), Flow::Continue))
+    }
+
+    /// Runs a block while holding the lock of a mutex.
+    ///
+    /// The guarded value is copied to the stack while the block runs,
+    /// and moved back into the mutex when the block succeeds.
+    /// When the block fails, the mutex keeps the original value.
+    pub(crate) fn lock(&mut self, lock: &ast::Lock) -> FlowResult {
+        let v = match self.expression(&lock.mutex, Side::Right)? {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return self.err(lock.mutex.source_range(),
+                            "Expected something. \
+                            Expression did not return a value.")
+        };
+        let mutex = match self.resolve(&v) {
+            &Variable::Mutex(ref mutex) => mutex.clone(),
+            x => return self.err(lock.mutex.source_range(), &self.expected(x, "mutex"))
+        };
+        // Locking a mutex held by this thread would never return.
+        let id = Arc::as_ptr(&mutex) as usize;
+        if LOCKED.with(|l| l.borrow().contains(&id)) {
+            return self.err(lock.source_range, "Mutex is already locked by this thread");
+        }
+        let mut guard = match pool::blocking(|| mutex.lock()) {
+            Ok(x) => x,
+            Err(err) => return self.err(lock.source_range,
+                &format!("Can not lock mutex:\n{}", err)),
+        };
+        LOCKED.with(|l| l.borrow_mut().push(id));
+
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        self.local_stack.push((lock.name.clone(), st));
+        self.stack.push(guard.clone());
+        let res = match self.block(&lock.block) {
+            Ok((x, flow)) => {
+                if st < self.stack.len() {
+                    *guard = self.resolve(&self.stack[st]).deep_clone(&self.stack);
+                }
+                // The result is copied, since it might refer to the guarded value.
+                Ok((x.map(|x| self.resolve(&x).deep_clone(&self.stack)), flow))
+            }
+            Err(err) => Err(err),
+        };
+        LOCKED.with(|l| l.borrow_mut().retain(|&x| x != id));
+        drop(guard);
+        self.stack.truncate(st);
+        self.local_stack.truncate(lc);
+        res
     }
 
     /// Call closure.
@@ -897,7 +1220,16 @@ impl Runtime {
             &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
             x => return self.err(call.source_range, &self.expected(x, "closure"))
         };
+        self.call_closure_value(call, f, env)
+    }
 
+    /// Calls a closure with the arguments of a closure call.
+    pub(crate) fn call_closure_value(
+        &mut self,
+        call: &ast::CallClosure,
+        f: Arc<ast::Closure>,
+        env: Box<::ClosureEnvironment>
+    ) -> FlowResult {
         if call.arg_len() != f.args.len() {
             return Err(self.module.error(call.source_range,
                 &format!("{}\nExpected {} arguments but found {}",
@@ -924,7 +1256,19 @@ impl Runtime {
                                 Check that expression returns a value.")
             };
         }
+        self.call_closure_body(call, f, &env, st, lc, cu)
+    }
 
+    /// Calls a closure with the arguments on the stack.
+    pub(crate) fn call_closure_body(
+        &mut self,
+        call: &ast::CallClosure,
+        f: Arc<ast::Closure>,
+        env: &::ClosureEnvironment,
+        st: usize,
+        lc: usize,
+        cu: usize
+    ) -> FlowResult {
         // Look for variable in current stack.
         if !f.currents.is_empty() {
             for current in &f.currents {
@@ -1201,8 +1545,6 @@ impl Runtime {
         custom_source: &Option<Arc<String>>,
         loader: bool
     ) -> FlowResult {
-        use std::sync::atomic::Ordering;
-
         let relative = if loader {0} else {
             self.call_stack.last().map(|c| c.index).unwrap_or(0)
         };
@@ -1260,6 +1602,31 @@ impl Runtime {
             };
         }
 
+        self.call_loaded_body(f, new_index, info, custom_source, st, lc, cu)
+    }
+
+    /// Calls a loaded function after the arguments are pushed on the stack.
+    ///
+    /// The stack length `st` is measured after the return slot.
+    #[allow(clippy::too_many_arguments)]
+    fn call_loaded_body(
+        &mut self,
+        f: &ast::Function,
+        new_index: usize,
+        info: &ast::CallInfo,
+        custom_source: &Option<Arc<String>>,
+        st: usize,
+        lc: usize,
+        cu: usize,
+    ) -> FlowResult {
+        use std::sync::atomic::Ordering;
+
+        if let Type::Coroutine(_) = f.ret {
+            // The body runs when the coroutine is resumed.
+            return Ok((Some(self.create_coroutine(f, new_index, &info.name, st, lc)),
+                       Flow::Continue));
+        }
+
         // Look for variable in current stack.
         if !f.currents.is_empty() {
             for current in &f.currents {
@@ -1318,7 +1685,11 @@ impl Runtime {
             // Do not resolve locals to keep fixed length from end of stack.
             self.local_stack.push((arg.name.clone(), st + i));
         }
-        let (x, flow) = self.block(&f.block)?;
+        let (x, flow) = if self.bytecode && self.debug_hook.is_none() && !self.limits.any() {
+            self.run_chunk(f.bytecode())?
+        } else {
+            self.block(&f.block)?
+        };
         match flow {
             Flow::Break(None) =>
                 return self.err(info.source_range, "Can not break from function"),
@@ -1353,7 +1724,7 @@ impl Runtime {
                     x => {
                         // This happens when return is only
                         // assigned to `return = x`.
-                        Ok((Some(x), Flow::Continue))
+                        Ok((Some(self.name_record(f, x)), Flow::Continue))
                     }
                 }
             }
@@ -1382,11 +1753,19 @@ impl Runtime {
             }
             (returns, b) => {
                 if returns { self.stack.pop(); }
-                Ok((b, Flow::Continue))
+                Ok((b.map(|b| self.name_record(f, b)), Flow::Continue))
             }
         }
     }
 
+    /// Names an object returned from a function with a record return type.
+    fn name_record(&self, f: &ast::Function, x: Variable) -> Variable {
+        match (&f.ret_record, x) {
+            (Some(name), Variable::Object(obj)) => Variable::Record(name.clone(), obj),
+            (_, x) => x
+        }
+    }
+
     /// Used internally because loaded functions are resolved
     /// relative to the caller, which stores its index on the
     /// call stack.
@@ -1415,12 +1794,106 @@ impl Runtime {
         }
     }
 
+    /// Sets the call stack of an error with location, unless it is already set.
+    ///
+    /// The stack trace is removed from the start of the message.
+    pub(crate) fn set_call_stack(&self, err: &mut RuntimeError) {
+        let info = err.info_mut();
+        if info.location.is_none() || !info.call_stack.is_empty() {return};
+        let trace = format!("{}\n", self.stack_trace());
+        if info.message.starts_with(&trace) {
+            info.message = info.message[trace.len()..].into();
+        }
+        info.call_stack = self.call_stack.iter().map(|c| c.to_string()).collect();
+    }
+
+    /// Converts an error to the error type of the public API.
+    pub(crate) fn runtime_error<E: Into<RuntimeError>>(&self, err: E) -> DyonError {
+        let mut err = err.into();
+        self.set_call_stack(&mut err);
+        err.into()
+    }
+
+    /// Calls a loaded function and returns the value and locals at the top of its block.
+    ///
+    /// The arguments are returned as locals too, such that changes are kept.
+    /// Used by the REPL to keep locals alive between inputs.
+    pub(crate) fn call_keep_locals(
+        &mut self,
+        module: &Arc<Module>,
+        f_index: usize,
+        args: Vec<Variable>
+    ) -> Result<(Option<Variable>, Locals), DyonError> {
+        use std::mem::replace;
+
+        let old_module = replace(&mut self.module, module.clone());
+        let cs = self.call_stack.len();
+        let f = &module.functions[f_index];
+        if f.returns() {
+            self.stack.push(Variable::Return);
+        }
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let cu = self.current_stack.len();
+        self.push_fn(f.name.clone(), f_index, Some(f.file.clone()), st, lc, cu);
+        if f.returns() {
+            self.local_stack.push((RETURN_TYPE.clone(), st - 1));
+        }
+        for (i, (arg, v)) in f.args.iter().zip(args).enumerate() {
+            self.local_stack.push((arg.name.clone(), st + i));
+            self.stack.push(v);
+        }
+        let mut res = Ok(None);
+        for e in &f.block.expressions {
+            if let Err(err) = self.debug(e.source_range(), true) {
+                res = Err(self.runtime_error(err));
+                break;
+            }
+            match self.expression(e, Side::Right) {
+                Ok((x, Flow::Continue)) => res = Ok(x),
+                Ok((x, Flow::Return)) => {
+                    res = Ok(x);
+                    break;
+                }
+                Ok(_) => {
+                    res = Err(self.runtime_error(self.module.error(e.source_range(),
+                        &format!("{}\nCan not break or continue from function",
+                                 self.stack_trace()), self)));
+                    break;
+                }
+                Err(err) => {
+                    res = Err(self.runtime_error(err));
+                    break;
+                }
+            }
+        }
+        let res = res.map(|x| {
+            let x = x.map(|x| self.resolve(&x).deep_clone(&self.stack));
+            let mut locals: Vec<(Arc<String>, Variable)> = vec![];
+            for &(ref name, ind) in &self.local_stack[lc..] {
+                if **name == **RETURN_TYPE {continue};
+                let v = self.stack[ind].deep_clone(&self.stack);
+                match locals.iter_mut().find(|l| &l.0 == name) {
+                    Some(l) => l.1 = v,
+                    None => locals.push((name.clone(), v)),
+                }
+            }
+            (x, locals)
+        });
+        self.call_stack.truncate(cs);
+        self.stack.truncate(if f.returns() {st - 1} else {st});
+        self.local_stack.truncate(lc);
+        self.current_stack.truncate(cu);
+        self.module = old_module;
+        res
+    }
+
     /// Calls function by name.
     pub fn call_str(&mut self,
         function: &str,
         args: &[Variable],
         module: &Arc<Module>
-    ) -> Result<(), String> {
+    ) -> Result<(), DyonError> {
         let name: Arc<String> = Arc::new(function.into());
         match module.find_function(&name, 0) {
             FnIndex::Loaded(f_index) => {
@@ -1437,10 +1910,10 @@ impl Runtime {
                         source_range: Range::empty(0),
                     })
                 };
-                self.call(&call, &module)?;
+                self.call(&call, &module).map_err(|err| self.runtime_error(err))?;
                 Ok(())
             }
-            _ => Err(format!("Could not find function `{}`",function))
+            _ => Err(self.runtime_error(format!("Could not find function `{}`",function)))
         }
     }
 
@@ -1450,11 +1923,11 @@ impl Runtime {
         function: &str,
         args: &[Variable],
         module: &Arc<Module>
-    ) -> Result<Variable, String> {
+    ) -> Result<Variable, DyonError> {
         let name: Arc<String> = Arc::new(function.into());
         let fn_index = module.find_function(&name, 0);
         if let FnIndex::None = fn_index {
-            return Err(format!("Could not find function `{}`", function));
+            return Err(self.runtime_error(format!("Could not find function `{}`", function)));
         }
 
         let call = ast::Call {
@@ -1472,16 +1945,19 @@ impl Runtime {
         };
         match self.call(&call, &module) {
             Ok((Some(val), Flow::Continue)) => Ok(val),
-            Err(err) => Err(err),
-            _ => Err(module.error(
-                call.info.source_range,
-                &format!("{}\nExpected something", self.stack_trace()),
-                self,
-            )),
+            Err(err) => Err(self.runtime_error(err)),
+            _ => {
+                let err = module.error(
+                    call.info.source_range,
+                    &format!("{}\nExpected something", self.stack_trace()),
+                    self,
+                );
+                Err(self.runtime_error(err))
+            }
         }
     }
 
-    fn swizzle(&mut self, sw: &ast::Swizzle) -> Result<Flow, String> {
+    fn swizzle(&mut self, sw: &ast::Swizzle) -> Result<Flow, RuntimeError> {
         let v = match self.expression(&sw.expr, Side::Right)? {
             (Some(x), Flow::Continue) => x,
             (_, Flow::Return) => { return Ok(Flow::Return); }
@@ -1537,6 +2013,48 @@ impl Runtime {
         }), Flow::Continue))
     }
 
+    fn interp(&mut self, interp: &ast::Interp) -> FlowResult {
+        use write::{write_variable, EscapeString};
+
+        let mut text = String::new();
+        for part in &interp.parts {
+            let item = match *part {
+                ast::InterpPart::Text(ref t) => {
+                    text.push_str(t);
+                    continue;
+                }
+                ast::InterpPart::Item(ref item) => item,
+            };
+            let v = match self.expression(&item.expr, Side::Right)? {
+                (Some(x), Flow::Continue) => x,
+                (x, Flow::Return) => return Ok((x, Flow::Return)),
+                _ => return self.err(item.expr.source_range(), "Expected something")
+            };
+            let (s, number) = match (self.resolve(&v), item.precision) {
+                (&Variable::F64(x, _), Some(precision)) =>
+                    (format!("{:.*}", precision, x), true),
+                (_, Some(_)) => return self.err(item.expr.source_range(),
+                    "Expected `f64` when using precision"),
+                (v, None) => {
+                    let mut buf: Vec<u8> = vec![];
+                    write_variable(&mut buf, self, v, EscapeString::None, 0).unwrap();
+                    let number = match *v {
+                        Variable::F64(_, _) | Variable::I64(_) => true,
+                        _ => false
+                    };
+                    (String::from_utf8(buf).unwrap(), number)
+                }
+            };
+            match item.width {
+                // Numbers are aligned to the right, like in Rust.
+                Some(width) if number => text.push_str(&format!("{:>1$}", s, width)),
+                Some(width) => text.push_str(&format!("{:<1$}", s, width)),
+                None => text.push_str(&s),
+            }
+        }
+        Ok((Some(Variable::Str(Arc::new(text))), Flow::Continue))
+    }
+
     fn object(&mut self, obj: &ast::Object) -> FlowResult {
         let mut object: HashMap<_, _> = HashMap::new();
         for &(ref key, ref expr) in &obj.key_values {
@@ -1567,6 +2085,23 @@ impl Runtime {
         Ok((Some(Variable::Array(Arc::new(array))), Flow::Continue))
     }
 
+    fn variant(&mut self, variant: &ast::Variant) -> FlowResult {
+        let mut args: Vec<Variable> = Vec::with_capacity(variant.args.len());
+        for arg in &variant.args {
+            let x = match self.expression(arg, Side::Right)? {
+                (Some(x), Flow::Continue) => x,
+                (x, Flow::Return) => return Ok((x, Flow::Return)),
+                _ => return self.err(arg.source_range(), "Expected something")
+            };
+            args.push(self.resolve(&x).deep_clone(&self.stack));
+        }
+        Ok((Some(Variable::Variant(Box::new(::Variant {
+            enum_name: variant.enum_name.clone(),
+            name: variant.name.clone(),
+            args,
+        }))), Flow::Continue))
+    }
+
     fn array_fill(&mut self, array_fill: &ast::ArrayFill) -> FlowResult {
         let fill = match self.expression(&array_fill.fill, Side::Right)? {
             (x, Flow::Return) => return Ok((x, Flow::Return)),
@@ -1593,444 +2128,536 @@ impl Runtime {
         op: ast::AssignOp,
         left: &ast::Expression,
         right: &ast::Expression
+    ) -> FlowResult {
+        // Evaluate right side before left because the left leaves
+        // an raw pointer on the stack which might point to wrong place
+        // if there are side effects of the right side affecting it.
+        let b = match self.expression(right, Side::Right)? {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => return Ok((x, Flow::Return)),
+            _ => return self.err(right.source_range(), "Expected something from the right side")
+        };
+        self.assign_value(op, left, b)
+    }
+
+    /// Assigns a value computed from the right side.
+    fn assign_value(
+        &mut self,
+        op: ast::AssignOp,
+        left: &ast::Expression,
+        b: Variable
     ) -> FlowResult {
         use ast::AssignOp::*;
         use ast::Expression;
 
         if op != Assign {
-            // Evaluate right side before left because the left leaves
-            // an raw pointer on the stack which might point to wrong place
-            // if there are side effects of the right side affecting it.
-            let b = match self.expression(right, Side::Right)? {
-                (Some(x), Flow::Continue) => x,
-                (x, Flow::Return) => return Ok((x, Flow::Return)),
-                _ => return self.err(right.source_range(), "Expected something from the right side")
-            };
             let a = match self.expression(left, Side::LeftInsert(false))? {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
                 _ => return self.err(left.source_range(), "Expected something from the left side")
             };
-            let r = match a {
-                Variable::UnsafeRef(r) => {
-                    // If reference, use a shallow clone to type check,
-                    // without affecting the original object.
-                    unsafe {
-                        if let Variable::Ref(ind) = *r.0 {
-                            *r.0 = self.stack[ind].clone()
+            self.assign_ref(op, left.source_range(), a, b)
+        } else {
+            match *left {
+                Expression::Item(ref item) => {
+                    let v = match b {
+                        // Use a shallow clone of a reference.
+                        Variable::Ref(ind) => self.stack[ind].clone(),
+                        x => x
+                    };
+                    if !item.ids.is_empty() {
+                        let x = match self.expression(left, Side::LeftInsert(true))? {
+                            (Some(x), Flow::Continue) => x,
+                            (x, Flow::Return) => return Ok((x, Flow::Return)),
+                            _ => return self.err(left.source_range(),
+                                                 "Expected something from the left side")
+                        };
+                        match x {
+                            Variable::UnsafeRef(r) => {
+                                unsafe { *r.0 = v }
+                            }
+                            _ => panic!("Expected unsafe reference")
+                        }
+                    } else {
+                        self.local_stack.push((item.name.clone(), self.stack.len()));
+                        if item.current {
+                            self.current_stack.push((item.name.clone(), self.stack.len()));
                         }
+                        self.stack.push(v);
                     }
-                    r
+                    Ok((None, Flow::Continue))
                 }
-                Variable::Ref(ind) => {
-                    UnsafeRef(&mut self.stack[ind] as *mut Variable)
+                _ => self.err(left.source_range(), "Expected item")
+            }
+        }
+    }
+
+    /// Assigns to a reference with an operator other than `:=`.
+    pub(crate) fn assign_ref(
+        &mut self,
+        op: ast::AssignOp,
+        range: Range,
+        a: Variable,
+        b: Variable
+    ) -> FlowResult {
+        use ast::AssignOp::*;
+
+        let r = match a {
+            Variable::UnsafeRef(r) => {
+                // If reference, use a shallow clone to type check,
+                // without affecting the original object.
+                unsafe {
+                    if let Variable::Ref(ind) = *r.0 {
+                        *r.0 = self.stack[ind].clone()
+                    }
                 }
-                x => panic!("Expected reference, found `{}`", x.typeof_var())
-            };
+                r
+            }
+            Variable::Ref(ind) => {
+                UnsafeRef(&mut self.stack[ind] as *mut Variable)
+            }
+            x => panic!("Expected reference, found `{}`", x.typeof_var())
+        };
 
-            match *self.resolve(&b) {
-                Variable::F64(b, ref sec) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::F64(ref mut n, ref mut n_sec) => {
-                                match op {
-                                    Set => *n = b,
-                                    Add => *n += b,
-                                    Sub => *n -= b,
-                                    Mul => *n *= b,
-                                    Div => *n /= b,
-                                    Rem => *n %= b,
-                                    Pow => *n = n.powf(b),
-                                    Assign => {}
-                                };
-                                *n_sec = sec.clone()
+        match *self.resolve(&b) {
+            Variable::F64(b, ref sec) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::F64(ref mut n, ref mut n_sec) => {
+                            match op {
+                                Set => *n = b,
+                                Add => *n += b,
+                                Sub => *n -= b,
+                                Mul => *n *= b,
+                                Div => *n /= b,
+                                Rem => *n %= b,
+                                Pow => *n = n.powf(b),
+                                Assign => {}
+                            };
+                            *n_sec = sec.clone()
+                        }
+                        Variable::Vec4(ref mut n) => {
+                            let b = b as f32;
+                            match op {
+                                Add => *n = [n[0] + b, n[1] + b,
+                                             n[2] + b, n[3] + b],
+                                Sub => *n = [n[0] - b, n[1] - b,
+                                             n[2] - b, n[3] - b],
+                                Mul => *n = [n[0] * b, n[1] * b,
+                                             n[2] * b, n[3] * b],
+                                Div => *n = [n[0] / b, n[1] / b,
+                                             n[2] / b, n[3] / b],
+                                Rem => *n = [n[0] % b, n[1] % b,
+                                             n[2] % b, n[3] % b],
+                                Pow => *n = [n[0].powf(b), n[1].powf(b),
+                                             n[2].powf(b), n[3].powf(b)],
+                                _ => return self.err(range,
+                                                     "Expected assigning to a number")
                             }
-                            Variable::Vec4(ref mut n) => {
-                                let b = b as f32;
-                                match op {
-                                    Add => *n = [n[0] + b, n[1] + b,
-                                                 n[2] + b, n[3] + b],
-                                    Sub => *n = [n[0] - b, n[1] - b,
-                                                 n[2] - b, n[3] - b],
-                                    Mul => *n = [n[0] * b, n[1] * b,
-                                                 n[2] * b, n[3] * b],
-                                    Div => *n = [n[0] / b, n[1] / b,
-                                                 n[2] / b, n[3] / b],
-                                    Rem => *n = [n[0] % b, n[1] % b,
-                                                 n[2] % b, n[3] % b],
-                                    Pow => *n = [n[0].powf(b), n[1].powf(b),
-                                                 n[2].powf(b), n[3].powf(b)],
-                                    _ => return self.err(left.source_range(),
-                                                         "Expected assigning to a number")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::F64(b, sec.clone())
+                            } else {
+                                return self.err(range, "Return has no value")
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::F64(b, sec.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                n.push(&Variable::f64(b))?;
+                            } else {
+                                return self.err(
+                                    range,
+                                    "Can not use this assignment \
+                                    operator with `link` and `number`")
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    n.push(&Variable::f64(b))?;
-                                } else {
-                                    return self.err(
-                                        left.source_range(),
-                                        "Can not use this assignment \
-                                        operator with `link` and `number`")
-                                }
+                        }
+                        _ => return self.err(
+                                range,
+                                "Expected assigning to a number")
+                    };
+                }
+            }
+            Variable::I64(b) => {
+                use dyon_std::{add, sub, mul, div, rem, pow};
+
+                unsafe {
+                    match *r.0 {
+                        Variable::I64(n) => {
+                            let (a, b) = (Variable::I64(n), Variable::I64(b));
+                            let res = match op {
+                                Set => Ok(b),
+                                Add => add(&a, &b),
+                                Sub => sub(&a, &b),
+                                Mul => mul(&a, &b),
+                                Div => div(&a, &b),
+                                Rem => rem(&a, &b),
+                                Pow => pow(&a, &b),
+                                Assign => Ok(a),
+                            };
+                            match res {
+                                Ok(x) => *r.0 = x,
+                                Err(err) => return self.err(range, &err)
                             }
-                            _ => return self.err(
-                                    left.source_range(),
-                                    "Expected assigning to a number")
-                        };
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::I64(b)
+                            } else {
+                                return self.err(range, "Return has no value")
+                            }
+                        }
+                        _ => return self.err(range,
+                                             "Expected assigning to an i64")
                     }
                 }
-                Variable::Vec4(b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Vec4(ref mut n) => {
-                                match op {
-                                    Set => *n = b,
-                                    Add => *n = [n[0] + b[0], n[1] + b[1],
-                                                 n[2] + b[2], n[3] + b[3]],
-                                    Sub => *n = [n[0] - b[0], n[1] - b[1],
-                                                 n[2] - b[2], n[3] - b[3]],
-                                    Mul => *n = [n[0] * b[0], n[1] * b[1],
-                                                 n[2] * b[2], n[3] * b[3]],
-                                    Div => *n = [n[0] / b[0], n[1] / b[1],
-                                                 n[2] / b[2], n[3] / b[3]],
-                                    Rem => *n = [n[0] % b[0], n[1] % b[1],
-                                                 n[2] % b[2], n[3] % b[3]],
-                                    Pow => *n = [n[0].powf(b[0]), n[1].powf(b[1]),
-                                                 n[2].powf(b[2]), n[3].powf(b[3])],
-                                    Assign => {}
-                                }
+            }
+            Variable::Vec4(b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Vec4(ref mut n) => {
+                            match op {
+                                Set => *n = b,
+                                Add => *n = [n[0] + b[0], n[1] + b[1],
+                                             n[2] + b[2], n[3] + b[3]],
+                                Sub => *n = [n[0] - b[0], n[1] - b[1],
+                                             n[2] - b[2], n[3] - b[3]],
+                                Mul => *n = [n[0] * b[0], n[1] * b[1],
+                                             n[2] * b[2], n[3] * b[3]],
+                                Div => *n = [n[0] / b[0], n[1] / b[1],
+                                             n[2] / b[2], n[3] / b[3]],
+                                Rem => *n = [n[0] % b[0], n[1] % b[1],
+                                             n[2] % b[2], n[3] % b[3]],
+                                Pow => *n = [n[0].powf(b[0]), n[1].powf(b[1]),
+                                             n[2].powf(b[2]), n[3].powf(b[3])],
+                                Assign => {}
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Vec4(b)
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Vec4(b)
+                            } else {
+                                return self.err(range, "Return has no value")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to a vec4")
-                        };
-                    }
+                        }
+                        _ => return self.err(range,
+                                             "Expected assigning to a vec4")
+                    };
                 }
-                Variable::Mat4(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Mat4(ref mut n) => {
-                                match op {
-                                    Set => {
-                                        **n = **b;
-                                    }
-                                    Mul => {
-                                        use vecmath::col_mat4_mul;
+            }
+            Variable::Mat4(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Mat4(ref mut n) => {
+                            match op {
+                                Set => {
+                                    **n = **b;
+                                }
+                                Mul => {
+                                    use vecmath::col_mat4_mul;
 
-                                        **n = col_mat4_mul(**n, **b);
-                                    }
-                                    Add => {
-                                        use vecmath::mat4_add;
+                                    **n = col_mat4_mul(**n, **b);
+                                }
+                                Add => {
+                                    use vecmath::mat4_add;
 
-                                        **n = mat4_add(**n, **b);
-                                    }
-                                    Sub => {
-                                        use vecmath::mat4_sub;
+                                    **n = mat4_add(**n, **b);
+                                }
+                                Sub => {
+                                    use vecmath::mat4_sub;
 
-                                        **n = mat4_sub(**n, **b);
-                                    }
-                                    _ => {
-                                        return self.err(
-                                            left.source_range(),
-                                            "Can not use this assignment \
-                                            operator with `mat4`")
-                                    }
+                                    **n = mat4_sub(**n, **b);
                                 }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Mat4(b.clone())
-                                } else {
-                                    return self.err(left.source_range(),
-                                                    "Return has no value")
+                                _ => {
+                                    return self.err(
+                                        range,
+                                        "Can not use this assignment \
+                                        operator with `mat4`")
                                 }
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to a mat4")
                         }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Mat4(b.clone())
+                            } else {
+                                return self.err(range,
+                                                "Return has no value")
+                            }
+                        }
+                        _ => return self.err(range,
+                                             "Expected assigning to a mat4")
                     }
                 }
-                Variable::Bool(b, ref sec) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Bool(ref mut n, ref mut n_sec) => {
-                                match op {
-                                    Set => *n = b,
-                                    _ => unimplemented!()
-                                };
-                                *n_sec = sec.clone();
-                            }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Bool(b, sec.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+            }
+            Variable::Bool(b, ref sec) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Bool(ref mut n, ref mut n_sec) => {
+                            match op {
+                                Set => *n = b,
+                                _ => unimplemented!()
+                            };
+                            *n_sec = sec.clone();
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Bool(b, sec.clone())
+                            } else {
+                                return self.err(range, "Return has no value")
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    n.push(&Variable::bool(b))?;
-                                } else {
-                                    return self.err(left.source_range(),
-                                        "Can not use this assignment \
-                                        operator with `link` and `bool`")
-                                }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                n.push(&Variable::bool(b))?;
+                            } else {
+                                return self.err(range,
+                                    "Can not use this assignment \
+                                    operator with `link` and `bool`")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to a bool")
-                        };
-                    }
+                        }
+                        _ => return self.err(range,
+                                             "Expected assigning to a bool")
+                    };
                 }
-                Variable::Str(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Str(ref mut n) => {
-                                match op {
-                                    Set => *n = b.clone(),
-                                    Add => Arc::make_mut(n).push_str(b),
-                                    _ => unimplemented!()
-                                }
+            }
+            Variable::Str(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Str(ref mut n) => {
+                            match op {
+                                Set => *n = b.clone(),
+                                Add => Arc::make_mut(n).push_str(b),
+                                _ => unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Str(b.clone())
-                                } else {
-                                    return self.err(left.source_range(),
-                                                    "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Str(b.clone())
+                            } else {
+                                return self.err(range,
+                                                "Return has no value")
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    n.push(&Variable::Str(b.clone()))?;
-                                } else {
-                                    return self.err(left.source_range(),
-                                        "Can not use this assignment \
-                                        operator with `link` and `text`")
-                                }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                n.push(&Variable::Str(b.clone()))?;
+                            } else {
+                                return self.err(range,
+                                    "Can not use this assignment \
+                                    operator with `link` and `text`")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to text")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to text")
                     }
                 }
-                Variable::Object(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Object(_) => {
-                                if let Set = op {
-                                    *r.0 = Variable::Object(b.clone())
-                                } else {
-                                    unimplemented!()
-                                }
+            }
+            Variable::Bytes(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Bytes(ref mut n) => {
+                            match op {
+                                Set => *n = b.clone(),
+                                Add => Arc::make_mut(n).extend_from_slice(b),
+                                _ => return self.err(range,
+                                    "Can not use this assignment operator with `bytes`")
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Object(b.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Bytes(b.clone())
+                            } else {
+                                return self.err(range,
+                                                "Return has no value")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to object")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to bytes")
                     }
                 }
-                Variable::Array(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Array(_) => {
-                                if let Set = op {
-                                    *r.0 = Variable::Array(b.clone())
-                                } else {
-                                    unimplemented!()
-                                }
+            }
+            Variable::Object(_) | Variable::Record(_, _) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Object(_) | Variable::Record(_, _) => {
+                            if let Set = op {
+                                *r.0 = self.resolve(&b).clone()
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Array(b.clone())
-                                } else {
-                                    return self.err(left.source_range(),
-                                                    "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = self.resolve(&b).clone()
+                            } else {
+                                return self.err(range, "Return has no value")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to array")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to object")
                     }
                 }
-                Variable::Link(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Link(ref mut n) => {
-                                match op {
-                                    Set => *n = b.clone(),
-                                    Add => **n = n.add(b),
-                                    Sub => **n = b.add(n),
-                                    _ => unimplemented!()
-                                }
+            }
+            Variable::Array(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Array(_) => {
+                            if let Set = op {
+                                *r.0 = Variable::Array(b.clone())
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Link(b.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Array(b.clone())
+                            } else {
+                                return self.err(range,
+                                                "Return has no value")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to link")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to array")
                     }
                 }
-                Variable::Option(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Option(_) => {
-                                if let Set = op {
-                                    *r.0 = Variable::Option(b.clone())
-                                } else {
-                                    unimplemented!()
-                                }
+            }
+            Variable::Link(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Link(ref mut n) => {
+                            match op {
+                                Set => *n = b.clone(),
+                                Add => **n = n.add(b),
+                                Sub => **n = b.add(n),
+                                _ => unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Option(b.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Link(b.clone())
+                            } else {
+                                return self.err(range, "Return has no value")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to option")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to link")
                     }
                 }
-                Variable::Result(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Result(_) => {
-                                if let Set = op {
-                                    *r.0 = Variable::Result(b.clone())
-                                } else {
-                                    unimplemented!()
-                                }
+            }
+            Variable::Option(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Option(_) => {
+                            if let Set = op {
+                                *r.0 = Variable::Option(b.clone())
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Result(b.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Option(b.clone())
+                            } else {
+                                return self.err(range, "Return has no value")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to result")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to option")
                     }
                 }
-                Variable::RustObject(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::RustObject(_) => {
-                                if let Set = op {
-                                    *r.0 = Variable::RustObject(b.clone())
-                                } else {
-                                    unimplemented!()
-                                }
-                            }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::RustObject(b.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+            }
+            Variable::Variant(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Variant(_) | Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Variant(b.clone())
+                            } else {
+                                return self.err(range,
+                                    "Can not use this assignment operator with enum")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to rust_object")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to enum")
                     }
                 }
-                Variable::Closure(ref b, ref env) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Closure(_, _) => {
-                                if let Set = op {
-                                    *r.0 = Variable::Closure(b.clone(), env.clone())
-                                } else {
-                                    unimplemented!()
-                                }
+            }
+            Variable::Result(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Result(_) => {
+                            if let Set = op {
+                                *r.0 = Variable::Result(b.clone())
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Closure(b.clone(), env.clone())
-                                } else {
-                                    return self.err(left.source_range(), "Return has no value")
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Result(b.clone())
+                            } else {
+                                return self.err(range, "Return has no value")
                             }
-                            _ => return self.err(left.source_range(),
-                                                 "Expected assigning to closure")
                         }
+                        _ => return self.err(range,
+                                             "Expected assigning to result")
                     }
                 }
-                ref x => {
-                    return Err(self.module.error(
-                        left.source_range(),
-                        &format!("{}\nCan not use this assignment operator with `{}`",
-                            self.stack_trace(), x.typeof_var()), self));
+            }
+            Variable::RustObject(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::RustObject(_) => {
+                            if let Set = op {
+                                *r.0 = Variable::RustObject(b.clone())
+                            } else {
+                                unimplemented!()
+                            }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::RustObject(b.clone())
+                            } else {
+                                return self.err(range, "Return has no value")
+                            }
+                        }
+                        _ => return self.err(range,
+                                             "Expected assigning to rust_object")
+                    }
                 }
-            };
-            Ok((None, Flow::Continue))
-        } else {
-            match *left {
-                Expression::Item(ref item) => {
-                    let x = match self.expression(right, Side::Right)? {
-                        (x, Flow::Return) => return Ok((x, Flow::Return)),
-                        (Some(x), Flow::Continue) => x,
-                        _ => return self.err(right.source_range(),
-                                             "Expected something from the right side")
-                    };
-                    let v = match x {
-                        // Use a shallow clone of a reference.
-                        Variable::Ref(ind) => self.stack[ind].clone(),
-                        x => x
-                    };
-                    if !item.ids.is_empty() {
-                        let x = match self.expression(left, Side::LeftInsert(true))? {
-                            (Some(x), Flow::Continue) => x,
-                            (x, Flow::Return) => return Ok((x, Flow::Return)),
-                            _ => return self.err(left.source_range(),
-                                                 "Expected something from the left side")
-                        };
-                        match x {
-                            Variable::UnsafeRef(r) => {
-                                unsafe { *r.0 = v }
+            }
+            Variable::Closure(ref b, ref env) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Closure(_, _) => {
+                            if let Set = op {
+                                *r.0 = Variable::Closure(b.clone(), env.clone())
+                            } else {
+                                unimplemented!()
                             }
-                            _ => panic!("Expected unsafe reference")
                         }
-                    } else {
-                        self.local_stack.push((item.name.clone(), self.stack.len()));
-                        if item.current {
-                            self.current_stack.push((item.name.clone(), self.stack.len()));
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Closure(b.clone(), env.clone())
+                            } else {
+                                return self.err(range, "Return has no value")
+                            }
                         }
-                        self.stack.push(v);
+                        _ => return self.err(range,
+                                             "Expected assigning to closure")
                     }
-                    Ok((None, Flow::Continue))
                 }
-                _ => self.err(left.source_range(), "Expected item")
             }
-        }
+            ref x => {
+                return Err(self.module.error(
+                    range,
+                    &format!("{}\nCan not use this assignment operator with `{}`",
+                        self.stack_trace(), x.typeof_var()), self));
+            }
+        };
+        Ok((None, Flow::Continue))
     }
+
     // `insert` is true for `:=` and false for `=`.
     // This works only on objects, but does not have to check since it is
     // ignored for arrays.
@@ -2070,7 +2697,7 @@ impl Runtime {
                     err.trace.push(module.error_fnindex(
                         source_range,
                         &format!("In function `{}`{}", call.fn_name, file),
-                        call.index));
+                        call.index).into());
                     Ok((Some(Variable::Result(Err(err))), Flow::Return))
                 }
             }
@@ -2174,6 +2801,17 @@ impl Runtime {
                 };
             }
         }
+        self.item_ids(item, stack_id, start_stack_len, side)
+    }
+
+    /// Looks up the ids of an item, with computed ids on the stack.
+    pub(crate) fn item_ids(
+        &mut self,
+        item: &ast::Item,
+        stack_id: usize,
+        start_stack_len: usize,
+        side: Side
+    ) -> FlowResult {
         let &mut Runtime {
             ref mut stack,
             ref mut call_stack,
@@ -2244,7 +2882,7 @@ impl Runtime {
                             item.ids[0].source_range(),
                             &format!("In function `{}`{}",
                                 &call.fn_name, file),
-                                call.index));
+                                call.index).into());
                         return Ok((Some(Variable::Result(Err(err))), Flow::Return));
                     }
                 }
@@ -2309,7 +2947,7 @@ impl Runtime {
                                 prop.source_range(),
                                 &format!("In function `{}`{}",
                                     &call.fn_name, file),
-                                    call.index));
+                                    call.index).into());
                             return Ok((Some(Variable::Result(Err(err))), Flow::Return));
                         }
                     }
@@ -2365,6 +3003,79 @@ impl Runtime {
             Ok((None, Flow::Continue))
         }
     }
+    fn match_expr(
+        &mut self,
+        match_expr: &ast::Match
+    ) -> FlowResult {
+        let v = match self.expression(&match_expr.expr, Side::Right)? {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return self.err(match_expr.expr.source_range(),
+                                 "Expected something from match expression")
+        };
+        let v = self.resolve(&v).clone();
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let mut binds = vec![];
+        for arm in &match_expr.arms {
+            binds.clear();
+            if !self.match_pattern(&arm.pattern, &v, &mut binds) {continue};
+            for (name, val) in binds.drain(..) {
+                self.local_stack.push((name, self.stack.len()));
+                self.stack.push(val);
+            }
+            let res = match self.expression(&arm.expr, Side::Right)? {
+                // Copy references to bound variables before they are removed.
+                (Some(x), flow) if self.stack.len() > st => {
+                    (Some(x.deep_clone(&self.stack)), flow)
+                }
+                x => x
+            };
+            self.stack.truncate(st);
+            self.local_stack.truncate(lc);
+            return Ok(res);
+        }
+        self.err(match_expr.source_range,
+                 &format!("No pattern matched `{}`", v.typeof_var()))
+    }
+
+    /// Matches a value against a pattern, pushing the bound variables.
+    fn match_pattern(
+        &self,
+        pattern: &ast::Pattern,
+        v: &Variable,
+        binds: &mut Vec<(Arc<String>, Variable)>
+    ) -> bool {
+        use ast::Pattern as P;
+
+        match (pattern, self.resolve(v)) {
+            (P::Any, _) => true,
+            (P::Bind(name), v) => {
+                binds.push((name.clone(), v.clone()));
+                true
+            }
+            (P::F64(a), Variable::F64(b, _)) => a == b,
+            (P::I64(a), Variable::I64(b)) => a == b,
+            (P::Str(a), Variable::Str(b)) => a == b,
+            (P::Bool(a), Variable::Bool(b, _)) => a == b,
+            (P::Some(p), Variable::Option(Some(v))) => self.match_pattern(p, v, binds),
+            (P::None, Variable::Option(None)) => true,
+            (P::Ok(p), Variable::Result(Ok(v))) => self.match_pattern(p, v, binds),
+            (P::Err(p), Variable::Result(Err(err))) => self.match_pattern(p, &err.message, binds),
+            (P::Object(fields), Variable::Object(obj)) |
+            (P::Object(fields), Variable::Record(_, obj)) => {
+                fields.iter().all(|(key, p)| match obj.get(key) {
+                    Some(v) => self.match_pattern(p, v, binds),
+                    None => false,
+                })
+            }
+            (P::Variant(enum_name, name, args), Variable::Variant(v)) => {
+                v.enum_name == *enum_name && v.name == *name && v.args.len() == args.len() &&
+                args.iter().zip(&v.args).all(|(p, v)| self.match_pattern(p, v, binds))
+            }
+            _ => false
+        }
+    }
     fn for_expr(&mut self, for_expr: &ast::For) -> FlowResult {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
@@ -2508,18 +3219,80 @@ impl Runtime {
     }
 
     pub(crate) fn stack_trace(&self) -> String {stack_trace(&self.call_stack)}
+
+    /// Reloads a source file of a module with new text, keeping the state of the runtime.
+    ///
+    /// The reload is rejected with an error if the new text fails to parse or check.
+    /// When called while running, e.g. from an external function,
+    /// the module in use is swapped such that following calls use the new functions.
+    /// If running code can not continue with the reloaded module,
+    /// see `Reload::needs_restart`, the module is left unchanged while running.
+    pub fn reload_str(
+        &mut self,
+        module: &mut Arc<Module>,
+        file: &str,
+        source: Arc<String>
+    ) -> Result<Reload, DyonError> {
+        let (new_module, reload) = reload::reload_str(module, file, source)?;
+        let running = !self.call_stack.is_empty();
+        if running && reload.needs_restart() {return Ok(reload)};
+        let new_module = Arc::new(new_module);
+        if running && Arc::ptr_eq(&self.module, module) {
+            self.module = new_module.clone();
+        }
+        *module = new_module;
+        Ok(reload)
+    }
+
+    /// Returns the source of the function of a call.
+    pub fn call_source(&self, call: &Call) -> Option<&Arc<String>> {
+        self.module.functions.get(call.index).map(|f| &f.source)
+    }
+
+    /// Returns the locals of the current function call with their values.
+    ///
+    /// When a local is shadowed, only the last declaration is included.
+    pub fn locals(&self) -> Vec<(Arc<String>, &Variable)> {
+        let lc = match self.call_stack.last() {
+            Some(call) => call.local_len,
+            None => 0,
+        };
+        let mut locals: Vec<(Arc<String>, &Variable)> = vec![];
+        for &(ref name, ind) in &self.local_stack[lc..] {
+            if **name == **RETURN_TYPE {continue};
+            let v = self.resolve(&self.stack[ind]);
+            match locals.iter_mut().find(|l| &l.0 == name) {
+                Some(l) => l.1 = v,
+                None => locals.push((name.clone(), v)),
+            }
+        }
+        locals
+    }
+}
+
+impl Call {
+    /// Returns the name of the called function.
+    pub fn name(&self) -> &Arc<String> {&self.fn_name}
+
+    /// Returns the file of the called function, if any.
+    pub fn file(&self) -> Option<&Arc<String>> {self.file.as_ref()}
 }
 
 fn stack_trace(call_stack: &[Call]) -> String {
     let mut s = String::new();
     for call in call_stack.iter() {
-        s.push_str(&call.fn_name);
-        if let Some(ref file) = call.file {
-            s.push_str(" (");
-            s.push_str(file);
-            s.push(')');
-        }
+        s.push_str(&call.to_string());
         s.push('\n')
     }
     s
 }
+
+impl ::std::fmt::Display for Call {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{}", self.fn_name)?;
+        if let Some(ref file) = self.file {
+            write!(f, " ({})", file)?;
+        }
+        Ok(())
+    }
+}
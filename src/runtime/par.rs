@@ -0,0 +1,297 @@
+//! Parallel mathematical loops, e.g. `par sum i n { ... }`.
+//!
+//! The index range is split into a fixed number of chunks run on the thread pool.
+//! Each chunk runs in a new runtime with a deep clone of the stack frame,
+//! such that the body can read variables outside the loop, but not change them.
+//! The lifetime checker makes sure the body does not mutate such variables.
+//!
+//! The results of chunks are merged in index order.
+//! When a chunk stops the loop, e.g. with `break` or `return`,
+//! the results of the chunks after it are ignored.
+//! This gives the same result as running on a single thread,
+//! except for rounding errors of `sum` and `prod`.
+//! The chunks do not depend on the number of threads,
+//! so rounding errors are the same on every machine.
+
+use super::*;
+
+use std::f64::NAN;
+
+/// The maximum number of chunks of a loop.
+const CHUNKS: usize = 32;
+
+/// The kind of parallel loop.
+#[derive(Clone, Copy)]
+enum Par {
+    Sum,
+    Prod,
+    Min,
+    Max,
+    Any,
+    All,
+    Sift,
+}
+
+/// The result of running a chunk of the index range.
+struct Chunk {
+    /// The merged value of the chunk.
+    value: Variable,
+    /// Whether the loop ends with this chunk.
+    stop: bool,
+    /// Set when leaving the loop by `return` or by a label of an outer loop.
+    exit: Option<(Option<Variable>, Flow)>,
+}
+
+/// Adds the index to the secret of a value.
+fn secret(sec: &Option<Box<Vec<Variable>>>, ind: Option<f64>) -> Option<Box<Vec<Variable>>> {
+    match ind {
+        None => sec.clone(),
+        Some(ind) => {
+            let mut arr = sec.clone().unwrap_or_default();
+            arr.push(Variable::f64(ind));
+            Some(arr)
+        }
+    }
+}
+
+impl Par {
+    fn init(self) -> Variable {
+        match self {
+            Par::Sum => Variable::f64(0.0),
+            Par::Prod => Variable::f64(1.0),
+            Par::Min | Par::Max => Variable::f64(NAN),
+            Par::Any => Variable::bool(false),
+            Par::All => Variable::bool(true),
+            Par::Sift => Variable::Array(Arc::new(vec![])),
+        }
+    }
+
+    fn expected(self) -> &'static str {
+        match self {
+            Par::Any | Par::All => "boolean",
+            _ => "number",
+        }
+    }
+
+    /// Merges a value into the result.
+    ///
+    /// When `min`, `max`, `any` or `all` picks the value, the index is added to the secret.
+    /// Returns `true` when the loop ends, or `None` if the value has the wrong type.
+    fn fold(self, acc: &mut Variable, v: &Variable, ind: Option<f64>) -> Option<bool> {
+        match self {
+            Par::Sum | Par::Prod => {
+                let b = if let Variable::F64(b, _) = *v {b} else {return None};
+                if let Variable::F64(ref mut a, _) = *acc {
+                    if let Par::Sum = self {*a += b} else {*a *= b}
+                }
+                Some(false)
+            }
+            Par::Min | Par::Max => {
+                let (b, sec) = if let Variable::F64(b, ref sec) = *v {(b, sec)} else {return None};
+                let a = if let Variable::F64(a, _) = *acc {a} else {NAN};
+                let pick = if let Par::Min = self {a > b} else {a < b};
+                if a.is_nan() || pick {
+                    *acc = Variable::F64(b, secret(sec, ind));
+                }
+                Some(false)
+            }
+            Par::Any | Par::All => {
+                let (b, sec) = if let Variable::Bool(b, ref sec) = *v {(b, sec)} else {return None};
+                // `any` ends at the first `true` and `all` at the first `false`.
+                let stop = if let Par::Any = self {b} else {!b};
+                if stop {
+                    *acc = Variable::Bool(b, secret(sec, ind));
+                }
+                Some(stop)
+            }
+            Par::Sift => {
+                if let Variable::Array(ref mut arr) = *acc {
+                    let arr = Arc::make_mut(arr);
+                    match (ind, v) {
+                        (None, &Variable::Array(ref items)) => arr.extend(items.iter().cloned()),
+                        _ => arr.push(v.clone()),
+                    }
+                }
+                Some(false)
+            }
+        }
+    }
+}
+
+impl Runtime {
+    /// Runs a `par` loop on the thread pool.
+    pub(crate) fn par_n_expr(&mut self, expr: &ast::Expression) -> FlowResult {
+        use ast::Expression as E;
+
+        let (par, for_n_expr) = match *expr {
+            E::Sum(ref for_n_expr) => (Par::Sum, for_n_expr),
+            E::Prod(ref for_n_expr) => (Par::Prod, for_n_expr),
+            E::Min(ref for_n_expr) => (Par::Min, for_n_expr),
+            E::Max(ref for_n_expr) => (Par::Max, for_n_expr),
+            E::Any(ref for_n_expr) => (Par::Any, for_n_expr),
+            E::All(ref for_n_expr) => (Par::All, for_n_expr),
+            E::Sift(ref for_n_expr) => (Par::Sift, for_n_expr),
+            _ => return self.err(expr.source_range(), "Expected parallel loop"),
+        };
+
+        let start = start!(self, for_n_expr);
+        let end = end!(self, for_n_expr);
+
+        let pool = self.pool();
+        self.share_fuel();
+        let n = if end > start {(end - start).ceil() as usize} else {0};
+        let chunks = CHUNKS.min(n).max(1);
+        let mut handles = Vec::with_capacity(chunks);
+        for i in 0..chunks {
+            let a = start + (n * i / chunks) as f64;
+            let b = if i + 1 == chunks {end} else {start + (n * (i + 1) / chunks) as f64};
+            let mut rt = self.fork(&pool);
+            let for_n_expr = (**for_n_expr).clone();
+            handles.push(pool.spawn(move || {
+                let res = rt.par_chunk(&for_n_expr, par, a, b);
+                res.map_err(|mut err| {rt.set_call_stack(&mut err); err})
+            }));
+        }
+
+        let mut value = par.init();
+        let mut flow = Flow::Continue;
+        for handle in handles {
+            let chunk = match handle.join() {
+                Ok(Ok(chunk)) => chunk,
+                Ok(Err(mut err)) => {
+                    // The call stack of a chunk starts at the current call.
+                    let info = err.info_mut();
+                    if !info.call_stack.is_empty() {
+                        let n = self.call_stack.len() - 1;
+                        info.call_stack.splice(0..0,
+                            self.call_stack[..n].iter().map(|c| c.to_string()));
+                    }
+                    return Err(err)
+                }
+                Err(_err) => return Err(self.module.error(for_n_expr.source_range,
+                    &format!("{}\nThread did not exit successfully", self.stack_trace()), self)),
+            };
+            let stop = par.fold(&mut value, &chunk.value, None).unwrap_or(true);
+            if let Some((x, exit)) = chunk.exit {
+                if let Flow::Return = exit {return Ok((x, Flow::Return))};
+                flow = exit;
+                break;
+            }
+            if stop || chunk.stop {break};
+        }
+        Ok((Some(value), flow))
+    }
+
+    /// Creates a runtime with a deep clone of the current stack frame and current objects.
+    fn fork(&self, pool: &Arc<pool::Pool>) -> Runtime {
+        let call = self.call_stack.last().expect(TINVOTS);
+        // Include the return slot, which is checked by the `?` operator.
+        let st = call.stack_len.saturating_sub(1);
+        let mut stack = Vec::with_capacity(self.current_stack.len() + self.stack.len() - st);
+        let mut current_stack = Vec::with_capacity(self.current_stack.len());
+        for &(ref name, ind) in &self.current_stack {
+            current_stack.push((name.clone(), stack.len()));
+            stack.push(self.stack[ind].deep_clone(&self.stack));
+        }
+        let offset = stack.len();
+        stack.extend(self.stack[st..].iter().map(|v| v.deep_clone(&self.stack)));
+        let local_stack = self.local_stack[call.local_len..].iter()
+            .map(|&(ref name, ind)| (name.clone(), ind - st + offset))
+            .collect();
+        Runtime {
+            module: self.module.clone(),
+            stack,
+            local_stack,
+            current_stack,
+            call_stack: vec![Call {
+                fn_name: call.fn_name.clone(),
+                index: call.index,
+                file: call.file.clone(),
+                stack_len: call.stack_len - st + offset,
+                local_len: 0,
+                current_len: 0,
+            }],
+            rng: self.rng.clone(),
+            arg_err_index: Cell::new(None),
+            bytecode: self.bytecode,
+            debug_hook: None,
+            limits: self.limits.clone(),
+            threads: self.threads,
+            // Cancelling the thread also stops the chunks.
+            cancel: self.cancel.clone(),
+            shared_fuel: self.shared_fuel.clone(),
+            ticks: 0,
+            vm: bytecode::Registers::default(),
+            pool: Some(pool.clone()),
+        }
+    }
+
+    /// Runs the loop body for indices in a range.
+    fn par_chunk(
+        &mut self,
+        for_n_expr: &ast::ForN,
+        par: Par,
+        start: f64,
+        end: f64
+    ) -> Result<Chunk, RuntimeError> {
+        let mut chunk = Chunk {value: par.init(), stop: false, exit: None};
+        // Initialize counter.
+        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
+        self.stack.push(Variable::f64(start));
+
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let same = |label: &Arc<String>| for_n_expr.label.as_ref() == Some(label);
+        let mut ind = start;
+        while ind < end {
+            self.stack[st - 1] = Variable::f64(ind);
+            match self.block(&for_n_expr.block)? {
+                (Some(x), Flow::Continue) => {
+                    let v = self.resolve(&x).deep_clone(&self.stack);
+                    match par.fold(&mut chunk.value, &v, Some(ind)) {
+                        Some(false) => {}
+                        Some(true) => {
+                            chunk.stop = true;
+                            break;
+                        }
+                        None => return Err(self.module.error(for_n_expr.block.source_range,
+                                &self.expected(&v, par.expected()), self))
+                    }
+                }
+                (x, Flow::Return) => {
+                    let x = x.map(|x| self.resolve(&x).deep_clone(&self.stack));
+                    chunk.exit = Some((x, Flow::Return));
+                    break;
+                }
+                (None, Flow::Continue) => {
+                    let msg = if let Par::Sift = par {"Expected variable".into()}
+                        else {format!("Expected `{}`", par.expected())};
+                    return Err(self.module.error(for_n_expr.block.source_range, &msg, self))
+                }
+                (_, Flow::Break(None)) => {
+                    chunk.stop = true;
+                    break;
+                }
+                (_, Flow::Break(Some(label))) => {
+                    if same(&label) {
+                        chunk.stop = true;
+                    } else {
+                        chunk.exit = Some((None, Flow::Break(Some(label))));
+                    }
+                    break;
+                }
+                (_, Flow::ContinueLoop(None)) => {}
+                (_, Flow::ContinueLoop(Some(label))) => {
+                    if !same(&label) {
+                        chunk.exit = Some((None, Flow::ContinueLoop(Some(label))));
+                        break;
+                    }
+                }
+            }
+            ind += 1.0;
+            self.stack.truncate(st);
+            self.local_stack.truncate(lc);
+        }
+        Ok(chunk)
+    }
+}
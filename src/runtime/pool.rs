@@ -0,0 +1,346 @@
+//! Thread pool with a work-stealing scheduler, used by `go` and parallel loops.
+//!
+//! Every worker has a local queue of tasks.
+//! Tasks spawned by a worker are pushed to its own queue,
+//! while tasks spawned from other threads are pushed to a shared queue.
+//! An idle worker takes tasks from its own queue first,
+//! then from the shared queue and finally steals from other workers.
+//!
+//! Joining a task runs it on the current thread if no worker has started it yet,
+//! unless the thread holds a mutex locked by Dyon code that the task might need.
+//! While waiting for a running task, the joining thread is blocked, see `blocking`.
+//! This means a task that joins another task inside a worker can not deadlock the pool.
+//!
+//! A worker that blocks for other reasons, e.g. waiting for a channel or a lock,
+//! is replaced by a spare worker while tasks are queued, see `blocking`.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use Variable;
+
+type Job<T> = Box<dyn FnOnce() -> T + Send>;
+
+thread_local! {
+    /// The pool and index of the worker running on this thread.
+    static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    /// The pool of the worker or spare worker running on this thread.
+    static POOL: RefCell<Option<Arc<Shared>>> = const { RefCell::new(None) };
+}
+
+struct TaskState<T> {
+    job: Option<Job<T>>,
+    result: Option<thread::Result<T>>,
+}
+
+/// A task that runs once, either on a worker or when joined.
+struct Task<T> {
+    state: Mutex<TaskState<T>>,
+    done: Condvar,
+}
+
+/// Implemented by tasks, such that tasks with different result types share queues.
+trait Run: Send + Sync {
+    /// Runs the task, unless it is already taken.
+    fn run(&self);
+}
+
+impl<T: Send> Run for Task<T> {
+    fn run(&self) {
+        let job = match self.state.lock() {
+            Ok(mut state) => state.job.take(),
+            Err(_) => None,
+        };
+        if let Some(job) = job {
+            let res = panic::catch_unwind(AssertUnwindSafe(job));
+            if let Ok(mut state) = self.state.lock() {
+                state.result = Some(res);
+            }
+            self.done.notify_all();
+        }
+    }
+}
+
+struct Shared {
+    /// Tasks spawned from threads outside the pool.
+    injector: Mutex<VecDeque<Arc<dyn Run>>>,
+    /// Local queues of workers.
+    locals: Vec<Mutex<VecDeque<Arc<dyn Run>>>>,
+    /// Number of queued tasks.
+    pending: AtomicUsize,
+    /// Used by idle workers to wait for tasks.
+    idle: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+    /// Number of workers waiting inside `blocking`.
+    blocked: AtomicUsize,
+    /// Number of spare workers running tasks in place of blocked workers.
+    spares: AtomicUsize,
+}
+
+impl Shared {
+    fn id(&self) -> usize {self as *const Shared as usize}
+
+    /// Returns the index of the worker on this thread, if it belongs to this pool.
+    fn worker(&self) -> Option<usize> {
+        match WORKER.with(|w| w.get()) {
+            Some((id, ind)) if id == self.id() => Some(ind),
+            _ => None
+        }
+    }
+
+    fn push(self: &Arc<Self>, task: Arc<dyn Run>) {
+        let queue = match self.worker() {
+            Some(ind) => &self.locals[ind],
+            None => &self.injector,
+        };
+        // Count before pushing, such that the count never goes below zero.
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if let Ok(mut queue) = queue.lock() {
+            queue.push_back(task);
+        }
+        // Lock before notifying, such that a worker going idle does not miss the task.
+        {
+            let _idle = self.idle.lock();
+            self.wake.notify_one();
+        }
+        if self.blocked.load(Ordering::SeqCst) > 0 {self.compensate()};
+    }
+
+    /// Starts spare workers while tasks are queued and there are fewer spares than blocked workers.
+    fn compensate(self: &Arc<Self>) {
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            let spares = self.spares.load(Ordering::SeqCst);
+            if spares >= self.blocked.load(Ordering::SeqCst) {break};
+            if self.spares.compare_exchange(spares, spares + 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err() {continue};
+            let shared = self.clone();
+            let spawned = thread::Builder::new()
+                .name("dyon-spare".into())
+                .spawn(move || shared.spare());
+            if spawned.is_err() {
+                self.spares.fetch_sub(1, Ordering::SeqCst);
+                break;
+            }
+        }
+    }
+
+    /// Runs queued tasks until there are none left or the blocked workers continue.
+    fn spare(self: Arc<Self>) {
+        POOL.with(|p| *p.borrow_mut() = Some(self.clone()));
+        while self.spares.load(Ordering::SeqCst) <= self.blocked.load(Ordering::SeqCst) {
+            match self.find() {
+                Some(task) => task.run(),
+                None => break,
+            }
+        }
+        self.spares.fetch_sub(1, Ordering::SeqCst);
+        // A task might have been queued after the last search.
+        self.compensate();
+    }
+
+    /// Finds a queued task.
+    fn find(&self) -> Option<Arc<dyn Run>> {
+        if self.pending.load(Ordering::SeqCst) == 0 {return None};
+        let worker = self.worker();
+        let pop = |queue: &Mutex<VecDeque<Arc<dyn Run>>>, back: bool| {
+            let mut queue = queue.lock().ok()?;
+            if back {queue.pop_back()} else {queue.pop_front()}
+        };
+        // Take the last task of the local queue, since it is most likely to be in cache.
+        let task = worker.and_then(|ind| pop(&self.locals[ind], true))
+            .or_else(|| pop(&self.injector, false))
+            .or_else(|| {
+                // Steal the oldest task of another worker.
+                let n = self.locals.len();
+                let start = worker.map(|ind| ind + 1).unwrap_or(0);
+                (0..n).filter_map(|i| pop(&self.locals[(start + i) % n], false)).next()
+            });
+        if task.is_some() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        task
+    }
+
+    fn work(self: Arc<Self>, ind: usize) {
+        WORKER.with(|w| w.set(Some((self.id(), ind))));
+        POOL.with(|p| *p.borrow_mut() = Some(self.clone()));
+        loop {
+            if let Some(task) = self.find() {
+                task.run();
+                continue;
+            }
+            if self.shutdown.load(Ordering::SeqCst) {break};
+            let idle = match self.idle.lock() {
+                Ok(x) => x,
+                Err(_) => break,
+            };
+            if self.pending.load(Ordering::SeqCst) == 0 &&
+               !self.shutdown.load(Ordering::SeqCst) {
+                let _idle = self.wake.wait(idle);
+            }
+        }
+    }
+}
+
+/// Leaves the blocked count when the blocking function returns or panics.
+struct Blocked(Arc<Shared>);
+
+impl Drop for Blocked {
+    fn drop(&mut self) {
+        self.0.blocked.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs a function that might wait a long time for other threads, e.g. receiving from a channel.
+///
+/// When called on a worker of a pool, a spare worker runs queued tasks while waiting,
+/// such that the waiting task does not keep the tasks it is waiting for from running.
+pub fn blocking<F, T>(f: F) -> T
+    where F: FnOnce() -> T
+{
+    let shared = match POOL.with(|p| p.borrow().clone()) {
+        Some(x) => x,
+        None => return f(),
+    };
+    shared.blocked.fetch_add(1, Ordering::SeqCst);
+    shared.compensate();
+    let _blocked = Blocked(shared);
+    f()
+}
+
+/// Runs tasks spawned by `go` and parallel loops on a fixed number of worker threads.
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+impl Pool {
+    /// Creates a new pool with a number of worker threads.
+    ///
+    /// When `size` is zero, the number of threads is the available parallelism.
+    pub fn new(size: usize) -> Pool {
+        let size = if size == 0 {
+            thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+        } else {
+            size
+        };
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            locals: (0..size).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: AtomicUsize::new(0),
+            idle: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            blocked: AtomicUsize::new(0),
+            spares: AtomicUsize::new(0),
+        });
+        for ind in 0..size {
+            let shared = shared.clone();
+            let _ = thread::Builder::new()
+                .name(format!("dyon-worker-{}", ind))
+                .spawn(move || shared.work(ind));
+        }
+        Pool {shared}
+    }
+
+    /// Returns the number of worker threads.
+    pub fn size(&self) -> usize {self.shared.locals.len()}
+
+    /// Spawns a task on the pool.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static, T: Send + 'static
+    {
+        let task = Arc::new(Task {
+            state: Mutex::new(TaskState {job: Some(Box::new(f)), result: None}),
+            done: Condvar::new(),
+        });
+        self.shared.push(task.clone());
+        JoinHandle {task}
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Workers finish the queued tasks before exiting.
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        let _idle = self.shared.idle.lock();
+        self.shared.wake.notify_all();
+    }
+}
+
+/// Handle of a task spawned on a pool.
+pub struct JoinHandle<T = Result<Variable, String>> {
+    task: Arc<Task<T>>,
+}
+
+impl<T: Send> JoinHandle<T> {
+    /// Waits for the task to finish.
+    ///
+    /// Returns an error if the task panicked.
+    pub fn join(self) -> Result<T, Box<dyn Any + Send>> {
+        // Run the task here if no worker has started it.
+        // Lock state is per thread, so a task must not run inside `lock` of another task.
+        if !super::holds_lock() {self.task.run()};
+        // A spare worker runs the queued tasks while waiting on a worker.
+        blocking(|| {
+            let mut state = match self.task.state.lock() {
+                Ok(x) => x,
+                Err(err) => return Err(Box::new(err.to_string()) as Box<dyn Any + Send>),
+            };
+            loop {
+                if let Some(res) = state.result.take() {return res};
+                state = match self.task.done.wait(state) {
+                    Ok(x) => x,
+                    Err(err) => return Err(Box::new(err.to_string())),
+                };
+            }
+        })
+    }
+
+    /// Returns `true` if the task has finished.
+    pub fn is_done(&self) -> bool {
+        match self.task.state.lock() {
+            Ok(state) => state.result.is_some(),
+            Err(_) => true,
+        }
+    }
+
+    /// Waits for the task to finish, at most `timeout`.
+    ///
+    /// Returns `true` if the task has finished.
+    /// Unlike `join`, this does not run tasks on the current thread, such that it returns in time.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        // Wait without a deadline when the timeout is too large to represent.
+        let deadline = Instant::now().checked_add(timeout);
+        // A spare worker runs the queued tasks while waiting on a worker.
+        blocking(|| {
+            let mut state = match self.task.state.lock() {
+                Ok(x) => x,
+                Err(_) => return true,
+            };
+            loop {
+                if state.result.is_some() {return true};
+                state = match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {return false};
+                        match self.task.done.wait_timeout(state, deadline - now) {
+                            Ok((x, _)) => x,
+                            Err(_) => return true,
+                        }
+                    }
+                    None => match self.task.done.wait(state) {
+                        Ok(x) => x,
+                        Err(_) => return true,
+                    }
+                };
+            }
+        })
+    }
+}
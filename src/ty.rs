@@ -17,18 +17,26 @@ pub enum Type {
     Bool,
     /// F64 type.
     F64,
+    /// I64 type.
+    I64,
     /// 4D vector type.
     Vec4,
     /// 4D matrix type.
     Mat4,
     /// String/text type.
     Str,
+    /// Byte buffer type.
+    Bytes,
     /// Link type.
     Link,
     /// Array type.
     Array(Box<Type>),
     /// Object type.
     Object,
+    /// Map type with key and value types.
+    Map(Box<Type>, Box<Type>),
+    /// Set type.
+    Set(Box<Type>),
     /// Option type.
     Option(Box<Type>),
     /// Result type.
@@ -39,6 +47,14 @@ pub enum Type {
     Thread(Box<Type>),
     /// In-type.
     In(Box<Type>),
+    /// Coroutine type.
+    Coroutine(Box<Type>),
+    /// Sending end of a channel.
+    Out(Box<Type>),
+    /// Atomic number shared between threads.
+    Atomic,
+    /// Value shared between threads, guarded by a lock.
+    Mutex(Box<Type>),
     /// Ad-hoc type.
     AdHoc(Arc<String>, Box<Type>),
     /// Closure type.
@@ -67,10 +83,13 @@ impl Type {
             Any => "any".into(),
             Bool => "bool".into(),
             F64 => "f64".into(),
+            I64 => "i64".into(),
             Vec4 => "vec4".into(),
             Mat4 => "mat4".into(),
             Str => "str".into(),
+            Bytes => "bytes".into(),
             Link => "link".into(),
+            Atomic => "atomic".into(),
             Array(ref ty) => {
                 if let Any = **ty {
                     "[]".into()
@@ -82,6 +101,23 @@ impl Type {
                 }
             }
             Object => "{}".into(),
+            Map(ref key, ref val) => {
+                if let (&Any, &Any) = (&**key, &**val) {
+                    "map".into()
+                } else {
+                    format!("map[{}, {}]", key.description(), val.description())
+                }
+            }
+            Set(ref ty) => {
+                if let Any = **ty {
+                    "set".into()
+                } else {
+                    let mut res = String::from("set[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
             Option(ref ty) => {
                 if let Any = **ty {
                     "opt".into()
@@ -129,6 +165,36 @@ impl Type {
                     res
                 }
             }
+            Coroutine(ref ty) => {
+                if let Any = **ty {
+                    "co".into()
+                } else {
+                    let mut res = String::from("co[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
+            Out(ref ty) => {
+                if let Any = **ty {
+                    "out".into()
+                } else {
+                    let mut res = String::from("out[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
+            Mutex(ref ty) => {
+                if let Any = **ty {
+                    "mutex".into()
+                } else {
+                    let mut res = String::from("mutex[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
             AdHoc(ref ad, ref ty) => {
                 (&**ad).clone() + " " + &ty.description()
             }
@@ -154,6 +220,12 @@ impl Type {
     /// Returns an object type.
     pub fn object() -> Type {Type::Object}
 
+    /// Returns a map type with `any` as key and value type.
+    pub fn map() -> Type {Type::Map(Box::new(Type::Any), Box::new(Type::Any))}
+
+    /// Returns a set type with an `any` as inner type.
+    pub fn set() -> Type {Type::Set(Box::new(Type::Any))}
+
     /// Returns an Option type with an `any` as inner type.
     pub fn option() -> Type {Type::Option(Box::new(Type::Any))}
 
@@ -166,6 +238,15 @@ impl Type {
     /// Returns an in-type with an `any` as inner type.
     pub fn in_ty() -> Type {Type::In(Box::new(Type::Any))}
 
+    /// Returns a coroutine type with an `any` as inner type.
+    pub fn coroutine() -> Type {Type::Coroutine(Box::new(Type::Any))}
+
+    /// Returns a sender type with an `any` as inner type.
+    pub fn out() -> Type {Type::Out(Box::new(Type::Any))}
+
+    /// Returns a mutex type with an `any` as inner type.
+    pub fn mutex() -> Type {Type::Mutex(Box::new(Type::Any))}
+
     /// Binds refinement type variables.
     ///
     /// Returns the type argument to compare to.
@@ -257,22 +338,36 @@ impl Type {
             (&AdHoc(ref xa, ref xb), &AdHoc(ref ya, ref yb)) if xa == ya => xb.ambiguous(yb),
             (&AdHoc(_, ref x), y) if x.goes_with(y) => true,
             (&Array(ref x), &Array(ref y)) if x.ambiguous(y) => true,
+            (&Map(ref xk, ref xv), &Map(ref yk, ref yv))
+                if xk.ambiguous(yk) || xv.ambiguous(yv) => true,
+            (&Set(ref x), &Set(ref y)) if x.ambiguous(y) => true,
             (&Option(ref x), &Option(ref y)) if x.ambiguous(y) => true,
             (&Result(ref x), &Result(ref y)) if x.ambiguous(y) => true,
             (&Thread(ref x), &Thread(ref y)) if x.ambiguous(y) => true,
             (&In(ref x), &In(ref y)) if x.ambiguous(y) => true,
+            (&Coroutine(ref x), &Coroutine(ref y)) if x.ambiguous(y) => true,
+            (&Out(ref x), &Out(ref y)) if x.ambiguous(y) => true,
+            (&Mutex(ref x), &Mutex(ref y)) if x.ambiguous(y) => true,
             (&Bool, &Any) => true,
             (&F64, &Any) => true,
+            (&I64, &Any) => true,
             (&Str, &Any) => true,
+            (&Bytes, &Any) => true,
             (&Vec4, &Any) => true,
             (&Mat4, &Any) => true,
             (&Link, &Any) => true,
             (&Array(_), &Any) => true,
+            (&Map(_, _), &Any) => true,
+            (&Set(_), &Any) => true,
             (&Option(_), &Any) => true,
             (&Result(_), &Any) => true,
             (&Thread(_), &Any) => true,
             (&Secret(_), &Any) => true,
             (&In(_), &Any) => true,
+            (&Coroutine(_), &Any) => true,
+            (&Out(_), &Any) => true,
+            (&Atomic, &Any) => true,
+            (&Mutex(_), &Any) => true,
             _ => false
         }
     }
@@ -337,6 +432,24 @@ impl Type {
                     false
                 }
             }
+            &Map(ref key, ref val) => {
+                if let Map(ref other_key, ref other_val) = *other {
+                    key.goes_with(other_key) && val.goes_with(other_val)
+                } else if let Any = *other {
+                    true
+                } else {
+                    false
+                }
+            }
+            &Set(ref ty) => {
+                if let Set(ref other_ty) = *other {
+                    ty.goes_with(other_ty)
+                } else if let Any = *other {
+                    true
+                } else {
+                    false
+                }
+            }
             &Option(ref opt) => {
                 if let Option(ref other_opt) = *other {
                     opt.goes_with(other_opt)
@@ -373,6 +486,33 @@ impl Type {
                     false
                 }
             }
+            &Coroutine(ref co) => {
+                if let Coroutine(ref other_co) = *other {
+                    co.goes_with(other_co)
+                } else if let Any = *other {
+                    true
+                } else {
+                    false
+                }
+            }
+            &Out(ref out_ty) => {
+                if let Out(ref other_ty) = *other {
+                    out_ty.goes_with(other_ty)
+                } else if let Any = *other {
+                    true
+                } else {
+                    false
+                }
+            }
+            &Mutex(ref mutex_ty) => {
+                if let Mutex(ref other_ty) = *other {
+                    mutex_ty.goes_with(other_ty)
+                } else if let Any = *other {
+                    true
+                } else {
+                    false
+                }
+            }
             &Closure(ref cl) => {
                 if let Closure(ref other_cl) = *other {
                     if cl.tys.len() != other_cl.tys.len() { return false; }
@@ -446,9 +586,15 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("sec_f64") {
                 convert.update(range);
                 ty = Some(Type::Secret(Box::new(Type::F64)));
+            } else if let Ok((range, _)) = convert.meta_bool("i64") {
+                convert.update(range);
+                ty = Some(Type::I64);
             } else if let Ok((range, _)) = convert.meta_bool("str") {
                 convert.update(range);
                 ty = Some(Type::Str);
+            } else if let Ok((range, _)) = convert.meta_bool("bytes") {
+                convert.update(range);
+                ty = Some(Type::Bytes);
             } else if let Ok((range, _)) = convert.meta_bool("vec4") {
                 convert.update(range);
                 ty = Some(Type::Vec4);
@@ -458,6 +604,9 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("link") {
                 convert.update(range);
                 ty = Some(Type::Link);
+            } else if let Ok((range, _)) = convert.meta_bool("atomic") {
+                convert.update(range);
+                ty = Some(Type::Atomic);
             } else if let Ok((range, _)) = convert.meta_bool("opt_any") {
                 convert.update(range);
                 ty = Some(Type::Option(Box::new(Type::Any)));
@@ -470,12 +619,27 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("obj_any") {
                 convert.update(range);
                 ty = Some(Type::Object);
+            } else if let Ok((range, _)) = convert.meta_bool("map_any") {
+                convert.update(range);
+                ty = Some(Type::map());
+            } else if let Ok((range, _)) = convert.meta_bool("set_any") {
+                convert.update(range);
+                ty = Some(Type::set());
             } else if let Ok((range, _)) = convert.meta_bool("thr_any") {
                 convert.update(range);
                 ty = Some(Type::Thread(Box::new(Type::Any)));
             } else if let Ok((range, _)) = convert.meta_bool("in_any") {
                 convert.update(range);
                 ty = Some(Type::In(Box::new(Type::Any)));
+            } else if let Ok((range, _)) = convert.meta_bool("co_any") {
+                convert.update(range);
+                ty = Some(Type::coroutine());
+            } else if let Ok((range, _)) = convert.meta_bool("out_any") {
+                convert.update(range);
+                ty = Some(Type::out());
+            } else if let Ok((range, _)) = convert.meta_bool("mutex_any") {
+                convert.update(range);
+                ty = Some(Type::mutex());
             } else if let Ok((range, val)) = Type::from_meta_data(
                     "opt", convert, ignored) {
                 convert.update(range);
@@ -488,6 +652,19 @@ impl Type {
                     "arr", convert, ignored) {
                 convert.update(range);
                 ty = Some(Type::Array(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "set", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Set(Box::new(val)));
+            } else if let Ok(range) = convert.start_node("map_type") {
+                convert.update(range);
+                let (range, key) = Type::from_meta_data("map_key", convert, ignored)?;
+                convert.update(range);
+                let (range, val) = Type::from_meta_data("map_val", convert, ignored)?;
+                convert.update(range);
+                let range = convert.end_node("map_type")?;
+                convert.update(range);
+                ty = Some(Type::Map(Box::new(key), Box::new(val)));
             } else if let Ok((range, val)) = Type::from_meta_data(
                     "thr", convert, ignored) {
                 convert.update(range);
@@ -496,6 +673,18 @@ impl Type {
                     "in", convert, ignored) {
                 convert.update(range);
                 ty = Some(Type::In(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "co", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Coroutine(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "out", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Out(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "mutex", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Mutex(Box::new(val)));
             } else if let Ok((range, val)) = convert.meta_string("ad_hoc") {
                 convert.update(range);
                 let inner_ty = if let Ok((range, val)) = Type::from_meta_data(
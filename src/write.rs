@@ -32,9 +32,24 @@ pub(crate) fn write_variable<W>(
                 }
             }
         }
+        Variable::Bytes(ref bytes) => {
+            // Uses the same format as data, such that it can be loaded again.
+            write!(w, "bytes \"")?;
+            for b in bytes.iter() {
+                write!(w, "{:02x}", b)?;
+            }
+            write!(w, "\"")?;
+        }
         Variable::F64(x, _) => {
             write!(w, "{}", x)?;
         }
+        Variable::I64(x) => {
+            // Keep the type when writing data, such that it can be loaded again.
+            match escape_string {
+                EscapeString::Json => write!(w, "{}i64", x)?,
+                EscapeString::None => write!(w, "{}", x)?,
+            }
+        }
         Variable::Vec4(v) => {
             write!(w, "({}, {}", v[0], v[1])?;
             if v[2] != 0.0 || v[3] != 0.0 {
@@ -86,7 +101,7 @@ pub(crate) fn write_variable<W>(
                 }
             }
         }
-        Variable::Object(ref obj) => {
+        Variable::Object(ref obj) | Variable::Record(_, ref obj) => {
             write!(w, "{{")?;
             let n = obj.len();
             for (i, (k, v)) in obj.iter().enumerate() {
@@ -103,6 +118,30 @@ pub(crate) fn write_variable<W>(
             }
             write!(w, "}}")?;
         }
+        Variable::Map(ref map) => {
+            write!(w, "map {{")?;
+            let n = map.len();
+            for (i, (k, v)) in map.iter().enumerate() {
+                write_variable(w, rt, k, EscapeString::Json, tabs)?;
+                write!(w, ": ")?;
+                write_variable(w, rt, v, EscapeString::Json, tabs)?;
+                if i + 1 < n {
+                    write!(w, ", ")?;
+                }
+            }
+            write!(w, "}}")?;
+        }
+        Variable::Set(ref set) => {
+            write!(w, "set {{")?;
+            let n = set.len();
+            for (i, v) in set.iter().enumerate() {
+                write_variable(w, rt, v, EscapeString::Json, tabs)?;
+                if i + 1 < n {
+                    write!(w, ", ")?;
+                }
+            }
+            write!(w, "}}")?;
+        }
         Variable::Array(ref arr) => {
             write!(w, "[")?;
             let n = arr.len();
@@ -114,6 +153,17 @@ pub(crate) fn write_variable<W>(
             }
             write!(w, "]")?;
         }
+        Variable::Variant(ref v) => {
+            write!(w, "{}::{}(", v.enum_name, v.name)?;
+            let n = v.args.len();
+            for (i, arg) in v.args.iter().enumerate() {
+                write_variable(w, rt, arg, EscapeString::Json, tabs)?;
+                if i + 1 < n {
+                    write!(w, ", ")?;
+                }
+            }
+            write!(w, ")")?;
+        }
         Variable::Option(ref opt) => {
             match *opt {
                 None => {
@@ -146,6 +196,10 @@ pub(crate) fn write_variable<W>(
         Variable::RustObject(_) => write!(w, "_rust_object")?,
         Variable::Closure(ref closure, _) => write_closure(w, rt, closure, tabs)?,
         Variable::In(_) => write!(w, "_in")?,
+        Variable::Coroutine(_) => write!(w, "_coroutine")?,
+        Variable::Out(_) => write!(w, "_out")?,
+        Variable::Atomic(_) => write!(w, "_atomic")?,
+        Variable::Mutex(_) => write!(w, "_mutex")?,
         // ref x => panic!("Could not print out `{:?}`", x)
     }
     Ok(())
@@ -200,10 +254,12 @@ fn write_expr<W: io::Write>(
         E::Variable(ref range_var) =>
             write_variable(w, rt, &range_var.1, EscapeString::Json, tabs)?,
         E::Link(ref link) => write_link(w, rt, link, tabs)?,
+        E::Interp(ref interp) => write_interp(w, rt, interp, tabs)?,
         E::Object(ref obj) => write_obj(w, rt, obj, tabs)?,
         E::Array(ref arr) => write_arr(w, rt, arr, tabs)?,
         E::ArrayFill(ref arr_fill) => write_arr_fill(w, rt, arr_fill, tabs)?,
         E::Call(ref call) => write_call(w, rt, &call.info.name, &call.args, tabs)?,
+        E::Variant(ref variant) => write_variant(w, rt, variant, tabs)?,
         E::CallVoid(ref call) => write_call(w, rt, &call.info.name, &call.args, tabs)?,
         E::CallReturn(ref call) => write_call(w, rt, &call.info.name, &call.args, tabs)?,
         E::CallBinOp(ref call) => write_call(w, rt, &call.info.name,
@@ -216,6 +272,10 @@ fn write_expr<W: io::Write>(
             write_expr(w, rt, expr, tabs)?;
         }
         E::ReturnVoid(_) => write!(w, "return")?,
+        E::Yield(ref expr) => {
+            write!(w, "yield ")?;
+            write_expr(w, rt, expr, tabs)?;
+        }
         E::Break(ref br) => {
             if let Some(ref label) = br.label {
                 write!(w, "break '{}", label)?;
@@ -233,7 +293,11 @@ fn write_expr<W: io::Write>(
         E::Block(ref b) => write_block(w, rt, b, tabs)?,
         E::Go(ref go) => {
             write!(w, "go ")?;
-            write_call(w, rt, &go.call.info.name, &go.call.args, tabs)?;
+            match go.call {
+                ast::GoCall::Call(ref call) =>
+                    write_call(w, rt, &call.info.name, &call.args, tabs)?,
+                ast::GoCall::Closure(ref call) => write_call_closure(w, rt, call, tabs)?,
+            }
         }
         E::Assign(ref assign) => write_assign(w, rt, assign, tabs)?,
         E::Vec4(ref vec4) => write_vec4(w, rt, vec4, tabs)?,
@@ -247,7 +311,16 @@ fn write_expr<W: io::Write>(
             write!(w, "for ")?;
             write_for_in(w, rt, for_in, tabs)?;
         }
+        E::Lock(ref lock) => {
+            write!(w, "lock {} := ", lock.name)?;
+            write_expr(w, rt, &lock.mutex, tabs)?;
+            write!(w, " ")?;
+            write_block(w, rt, &lock.block, tabs + 1)?;
+        }
         E::Sum(ref for_n) => {
+            if for_n.par {
+                write!(w, "par ")?;
+            }
             write!(w, "sum ")?;
             write_for_n(w, rt, for_n, tabs)?;
         }
@@ -260,6 +333,9 @@ fn write_expr<W: io::Write>(
             write_for_n(w, rt, for_n, tabs)?;
         }
         E::Prod(ref for_n) => {
+            if for_n.par {
+                write!(w, "par ")?;
+            }
             write!(w, "prod ")?;
             write_for_n(w, rt, for_n, tabs)?;
         }
@@ -272,6 +348,9 @@ fn write_expr<W: io::Write>(
             write_for_n(w, rt, for_n, tabs)?;
         }
         E::Min(ref for_n) => {
+            if for_n.par {
+                write!(w, "par ")?;
+            }
             write!(w, "min ")?;
             write_for_n(w, rt, for_n, tabs)?;
         }
@@ -280,6 +359,9 @@ fn write_expr<W: io::Write>(
             write_for_in(w, rt, for_in, tabs)?;
         }
         E::Max(ref for_n) => {
+            if for_n.par {
+                write!(w, "par ")?;
+            }
             write!(w, "max ")?;
             write_for_n(w, rt, for_n, tabs)?;
         }
@@ -288,6 +370,9 @@ fn write_expr<W: io::Write>(
             write_for_in(w, rt, for_in, tabs)?;
         }
         E::Sift(ref for_n) => {
+            if for_n.par {
+                write!(w, "par ")?;
+            }
             write!(w, "sift ")?;
             write_for_n(w, rt, for_n, tabs)?;
         }
@@ -296,6 +381,9 @@ fn write_expr<W: io::Write>(
             write_for_in(w, rt, for_in, tabs)?;
         }
         E::Any(ref for_n) => {
+            if for_n.par {
+                write!(w, "par ")?;
+            }
             write!(w, "any ")?;
             write_for_n(w, rt, for_n, tabs)?;
         }
@@ -304,6 +392,9 @@ fn write_expr<W: io::Write>(
             write_for_in(w, rt, for_in, tabs)?;
         }
         E::All(ref for_n) => {
+            if for_n.par {
+                write!(w, "par ")?;
+            }
             write!(w, "all ")?;
             write_for_n(w, rt, for_n, tabs)?;
         }
@@ -320,6 +411,7 @@ fn write_expr<W: io::Write>(
             write_for_in(w, rt, for_in, tabs)?;
         }
         E::If(ref if_expr) => write_if(w, rt, if_expr, tabs)?,
+        E::Match(ref match_expr) => write_match(w, rt, match_expr, tabs)?,
         E::Try(ref expr) => {
             write_expr(w, rt, expr, tabs)?;
             write!(w, "?")?;
@@ -492,6 +584,48 @@ fn write_link<W: io::Write>(
     Ok(())
 }
 
+fn write_interp<W: io::Write>(
+    w: &mut W,
+    rt: &Runtime,
+    interp: &ast::Interp,
+    tabs: u32,
+) -> Result<(), io::Error> {
+    write!(w, "$\"")?;
+    for part in &interp.parts {
+        match *part {
+            ast::InterpPart::Text(ref text) => {
+                for c in text.chars() {
+                    match c {
+                        '{' => write!(w, "{{{{")?,
+                        '}' => write!(w, "}}}}")?,
+                        '"' => write!(w, "\\\"")?,
+                        '\\' => write!(w, "\\\\")?,
+                        '\n' => write!(w, "\\n")?,
+                        '\t' => write!(w, "\\t")?,
+                        c => write!(w, "{}", c)?,
+                    }
+                }
+            }
+            ast::InterpPart::Item(ref item) => {
+                write!(w, "{{")?;
+                write_expr(w, rt, &item.expr, tabs)?;
+                if item.width.is_some() || item.precision.is_some() {
+                    write!(w, ":")?;
+                    if let Some(width) = item.width {
+                        write!(w, "{}", width)?;
+                    }
+                    if let Some(precision) = item.precision {
+                        write!(w, ".{}", precision)?;
+                    }
+                }
+                write!(w, "}}")?;
+            }
+        }
+    }
+    write!(w, "\"")?;
+    Ok(())
+}
+
 fn write_obj<W: io::Write>(
     w: &mut W,
     rt: &Runtime,
@@ -543,6 +677,23 @@ fn write_call<W: io::Write>(
     }
 }
 
+fn write_variant<W: io::Write>(
+    w: &mut W,
+    rt: &Runtime,
+    variant: &ast::Variant,
+    tabs: u32,
+) -> Result<(), io::Error> {
+    write!(w, "{}::{}(", variant.enum_name, variant.name)?;
+    for (i, arg) in variant.args.iter().enumerate() {
+        write_expr(w, rt, arg, tabs)?;
+        if i + 1 < variant.args.len() {
+            write!(w, ", ")?;
+        }
+    }
+    write!(w, ")")?;
+    Ok(())
+}
+
 fn write_call_closure<W: io::Write>(
     w: &mut W,
     rt: &Runtime,
@@ -738,6 +889,11 @@ fn standard_binop(name: &Arc<String>, args: &[ast::Expression]) -> Option<ast::B
         "pow" => Pow,
         "and_also" => AndAlso,
         "or_else" => OrElse,
+        "bit_and" => BitAnd,
+        "bit_or" => BitOr,
+        "xor" => Xor,
+        "shl" => Shl,
+        "shr" => Shr,
         "less" => Less,
         "less_or_equal" => LessOrEqual,
         "greater" => Greater,
@@ -806,6 +962,85 @@ fn write_if<W: io::Write>(
     Ok(())
 }
 
+fn write_match<W: io::Write>(
+    w: &mut W,
+    rt: &Runtime,
+    match_expr: &ast::Match,
+    tabs: u32,
+) -> Result<(), io::Error> {
+    write!(w, "match ")?;
+    write_expr(w, rt, &match_expr.expr, tabs)?;
+    writeln!(w, " {{")?;
+    for (i, arm) in match_expr.arms.iter().enumerate() {
+        write_tabs(w, tabs + 1)?;
+        write_pattern(w, &arm.pattern)?;
+        write!(w, " => ")?;
+        write_expr(w, rt, &arm.expr, tabs + 1)?;
+        if i + 1 < match_expr.arms.len() {
+            writeln!(w, ",")?;
+        } else {
+            writeln!(w)?;
+        }
+    }
+    write_tabs(w, tabs)?;
+    write!(w, "}}")?;
+    Ok(())
+}
+
+fn write_pattern<W: io::Write>(
+    w: &mut W,
+    pattern: &ast::Pattern,
+) -> Result<(), io::Error> {
+    use ast::Pattern as P;
+
+    match *pattern {
+        P::Any => write!(w, "_")?,
+        P::Bind(ref name) => write!(w, "{}", name)?,
+        P::F64(val) => write!(w, "{}", val)?,
+        P::I64(val) => write!(w, "{}i64", val)?,
+        P::Str(ref val) => json::write_string(w, val)?,
+        P::Bool(val) => write!(w, "{}", val)?,
+        P::Some(ref p) => {
+            write!(w, "some(")?;
+            write_pattern(w, p)?;
+            write!(w, ")")?;
+        }
+        P::None => write!(w, "none()")?,
+        P::Ok(ref p) => {
+            write!(w, "ok(")?;
+            write_pattern(w, p)?;
+            write!(w, ")")?;
+        }
+        P::Err(ref p) => {
+            write!(w, "err(")?;
+            write_pattern(w, p)?;
+            write!(w, ")")?;
+        }
+        P::Object(ref fields) => {
+            write!(w, "{{")?;
+            for (i, (key, p)) in fields.iter().enumerate() {
+                write!(w, "{}: ", key)?;
+                write_pattern(w, p)?;
+                if i + 1 < fields.len() {
+                    write!(w, ", ")?;
+                }
+            }
+            write!(w, "}}")?;
+        }
+        P::Variant(ref enum_name, ref name, ref args) => {
+            write!(w, "{}::{}(", enum_name, name)?;
+            for (i, p) in args.iter().enumerate() {
+                write_pattern(w, p)?;
+                if i + 1 < args.len() {
+                    write!(w, ", ")?;
+                }
+            }
+            write!(w, ")")?;
+        }
+    }
+    Ok(())
+}
+
 fn write_grab<W: io::Write>(
     w: &mut W,
     rt: &Runtime,
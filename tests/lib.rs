@@ -15,7 +15,7 @@ pub fn test_fail_src(source: &str) {
     match load(source, &mut module) {
         Ok(_) => panic!("`{}` should fail", source),
         Err(err) => {
-            if err.starts_with(&format!("Could not open `{}`", source)) {
+            if let DyonError::Io(_) = err {
                 panic!("{}", err)
             }
         }
@@ -124,7 +124,22 @@ fn test_syntax() {
     test_src("source/syntax/try_expr.dyon");
     test_src("source/syntax/start_true.dyon");
     test_fail_src("source/syntax/push_ref.dyon");
-    test_src("source/syntax/for_in.dyon");
+    test_src("source/syntax/for_in_collections.dyon");
+    test_src("source/syntax/match.dyon");
+    test_src("source/syntax/record.dyon");
+    test_src("source/syntax/enum.dyon");
+    test_src("source/syntax/i64.dyon");
+    test_src("source/syntax/bytes.dyon");
+    test_src("source/syntax/map.dyon");
+    test_src("source/syntax/interp.dyon");
+    test_fail_src("source/syntax/interp_fail_1.dyon");
+    test_fail_src("source/syntax/interp_fail_2.dyon");
+    test_src("source/syntax/coroutine.dyon");
+    test_fail_src("source/syntax/coroutine_fail_1.dyon");
+    test_src("source/syntax/par.dyon");
+    test_fail_src("source/syntax/par_fail_1.dyon");
+    test_fail_src("source/syntax/par_fail_2.dyon");
+    test_fail_src("source/syntax/par_fail_3.dyon");
     test_src("source/syntax/return_arr.dyon");
     test_src("source/syntax/return_cmp.dyon");
     test_src("source/syntax/try_pass_1.dyon");
@@ -146,6 +161,23 @@ fn test_syntax() {
     test_src("source/syntax/lazy_pass_8.dyon");
 }
 
+#[cfg(feature = "threading")]
+#[test]
+fn test_threading() {
+    test_src("source/syntax/for_in.dyon");
+    test_src("source/syntax/channel.dyon");
+    test_src("source/syntax/go_blocking.dyon");
+    test_src("source/syntax/go_closure.dyon");
+    test_fail_src("source/syntax/go_closure_fail.dyon");
+    test_src("source/syntax/thread_cancel.dyon");
+    test_src("source/syntax/lock.dyon");
+    test_fail_src("source/syntax/lock_fail.dyon");
+    test_src("source/typechk/refinement_25.dyon");
+    test_fail_src("source/typechk/go.dyon");
+    test_fail_src("source/typechk/channel.dyon");
+    test_fail_src("source/typechk/lock.dyon");
+}
+
 #[test]
 fn test_typechk() {
     test_fail_src("source/typechk/opt.dyon");
@@ -181,7 +213,6 @@ fn test_typechk() {
     test_src("source/typechk/arr_pass_1.dyon");
     test_fail_src("source/typechk/arr_fail_1.dyon");
     test_fail_src("source/typechk/arr_fail_2.dyon");
-    test_fail_src("source/typechk/go.dyon");
     test_fail_src("source/typechk/unused_result.dyon");
     test_fail_src("source/typechk/unused_result_2.dyon");
     test_src("source/typechk/res.dyon");
@@ -256,7 +287,6 @@ fn test_typechk() {
     test_fail_src("source/typechk/refinement_22.dyon");
     test_src("source/typechk/refinement_23.dyon");
     test_fail_src("source/typechk/refinement_24.dyon");
-    test_src("source/typechk/refinement_25.dyon");
     test_fail_src("source/typechk/refinement_26.dyon");
     test_src("source/typechk/refinement_27.dyon");
     test_fail_src("source/typechk/void_refinement.dyon");
@@ -274,6 +304,22 @@ fn test_typechk() {
     test_src("source/typechk/refine_quantifier_pass_5.dyon");
     test_fail_src("source/typechk/refine_quantifier_fail_1.dyon");
     test_fail_src("source/typechk/refine_quantifier_fail_2.dyon");
+    test_fail_src("source/typechk/record.dyon");
+    test_fail_src("source/typechk/record_2.dyon");
+    test_fail_src("source/typechk/record_3.dyon");
+    test_fail_src("source/typechk/record_4.dyon");
+    test_fail_src("source/typechk/enum.dyon");
+    test_fail_src("source/typechk/enum_2.dyon");
+    test_fail_src("source/typechk/enum_3.dyon");
+    test_fail_src("source/typechk/enum_4.dyon");
+    test_fail_src("source/typechk/i64.dyon");
+    test_fail_src("source/typechk/i64_2.dyon");
+    test_fail_src("source/typechk/bytes.dyon");
+    test_fail_src("source/typechk/map.dyon");
+    test_fail_src("source/typechk/interp.dyon");
+    test_fail_src("source/typechk/interp_2.dyon");
+    test_fail_src("source/typechk/coroutine.dyon");
+    test_fail_src("source/typechk/coroutine_2.dyon");
 }
 
 #[test]